@@ -0,0 +1,271 @@
+// command line options
+use structopt::StructOpt;
+// pbrt
+use rs_pbrt::accelerators::bvh::{BVHAccel, SplitMethod, MAX_PACKET_SIZE};
+use rs_pbrt::accelerators::widebvh::WideBVHAccel;
+use rs_pbrt::core::geometry::{Point3f, Ray, Vector3f};
+use rs_pbrt::core::interaction::SurfaceInteraction;
+use rs_pbrt::core::medium::MediumInterface;
+use rs_pbrt::core::pbrt::Float;
+use rs_pbrt::core::primitive::{GeometricPrimitive, Primitive};
+use rs_pbrt::core::rng::Rng;
+use rs_pbrt::core::shape::Shape;
+use rs_pbrt::core::transform::Transform;
+use rs_pbrt::shapes::triangle::{Triangle, TriangleMesh};
+// std
+use std::cell::Cell;
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Compare ray traversal of the binary BVH with the wide BVH (and
+/// single rays with ray packets) for a random triangle soup.
+#[derive(StructOpt)]
+struct Cli {
+    /// number of random triangles
+    #[structopt(short = "t", long = "triangles", default_value = "200000")]
+    triangles: u32,
+    /// number of random rays
+    #[structopt(short = "r", long = "rays", default_value = "100000")]
+    rays: usize,
+    /// children per node of the wide BVH (4 or 8)
+    #[structopt(short = "w", long = "width", default_value = "4")]
+    width: usize,
+}
+
+fn random_point(rng: &mut Rng) -> Point3f {
+    Point3f {
+        x: rng.uniform_float() * 2.0 - 1.0,
+        y: rng.uniform_float() * 2.0 - 1.0,
+        z: rng.uniform_float() * 2.0 - 1.0,
+    }
+}
+
+fn make_triangles(n_triangles: u32, rng: &mut Rng) -> Vec<Arc<Primitive>> {
+    let mut p: Vec<Point3f> = Vec::with_capacity(3 * n_triangles as usize);
+    let size: Float = 1.0 / (n_triangles as Float).cbrt();
+    for _ in 0..n_triangles {
+        let center: Point3f = random_point(rng);
+        for _ in 0..3 {
+            let offset: Point3f = random_point(rng);
+            p.push(Point3f {
+                x: center.x + offset.x * size,
+                y: center.y + offset.y * size,
+                z: center.z + offset.z * size,
+            });
+        }
+    }
+    let vertex_indices: Vec<u32> = (0..3 * n_triangles).collect();
+    let n_vertices: usize = p.len();
+    let mesh: Arc<TriangleMesh> = Arc::new(TriangleMesh::new(
+        Transform::default(),
+        Transform::default(),
+        false,
+        n_triangles,
+        vertex_indices,
+        n_vertices.try_into().unwrap(),
+        p,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        None,
+        None,
+    ));
+    let mi: Arc<MediumInterface> = Arc::new(MediumInterface::default());
+    (0..n_triangles)
+        .map(|id| {
+            let shape: Arc<Shape> = Arc::new(Shape::Trngl(Triangle::new(mesh.clone(), id)));
+            Arc::new(Primitive::Geometric(Box::new(GeometricPrimitive::new(
+                shape,
+                None,
+                None,
+                Some(mi.clone()),
+            ))))
+        })
+        .collect()
+}
+
+fn make_rays(n_rays: usize, rng: &mut Rng) -> Vec<Ray> {
+    (0..n_rays)
+        .map(|_| {
+            let o: Point3f = random_point(rng) * 1.5 as Float;
+            let target: Point3f = random_point(rng) * 0.5 as Float;
+            let d: Vector3f = (target - o).normalize();
+            Ray {
+                o,
+                d,
+                t_max: Cell::new(std::f32::INFINITY),
+                time: 0.0 as Float,
+                medium: None,
+                differential: None,
+            }
+        })
+        .collect()
+}
+
+/// Coherent rays, one packet of nearly parallel rays (sharing their
+/// origin) after the other.
+fn make_coherent_rays(n_rays: usize, rng: &mut Rng) -> Vec<Ray> {
+    let mut rays: Vec<Ray> = Vec::with_capacity(n_rays);
+    while rays.len() < n_rays {
+        let o: Point3f = random_point(rng) * 1.5 as Float;
+        let target: Point3f = random_point(rng) * 0.5 as Float;
+        for _ in 0..MAX_PACKET_SIZE.min(n_rays - rays.len()) {
+            let jitter: Point3f = random_point(rng) * 0.005 as Float;
+            let d: Vector3f = (target + jitter - o).normalize();
+            rays.push(Ray {
+                o,
+                d,
+                t_max: Cell::new(std::f32::INFINITY),
+                time: 0.0 as Float,
+                medium: None,
+                differential: None,
+            });
+        }
+    }
+    rays
+}
+
+fn copy_ray(r: &Ray) -> Ray {
+    Ray {
+        o: r.o,
+        d: r.d,
+        t_max: Cell::new(r.t_max.get()),
+        time: r.time,
+        medium: None,
+        differential: None,
+    }
+}
+
+/// Same as [trace()](fn.trace.html), but for whole packets of rays.
+fn trace_packets(rays: &[Ray], intersect: &dyn Fn(&[Ray], &mut [bool])) -> (Vec<Float>, f64) {
+    let start = Instant::now();
+    let mut t: Vec<Float> = Vec::with_capacity(rays.len());
+    let mut hits: [bool; MAX_PACKET_SIZE] = [false; MAX_PACKET_SIZE];
+    for packet in rays.chunks(MAX_PACKET_SIZE) {
+        let packet: Vec<Ray> = packet.iter().map(copy_ray).collect();
+        intersect(&packet, &mut hits[..packet.len()]);
+        for (ray, hit) in packet.iter().zip(hits.iter()) {
+            if *hit {
+                t.push(ray.t_max.get());
+            } else {
+                t.push(std::f32::INFINITY);
+            }
+        }
+    }
+    let rays_per_second: f64 = rays.len() as f64 / start.elapsed().as_secs_f64();
+    (t, rays_per_second)
+}
+
+/// Returns the hit distances (infinity for misses) and the number of
+/// rays per second.
+fn trace(rays: &[Ray], intersect: &dyn Fn(&Ray) -> bool) -> (Vec<Float>, f64) {
+    let start = Instant::now();
+    let t: Vec<Float> = rays
+        .iter()
+        .map(|r| {
+            let ray: Ray = copy_ray(r);
+            if intersect(&ray) {
+                ray.t_max.get()
+            } else {
+                std::f32::INFINITY
+            }
+        })
+        .collect();
+    let rays_per_second: f64 = rays.len() as f64 / start.elapsed().as_secs_f64();
+    (t, rays_per_second)
+}
+
+fn main() {
+    let args = Cli::from_args();
+    let mut rng: Rng = Rng::new();
+    rng.set_sequence(0);
+    println!("{} triangles, {} rays", args.triangles, args.rays);
+    let primitives: Vec<Arc<Primitive>> = make_triangles(args.triangles, &mut rng);
+    let rays: Vec<Ray> = make_rays(args.rays, &mut rng);
+    // binary BVH
+    let bvh: BVHAccel = BVHAccel::new(primitives.clone(), 4, SplitMethod::SAH, 12);
+    bvh.print_stats();
+    let (t_bvh, speed_bvh) = trace(&rays, &|ray: &Ray| {
+        let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+        bvh.intersect(ray, &mut isect)
+    });
+    let (_, speed_bvh_p) = trace(&rays, &|ray: &Ray| bvh.intersect_p(ray));
+    // wide BVH
+    let start = Instant::now();
+    let wide_bvh: WideBVHAccel = WideBVHAccel::new(
+        BVHAccel::new(primitives, 4, SplitMethod::SAH, 12),
+        args.width,
+    );
+    println!(
+        "wide BVH ({} children) with {} nodes built in {:.3} seconds",
+        wide_bvh.width,
+        wide_bvh.nodes.len(),
+        start.elapsed().as_secs_f64()
+    );
+    let (t_wide, speed_wide) = trace(&rays, &|ray: &Ray| {
+        let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+        wide_bvh.intersect(ray, &mut isect)
+    });
+    let (_, speed_wide_p) = trace(&rays, &|ray: &Ray| wide_bvh.intersect_p(ray));
+    // compare
+    let n_hits: usize = t_bvh.iter().filter(|t| t.is_finite()).count();
+    let n_mismatches: usize = t_bvh
+        .iter()
+        .zip(t_wide.iter())
+        .filter(|(a, b)| a != b)
+        .count();
+    println!("{} hits, {} mismatches", n_hits, n_mismatches);
+    println!(
+        "intersect:   binary {:.3} Mrays/s, wide {:.3} Mrays/s ({:.2}x)",
+        speed_bvh * 1e-6,
+        speed_wide * 1e-6,
+        speed_wide / speed_bvh
+    );
+    println!(
+        "intersect_p: binary {:.3} Mrays/s, wide {:.3} Mrays/s ({:.2}x)",
+        speed_bvh_p * 1e-6,
+        speed_wide_p * 1e-6,
+        speed_wide_p / speed_bvh_p
+    );
+    // single rays vs. ray packets (coherent rays)
+    let coherent_rays: Vec<Ray> = make_coherent_rays(args.rays, &mut rng);
+    let (t_single, speed_single) = trace(&coherent_rays, &|ray: &Ray| {
+        let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+        bvh.intersect(ray, &mut isect)
+    });
+    let (t_packet, speed_packet) =
+        trace_packets(&coherent_rays, &|rays: &[Ray], hits: &mut [bool]| {
+            let mut isects: Vec<SurfaceInteraction> =
+                rays.iter().map(|_| SurfaceInteraction::default()).collect();
+            bvh.intersect_packet(rays, &mut isects, hits)
+        });
+    let (_, speed_single_p) = trace(&coherent_rays, &|ray: &Ray| bvh.intersect_p(ray));
+    let (_, speed_packet_p) =
+        trace_packets(&coherent_rays, &|rays: &[Ray], occluded: &mut [bool]| {
+            bvh.intersect_p_packet(rays, occluded)
+        });
+    let n_mismatches: usize = t_single
+        .iter()
+        .zip(t_packet.iter())
+        .filter(|(a, b)| a != b)
+        .count();
+    println!(
+        "{} coherent rays in packets of {}, {} mismatches",
+        coherent_rays.len(),
+        MAX_PACKET_SIZE,
+        n_mismatches
+    );
+    println!(
+        "intersect:   single {:.3} Mrays/s, packet {:.3} Mrays/s ({:.2}x)",
+        speed_single * 1e-6,
+        speed_packet * 1e-6,
+        speed_packet / speed_single
+    );
+    println!(
+        "intersect_p: single {:.3} Mrays/s, packet {:.3} Mrays/s ({:.2}x)",
+        speed_single_p * 1e-6,
+        speed_packet_p * 1e-6,
+        speed_packet_p / speed_single_p
+    );
+}
@@ -8,6 +8,7 @@ use structopt::StructOpt;
 // pbrt
 use rs_pbrt::core::api::{make_accelerator, make_camera, make_film, make_filter, make_sampler};
 use rs_pbrt::core::camera::Camera;
+use rs_pbrt::core::colorspace::RGBColorSpace;
 use rs_pbrt::core::film::Film;
 use rs_pbrt::core::geometry::{Bounds2f, Bounds2i, Normal3f, Point2f, Point3f, Vector3f};
 use rs_pbrt::core::integrator::{Integrator, SamplerIntegrator};
@@ -171,8 +172,13 @@ pub fn make_perspective_camera(
             p_min: Point2f { x: 0.0, y: 0.0 },
             p_max: Point2f { x: 1.0, y: 1.0 },
         };
-        let some_film: Option<Arc<Film>> =
-            make_film(&film_name, &film_params, filter, &crop_window);
+        let some_film: Option<Arc<Film>> = make_film(
+            &film_name,
+            &film_params,
+            filter,
+            &crop_window,
+            RGBColorSpace::SRGB,
+        );
         if let Some(film) = some_film {
             let camera_name: String = String::from("perspective");
             let mut camera_params: ParamSet = ParamSet::default();
@@ -223,6 +229,7 @@ fn make_path_integrator(
                     pixel_bounds,
                     rr_threshold,
                     light_strategy,
+                    0_u32,
                 ),
             )));
             some_integrator = Some(integrator);
@@ -1091,7 +1098,7 @@ fn main() -> std::io::Result<()> {
                                         if metalness == 1.0 as Float {
                                             let kr = Arc::new(ConstantTexture::new(specular_color));
                                             let mirror = Arc::new(Material::Mirror(Box::new(
-                                                MirrorMaterial::new(kr, None),
+                                                MirrorMaterial::new(kr, None, None),
                                             )));
                                             named_materials.insert(node_name.clone(), mirror);
                                         } else {
@@ -1121,6 +1128,7 @@ fn main() -> std::io::Result<()> {
                                                     None,
                                                     None,
                                                     None,
+                                                    None,
                                                     remap_roughness,
                                                 ),
                                             )));
@@ -1131,7 +1139,7 @@ fn main() -> std::io::Result<()> {
                                         let kd = Arc::new(ConstantTexture::new(base_color));
                                         let sigma = Arc::new(ConstantTexture::new(0.0 as Float));
                                         let matte = Arc::new(Material::Matte(Box::new(
-                                            MatteMaterial::new(kd, sigma, None),
+                                            MatteMaterial::new(kd, sigma, None, None),
                                         )));
                                         named_materials.insert(node_name.clone(), matte);
                                     }
@@ -1,8 +1,9 @@
 // std
+use std::ops::Range;
 use std::sync::Arc;
+use std::time::Instant;
 // others
-// use time::PreciseTime;
-use typed_arena::Arena;
+use rayon::prelude::*;
 // pbrt
 use crate::core::geometry::{bnd3_union_bnd3f, bnd3_union_pnt3f};
 use crate::core::geometry::{Bounds3f, Point3f, Ray, Vector3f, XYZEnum};
@@ -15,6 +16,13 @@ use crate::core::primitive::Primitive;
 
 // see bvh.h
 
+/// Subtrees with more primitives than this are built in parallel.
+const PARALLEL_BUILD_THRESHOLD: usize = 4096;
+
+/// Maximum number of rays traversed together by
+/// [intersect_packet()](struct.BVHAccel.html#method.intersect_packet).
+pub const MAX_PACKET_SIZE: usize = 16;
+
 #[derive(Debug, Clone)]
 pub enum SplitMethod {
     SAH,
@@ -40,30 +48,19 @@ impl BVHPrimitiveInfo {
     }
 }
 
-#[derive(Debug)]
-pub struct BVHBuildNode<'a> {
+/// The build nodes own their children, so subtrees can be built on
+/// different threads and get moved into their parent afterwards.
+#[derive(Debug, Default)]
+pub struct BVHBuildNode {
     pub bounds: Bounds3f,
-    pub child1: Option<&'a BVHBuildNode<'a>>,
-    pub child2: Option<&'a BVHBuildNode<'a>>,
+    pub child1: Option<Box<BVHBuildNode>>,
+    pub child2: Option<Box<BVHBuildNode>>,
     pub split_axis: u8,
     pub first_prim_offset: usize,
     pub n_primitives: usize,
 }
 
-impl<'a> Default for BVHBuildNode<'a> {
-    fn default() -> Self {
-        BVHBuildNode {
-            bounds: Bounds3f::default(),
-            child1: None,
-            child2: None,
-            split_axis: 0_u8,
-            first_prim_offset: 0_usize,
-            n_primitives: 0_usize,
-        }
-    }
-}
-
-impl<'a> BVHBuildNode<'a> {
+impl BVHBuildNode {
     pub fn init_leaf(&mut self, first: usize, n: usize, b: &Bounds3f) {
         self.first_prim_offset = first;
         self.n_primitives = n;
@@ -71,7 +68,7 @@ impl<'a> BVHBuildNode<'a> {
         self.child1 = None;
         self.child2 = None;
     }
-    pub fn init_interior(&mut self, axis: u8, c0: &'a BVHBuildNode<'a>, c1: &'a BVHBuildNode<'a>) {
+    pub fn init_interior(&mut self, axis: u8, c0: Box<BVHBuildNode>, c1: Box<BVHBuildNode>) {
         self.n_primitives = 0;
         self.bounds = bnd3_union_bnd3f(&c0.bounds, &c1.bounds);
         self.child1 = Some(c0);
@@ -95,23 +92,425 @@ impl Default for BucketInfo {
     }
 }
 
+/// Returns the cost of the cheapest split (after a bucket) and the
+/// index of that bucket. The costs of both sides are accumulated in
+/// one sweep from the left and one from the right.
+fn find_sah_split(buckets: &[BucketInfo], traversal_cost: Float, area: Float) -> (Float, usize) {
+    let n_buckets: usize = buckets.len();
+    let mut cost: Vec<Float> = vec![0.0 as Float; n_buckets - 1];
+    let mut b0: Bounds3f = Bounds3f::default();
+    let mut count0: usize = 0;
+    for i in 0..(n_buckets - 1) {
+        b0 = bnd3_union_bnd3f(&b0, &buckets[i].bounds);
+        count0 += buckets[i].count;
+        if count0 > 0 {
+            cost[i] = count0 as Float * b0.surface_area();
+        }
+    }
+    let mut b1: Bounds3f = Bounds3f::default();
+    let mut count1: usize = 0;
+    for i in (1..n_buckets).rev() {
+        b1 = bnd3_union_bnd3f(&b1, &buckets[i].bounds);
+        count1 += buckets[i].count;
+        if count1 > 0 {
+            cost[i - 1] += count1 as Float * b1.surface_area();
+        }
+    }
+    // find bucket to split at that minimizes SAH metric
+    let mut min_cost: Float = Float::INFINITY;
+    let mut min_cost_split_bucket: usize = 0;
+    for (i, item) in cost.iter().enumerate() {
+        let c: Float = traversal_cost + item / area;
+        if c < min_cost {
+            min_cost = c;
+            min_cost_split_bucket = i;
+        }
+    }
+    (min_cost, min_cost_split_bucket)
+}
+
+/// Moves all elements for which *pred* is true to the front and
+/// returns the number of those elements.
+fn partition_in_place<T, F>(v: &mut [T], pred: F) -> usize
+where
+    F: Fn(&T) -> bool,
+{
+    let mut first: usize = 0;
+    for i in 0..v.len() {
+        if pred(&v[i]) {
+            v.swap(first, i);
+            first += 1;
+        }
+    }
+    first
+}
+
+/// Split in the middle of the list after partially sorting along the
+/// given axis.
+fn split_equal_counts(primitive_info: &mut [BVHPrimitiveInfo], dim_i: XYZEnum) -> usize {
+    let mid: usize = primitive_info.len() / 2;
+    primitive_info.select_nth_unstable_by(mid, |a, b| {
+        a.centroid[dim_i]
+            .partial_cmp(&b.centroid[dim_i])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    mid
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct MortonPrimitive {
+    primitive_index: usize,
+    morton_code: u32,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct LBVHTreelet {
+    start_index: usize,
+    n_primitives: usize,
+}
+
+fn left_shift_3(x: u32) -> u32 {
+    let mut x: u32 = x;
+    if x == (1 << 10) {
+        x -= 1;
+    }
+    x = (x | (x << 16)) & 0b0000_0011_0000_0000_0000_0000_1111_1111;
+    // x = ---- --98 ---- ---- ---- ---- 7654 3210
+    x = (x | (x << 8)) & 0b0000_0011_0000_0000_1111_0000_0000_1111;
+    // x = ---- --98 ---- ---- 7654 ---- ---- 3210
+    x = (x | (x << 4)) & 0b0000_0011_0000_1100_0011_0000_1100_0011;
+    // x = ---- --98 ---- 76-- --54 ---- 32-- --10
+    x = (x | (x << 2)) & 0b0000_1001_0010_0100_1001_0010_0100_1001;
+    // x = ---- 9--8 --7- -6-- 5--4 --3- -2-- 1--0
+    x
+}
+
+pub fn encode_morton_3(v: &Vector3f) -> u32 {
+    assert!(v.x >= 0.0 as Float);
+    assert!(v.y >= 0.0 as Float);
+    assert!(v.z >= 0.0 as Float);
+    (left_shift_3(v.z as u32) << 2) | (left_shift_3(v.y as u32) << 1) | left_shift_3(v.x as u32)
+}
+
+fn radix_sort(v: &mut Vec<MortonPrimitive>) {
+    let mut temp_vector: Vec<MortonPrimitive> = vec![MortonPrimitive::default(); v.len()];
+    const BITS_PER_PASS: u32 = 6;
+    const N_BITS: u32 = 30;
+    const N_PASSES: u32 = N_BITS / BITS_PER_PASS;
+    const N_BUCKETS: usize = 1 << BITS_PER_PASS;
+    const BIT_MASK: u32 = (1 << BITS_PER_PASS) - 1;
+    for pass in 0..N_PASSES {
+        // perform one pass of radix sort, sorting _BITS_PER_PASS_ bits
+        let low_bit: u32 = pass * BITS_PER_PASS;
+        // count number of zero bits in array for current radix sort bit
+        let mut bucket_count: [usize; N_BUCKETS] = [0_usize; N_BUCKETS];
+        for mp in v.iter() {
+            let bucket: usize = ((mp.morton_code >> low_bit) & BIT_MASK) as usize;
+            bucket_count[bucket] += 1;
+        }
+        // compute starting index in output array for each bucket
+        let mut out_index: [usize; N_BUCKETS] = [0_usize; N_BUCKETS];
+        for i in 1..N_BUCKETS {
+            out_index[i] = out_index[i - 1] + bucket_count[i - 1];
+        }
+        // store sorted values in output array
+        for mp in v.iter() {
+            let bucket: usize = ((mp.morton_code >> low_bit) & BIT_MASK) as usize;
+            temp_vector[out_index[bucket]] = *mp;
+            out_index[bucket] += 1;
+        }
+        std::mem::swap(v, &mut temp_vector);
+    }
+}
+
+/// Build the BVH of a treelet by splitting the (sorted) Morton codes
+/// from bit *bit_index* downwards. The primitives are expected to be
+/// in Morton order already, *offset* is the index of the first one.
+fn emit_lbvh(
+    primitive_info: &[BVHPrimitiveInfo],
+    morton_prims: &[MortonPrimitive],
+    offset: usize,
+    bit_index: i32,
+    max_prims_in_node: usize,
+) -> Box<BVHBuildNode> {
+    let n_primitives: usize = morton_prims.len();
+    assert!(n_primitives > 0);
+    let mut node: Box<BVHBuildNode> = Box::default();
+    if bit_index == -1 || n_primitives < max_prims_in_node {
+        // create and return leaf node of LBVH treelet
+        let mut bounds: Bounds3f = Bounds3f::default();
+        for item in primitive_info.iter().skip(offset).take(n_primitives) {
+            bounds = bnd3_union_bnd3f(&bounds, &item.bounds);
+        }
+        node.init_leaf(offset, n_primitives, &bounds);
+        node
+    } else {
+        let mask: u32 = 1 << bit_index;
+        // advance to next subtree level if there's no LBVH split for this bit
+        if (morton_prims[0].morton_code & mask)
+            == (morton_prims[n_primitives - 1].morton_code & mask)
+        {
+            return emit_lbvh(
+                primitive_info,
+                morton_prims,
+                offset,
+                bit_index - 1,
+                max_prims_in_node,
+            );
+        }
+        // find LBVH split point for this dimension
+        let split_offset: usize = morton_prims.partition_point(|mp| mp.morton_code & mask == 0);
+        assert!(split_offset > 0 && split_offset < n_primitives);
+        // create and return interior LBVH node
+        let c0: Box<BVHBuildNode> = emit_lbvh(
+            primitive_info,
+            &morton_prims[..split_offset],
+            offset,
+            bit_index - 1,
+            max_prims_in_node,
+        );
+        let c1: Box<BVHBuildNode> = emit_lbvh(
+            primitive_info,
+            &morton_prims[split_offset..],
+            offset + split_offset,
+            bit_index - 1,
+            max_prims_in_node,
+        );
+        node.init_interior((bit_index % 3) as u8, c0, c1);
+        node
+    }
+}
+
+/// Per-ray data needed to test a packet of rays against BVH node
+/// bounds.
+struct PacketInfo {
+    inv_dir: [Vector3f; MAX_PACKET_SIZE],
+    dir_is_neg: [[u8; 3]; MAX_PACKET_SIZE],
+    /// for each axis the rays with a negative direction component
+    neg_mask: [u32; 3],
+    n_rays: usize,
+}
+
+impl PacketInfo {
+    fn new(rays: &[Ray]) -> Self {
+        assert!(!rays.is_empty() && rays.len() <= MAX_PACKET_SIZE);
+        let mut packet: PacketInfo = PacketInfo {
+            inv_dir: [Vector3f::default(); MAX_PACKET_SIZE],
+            dir_is_neg: [[0_u8; 3]; MAX_PACKET_SIZE],
+            neg_mask: [0_u32; 3],
+            n_rays: rays.len(),
+        };
+        for (i, ray) in rays.iter().enumerate() {
+            let inv_dir: Vector3f = Vector3f {
+                x: 1.0 / ray.d.x,
+                y: 1.0 / ray.d.y,
+                z: 1.0 / ray.d.z,
+            };
+            packet.dir_is_neg[i] = [
+                (inv_dir.x < 0.0) as u8,
+                (inv_dir.y < 0.0) as u8,
+                (inv_dir.z < 0.0) as u8,
+            ];
+            for axis in 0..3 {
+                packet.neg_mask[axis] |= (packet.dir_is_neg[i][axis] as u32) << i;
+            }
+            packet.inv_dir[i] = inv_dir;
+        }
+        packet
+    }
+    /// Bit mask with one bit set for each ray of the packet.
+    fn all(&self) -> u32 {
+        ((1_u64 << self.n_rays) - 1_u64) as u32
+    }
+    /// Returns the subset of the rays in *mask* which hit *bounds*
+    /// (within their current *t_max*).
+    fn intersect_bounds(&self, rays: &[Ray], bounds: &Bounds3f, mask: u32) -> u32 {
+        let mut hit: u32 = 0_u32;
+        let mut remaining: u32 = mask;
+        while remaining != 0_u32 {
+            let i: usize = remaining.trailing_zeros() as usize;
+            remaining &= !(1_u32 << i);
+            if bounds.intersect_p(&rays[i], &self.inv_dir[i], &self.dir_is_neg[i]) {
+                hit |= 1_u32 << i;
+            }
+        }
+        hit
+    }
+    /// True if most of the rays in *mask* point in negative direction
+    /// along *axis*, i.e. the second child of an interior node split
+    /// along *axis* should be visited first.
+    fn second_child_first(&self, mask: u32, axis: u8) -> bool {
+        2 * (mask & self.neg_mask[axis as usize]).count_ones() > mask.count_ones()
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct LinearBVHNode {
-    bounds: Bounds3f,
+    pub bounds: Bounds3f,
     // in C++ a union { int primitivesOffset;     // leaf
     //                  int secondChildOffset; }; // interior
-    offset: i32,
-    n_primitives: u16,
-    axis: u8,
+    pub offset: i32,
+    pub n_primitives: u16,
+    pub axis: u8,
     // pad: u8,
 }
 
+/// Statistics about a BVH which are gathered after construction and
+/// help to tune parameters like *maxnodeprims*.
+#[derive(Debug, Default, Clone)]
+pub struct BVHBuildStats {
+    /// build time (including flattening) in seconds
+    pub build_time: f64,
+    pub n_primitives: usize,
+    pub interior_nodes: usize,
+    pub leaf_nodes: usize,
+    pub max_depth: usize,
+    /// expected cost of a ray intersection (relative to a primitive
+    /// intersection) based on the surface area heuristic
+    pub sah_cost: Float,
+    /// number of leaves for each number of primitives in a leaf
+    pub leaf_sizes: Vec<usize>,
+}
+
+impl BVHBuildStats {
+    pub fn new(nodes: &[LinearBVHNode], n_primitives: usize, build_time: f64) -> Self {
+        let mut stats: BVHBuildStats = BVHBuildStats {
+            build_time,
+            n_primitives,
+            ..Default::default()
+        };
+        if nodes.is_empty() {
+            return stats;
+        }
+        let root_area: Float = nodes[0].bounds.surface_area();
+        // walk the flattened tree, remember depth of each node
+        let mut nodes_to_visit: Vec<(usize, usize)> = vec![(0_usize, 1_usize)];
+        while let Some((index, depth)) = nodes_to_visit.pop() {
+            let node: &LinearBVHNode = &nodes[index];
+            stats.max_depth = stats.max_depth.max(depth);
+            let relative_area: Float = if root_area > 0.0 as Float {
+                node.bounds.surface_area() / root_area
+            } else {
+                1.0 as Float
+            };
+            if node.n_primitives > 0 {
+                let n: usize = node.n_primitives as usize;
+                stats.leaf_nodes += 1;
+                if stats.leaf_sizes.len() <= n {
+                    stats.leaf_sizes.resize(n + 1, 0_usize);
+                }
+                stats.leaf_sizes[n] += 1;
+                stats.sah_cost += relative_area * n as Float;
+            } else {
+                stats.interior_nodes += 1;
+                stats.sah_cost += relative_area;
+                nodes_to_visit.push((index + 1, depth + 1));
+                nodes_to_visit.push((node.offset as usize, depth + 1));
+            }
+        }
+        stats
+    }
+    pub fn total_nodes(&self) -> usize {
+        self.interior_nodes + self.leaf_nodes
+    }
+    pub fn print(&self, split_method: &SplitMethod, max_prims_in_node: usize) {
+        println!(
+            "BVH ({:?}, maxnodeprims {}) built in {:.3} seconds",
+            split_method, max_prims_in_node, self.build_time
+        );
+        println!(
+            "  {} primitives, {} nodes ({} interior, {} leaves), max depth {}",
+            self.n_primitives,
+            self.total_nodes(),
+            self.interior_nodes,
+            self.leaf_nodes,
+            self.max_depth
+        );
+        println!("  SAH cost {:.3}", self.sah_cost);
+        if self.leaf_nodes > 0 {
+            let min_size: usize = self.leaf_sizes.iter().position(|c| *c > 0).unwrap_or(0);
+            println!(
+                "  leaf sizes: min {}, max {}, avg {:.2}",
+                min_size,
+                self.leaf_sizes.len() - 1,
+                self.n_primitives as f64 / self.leaf_nodes as f64
+            );
+            for (size, count) in self.leaf_sizes.iter().enumerate() {
+                if *count > 0 {
+                    println!("    {:>5} primitives: {} leaves", size, count);
+                }
+            }
+        }
+    }
+}
+
+/// Follows *ray* through the nodes of a flattened BVH (front to back)
+/// and calls *leaf* with the range of primitives of each leaf node it
+/// reaches, until *leaf* returns true (e.g. for a shadow ray which
+/// found an occluder).
+pub fn traverse_bvh<F>(nodes: &[LinearBVHNode], ray: &Ray, mut leaf: F)
+where
+    F: FnMut(Range<usize>) -> bool,
+{
+    if nodes.is_empty() {
+        return;
+    }
+    let inv_dir: Vector3f = Vector3f {
+        x: 1.0 / ray.d.x,
+        y: 1.0 / ray.d.y,
+        z: 1.0 / ray.d.z,
+    };
+    let dir_is_neg: [u8; 3] = [
+        (inv_dir.x < 0.0) as u8,
+        (inv_dir.y < 0.0) as u8,
+        (inv_dir.z < 0.0) as u8,
+    ];
+    let mut to_visit_offset: u32 = 0;
+    let mut current_node_index: u32 = 0;
+    let mut nodes_to_visit: [u32; 64] = [0_u32; 64];
+    loop {
+        let node: &LinearBVHNode = &nodes[current_node_index as usize];
+        // check ray against BVH node
+        if node.bounds.intersect_p(ray, &inv_dir, &dir_is_neg) {
+            if node.n_primitives > 0 {
+                let offset: usize = node.offset as usize;
+                if leaf(offset..offset + node.n_primitives as usize) {
+                    break;
+                }
+                if to_visit_offset == 0_u32 {
+                    break;
+                }
+                to_visit_offset -= 1_u32;
+                current_node_index = nodes_to_visit[to_visit_offset as usize];
+            } else if dir_is_neg[node.axis as usize] == 1_u8 {
+                // put far BVH node on _nodesToVisit_ stack,
+                // advance to near node
+                nodes_to_visit[to_visit_offset as usize] = current_node_index + 1_u32;
+                to_visit_offset += 1_u32;
+                current_node_index = node.offset as u32;
+            } else {
+                nodes_to_visit[to_visit_offset as usize] = node.offset as u32;
+                to_visit_offset += 1_u32;
+                current_node_index += 1_u32;
+            }
+        } else {
+            if to_visit_offset == 0_u32 {
+                break;
+            }
+            to_visit_offset -= 1_u32;
+            current_node_index = nodes_to_visit[to_visit_offset as usize];
+        }
+    }
+}
+
 // BVHAccel -> Aggregate -> Primitive
 pub struct BVHAccel {
     max_prims_in_node: usize,
     split_method: SplitMethod,
+    n_buckets: usize,
     pub primitives: Vec<Arc<Primitive>>,
     pub nodes: Vec<LinearBVHNode>,
+    pub stats: BVHBuildStats,
 }
 
 impl BVHAccel {
@@ -119,60 +518,71 @@ impl BVHAccel {
         p: Vec<Arc<Primitive>>,
         max_prims_in_node: usize,
         split_method: SplitMethod,
+        n_buckets: usize,
     ) -> Self {
-        let bvh = Arc::new(BVHAccel {
+        let mut bvh = BVHAccel {
             max_prims_in_node: std::cmp::min(max_prims_in_node, 255),
-            split_method: split_method.clone(),
+            split_method,
+            n_buckets: std::cmp::max(n_buckets, 2),
             primitives: p,
             nodes: Vec::new(),
-        });
+            stats: BVHBuildStats::default(),
+        };
         let num_prims = bvh.primitives.len();
         if num_prims == 0_usize {
-            let unwrapped = Arc::try_unwrap(bvh);
-            return unwrapped.ok().unwrap();
-        }
-        let mut primitive_info = vec![BVHPrimitiveInfo::default(); num_prims];
-        for (i, item) in primitive_info.iter_mut().enumerate().take(num_prims) {
-            let world_bound = bvh.primitives[i].world_bound();
-            *item = BVHPrimitiveInfo::new(i, world_bound);
-        }
-        // TODO: if (splitMethod == SplitMethod::HLBVH)
-        let arena: Arena<BVHBuildNode> = Arena::with_capacity(1024 * 1024);
-        let mut total_nodes: usize = 0;
-        let mut ordered_prims: Vec<Arc<Primitive>> = Vec::with_capacity(num_prims);
-        // println!("BVHAccel::recursive_build(..., {}, ...)", num_prims);
-        // let start = PreciseTime::now();
-        let root = BVHAccel::recursive_build(
-            bvh, // instead of self
-            &arena,
-            &mut primitive_info,
-            0,
-            num_prims,
-            &mut total_nodes,
-            &mut ordered_prims,
-        );
-        // let end = PreciseTime::now();
-        // println!("{} seconds for building BVH ...", start.to(end));
-        // flatten first
-        let mut nodes = vec![LinearBVHNode::default(); total_nodes];
-        let mut offset: usize = 0;
-        // println!("BVHAccel::flatten_bvh_tree(...)");
-        // let start = PreciseTime::now();
-        BVHAccel::flatten_bvh_tree(root, &mut nodes, &mut offset);
-        // let end = PreciseTime::now();
-        // println!("{} seconds for flattening BVH ...", start.to(end));
-        assert!(nodes.len() == total_nodes);
+            return bvh;
+        }
+        let primitive_bounds: Vec<Bounds3f> = bvh
+            .primitives
+            .par_iter()
+            .map(|prim| prim.world_bound())
+            .collect();
+        let order: Vec<usize> = bvh.build_nodes(&primitive_bounds);
         // primitives.swap(orderedPrims);
-        let bvh_ordered_prims = Arc::new(BVHAccel {
-            max_prims_in_node: std::cmp::min(max_prims_in_node, 255),
-            split_method,
-            primitives: ordered_prims,
-            nodes,
-        });
-        let unwrapped = Arc::try_unwrap(bvh_ordered_prims);
-        unwrapped.ok().unwrap()
+        bvh.primitives = order.iter().map(|i| bvh.primitives[*i].clone()).collect();
+        bvh
     }
+    /// Builds (and flattens) the nodes for primitives with the given
+    /// bounds. Returns the primitive numbers in the order the leaves
+    /// refer to them. This allows other primitive types (like
+    /// **MeshPrimitive**) to use the BVH without one **Primitive**
+    /// per element.
+    pub fn build_nodes(&mut self, primitive_bounds: &[Bounds3f]) -> Vec<usize> {
+        let start = Instant::now();
+        let num_prims: usize = primitive_bounds.len();
+        let mut primitive_info: Vec<BVHPrimitiveInfo> = primitive_bounds
+            .par_iter()
+            .enumerate()
+            .map(|(i, bounds)| BVHPrimitiveInfo::new(i, *bounds))
+            .collect();
+        let mut nodes: Vec<LinearBVHNode> = Vec::with_capacity(2 * num_prims);
+        if num_prims > 0_usize {
+            let root: Box<BVHBuildNode> = match self.split_method {
+                SplitMethod::HLBVH => self.hlbvh_build(&mut primitive_info),
+                _ => self.recursive_build(&mut primitive_info, 0),
+            };
+            // flatten
+            BVHAccel::flatten_bvh_tree(&root, &mut nodes);
+        }
+        self.nodes = nodes;
+        self.stats = BVHBuildStats::new(&self.nodes, num_prims, start.elapsed().as_secs_f64());
+        // the leaves refer to ranges of the reordered primitive infos
+        primitive_info
+            .iter()
+            .map(|item| item.primitive_number)
+            .collect()
+    }
+    /// Builds the scene's aggregate and prints its build statistics.
+    /// BVHs of object instances (and other internal BVHs) are built
+    /// with [new()](#method.new), which doesn't print anything.
     pub fn create(prims: Vec<Arc<Primitive>>, ps: &ParamSet) -> Primitive {
+        let bvh: BVHAccel = BVHAccel::from_params(prims, ps);
+        bvh.print_stats();
+        Primitive::BVH(Box::new(bvh))
+    }
+    /// Parses the BVH parameters and builds the binary BVH (which is
+    /// also the starting point for other BVH layouts).
+    pub fn from_params(prims: Vec<Arc<Primitive>>, ps: &ParamSet) -> BVHAccel {
         let split_method_name: String = ps.find_one_string("splitmethod", String::from("sah"));
         let split_method;
         if split_method_name == "sah" {
@@ -191,199 +601,253 @@ impl BVHAccel {
             split_method = SplitMethod::SAH;
         }
         let max_prims_in_node: i32 = ps.find_one_int("maxnodeprims", 4);
-        Primitive::BVH(Box::new(BVHAccel::new(
+        let n_buckets: i32 = ps.find_one_int("nbuckets", 12);
+        BVHAccel::new(
             prims,
             max_prims_in_node as usize,
             split_method,
-        )))
-    }
-    pub fn recursive_build<'a>(
-        bvh: Arc<BVHAccel>,
-        arena: &'a Arena<BVHBuildNode<'a>>,
-        primitive_info: &mut Vec<BVHPrimitiveInfo>,
-        start: usize,
-        end: usize,
-        total_nodes: &mut usize,
-        ordered_prims: &mut Vec<Arc<Primitive>>,
-    ) -> &'a BVHBuildNode<'a> {
-        assert_ne!(start, end);
-        let node: &mut BVHBuildNode<'a> = arena.alloc(BVHBuildNode::default());
-        *total_nodes += 1_usize;
+            n_buckets as usize,
+        )
+    }
+    pub fn print_stats(&self) {
+        self.stats.print(&self.split_method, self.max_prims_in_node);
+    }
+    /// Builds the subtree for *primitive_info*, which gets reordered
+    /// in place; *offset* is the index of its first element in the
+    /// list of all primitives. Large subtrees get built in parallel.
+    pub fn recursive_build(
+        &self,
+        primitive_info: &mut [BVHPrimitiveInfo],
+        offset: usize,
+    ) -> Box<BVHBuildNode> {
+        let n_primitives: usize = primitive_info.len();
+        assert_ne!(n_primitives, 0);
+        let mut node: Box<BVHBuildNode> = Box::default();
         // compute bounds of all primitives in BVH node
         let mut bounds: Bounds3f = Bounds3f::default();
-        for item in primitive_info.iter().take(end).skip(start) {
+        for item in primitive_info.iter() {
             bounds = bnd3_union_bnd3f(&bounds, &item.bounds);
         }
-        let n_primitives: usize = end - start;
         if n_primitives == 1 {
             // create leaf _BVHBuildNode_
-            let first_prim_offset: usize = ordered_prims.len();
-            for item in primitive_info.iter().take(end).skip(start) {
-                let prim_num: usize = item.primitive_number;
-                ordered_prims.push(bvh.primitives[prim_num].clone());
-            }
-            node.init_leaf(first_prim_offset, n_primitives, &bounds);
+            node.init_leaf(offset, n_primitives, &bounds);
             return node;
-        } else {
-            // compute bound of primitive centroids, choose split dimension _dim_
-            let mut centroid_bounds: Bounds3f = Bounds3f::default();
-            for item in primitive_info.iter().take(end).skip(start) {
-                centroid_bounds = bnd3_union_pnt3f(&centroid_bounds, &item.centroid);
-            }
-            let dim: u8 = centroid_bounds.maximum_extent();
-            let dim_i: XYZEnum = match dim {
-                0 => XYZEnum::X,
-                1 => XYZEnum::Y,
-                _ => XYZEnum::Z,
-            };
-            // partition primitives into two sets and build children
-            let mut mid: usize = (start + end) / 2_usize;
-            if centroid_bounds.p_max[dim_i] == centroid_bounds.p_min[dim_i] {
-                // create leaf _BVHBuildNode_
-                let first_prim_offset: usize = ordered_prims.len();
-                for item in primitive_info.iter().take(end).skip(start) {
-                    let prim_num: usize = item.primitive_number;
-                    ordered_prims.push(bvh.primitives[prim_num].clone());
+        }
+        // compute bound of primitive centroids, choose split dimension _dim_
+        let mut centroid_bounds: Bounds3f = Bounds3f::default();
+        for item in primitive_info.iter() {
+            centroid_bounds = bnd3_union_pnt3f(&centroid_bounds, &item.centroid);
+        }
+        let dim: u8 = centroid_bounds.maximum_extent();
+        let dim_i: XYZEnum = match dim {
+            0 => XYZEnum::X,
+            1 => XYZEnum::Y,
+            _ => XYZEnum::Z,
+        };
+        if centroid_bounds.p_max[dim_i] == centroid_bounds.p_min[dim_i] {
+            // create leaf _BVHBuildNode_
+            node.init_leaf(offset, n_primitives, &bounds);
+            return node;
+        }
+        // partition primitives based on _splitMethod_
+        let mid: usize = match self.split_method {
+            SplitMethod::Middle => {
+                // partition primitives through node's midpoint
+                let p_mid: Float =
+                    (centroid_bounds.p_min[dim_i] + centroid_bounds.p_max[dim_i]) / 2.0;
+                let mid: usize =
+                    partition_in_place(primitive_info, |pi| pi.centroid[dim_i] < p_mid);
+                if mid == 0 || mid == n_primitives {
+                    split_equal_counts(primitive_info, dim_i)
+                } else {
+                    mid
                 }
-                node.init_leaf(first_prim_offset, n_primitives, &bounds);
-                return node;
-            } else {
-                // partition primitives based on _splitMethod_
-                match bvh.split_method {
-                    SplitMethod::Middle => {
-                        // TODO
-                    }
-                    SplitMethod::EqualCounts => {
-                        // TODO
+            }
+            SplitMethod::EqualCounts => split_equal_counts(primitive_info, dim_i),
+            SplitMethod::SAH | SplitMethod::HLBVH => {
+                if n_primitives <= 2 {
+                    split_equal_counts(primitive_info, dim_i)
+                } else {
+                    // initialize _BucketInfo_ for SAH partition buckets
+                    let n_buckets: usize = self.n_buckets;
+                    let mut buckets: Vec<BucketInfo> = vec![BucketInfo::default(); n_buckets];
+                    let bucket_index = |pi: &BVHPrimitiveInfo| -> usize {
+                        let b: usize = (n_buckets as Float
+                            * centroid_bounds.offset(&pi.centroid)[dim_i])
+                            as usize;
+                        b.min(n_buckets - 1)
+                    };
+                    for item in primitive_info.iter() {
+                        let b: usize = bucket_index(item);
+                        buckets[b].count += 1;
+                        buckets[b].bounds = bnd3_union_bnd3f(&buckets[b].bounds, &item.bounds);
                     }
-                    SplitMethod::SAH | SplitMethod::HLBVH => {
-                        if n_primitives <= 2 {
-                            mid = (start + end) / 2;
-                            if start != end - 1
-                                && primitive_info[end - 1].centroid[dim_i]
-                                    < primitive_info[start].centroid[dim_i]
-                            {
-                                primitive_info.swap(start, end - 1);
-                            }
+                    let (min_cost, min_cost_split_bucket) =
+                        find_sah_split(&buckets, 1.0 as Float, bounds.surface_area());
+                    // either create leaf or split primitives
+                    // at selected SAH bucket
+                    let leaf_cost: Float = n_primitives as Float;
+                    if n_primitives > self.max_prims_in_node || min_cost < leaf_cost {
+                        let mid: usize = partition_in_place(primitive_info, |pi| {
+                            bucket_index(pi) <= min_cost_split_bucket
+                        });
+                        if mid == 0 || mid == n_primitives {
+                            split_equal_counts(primitive_info, dim_i)
                         } else {
-                            // allocate _BucketInfo_ for SAH partition buckets
-                            let n_buckets: usize = 12;
-                            let mut buckets: [BucketInfo; 12] = [BucketInfo::default(); 12];
-                            // initialize _BucketInfo_ for SAH partition buckets
-                            for item in primitive_info.iter().take(end).skip(start) {
-                                let mut b: usize = (n_buckets as Float
-                                    * centroid_bounds.offset(&item.centroid)[dim_i])
-                                    as usize;
-                                if b == n_buckets {
-                                    b = n_buckets - 1;
-                                }
-                                // assert!(b >= 0_usize, "b >= 0");
-                                assert!(b < n_buckets, "b < {}", n_buckets);
-                                buckets[b].count += 1;
-                                buckets[b].bounds =
-                                    bnd3_union_bnd3f(&buckets[b].bounds, &item.bounds);
-                            }
-                            // compute costs for splitting after each bucket
-                            let mut cost: [Float; 11] = [0.0; 11];
-                            for (i, cost_item) in cost.iter_mut().enumerate().take(n_buckets - 1) {
-                                let mut b0: Bounds3f = Bounds3f::default();
-                                let mut b1: Bounds3f = Bounds3f::default();
-                                let mut count0: usize = 0;
-                                let mut count1: usize = 0;
-                                for item in buckets.iter().take(i + 1) {
-                                    b0 = bnd3_union_bnd3f(&b0, &item.bounds);
-                                    count0 += item.count;
-                                }
-                                for item in buckets.iter().take(n_buckets).skip(i + 1) {
-                                    b1 = bnd3_union_bnd3f(&b1, &item.bounds);
-                                    count1 += item.count;
-                                }
-                                *cost_item = 1.0
-                                    + (count0 as Float * b0.surface_area()
-                                        + count1 as Float * b1.surface_area())
-                                        / bounds.surface_area();
-                            }
-                            // find bucket to split at that minimizes SAH metric
-                            let mut min_cost: Float = cost[0];
-                            let mut min_cost_split_bucket: usize = 0;
-                            for (i, item) in cost.iter().enumerate().take(n_buckets - 1) {
-                                if item < &min_cost {
-                                    min_cost = *item;
-                                    min_cost_split_bucket = i;
-                                }
-                            }
-                            // either create leaf or split primitives
-                            // at selected SAH bucket
-                            let leaf_cost: Float = n_primitives as Float;
-                            if n_primitives > bvh.max_prims_in_node || min_cost < leaf_cost {
-                                let (mut left, mut right): (
-                                    Vec<BVHPrimitiveInfo>,
-                                    Vec<BVHPrimitiveInfo>,
-                                ) = primitive_info[start..end].iter().partition(|&pi| {
-                                    let mut b: usize = (n_buckets as Float
-                                        * centroid_bounds.offset(&pi.centroid)[dim_i])
-                                        as usize;
-                                    if b == n_buckets {
-                                        b = n_buckets - 1;
-                                    }
-                                    // assert!(b >= 0_usize, "b >= 0");
-                                    assert!(b < n_buckets, "b < {}", n_buckets);
-                                    b <= min_cost_split_bucket
-                                });
-                                mid = start + left.len();
-                                let combined_len = left.len() + right.len();
-                                if combined_len == primitive_info.len() {
-                                    primitive_info.clear();
-                                    primitive_info.append(&mut left);
-                                    primitive_info.append(&mut right);
-                                } else {
-                                    primitive_info.splice(start..mid, left.iter().cloned());
-                                    primitive_info.splice(mid..end, right.iter().cloned());
-                                }
-                            } else {
-                                // create leaf _BVHBuildNode_
-                                let first_prim_offset: usize = ordered_prims.len();
-                                for item in primitive_info.iter().take(end).skip(start) {
-                                    let prim_num: usize = item.primitive_number;
-                                    ordered_prims.push(bvh.primitives[prim_num].clone());
-                                }
-                                node.init_leaf(first_prim_offset, n_primitives, &bounds);
-                                return node;
-                            }
+                            mid
                         }
+                    } else {
+                        // create leaf _BVHBuildNode_
+                        node.init_leaf(offset, n_primitives, &bounds);
+                        return node;
                     }
                 }
-                // make sure we get result for c1 before c0
-                let c1 = BVHAccel::recursive_build(
-                    bvh.clone(),
-                    arena,
-                    primitive_info,
-                    mid,
-                    end,
-                    total_nodes,
-                    ordered_prims,
-                );
-                let c0 = BVHAccel::recursive_build(
-                    bvh,
-                    arena,
-                    primitive_info,
-                    start,
-                    mid,
-                    total_nodes,
-                    ordered_prims,
-                );
-                node.init_interior(dim, c0, c1);
             }
+        };
+        let (left, right) = primitive_info.split_at_mut(mid);
+        let (c0, c1) = if n_primitives > PARALLEL_BUILD_THRESHOLD {
+            rayon::join(
+                || self.recursive_build(left, offset),
+                || self.recursive_build(right, offset + mid),
+            )
+        } else {
+            (
+                self.recursive_build(left, offset),
+                self.recursive_build(right, offset + mid),
+            )
+        };
+        node.init_interior(dim, c0, c1);
+        node
+    }
+    /// Sorts *primitive_info* along a Morton curve, builds treelets
+    /// for clusters of primitives in parallel and connects them with
+    /// a SAH tree.
+    pub fn hlbvh_build(&self, primitive_info: &mut Vec<BVHPrimitiveInfo>) -> Box<BVHBuildNode> {
+        // compute bounding box of all primitive centroids
+        let mut bounds: Bounds3f = Bounds3f::default();
+        for pi in primitive_info.iter() {
+            bounds = bnd3_union_pnt3f(&bounds, &pi.centroid);
+        }
+        // compute Morton indices of primitives
+        let morton_bits: i32 = 10;
+        let morton_scale: Float = (1 << morton_bits) as Float;
+        let mut morton_prims: Vec<MortonPrimitive> = primitive_info
+            .par_iter()
+            .enumerate()
+            .map(|(i, pi)| {
+                let centroid_offset: Vector3f = bounds.offset(&pi.centroid);
+                MortonPrimitive {
+                    primitive_index: i,
+                    morton_code: encode_morton_3(&(centroid_offset * morton_scale)),
+                }
+            })
+            .collect();
+        // radix sort primitive Morton indices
+        radix_sort(&mut morton_prims);
+        *primitive_info = morton_prims
+            .iter()
+            .map(|mp| primitive_info[mp.primitive_index])
+            .collect();
+        // create LBVH treelets at bottom of BVH
+
+        // find intervals of primitives for each treelet
+        let mut treelets_to_build: Vec<LBVHTreelet> = Vec::new();
+        let mask: u32 = 0b0011_1111_1111_1100_0000_0000_0000_0000;
+        let mut start: usize = 0;
+        for end in 1..=morton_prims.len() {
+            if end == morton_prims.len()
+                || (morton_prims[start].morton_code & mask)
+                    != (morton_prims[end].morton_code & mask)
+            {
+                // add entry to _treelets_to_build_ for this treelet
+                treelets_to_build.push(LBVHTreelet {
+                    start_index: start,
+                    n_primitives: end - start,
+                });
+                start = end;
+            }
+        }
+        // create LBVHs for treelets in parallel
+        let first_bit_index: i32 = 29 - 12;
+        let primitive_info: &[BVHPrimitiveInfo] = primitive_info;
+        let finished_treelets: Vec<BVHBuildNode> = treelets_to_build
+            .par_iter()
+            .map(|tr| {
+                *emit_lbvh(
+                    primitive_info,
+                    &morton_prims[tr.start_index..tr.start_index + tr.n_primitives],
+                    tr.start_index,
+                    first_bit_index,
+                    self.max_prims_in_node,
+                )
+            })
+            .collect();
+        // create and return SAH BVH from LBVH treelets
+        self.build_upper_sah(finished_treelets)
+    }
+    fn build_upper_sah(&self, mut treelet_roots: Vec<BVHBuildNode>) -> Box<BVHBuildNode> {
+        let n_nodes: usize = treelet_roots.len();
+        assert!(n_nodes > 0);
+        if n_nodes == 1 {
+            return Box::new(treelet_roots.pop().unwrap());
+        }
+        let mut node: Box<BVHBuildNode> = Box::default();
+        // compute bounds of all nodes under this HLBVH node
+        let mut bounds: Bounds3f = Bounds3f::default();
+        for item in treelet_roots.iter() {
+            bounds = bnd3_union_bnd3f(&bounds, &item.bounds);
+        }
+        // compute bound of HLBVH node centroids, choose split dimension _dim_
+        let mut centroid_bounds: Bounds3f = Bounds3f::default();
+        for item in treelet_roots.iter() {
+            let centroid: Point3f = (item.bounds.p_min + item.bounds.p_max) * 0.5 as Float;
+            centroid_bounds = bnd3_union_pnt3f(&centroid_bounds, &centroid);
         }
+        let dim: u8 = centroid_bounds.maximum_extent();
+        let dim_i: XYZEnum = match dim {
+            0 => XYZEnum::X,
+            1 => XYZEnum::Y,
+            _ => XYZEnum::Z,
+        };
+        let (left, right): (Vec<BVHBuildNode>, Vec<BVHBuildNode>) = if centroid_bounds.p_max[dim_i]
+            == centroid_bounds.p_min[dim_i]
+        {
+            // no way to separate the treelets spatially
+            let right: Vec<BVHBuildNode> = treelet_roots.split_off(n_nodes / 2);
+            (treelet_roots, right)
+        } else {
+            // initialize _BucketInfo_ for HLBVH SAH partition buckets
+            let n_buckets: usize = self.n_buckets;
+            let mut buckets: Vec<BucketInfo> = vec![BucketInfo::default(); n_buckets];
+            let bucket_index = |item: &BVHBuildNode| -> usize {
+                let centroid: Float = (item.bounds.p_min[dim_i] + item.bounds.p_max[dim_i]) * 0.5;
+                let b: usize = (n_buckets as Float
+                    * ((centroid - centroid_bounds.p_min[dim_i])
+                        / (centroid_bounds.p_max[dim_i] - centroid_bounds.p_min[dim_i])))
+                    as usize;
+                b.min(n_buckets - 1)
+            };
+            for item in treelet_roots.iter() {
+                let b: usize = bucket_index(item);
+                buckets[b].count += 1;
+                buckets[b].bounds = bnd3_union_bnd3f(&buckets[b].bounds, &item.bounds);
+            }
+            let (_min_cost, min_cost_split_bucket) =
+                find_sah_split(&buckets, 0.125 as Float, bounds.surface_area());
+            // split nodes and create interior HLBVH SAH node
+            treelet_roots
+                .into_iter()
+                .partition(|item| bucket_index(item) <= min_cost_split_bucket)
+        };
+        assert!(!left.is_empty() && !right.is_empty());
+        let c0: Box<BVHBuildNode> = self.build_upper_sah(left);
+        let c1: Box<BVHBuildNode> = self.build_upper_sah(right);
+        node.init_interior(dim, c0, c1);
         node
     }
-    pub fn flatten_bvh_tree<'a>(
-        node: &BVHBuildNode<'a>,
-        nodes: &mut Vec<LinearBVHNode>,
-        offset: &mut usize,
-    ) -> usize {
-        let my_offset: usize = *offset;
-        *offset += 1;
+    pub fn flatten_bvh_tree(node: &BVHBuildNode, nodes: &mut Vec<LinearBVHNode>) -> usize {
+        let my_offset: usize = nodes.len();
+        nodes.push(LinearBVHNode::default());
         if node.n_primitives > 0 {
             // leaf
             let linear_node = LinearBVHNode {
@@ -396,13 +860,13 @@ impl BVHAccel {
             nodes[my_offset] = linear_node;
         } else {
             // interior
-            if let Some(ref child1) = node.child1 {
-                BVHAccel::flatten_bvh_tree(child1, nodes, offset);
+            if let Some(child1) = &node.child1 {
+                BVHAccel::flatten_bvh_tree(child1, nodes);
             }
-            if let Some(ref child2) = node.child2 {
+            if let Some(child2) = &node.child2 {
                 let linear_node = LinearBVHNode {
                     bounds: node.bounds,
-                    offset: BVHAccel::flatten_bvh_tree(child2, nodes, offset) as i32,
+                    offset: BVHAccel::flatten_bvh_tree(child2, nodes) as i32,
                     n_primitives: 0_u16,
                     axis: node.split_axis,
                     // pad: 0_u8,
@@ -421,118 +885,182 @@ impl BVHAccel {
         }
     }
     pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
-        if self.nodes.is_empty() {
-            return false;
-        }
         // TODO: ProfilePhase p(Prof::AccelIntersect);
         let mut hit: bool = false;
-        let inv_dir: Vector3f = Vector3f {
-            x: 1.0 / ray.d.x,
-            y: 1.0 / ray.d.y,
-            z: 1.0 / ray.d.z,
-        };
-        let dir_is_neg: [u8; 3] = [
-            (inv_dir.x < 0.0) as u8,
-            (inv_dir.y < 0.0) as u8,
-            (inv_dir.z < 0.0) as u8,
-        ];
-        // follow ray through BVH nodes to find primitive intersections
-        let mut to_visit_offset: u32 = 0;
-        let mut current_node_index: u32 = 0;
-        let mut nodes_to_visit: [u32; 64] = [0_u32; 64];
-        loop {
-            let node: &LinearBVHNode = &self.nodes[current_node_index as usize];
-            // check ray against BVH node
-            if node.bounds.intersect_p(ray, &inv_dir, &dir_is_neg) {
-                if node.n_primitives > 0 {
-                    // intersect ray with primitives in leaf BVH node
-                    for i in 0..node.n_primitives {
-                        // see primitive.h GeometricPrimitive::Intersect() ...
-                        if self.primitives[node.offset as usize + i as usize].intersect(ray, isect)
-                        {
-                            // TODO: CHECK_GE(...)
-                            hit = true;
+        traverse_bvh(&self.nodes, ray, |leaf| {
+            // intersect ray with primitives in leaf BVH node
+            for primitive in &self.primitives[leaf] {
+                // see primitive.h GeometricPrimitive::Intersect() ...
+                if primitive.intersect(ray, isect) {
+                    // TODO: CHECK_GE(...)
+                    hit = true;
+                }
+            }
+            false
+        });
+        hit
+    }
+    pub fn intersect_p(&self, ray: &Ray) -> bool {
+        // TODO: ProfilePhase p(Prof::AccelIntersectP);
+        let mut occluded: bool = false;
+        traverse_bvh(&self.nodes, ray, |leaf| {
+            occluded = self.primitives[leaf]
+                .iter()
+                .any(|primitive| primitive.intersect_p(ray));
+            occluded
+        });
+        occluded
+    }
+    /// Intersects a packet of (coherent) rays with the BVH. All rays
+    /// share one traversal (in the front-to-back order of the majority
+    /// of the rays). Every active ray is tested against the bounds of
+    /// each visited node, and only the rays which hit them stay active
+    /// below that node and get tested against its primitives. Packets
+    /// with more than
+    /// [MAX_PACKET_SIZE](constant.MAX_PACKET_SIZE.html) rays are
+    /// split.
+    pub fn intersect_packet(
+        &self,
+        rays: &[Ray],
+        isects: &mut [SurfaceInteraction],
+        hits: &mut [bool],
+    ) {
+        assert_eq!(rays.len(), isects.len());
+        assert_eq!(rays.len(), hits.len());
+        for ((rays, isects), hits) in rays
+            .chunks(MAX_PACKET_SIZE)
+            .zip(isects.chunks_mut(MAX_PACKET_SIZE))
+            .zip(hits.chunks_mut(MAX_PACKET_SIZE))
+        {
+            for hit in hits.iter_mut() {
+                *hit = false;
+            }
+            if self.nodes.is_empty() {
+                continue;
+            }
+            let packet: PacketInfo = PacketInfo::new(rays);
+            // nodes to visit, together with the rays which hit their parent
+            let mut to_visit_offset: usize = 0;
+            let mut current_node_index: u32 = 0;
+            let mut current_mask: u32 = packet.all();
+            let mut nodes_to_visit: [(u32, u32); 64] = [(0_u32, 0_u32); 64];
+            loop {
+                let node: &LinearBVHNode = &self.nodes[current_node_index as usize];
+                // find the rays which (might) hit the node's bounds
+                let mask: u32 = packet.intersect_bounds(rays, &node.bounds, current_mask);
+                if mask != 0_u32 {
+                    if node.n_primitives > 0 {
+                        // intersect active rays with primitives in leaf BVH node
+                        let mut remaining: u32 = mask;
+                        while remaining != 0_u32 {
+                            let i: usize = remaining.trailing_zeros() as usize;
+                            remaining &= !(1_u32 << i);
+                            for j in 0..node.n_primitives {
+                                if self.primitives[node.offset as usize + j as usize]
+                                    .intersect(&rays[i], &mut isects[i])
+                                {
+                                    hits[i] = true;
+                                }
+                            }
                         }
+                        if to_visit_offset == 0 {
+                            break;
+                        }
+                        to_visit_offset -= 1;
+                        current_node_index = nodes_to_visit[to_visit_offset].0;
+                        current_mask = nodes_to_visit[to_visit_offset].1;
+                    } else {
+                        // put far BVH node on _nodes_to_visit_ stack,
+                        // advance to near node (for most of the active rays)
+                        if packet.second_child_first(mask, node.axis) {
+                            nodes_to_visit[to_visit_offset] = (current_node_index + 1_u32, mask);
+                            current_node_index = node.offset as u32;
+                        } else {
+                            nodes_to_visit[to_visit_offset] = (node.offset as u32, mask);
+                            current_node_index += 1_u32;
+                        }
+                        to_visit_offset += 1;
+                        current_mask = mask;
                     }
-                    if to_visit_offset == 0_u32 {
-                        break;
-                    }
-                    to_visit_offset -= 1_u32;
-                    current_node_index = nodes_to_visit[to_visit_offset as usize];
                 } else {
-                    // put far BVH node on _nodesToVisit_ stack,
-                    // advance to near node
-                    if dir_is_neg[node.axis as usize] == 1_u8 {
-                        nodes_to_visit[to_visit_offset as usize] = current_node_index + 1_u32;
-                        to_visit_offset += 1_u32;
-                        current_node_index = node.offset as u32;
-                    } else {
-                        nodes_to_visit[to_visit_offset as usize] = node.offset as u32;
-                        to_visit_offset += 1_u32;
-                        current_node_index += 1_u32;
+                    if to_visit_offset == 0 {
+                        break;
                     }
+                    to_visit_offset -= 1;
+                    current_node_index = nodes_to_visit[to_visit_offset].0;
+                    current_mask = nodes_to_visit[to_visit_offset].1;
                 }
-            } else {
-                if to_visit_offset == 0_u32 {
-                    break;
-                }
-                to_visit_offset -= 1_u32;
-                current_node_index = nodes_to_visit[to_visit_offset as usize];
             }
         }
-        hit
     }
-    pub fn intersect_p(&self, ray: &Ray) -> bool {
-        if self.nodes.is_empty() {
-            return false;
-        }
-        // TODO: ProfilePhase p(Prof::AccelIntersectP);
-        let inv_dir: Vector3f = Vector3f {
-            x: 1.0 / ray.d.x,
-            y: 1.0 / ray.d.y,
-            z: 1.0 / ray.d.z,
-        };
-        let dir_is_neg: [u8; 3] = [
-            (inv_dir.x < 0.0) as u8,
-            (inv_dir.y < 0.0) as u8,
-            (inv_dir.z < 0.0) as u8,
-        ];
-        let mut to_visit_offset: u32 = 0;
-        let mut current_node_index: u32 = 0;
-        let mut nodes_to_visit: [u32; 64] = [0_u32; 64];
-        loop {
-            let node: &LinearBVHNode = &self.nodes[current_node_index as usize];
-            if node.bounds.intersect_p(ray, &inv_dir, &dir_is_neg) {
-                // process BVH node _node_ for traversal
-                if node.n_primitives > 0 {
-                    for i in 0..node.n_primitives {
-                        if self.primitives[node.offset as usize + i as usize].intersect_p(ray) {
-                            return true;
+    /// Shadow ray version of
+    /// [intersect_packet()](struct.BVHAccel.html#method.intersect_packet). Rays
+    /// leave the packet as soon as they are known to be occluded.
+    pub fn intersect_p_packet(&self, rays: &[Ray], occluded: &mut [bool]) {
+        assert_eq!(rays.len(), occluded.len());
+        for (rays, occluded) in rays
+            .chunks(MAX_PACKET_SIZE)
+            .zip(occluded.chunks_mut(MAX_PACKET_SIZE))
+        {
+            for o in occluded.iter_mut() {
+                *o = false;
+            }
+            if self.nodes.is_empty() {
+                continue;
+            }
+            let packet: PacketInfo = PacketInfo::new(rays);
+            // rays which are not known to be occluded yet
+            let mut active: u32 = packet.all();
+            let mut to_visit_offset: usize = 0;
+            let mut current_node_index: u32 = 0;
+            let mut current_mask: u32 = active;
+            let mut nodes_to_visit: [(u32, u32); 64] = [(0_u32, 0_u32); 64];
+            loop {
+                let node: &LinearBVHNode = &self.nodes[current_node_index as usize];
+                let mask: u32 = packet.intersect_bounds(rays, &node.bounds, current_mask & active);
+                if mask != 0_u32 {
+                    if node.n_primitives > 0 {
+                        let mut remaining: u32 = mask;
+                        while remaining != 0_u32 {
+                            let i: usize = remaining.trailing_zeros() as usize;
+                            remaining &= !(1_u32 << i);
+                            for j in 0..node.n_primitives {
+                                if self.primitives[node.offset as usize + j as usize]
+                                    .intersect_p(&rays[i])
+                                {
+                                    occluded[i] = true;
+                                    active &= !(1_u32 << i);
+                                    break;
+                                }
+                            }
                         }
+                        if active == 0_u32 || to_visit_offset == 0 {
+                            break;
+                        }
+                        to_visit_offset -= 1;
+                        current_node_index = nodes_to_visit[to_visit_offset].0;
+                        current_mask = nodes_to_visit[to_visit_offset].1;
+                    } else {
+                        if packet.second_child_first(mask, node.axis) {
+                            nodes_to_visit[to_visit_offset] = (current_node_index + 1_u32, mask);
+                            current_node_index = node.offset as u32;
+                        } else {
+                            nodes_to_visit[to_visit_offset] = (node.offset as u32, mask);
+                            current_node_index += 1_u32;
+                        }
+                        to_visit_offset += 1;
+                        current_mask = mask;
                     }
-                    if to_visit_offset == 0_u32 {
+                } else {
+                    if to_visit_offset == 0 {
                         break;
                     }
-                    to_visit_offset -= 1_u32;
-                    current_node_index = nodes_to_visit[to_visit_offset as usize];
-                } else if dir_is_neg[node.axis as usize] == 1_u8 {
-                    nodes_to_visit[to_visit_offset as usize] = current_node_index + 1_u32;
-                    to_visit_offset += 1_u32;
-                    current_node_index = node.offset as u32;
-                } else {
-                    nodes_to_visit[to_visit_offset as usize] = node.offset as u32;
-                    to_visit_offset += 1_u32;
-                    current_node_index += 1_u32;
-                }
-            } else {
-                if to_visit_offset == 0_u32 {
-                    break;
+                    to_visit_offset -= 1;
+                    current_node_index = nodes_to_visit[to_visit_offset].0;
+                    current_mask = nodes_to_visit[to_visit_offset].1;
                 }
-                to_visit_offset -= 1_u32;
-                current_node_index = nodes_to_visit[to_visit_offset as usize];
             }
         }
-        false
     }
     pub fn get_material(&self) -> Option<Arc<Material>> {
         None
@@ -541,3 +1069,147 @@ impl BVHAccel {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn morton_codes_interleave_bits() {
+        let code = |x: Float, y: Float, z: Float| encode_morton_3(&Vector3f { x, y, z });
+        assert_eq!(code(1.0, 0.0, 0.0), 0b001);
+        assert_eq!(code(0.0, 1.0, 0.0), 0b010);
+        assert_eq!(code(0.0, 0.0, 1.0), 0b100);
+        assert_eq!(code(3.0, 0.0, 0.0), 0b1001);
+        assert_eq!(code(5.0, 6.0, 7.0), 0b111_110_101);
+        assert_eq!(code(1023.0, 1023.0, 1023.0), (1 << 30) - 1);
+        // the upper end of the range gets clamped to 10 bits
+        assert_eq!(code(1024.0, 1024.0, 1024.0), (1 << 30) - 1);
+    }
+
+    fn bucket(count: usize, x0: Float, x1: Float) -> BucketInfo {
+        BucketInfo {
+            count,
+            bounds: Bounds3f::new(
+                Point3f {
+                    x: x0,
+                    y: 0.0,
+                    z: 0.0,
+                },
+                Point3f {
+                    x: x1,
+                    y: 1.0,
+                    z: 1.0,
+                },
+            ),
+        }
+    }
+
+    #[test]
+    fn sah_split_separates_clusters() {
+        // two clusters of primitives with an empty bucket in between
+        let buckets: [BucketInfo; 4] = [
+            bucket(2, 0.0, 0.5),
+            bucket(2, 0.5, 1.0),
+            BucketInfo::default(),
+            bucket(1, 9.0, 10.0),
+        ];
+        let area: Float = bucket(0, 0.0, 10.0).bounds.surface_area();
+        let (cost, split) = find_sah_split(&buckets, 0.125, area);
+        // the first of the two equally good splits is taken
+        assert_eq!(split, 1);
+        let left: Float = 4.0 * bucket(0, 0.0, 1.0).bounds.surface_area();
+        let right: Float = bucket(0, 9.0, 10.0).bounds.surface_area();
+        assert!((cost - (0.125 + (left + right) / area)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn sah_split_with_one_bucket_on_each_side() {
+        let buckets: [BucketInfo; 2] = [bucket(3, 0.0, 1.0), bucket(1, 1.0, 2.0)];
+        let area: Float = bucket(0, 0.0, 2.0).bounds.surface_area();
+        let (cost, split) = find_sah_split(&buckets, 1.0, area);
+        assert_eq!(split, 0);
+        let expected: Float = 1.0 + 4.0 * bucket(0, 0.0, 1.0).bounds.surface_area() / area;
+        assert!((cost - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn packet_bounds_test_checks_every_ray() {
+        let bounds: Bounds3f = Bounds3f::new(
+            Point3f {
+                x: -1.0,
+                y: -1.0,
+                z: -1.0,
+            },
+            Point3f {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+        );
+        // rays from z = -5 towards the box, every second one misses it
+        let rays: Vec<Ray> = (0..8)
+            .map(|i| Ray {
+                o: Point3f {
+                    x: if i % 2 == 0 { 0.0 } else { 3.0 },
+                    y: 0.0,
+                    z: -5.0,
+                },
+                d: Vector3f {
+                    x: 0.0,
+                    y: 0.0,
+                    z: if i < 6 { 1.0 } else { -1.0 },
+                },
+                t_max: Cell::new(Float::INFINITY),
+                ..Default::default()
+            })
+            .collect();
+        let packet: PacketInfo = PacketInfo::new(&rays);
+        // rays 6 and 7 point away from the box
+        assert_eq!(
+            packet.intersect_bounds(&rays, &bounds, packet.all()),
+            0b0001_0101
+        );
+        assert_eq!(
+            packet.intersect_bounds(&rays, &bounds, 0b1111_1110),
+            0b0001_0100
+        );
+        // too short to reach the box
+        rays[0].t_max.set(2.0);
+        assert_eq!(packet.intersect_bounds(&rays, &bounds, 0b0000_0001), 0);
+        assert!(!packet.second_child_first(0b0011_1111, 2));
+        assert!(packet.second_child_first(0b1100_0001, 2));
+    }
+
+    #[test]
+    fn radix_sort_is_stable() {
+        let codes: [u32; 8] = [7, 1 << 29, 3, 7, 0, 1 << 12, 3, 42];
+        let mut v: Vec<MortonPrimitive> = codes
+            .iter()
+            .enumerate()
+            .map(|(primitive_index, morton_code)| MortonPrimitive {
+                primitive_index,
+                morton_code: *morton_code,
+            })
+            .collect();
+        radix_sort(&mut v);
+        let sorted: Vec<(u32, usize)> = v
+            .iter()
+            .map(|mp| (mp.morton_code, mp.primitive_index))
+            .collect();
+        assert_eq!(
+            sorted,
+            vec![
+                (0, 4),
+                (3, 2),
+                (3, 6),
+                (7, 0),
+                (7, 3),
+                (42, 7),
+                (1 << 12, 5),
+                (1 << 29, 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Fires random rays at the primitives of a scene through both
+//! **BVHAccel** and **KdTreeAccel** and reports rays where the two
+//! acceleration structures disagree (about a hit, the hit distance,
+//! or the primitive which was hit).
+
+// std
+use std::cell::Cell;
+use std::sync::Arc;
+// pbrt
+use crate::accelerators::bvh::{BVHAccel, SplitMethod};
+use crate::accelerators::kdtreeaccel::KdTreeAccel;
+use crate::core::geometry::{Bounds3f, Point2f, Point3f, Ray, Vector3f};
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::pbrt::Float;
+use crate::core::primitive::Primitive;
+use crate::core::rng::Rng;
+use crate::core::sampling::uniform_sample_sphere;
+
+/// Maximum number of mismatching rays which get printed.
+const MAX_REPORTED: usize = 10;
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ConsistencyReport {
+    pub n_rays: usize,
+    pub n_hits: usize,
+    /// one accelerator found an intersection, the other didn't
+    pub hit_mismatches: usize,
+    /// both found an intersection, but at different distances
+    pub distance_mismatches: usize,
+    /// same distance, but different primitives
+    pub primitive_mismatches: usize,
+    /// the shadow ray tests (*intersect_p*) disagree
+    pub shadow_mismatches: usize,
+}
+
+impl ConsistencyReport {
+    pub fn n_mismatches(&self) -> usize {
+        self.hit_mismatches
+            + self.distance_mismatches
+            + self.primitive_mismatches
+            + self.shadow_mismatches
+    }
+}
+
+/// Builds a BVH and a kd-tree for *primitives* and compares the
+/// results for *n_rays* random rays. Hit distances are considered
+/// equal if they differ by less than *tolerance* (relative).
+pub fn check_accelerators(
+    primitives: &[Arc<Primitive>],
+    n_rays: usize,
+    tolerance: Float,
+) -> ConsistencyReport {
+    let mut report: ConsistencyReport = ConsistencyReport {
+        n_rays,
+        ..Default::default()
+    };
+    if primitives.is_empty() {
+        return report;
+    }
+    let bvh: BVHAccel = BVHAccel::new(primitives.to_owned(), 4, SplitMethod::SAH, 12);
+    let kdtree: KdTreeAccel = KdTreeAccel::new(primitives.to_owned(), 80, 1, 0.5, 1, -1);
+    let bounds: Bounds3f = bvh.world_bound();
+    // start rays in (or around) the bounding sphere of the scene
+    let mut center: Point3f = Point3f::default();
+    let mut radius: Float = 0.0;
+    Bounds3f::bounding_sphere(&bounds, &mut center, &mut radius);
+    let mut rng: Rng = Rng::new();
+    rng.set_sequence(0);
+    for i in 0..n_rays {
+        let u: Point2f = Point2f {
+            x: rng.uniform_float(),
+            y: rng.uniform_float(),
+        };
+        let o: Point3f =
+            center + uniform_sample_sphere(u) * (radius * 1.5 as Float * rng.uniform_float());
+        // aim at a random point within the bounds
+        let target: Point3f = bounds.lerp(&Point3f {
+            x: rng.uniform_float(),
+            y: rng.uniform_float(),
+            z: rng.uniform_float(),
+        });
+        let d: Vector3f = target - o;
+        if d.length_squared() == 0.0 as Float {
+            continue;
+        }
+        let d: Vector3f = d.normalize();
+        let new_ray = |t_max: Float| -> Ray {
+            Ray {
+                o,
+                d,
+                t_max: Cell::new(t_max),
+                time: 0.0 as Float,
+                medium: None,
+                differential: None,
+            }
+        };
+        // closest hit
+        let bvh_ray: Ray = new_ray(Float::INFINITY);
+        let kdtree_ray: Ray = new_ray(Float::INFINITY);
+        let mut bvh_isect: SurfaceInteraction = SurfaceInteraction::default();
+        let mut kdtree_isect: SurfaceInteraction = SurfaceInteraction::default();
+        let bvh_hit: bool = bvh.intersect(&bvh_ray, &mut bvh_isect);
+        let kdtree_hit: bool = kdtree.intersect(&kdtree_ray, &mut kdtree_isect);
+        let mut problem: Option<&str> = None;
+        if bvh_hit != kdtree_hit {
+            report.hit_mismatches += 1;
+            problem = Some("hit");
+        } else if bvh_hit {
+            report.n_hits += 1;
+            let t_bvh: Float = bvh_ray.t_max.get();
+            let t_kdtree: Float = kdtree_ray.t_max.get();
+            if (t_bvh - t_kdtree).abs() > tolerance * t_bvh.abs().max(1.0 as Float) {
+                report.distance_mismatches += 1;
+                problem = Some("distance");
+            } else if bvh_isect.primitive != kdtree_isect.primitive {
+                report.primitive_mismatches += 1;
+                problem = Some("primitive");
+            }
+        }
+        // shadow ray to half of the closest distance (or some point
+        // beyond the scene)
+        let t_shadow: Float = if bvh_hit {
+            0.5 as Float * bvh_ray.t_max.get()
+        } else {
+            2.0 as Float * radius
+        };
+        let bvh_occluded: bool = bvh.intersect_p(&new_ray(t_shadow));
+        let kdtree_occluded: bool = kdtree.intersect_p(&new_ray(t_shadow));
+        if problem.is_none() && bvh_occluded != kdtree_occluded {
+            report.shadow_mismatches += 1;
+            problem = Some("shadow");
+        }
+        if let Some(problem) = problem {
+            if report.n_mismatches() <= MAX_REPORTED {
+                println!(
+                    "WARNING: {} mismatch for ray {}: o = {:?} d = {:?}",
+                    problem, i, o, d
+                );
+                println!(
+                    "  BVH: hit {} t {} occluded {}, kd-tree: hit {} t {} occluded {}",
+                    bvh_hit,
+                    bvh_ray.t_max.get(),
+                    bvh_occluded,
+                    kdtree_hit,
+                    kdtree_ray.t_max.get(),
+                    kdtree_occluded
+                );
+            }
+        }
+    }
+    report
+}
@@ -7,6 +7,12 @@
 //!
 //! - BVHAccel
 //! - KdTreeAccel
+//! - WideBVHAccel
+//!
+//! The **consistency** module compares the results of BVHAccel and
+//! KdTreeAccel for random rays.
 
 pub mod bvh;
+pub mod consistency;
 pub mod kdtreeaccel;
+pub mod widebvh;
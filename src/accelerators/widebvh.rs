@@ -0,0 +1,334 @@
+//! A wide BVH is built by collapsing the binary BVH of **BVHAccel**
+//! into nodes with up to 4 or 8 children. The bounds of all children
+//! of a node are stored as a structure of arrays, so a ray can be
+//! tested against all of them in one (auto-vectorizable) loop.
+
+// std
+use std::sync::Arc;
+// pbrt
+use crate::accelerators::bvh::{BVHAccel, LinearBVHNode};
+use crate::core::geometry::{Bounds3f, Point3f, Ray, Vector3f};
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::light::Light;
+use crate::core::material::Material;
+use crate::core::paramset::ParamSet;
+use crate::core::pbrt::{gamma, Float};
+use crate::core::primitive::Primitive;
+
+/// Maximum number of children of a **WideBVHNode**.
+pub const MAX_WIDTH: usize = 8;
+
+/// Stack size for traversal (per level up to *MAX_WIDTH* - 1 nodes
+/// have to be remembered).
+const MAX_TODO: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct WideBVHNode {
+    // child bounds (structure of arrays)
+    pub p_min_x: [Float; MAX_WIDTH],
+    pub p_min_y: [Float; MAX_WIDTH],
+    pub p_min_z: [Float; MAX_WIDTH],
+    pub p_max_x: [Float; MAX_WIDTH],
+    pub p_max_y: [Float; MAX_WIDTH],
+    pub p_max_z: [Float; MAX_WIDTH],
+    /// index of child node, or of the first primitive for leaves
+    pub offset: [u32; MAX_WIDTH],
+    /// number of primitives for leaves, zero for interior children
+    pub n_primitives: [u16; MAX_WIDTH],
+    pub n_children: u8,
+}
+
+impl Default for WideBVHNode {
+    fn default() -> Self {
+        // unused slots get empty bounds which can't be hit
+        WideBVHNode {
+            p_min_x: [Float::MAX; MAX_WIDTH],
+            p_min_y: [Float::MAX; MAX_WIDTH],
+            p_min_z: [Float::MAX; MAX_WIDTH],
+            p_max_x: [Float::MIN; MAX_WIDTH],
+            p_max_y: [Float::MIN; MAX_WIDTH],
+            p_max_z: [Float::MIN; MAX_WIDTH],
+            offset: [0_u32; MAX_WIDTH],
+            n_primitives: [0_u16; MAX_WIDTH],
+            n_children: 0_u8,
+        }
+    }
+}
+
+impl WideBVHNode {
+    fn set_child_bounds(&mut self, i: usize, b: &Bounds3f) {
+        self.p_min_x[i] = b.p_min.x;
+        self.p_min_y[i] = b.p_min.y;
+        self.p_min_z[i] = b.p_min.z;
+        self.p_max_x[i] = b.p_max.x;
+        self.p_max_y[i] = b.p_max.y;
+        self.p_max_z[i] = b.p_max.z;
+    }
+    /// Tests the ray against the bounds of all children at once and
+    /// stores the entry distances in *t_hit*.
+    fn intersect_children(
+        &self,
+        o: &Point3f,
+        inv_dir: &Vector3f,
+        dir_is_neg: &[u8; 3],
+        ray_t_max: Float,
+        t_hit: &mut [Float; MAX_WIDTH],
+    ) -> [bool; MAX_WIDTH] {
+        let (near_x, far_x) = if dir_is_neg[0] == 1_u8 {
+            (&self.p_max_x, &self.p_min_x)
+        } else {
+            (&self.p_min_x, &self.p_max_x)
+        };
+        let (near_y, far_y) = if dir_is_neg[1] == 1_u8 {
+            (&self.p_max_y, &self.p_min_y)
+        } else {
+            (&self.p_min_y, &self.p_max_y)
+        };
+        let (near_z, far_z) = if dir_is_neg[2] == 1_u8 {
+            (&self.p_max_z, &self.p_min_z)
+        } else {
+            (&self.p_min_z, &self.p_max_z)
+        };
+        // ensure robust ray--bounds intersection
+        let robust: Float = 1.0 as Float + 2.0 as Float * gamma(3_i32);
+        let mut hit: [bool; MAX_WIDTH] = [false; MAX_WIDTH];
+        for i in 0..MAX_WIDTH {
+            let tx_min: Float = (near_x[i] - o.x) * inv_dir.x;
+            let ty_min: Float = (near_y[i] - o.y) * inv_dir.y;
+            let tz_min: Float = (near_z[i] - o.z) * inv_dir.z;
+            let tx_max: Float = (far_x[i] - o.x) * inv_dir.x * robust;
+            let ty_max: Float = (far_y[i] - o.y) * inv_dir.y * robust;
+            let tz_max: Float = (far_z[i] - o.z) * inv_dir.z * robust;
+            let t0: Float = tx_min.max(ty_min).max(tz_min).max(0.0 as Float);
+            let t1: Float = tx_max.min(ty_max).min(tz_max).min(ray_t_max);
+            t_hit[i] = t0;
+            hit[i] = t0 <= t1;
+        }
+        hit
+    }
+}
+
+// WideBVHAccel -> Aggregate -> Primitive
+pub struct WideBVHAccel {
+    pub width: usize,
+    pub primitives: Vec<Arc<Primitive>>,
+    pub nodes: Vec<WideBVHNode>,
+    world_bound: Bounds3f,
+}
+
+impl WideBVHAccel {
+    pub fn new(bvh: BVHAccel, width: usize) -> Self {
+        let width: usize = width.clamp(2, MAX_WIDTH);
+        let world_bound: Bounds3f = bvh.world_bound();
+        let mut nodes: Vec<WideBVHNode> = Vec::new();
+        if !bvh.nodes.is_empty() {
+            WideBVHAccel::collapse(&bvh.nodes, 0, width, &mut nodes);
+        }
+        WideBVHAccel {
+            width,
+            primitives: bvh.primitives,
+            nodes,
+            world_bound,
+        }
+    }
+    /// The supported **"integer width"** (4 or 8) of the parameters.
+    pub fn width_from_params(ps: &ParamSet) -> usize {
+        let width: i32 = ps.find_one_int("width", 4);
+        if width != 4 && width != 8 {
+            println!("WARNING: Wide BVH width {} not supported.  Using 4.", width);
+            4
+        } else {
+            width as usize
+        }
+    }
+    pub fn create(prims: Vec<Arc<Primitive>>, ps: &ParamSet) -> Primitive {
+        let width: usize = WideBVHAccel::width_from_params(ps);
+        let bvh: BVHAccel = BVHAccel::from_params(prims, ps);
+        bvh.print_stats();
+        let wide_bvh: WideBVHAccel = WideBVHAccel::new(bvh, width);
+        println!(
+            "  collapsed into {} nodes with up to {} children",
+            wide_bvh.nodes.len(),
+            wide_bvh.width
+        );
+        Primitive::WideBVH(Box::new(wide_bvh))
+    }
+    /// Creates a wide node for the binary node at *index* by
+    /// repeatedly replacing the interior child with the largest
+    /// surface area by its two children. Returns the index of the
+    /// new node.
+    fn collapse(
+        binary_nodes: &[LinearBVHNode],
+        index: usize,
+        width: usize,
+        nodes: &mut Vec<WideBVHNode>,
+    ) -> usize {
+        let my_offset: usize = nodes.len();
+        nodes.push(WideBVHNode::default());
+        let binary_node: &LinearBVHNode = &binary_nodes[index];
+        let mut children: Vec<usize> = if binary_node.n_primitives > 0 {
+            // a single leaf (only possible for the root)
+            vec![index]
+        } else {
+            vec![index + 1, binary_node.offset as usize]
+        };
+        while children.len() < width {
+            let mut largest: Option<usize> = None;
+            let mut largest_area: Float = -1.0 as Float;
+            for (i, child) in children.iter().enumerate() {
+                let child_node: &LinearBVHNode = &binary_nodes[*child];
+                if child_node.n_primitives == 0 {
+                    let area: Float = child_node.bounds.surface_area();
+                    if area > largest_area {
+                        largest_area = area;
+                        largest = Some(i);
+                    }
+                }
+            }
+            if let Some(i) = largest {
+                // keep children in the order of the binary tree
+                let child: usize = children[i];
+                let second_child: usize = binary_nodes[child].offset as usize;
+                children.splice(i..=i, vec![child + 1, second_child]);
+            } else {
+                break;
+            }
+        }
+        let mut node: WideBVHNode = WideBVHNode {
+            n_children: children.len() as u8,
+            ..Default::default()
+        };
+        for (i, child) in children.iter().enumerate() {
+            let child_node: &LinearBVHNode = &binary_nodes[*child];
+            node.set_child_bounds(i, &child_node.bounds);
+            if child_node.n_primitives > 0 {
+                node.offset[i] = child_node.offset as u32;
+                node.n_primitives[i] = child_node.n_primitives;
+            } else {
+                node.offset[i] = WideBVHAccel::collapse(binary_nodes, *child, width, nodes) as u32;
+            }
+        }
+        nodes[my_offset] = node;
+        my_offset
+    }
+    // Primitive
+    pub fn world_bound(&self) -> Bounds3f {
+        self.world_bound
+    }
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let mut hit: bool = false;
+        let inv_dir: Vector3f = Vector3f {
+            x: 1.0 / ray.d.x,
+            y: 1.0 / ray.d.y,
+            z: 1.0 / ray.d.z,
+        };
+        let dir_is_neg: [u8; 3] = [
+            (inv_dir.x < 0.0) as u8,
+            (inv_dir.y < 0.0) as u8,
+            (inv_dir.z < 0.0) as u8,
+        ];
+        let mut to_visit_offset: usize = 0;
+        let mut nodes_to_visit: [u32; MAX_TODO] = [0_u32; MAX_TODO];
+        let mut current_node_index: u32 = 0;
+        let mut t_hit: [Float; MAX_WIDTH] = [0.0 as Float; MAX_WIDTH];
+        loop {
+            let node: &WideBVHNode = &self.nodes[current_node_index as usize];
+            let child_hit: [bool; MAX_WIDTH] =
+                node.intersect_children(&ray.o, &inv_dir, &dir_is_neg, ray.t_max.get(), &mut t_hit);
+            // sort the children which were hit front to back
+            let mut n_hits: usize = 0;
+            let mut order: [usize; MAX_WIDTH] = [0_usize; MAX_WIDTH];
+            for i in 0..node.n_children as usize {
+                if child_hit[i] {
+                    let mut j: usize = n_hits;
+                    while j > 0 && t_hit[order[j - 1]] > t_hit[i] {
+                        order[j] = order[j - 1];
+                        j -= 1;
+                    }
+                    order[j] = i;
+                    n_hits += 1;
+                }
+            }
+            // intersect leaves front to back
+            for i in order.iter().take(n_hits) {
+                let n_primitives: usize = node.n_primitives[*i] as usize;
+                if n_primitives > 0 && t_hit[*i] <= ray.t_max.get() {
+                    let first: usize = node.offset[*i] as usize;
+                    for primitive in &self.primitives[first..first + n_primitives] {
+                        if primitive.intersect(ray, isect) {
+                            hit = true;
+                        }
+                    }
+                }
+            }
+            // push interior children back to front (nearest gets popped first)
+            for i in order.iter().take(n_hits).rev() {
+                if node.n_primitives[*i] == 0 {
+                    nodes_to_visit[to_visit_offset] = node.offset[*i];
+                    to_visit_offset += 1;
+                }
+            }
+            if to_visit_offset == 0 {
+                break;
+            }
+            to_visit_offset -= 1;
+            current_node_index = nodes_to_visit[to_visit_offset];
+        }
+        hit
+    }
+    pub fn intersect_p(&self, ray: &Ray) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let inv_dir: Vector3f = Vector3f {
+            x: 1.0 / ray.d.x,
+            y: 1.0 / ray.d.y,
+            z: 1.0 / ray.d.z,
+        };
+        let dir_is_neg: [u8; 3] = [
+            (inv_dir.x < 0.0) as u8,
+            (inv_dir.y < 0.0) as u8,
+            (inv_dir.z < 0.0) as u8,
+        ];
+        let mut to_visit_offset: usize = 0;
+        let mut nodes_to_visit: [u32; MAX_TODO] = [0_u32; MAX_TODO];
+        let mut current_node_index: u32 = 0;
+        let mut t_hit: [Float; MAX_WIDTH] = [0.0 as Float; MAX_WIDTH];
+        loop {
+            let node: &WideBVHNode = &self.nodes[current_node_index as usize];
+            let child_hit: [bool; MAX_WIDTH] =
+                node.intersect_children(&ray.o, &inv_dir, &dir_is_neg, ray.t_max.get(), &mut t_hit);
+            for (i, child_was_hit) in child_hit.iter().enumerate().take(node.n_children as usize) {
+                if *child_was_hit {
+                    let n_primitives: usize = node.n_primitives[i] as usize;
+                    if n_primitives > 0 {
+                        let first: usize = node.offset[i] as usize;
+                        for primitive in &self.primitives[first..first + n_primitives] {
+                            if primitive.intersect_p(ray) {
+                                return true;
+                            }
+                        }
+                    } else {
+                        nodes_to_visit[to_visit_offset] = node.offset[i];
+                        to_visit_offset += 1;
+                    }
+                }
+            }
+            if to_visit_offset == 0 {
+                break;
+            }
+            to_visit_offset -= 1;
+            current_node_index = nodes_to_visit[to_visit_offset];
+        }
+        false
+    }
+    pub fn get_material(&self) -> Option<Arc<Material>> {
+        None
+    }
+    pub fn get_area_light(&self) -> Option<Arc<Light>> {
+        None
+    }
+}
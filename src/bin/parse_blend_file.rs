@@ -27,6 +27,7 @@ use blend_info::{
 // pbrt
 use rs_pbrt::core::api::{make_accelerator, make_camera, make_film, make_filter, make_sampler};
 use rs_pbrt::core::camera::Camera;
+use rs_pbrt::core::colorspace::{RGBColorSpace, TextureColorSpace};
 use rs_pbrt::core::film::Film;
 use rs_pbrt::core::geometry::{Bounds2f, Bounds2i, Normal3f, Point2f, Point2i, Point3f, Vector3f};
 use rs_pbrt::core::integrator::{Integrator, SamplerIntegrator};
@@ -382,6 +383,7 @@ impl SceneDescriptionBuilder {
             &(l * sc),
             n_samples,
             texmap,
+            RGBColorSpace::SRGB,
         ))));
         self.lights.push(infinte_light);
         self
@@ -474,7 +476,7 @@ impl RenderOptions {
         let kd = Arc::new(ConstantTexture::new(Spectrum::new(1.0)));
         let sigma = Arc::new(ConstantTexture::new(0.0 as Float));
         let default_material = Arc::new(Material::Matte(Box::new(MatteMaterial::new(
-            kd, sigma, None,
+            kd, sigma, None, None,
         ))));
         // lights
         for light in &scene.lights {
@@ -527,6 +529,7 @@ impl RenderOptions {
                             v_roughness: v_roughness,
                             index: index,
                             bump_map: None,
+                            normal_map: None,
                             remap_roughness: true,
                         })));
                         shapes.push(cylinder.clone());
@@ -557,6 +560,7 @@ impl RenderOptions {
                                 None,
                                 None,
                                 None,
+                                None,
                                 remap_roughness,
                             ))));
                             shapes.push(cylinder.clone());
@@ -569,8 +573,9 @@ impl RenderOptions {
                                 mat.mirg * mat.ray_mirror,
                                 mat.mirb * mat.ray_mirror,
                             )));
-                            let mirror =
-                                Arc::new(Material::Mirror(Box::new(MirrorMaterial::new(kr, None))));
+                            let mirror = Arc::new(Material::Mirror(Box::new(MirrorMaterial::new(
+                                kr, None, None,
+                            ))));
                             shapes.push(cylinder.clone());
                             shape_materials.push(mirror.clone());
                             shape_lights.push(None);
@@ -597,7 +602,7 @@ impl RenderOptions {
                             let max_aniso: Float = 8.0;
                             let wrap_mode: ImageWrap = ImageWrap::Repeat;
                             let scale: Float = 1.0;
-                            let gamma: bool = false;
+                            let colorspace: TextureColorSpace = TextureColorSpace::Raw;
                             kd = Arc::new(ImageTexture::new(
                                 mapping,
                                 filename,
@@ -605,7 +610,8 @@ impl RenderOptions {
                                 max_aniso,
                                 wrap_mode,
                                 scale,
-                                gamma,
+                                colorspace,
+                                RGBColorSpace::SRGB,
                                 convert_to_spectrum,
                             ));
                         } else {
@@ -644,7 +650,7 @@ impl RenderOptions {
                                 let max_aniso: Float = 8.0;
                                 let wrap_mode: ImageWrap = ImageWrap::Repeat;
                                 let scale: Float = 1.0;
-                                let gamma: bool = false;
+                                let colorspace: TextureColorSpace = TextureColorSpace::Raw;
                                 kd = Arc::new(ImageTexture::new(
                                     mapping,
                                     filename,
@@ -652,7 +658,8 @@ impl RenderOptions {
                                     max_aniso,
                                     wrap_mode,
                                     scale,
-                                    gamma,
+                                    colorspace,
+                                    RGBColorSpace::SRGB,
                                     convert_to_spectrum,
                                 ));
                             }
@@ -662,6 +669,7 @@ impl RenderOptions {
                             kd,
                             sigma.clone(),
                             None,
+                            None,
                         ))));
                         shapes.push(cylinder.clone());
                         shape_materials.push(matte.clone());
@@ -717,6 +725,7 @@ impl RenderOptions {
                             v_roughness: v_roughness,
                             index: index,
                             bump_map: None,
+                            normal_map: None,
                             remap_roughness: true,
                         })));
                         shapes.push(disk.clone());
@@ -747,6 +756,7 @@ impl RenderOptions {
                                 None,
                                 None,
                                 None,
+                                None,
                                 remap_roughness,
                             ))));
                             shapes.push(disk.clone());
@@ -759,8 +769,9 @@ impl RenderOptions {
                                 mat.mirg * mat.ray_mirror,
                                 mat.mirb * mat.ray_mirror,
                             )));
-                            let mirror =
-                                Arc::new(Material::Mirror(Box::new(MirrorMaterial::new(kr, None))));
+                            let mirror = Arc::new(Material::Mirror(Box::new(MirrorMaterial::new(
+                                kr, None, None,
+                            ))));
                             shapes.push(disk.clone());
                             shape_materials.push(mirror.clone());
                             shape_lights.push(None);
@@ -787,7 +798,7 @@ impl RenderOptions {
                             let max_aniso: Float = 8.0;
                             let wrap_mode: ImageWrap = ImageWrap::Repeat;
                             let scale: Float = 1.0;
-                            let gamma: bool = false;
+                            let colorspace: TextureColorSpace = TextureColorSpace::Raw;
                             kd = Arc::new(ImageTexture::new(
                                 mapping,
                                 filename,
@@ -795,7 +806,8 @@ impl RenderOptions {
                                 max_aniso,
                                 wrap_mode,
                                 scale,
-                                gamma,
+                                colorspace,
+                                RGBColorSpace::SRGB,
                                 convert_to_spectrum,
                             ));
                         } else {
@@ -834,7 +846,7 @@ impl RenderOptions {
                                 let max_aniso: Float = 8.0;
                                 let wrap_mode: ImageWrap = ImageWrap::Repeat;
                                 let scale: Float = 1.0;
-                                let gamma: bool = false;
+                                let colorspace: TextureColorSpace = TextureColorSpace::Raw;
                                 kd = Arc::new(ImageTexture::new(
                                     mapping,
                                     filename,
@@ -842,7 +854,8 @@ impl RenderOptions {
                                     max_aniso,
                                     wrap_mode,
                                     scale,
-                                    gamma,
+                                    colorspace,
+                                    RGBColorSpace::SRGB,
                                     convert_to_spectrum,
                                 ));
                             }
@@ -852,6 +865,7 @@ impl RenderOptions {
                             kd,
                             sigma.clone(),
                             None,
+                            None,
                         ))));
                         shapes.push(disk.clone());
                         shape_materials.push(matte.clone());
@@ -907,6 +921,7 @@ impl RenderOptions {
                             v_roughness: v_roughness,
                             index: index,
                             bump_map: None,
+                            normal_map: None,
                             remap_roughness: true,
                         })));
                         shapes.push(sphere.clone());
@@ -937,6 +952,7 @@ impl RenderOptions {
                                 None,
                                 None,
                                 None,
+                                None,
                                 remap_roughness,
                             ))));
                             shapes.push(sphere.clone());
@@ -949,8 +965,9 @@ impl RenderOptions {
                                 mat.mirg * mat.ray_mirror,
                                 mat.mirb * mat.ray_mirror,
                             )));
-                            let mirror =
-                                Arc::new(Material::Mirror(Box::new(MirrorMaterial::new(kr, None))));
+                            let mirror = Arc::new(Material::Mirror(Box::new(MirrorMaterial::new(
+                                kr, None, None,
+                            ))));
                             shapes.push(sphere.clone());
                             shape_materials.push(mirror.clone());
                             shape_lights.push(None);
@@ -977,7 +994,7 @@ impl RenderOptions {
                             let max_aniso: Float = 8.0;
                             let wrap_mode: ImageWrap = ImageWrap::Repeat;
                             let scale: Float = 1.0;
-                            let gamma: bool = false;
+                            let colorspace: TextureColorSpace = TextureColorSpace::Raw;
                             kd = Arc::new(ImageTexture::new(
                                 mapping,
                                 filename,
@@ -985,7 +1002,8 @@ impl RenderOptions {
                                 max_aniso,
                                 wrap_mode,
                                 scale,
-                                gamma,
+                                colorspace,
+                                RGBColorSpace::SRGB,
                                 convert_to_spectrum,
                             ));
                         } else {
@@ -1024,7 +1042,7 @@ impl RenderOptions {
                                 let max_aniso: Float = 8.0;
                                 let wrap_mode: ImageWrap = ImageWrap::Repeat;
                                 let scale: Float = 1.0;
-                                let gamma: bool = false;
+                                let colorspace: TextureColorSpace = TextureColorSpace::Raw;
                                 kd = Arc::new(ImageTexture::new(
                                     mapping,
                                     filename,
@@ -1032,7 +1050,8 @@ impl RenderOptions {
                                     max_aniso,
                                     wrap_mode,
                                     scale,
-                                    gamma,
+                                    colorspace,
+                                    RGBColorSpace::SRGB,
                                     convert_to_spectrum,
                                 ));
                             }
@@ -1042,6 +1061,7 @@ impl RenderOptions {
                             kd,
                             sigma.clone(),
                             None,
+                            None,
                         ))));
                         shapes.push(sphere.clone());
                         shape_materials.push(matte.clone());
@@ -1107,6 +1127,7 @@ impl RenderOptions {
                             v_roughness: v_roughness,
                             index: index,
                             bump_map: None,
+                            normal_map: None,
                             remap_roughness: true,
                         })));
                         for _i in 0..triangles.len() {
@@ -1138,6 +1159,7 @@ impl RenderOptions {
                                 None,
                                 None,
                                 None,
+                                None,
                                 remap_roughness,
                             ))));
                             for _i in 0..triangles.len() {
@@ -1151,8 +1173,9 @@ impl RenderOptions {
                                 mat.mirg * mat.ray_mirror,
                                 mat.mirb * mat.ray_mirror,
                             )));
-                            let mirror =
-                                Arc::new(Material::Mirror(Box::new(MirrorMaterial::new(kr, None))));
+                            let mirror = Arc::new(Material::Mirror(Box::new(MirrorMaterial::new(
+                                kr, None, None,
+                            ))));
                             for _i in 0..triangles.len() {
                                 shape_materials.push(mirror.clone());
                                 shape_lights.push(None);
@@ -1173,7 +1196,7 @@ impl RenderOptions {
                         );
                         let translucent =
                             Arc::new(Material::Translucent(Box::new(TranslucentMaterial::new(
-                                kd, ks, roughness, reflect, transmit, None, true,
+                                kd, ks, roughness, reflect, transmit, None, None, true,
                             ))));
                         for _i in 0..triangles.len() {
                             shape_materials.push(translucent.clone());
@@ -1201,7 +1224,7 @@ impl RenderOptions {
                             let max_aniso: Float = 8.0;
                             let wrap_mode: ImageWrap = ImageWrap::Repeat;
                             let scale: Float = 1.0;
-                            let gamma: bool = false;
+                            let colorspace: TextureColorSpace = TextureColorSpace::Raw;
                             kd = Arc::new(ImageTexture::new(
                                 mapping,
                                 filename,
@@ -1209,7 +1232,8 @@ impl RenderOptions {
                                 max_aniso,
                                 wrap_mode,
                                 scale,
-                                gamma,
+                                colorspace,
+                                RGBColorSpace::SRGB,
                                 convert_to_spectrum,
                             ));
                         } else {
@@ -1248,7 +1272,7 @@ impl RenderOptions {
                                 let max_aniso: Float = 8.0;
                                 let wrap_mode: ImageWrap = ImageWrap::Repeat;
                                 let scale: Float = 1.0;
-                                let gamma: bool = false;
+                                let colorspace: TextureColorSpace = TextureColorSpace::Raw;
                                 kd = Arc::new(ImageTexture::new(
                                     mapping,
                                     filename,
@@ -1256,7 +1280,8 @@ impl RenderOptions {
                                     max_aniso,
                                     wrap_mode,
                                     scale,
-                                    gamma,
+                                    colorspace,
+                                    RGBColorSpace::SRGB,
                                     convert_to_spectrum,
                                 ));
                             }
@@ -1266,6 +1291,7 @@ impl RenderOptions {
                             kd,
                             sigma.clone(),
                             None,
+                            None,
                         ))));
                         if triangle_colors.len() != 0_usize {
                             assert!(triangle_colors.len() == triangles.len());
@@ -1277,6 +1303,7 @@ impl RenderOptions {
                                     kd,
                                     sigma.clone(),
                                     None,
+                                    None,
                                 ))));
                                 shape_materials.push(matte.clone());
                                 shape_lights.push(None);
@@ -1542,8 +1569,13 @@ pub fn make_perspective_camera(
             p_min: Point2f { x: 0.0, y: 0.0 },
             p_max: Point2f { x: 1.0, y: 1.0 },
         };
-        let some_film: Option<Arc<Film>> =
-            make_film(&film_name, &film_params, filter, &crop_window);
+        let some_film: Option<Arc<Film>> = make_film(
+            &film_name,
+            &film_params,
+            filter,
+            &crop_window,
+            RGBColorSpace::SRGB,
+        );
         if let Some(film) = some_film {
             let camera_name: String = String::from("perspective");
             let mut camera_params: ParamSet = ParamSet::default();
@@ -1644,6 +1676,7 @@ fn make_integrator(
                         pixel_bounds,
                         rr_threshold,
                         light_strategy,
+                        0_u32,
                     ),
                 )));
                 some_integrator = Some(integrator);
@@ -1663,6 +1696,7 @@ fn make_integrator(
                         pixel_bounds,
                         rr_threshold,
                         light_strategy,
+                        0_u32,
                     ),
                 )));
                 some_integrator = Some(integrator);
@@ -1679,6 +1713,8 @@ fn make_integrator(
                     sampler,
                     pixel_bounds,
                     max_depth as u32,
+                    false,
+                    false,
                     light_strategy,
                 )));
                 some_integrator = Some(integrator);
@@ -1702,6 +1738,7 @@ fn make_integrator(
                 println!("  sigma = {}", sigma);
                 let integrator = Box::new(Integrator::MLT(MLTIntegrator::new(
                     camera.clone(),
+                    camera.get_film().get_sample_bounds(),
                     max_depth as u32,
                     n_bootstrap as u32,
                     n_chains as u32,
@@ -1737,6 +1774,7 @@ fn make_integrator(
                 // TODO: if (PbrtOptions.quickRender) nIterations = std::max(1, nIterations / 16);
                 let integrator = Box::new(Integrator::SPPM(SPPMIntegrator::new(
                     camera.clone(),
+                    camera.get_film().get_sample_bounds(),
                     n_iterations,
                     photons_per_iter,
                     max_depth as u32,
@@ -2518,7 +2556,8 @@ fn main() -> std::io::Result<()> {
                                                                 base_name.clone(),
                                                                 use_alpha_to_hide,
                                                             );
-							    search_for_cycles_visibility_camera = false;
+                                                            search_for_cycles_visibility_camera =
+                                                                false;
                                                         }
                                                     }
                                                     _ => {}
@@ -14,16 +14,17 @@ use structopt::StructOpt;
 // pbrt
 use rs_pbrt::core::api::{
     pbrt_accelerator, pbrt_active_transform_all, pbrt_active_transform_end_time,
-    pbrt_active_transform_start_time, pbrt_area_light_source, pbrt_attribute_begin,
-    pbrt_attribute_end, pbrt_camera, pbrt_cleanup, pbrt_concat_transform, pbrt_coord_sys_transform,
-    pbrt_film, pbrt_init, pbrt_integrator, pbrt_light_source, pbrt_look_at,
-    pbrt_make_named_material, pbrt_make_named_medium, pbrt_material, pbrt_medium_interface,
-    pbrt_named_material, pbrt_object_begin, pbrt_object_end, pbrt_object_instance,
-    pbrt_pixel_filter, pbrt_reverse_orientation, pbrt_rotate, pbrt_sampler, pbrt_scale, pbrt_shape,
-    pbrt_texture, pbrt_transform, pbrt_transform_begin, pbrt_transform_end, pbrt_translate,
-    pbrt_world_begin,
+    pbrt_active_transform_start_time, pbrt_active_transform_time, pbrt_area_light_source,
+    pbrt_attribute_begin, pbrt_attribute_end, pbrt_camera, pbrt_cleanup, pbrt_color_space,
+    pbrt_concat_transform, pbrt_coord_sys_transform, pbrt_film, pbrt_init, pbrt_integrator,
+    pbrt_light_source, pbrt_look_at, pbrt_make_named_material, pbrt_make_named_medium,
+    pbrt_material, pbrt_medium_interface, pbrt_named_material, pbrt_object_begin, pbrt_object_end,
+    pbrt_object_instance, pbrt_pixel_filter, pbrt_reverse_orientation, pbrt_rotate, pbrt_sampler,
+    pbrt_scale, pbrt_shape, pbrt_texture, pbrt_transform, pbrt_transform_begin, pbrt_transform_end,
+    pbrt_transform_times, pbrt_translate, pbrt_world_begin,
 };
 use rs_pbrt::core::api::{ApiState, BsdfState};
+use rs_pbrt::core::colorspace::RGBColorSpace;
 use rs_pbrt::core::geometry::{Normal3f, Point2f, Point3f, Vector3f};
 use rs_pbrt::core::paramset::ParamSet;
 use rs_pbrt::core::pbrt::{Float, Spectrum};
@@ -60,6 +61,18 @@ struct Cli {
     /// pixel samples
     #[structopt(short = "s", long = "samples", default_value = "0")]
     samples: u32,
+    /// exposure in stops (EV), applied before tone mapping
+    #[structopt(long = "exposure")]
+    exposure: Option<f32>,
+    /// color temperature (in Kelvin) which should appear white
+    #[structopt(long = "whitebalance")]
+    whitebalance: Option<f32>,
+    /// clamp, reinhard, aces, agx
+    #[structopt(long = "tonemap")]
+    tonemap: Option<String>,
+    /// write the linear HDR image to this file (.exr or .pfm)
+    #[structopt(long = "hdr")]
+    hdr: Option<String>,
     /// The path to the file to read
     #[structopt(parse(from_os_str))]
     path: std::path::PathBuf,
@@ -68,7 +81,6 @@ struct Cli {
 // Accelerator
 // CoordinateSystem
 // Identity
-// TransformTimes
 
 fn pbrt_bool_parameter(pairs: &mut pest::iterators::Pairs<Rule>) -> (String, bool) {
     // single string with or without brackets
@@ -217,7 +229,11 @@ fn pbrt_texture_parameter(pairs: &mut pest::iterators::Pairs<Rule>) -> (String,
     (string1, string2)
 }
 
-fn extract_params(key_word: String, pairs: pest::iterators::Pair<Rule>) -> ParamSet {
+fn extract_params(
+    key_word: String,
+    pairs: pest::iterators::Pair<Rule>,
+    color_space: RGBColorSpace,
+) -> ParamSet {
     let mut params: ParamSet = ParamSet::default();
     params.key_word = key_word;
     let mut counter: u8 = 0_u8;
@@ -286,7 +302,7 @@ fn extract_params(key_word: String, pairs: pest::iterators::Pair<Rule>) -> Param
                                 pbrt_float_parameter(&mut parameter_pair.into_inner());
                             let string: String = tuple.0;
                             let floats: Vec<Float> = tuple.1;
-                            params.add_blackbody_spectrum(string, floats);
+                            params.add_blackbody_spectrum(string, floats, color_space);
                         }
                         Rule::float_param => {
                             let tuple: (String, Vec<Float>) =
@@ -395,7 +411,7 @@ fn extract_params(key_word: String, pairs: pest::iterators::Pair<Rule>) -> Param
                             let string2: String = tuple.1;
                             let mut strings: Vec<String> = Vec::with_capacity(1_usize);
                             strings.push(string2);
-                            params.add_sampled_spectrum_files(string1, strings);
+                            params.add_sampled_spectrum_files(string1, strings, color_space);
                         }
                         Rule::string_param => {
                             let tuple: (String, String) =
@@ -504,7 +520,11 @@ fn parse_line(
                     // identifier "type" parameter-list
                     let for_printing = inner_pair.as_str();
                     // println!("DEBUG: {}", for_printing);
-                    let params = extract_params(String::from(identifier), inner_pair);
+                    let params = extract_params(
+                        String::from(identifier),
+                        inner_pair,
+                        api_state.color_space(),
+                    );
                     match identifier {
                         "Accelerator" => {
                             // Accelerator
@@ -518,6 +538,10 @@ fn parse_line(
                             // Camera
                             pbrt_camera(api_state, params);
                         }
+                        "ColorSpace" => {
+                            // ColorSpace
+                            pbrt_color_space(api_state, params);
+                        }
                         "CoordSysTransform" => {
                             // CoordSysTransform
                             pbrt_coord_sys_transform(api_state, params);
@@ -610,6 +634,11 @@ fn parse_line(
                             Rule::end_time => {
                                 pbrt_active_transform_end_time(api_state);
                             }
+                            Rule::key_time => {
+                                let number = rule_pair.into_inner().next().unwrap();
+                                let time: Float = f32::from_str(number.as_str()).unwrap();
+                                pbrt_active_transform_time(api_state, time);
+                            }
                             _ => unreachable!(),
                         }
                     }
@@ -747,6 +776,16 @@ fn parse_line(
                     );
                     pbrt_transform(api_state, &tr);
                 }
+                Rule::transform_times => {
+                    // TransformTimes start end
+                    let mut v: Vec<Float> = Vec::new();
+                    for rule_pair in inner_pair.into_inner() {
+                        let number: Float =
+                            f32::from_str(rule_pair.clone().as_span().as_str()).unwrap();
+                        v.push(number);
+                    }
+                    pbrt_transform_times(api_state, v[0], v[1]);
+                }
                 Rule::translate => {
                     // Translate x y z
                     let mut v: Vec<Float> = Vec::new();
@@ -895,6 +934,20 @@ fn main() {
     let cropx1: f32 = args.cropx1;
     let cropy0: f32 = args.cropy0;
     let cropy1: f32 = args.cropy1;
+    // display transform options override the film parameters
+    let mut film_overrides: ParamSet = ParamSet::default();
+    if let Some(exposure) = args.exposure {
+        film_overrides.add_float(String::from("exposure"), exposure);
+    }
+    if let Some(whitebalance) = args.whitebalance {
+        film_overrides.add_float(String::from("whitebalance"), whitebalance);
+    }
+    if let Some(tonemap) = args.tonemap {
+        film_overrides.add_string(String::from("tonemap"), tonemap);
+    }
+    if let Some(hdr) = args.hdr {
+        film_overrides.add_string(String::from("hdrfilename"), hdr);
+    }
     let num_cores = num_cpus::get();
     let git_describe = option_env!("GIT_DESCRIBE").unwrap_or("unknown");
     println!(
@@ -910,6 +963,7 @@ fn main() {
         cropx1,
         cropy0,
         cropy1,
+        film_overrides,
     );
     parse_file(
         args.path.into_os_string().into_string().unwrap(),
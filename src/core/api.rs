@@ -9,26 +9,30 @@ use std::path::PathBuf;
 use std::sync::Arc;
 // pbrt
 use crate::accelerators::bvh::{BVHAccel, SplitMethod};
+use crate::accelerators::consistency::{check_accelerators, ConsistencyReport};
 use crate::accelerators::kdtreeaccel::KdTreeAccel;
+use crate::accelerators::widebvh::WideBVHAccel;
 use crate::cameras::environment::EnvironmentCamera;
 use crate::cameras::orthographic::OrthographicCamera;
 use crate::cameras::perspective::PerspectiveCamera;
 use crate::cameras::realistic::RealisticCamera;
 use crate::core::camera::Camera;
+use crate::core::colorspace::{RGBColorSpace, TextureColorSpace};
 use crate::core::film::Film;
 use crate::core::filter::Filter;
-use crate::core::geometry::{vec3_coordinate_system, vec3_cross_vec3};
+use crate::core::geometry::{bnd2_intersect_bnd2i, vec3_coordinate_system, vec3_cross_vec3};
 use crate::core::geometry::{Bounds2f, Bounds2i, Normal3f, Point2f, Point2i, Point3f, Vector3f};
+use crate::core::imageio::is_srgb_encoded;
 use crate::core::integrator::{Integrator, SamplerIntegrator};
 use crate::core::light::Light;
 use crate::core::material::Material;
 use crate::core::medium::get_medium_scattering_properties;
-use crate::core::medium::{Medium, MediumInterface};
+use crate::core::medium::{Medium, MediumEmission, MediumInterface};
 use crate::core::mipmap::ImageWrap;
 use crate::core::paramset::{ParamSet, TextureParams};
 use crate::core::pbrt::{clamp_t, lerp};
 use crate::core::pbrt::{Float, Spectrum};
-use crate::core::primitive::{GeometricPrimitive, Primitive, TransformedPrimitive};
+use crate::core::primitive::{GeometricPrimitive, MeshPrimitive, Primitive, TransformedPrimitive};
 use crate::core::reflection::FourierBSDFTable;
 use crate::core::sampler::Sampler;
 use crate::core::scene::Scene;
@@ -46,9 +50,12 @@ use crate::filters::triangle::TriangleFilter;
 use crate::integrators::ao::AOIntegrator;
 use crate::integrators::bdpt::BDPTIntegrator;
 use crate::integrators::directlighting::{DirectLightingIntegrator, LightStrategy};
+use crate::integrators::gpt::{GPTIntegrator, Reconstruction};
+use crate::integrators::lighttracer::LightTracerIntegrator;
 use crate::integrators::mlt::MLTIntegrator;
 use crate::integrators::path::PathIntegrator;
 use crate::integrators::sppm::SPPMIntegrator;
+use crate::integrators::vcm::VCMIntegrator;
 use crate::integrators::volpath::VolPathIntegrator;
 use crate::integrators::whitted::WhittedIntegrator;
 use crate::lights::diffuse::DiffuseAreaLight;
@@ -73,16 +80,20 @@ use crate::materials::translucent::TranslucentMaterial;
 use crate::materials::uber::UberMaterial;
 use crate::media::grid::GridDensityMedium;
 use crate::media::homogeneous::HomogeneousMedium;
+use crate::media::sparse::{read_brick_grids, BrickGrid, SparseGridMedium};
 use crate::samplers::halton::HaltonSampler;
 use crate::samplers::maxmin::MaxMinDistSampler;
 use crate::samplers::random::RandomSampler;
 use crate::samplers::sobol::SobolSampler;
 use crate::samplers::stratified::StratifiedSampler;
 use crate::samplers::zerotwosequence::ZeroTwoSequenceSampler;
+use crate::shapes::compressedmesh::CompressedTriangleMesh;
 use crate::shapes::curve::create_curve_shape;
 use crate::shapes::cylinder::Cylinder;
 use crate::shapes::disk::Disk;
+use crate::shapes::displacement::displace_mesh_from_params;
 use crate::shapes::loopsubdiv::loop_subdivide;
+use crate::shapes::nurbs::nurbs_adaptive_dicing;
 use crate::shapes::nurbs::nurbs_evaluate_surface;
 use crate::shapes::nurbs::Homogeneous3;
 use crate::shapes::plymesh::create_ply_mesh;
@@ -92,8 +103,8 @@ use crate::textures::checkerboard::Checkerboard2DTexture;
 use crate::textures::constant::ConstantTexture;
 use crate::textures::dots::DotsTexture;
 use crate::textures::fbm::FBmTexture;
-use crate::textures::imagemap::ImageTexture;
 use crate::textures::imagemap::{convert_to_float, convert_to_spectrum};
+use crate::textures::imagemap::{read_texels, ImageTexture, ImageTextureSource};
 use crate::textures::marble::MarbleTexture;
 use crate::textures::mix::MixTexture;
 use crate::textures::scale::ScaleTexture;
@@ -129,6 +140,14 @@ pub struct ApiState {
     param_set: ParamSet,
 }
 
+impl ApiState {
+    /// The working color space of the scene, which is needed to
+    /// convert spectral parameters while they are parsed.
+    pub fn color_space(&self) -> RGBColorSpace {
+        self.render_options.color_space
+    }
+}
+
 impl Default for ApiState {
     fn default() -> Self {
         ApiState {
@@ -153,10 +172,11 @@ impl Default for ApiState {
                             [0.0, 0.0, 0.0, 1.0],
                         ],
                     },
-                }; 2],
+                }; MAX_TRANSFORMS],
+                keyed: 0_u8,
             },
             named_coordinate_systems: HashMap::new(),
-            active_transform_bits: 3_u8, // 0x11 for MaxTransforms = 2
+            active_transform_bits: ALL_TRANSFORMS_BITS,
             render_options: RenderOptions::default(),
             graphics_state: GraphicsState::new(),
             pushed_graphics_states: Vec::new(),
@@ -167,25 +187,82 @@ impl Default for ApiState {
     }
 }
 
+/// Number of transformation keys in a `TransformSet`: the start and
+/// end of the shutter interval (slots 0 and 1) plus up to six
+/// intermediate keys selected by `ActiveTransform Time`.
+pub const MAX_TRANSFORMS: usize = 8;
+/// `ActiveTransform All` (one bit per transformation key)
+const ALL_TRANSFORMS_BITS: u8 = 0xff;
+
 #[derive(Debug, Default, Copy, Clone)]
 pub struct TransformSet {
-    pub t: [Transform; 2],
+    pub t: [Transform; MAX_TRANSFORMS],
+    /// bit *i* is set if the intermediate key *i* was specified
+    pub keyed: u8,
 }
 
 impl TransformSet {
     pub fn is_animated(&self) -> bool {
-        // for (int i = 0; i < MaxTransforms - 1; ++i)
-        //     if (t[i] != t[i + 1]) return true;
-        // return false;
+        (1..MAX_TRANSFORMS).any(|i| self.has_key(i) && self.t[i] != self.t[0])
+    }
+    fn has_key(&self, i: usize) -> bool {
+        i < 2 || self.keyed & (1_u8 << i) > 0_u8
+    }
+    pub fn inverse(&self) -> TransformSet {
+        let mut inverse: TransformSet = *self;
+        for t in inverse.t.iter_mut() {
+            *t = Transform::inverse(t);
+        }
+        inverse
+    }
+    /// Interpolates between the start, end and (specified)
+    /// intermediate keys over the shutter interval.
+    pub fn animated_transform(
+        &self,
+        start_time: Float,
+        end_time: Float,
+        key_times: &[Float],
+    ) -> AnimatedTransform {
+        let mut keys: Vec<(Float, Transform)> = vec![(start_time, self.t[0])];
+        for i in 2..MAX_TRANSFORMS {
+            if self.has_key(i) {
+                let time: Float = key_times[i - 2];
+                if time > start_time && time < end_time {
+                    keys.push((time, self.t[i]));
+                }
+            }
+        }
+        keys.push((end_time, self.t[1]));
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let transforms: Vec<Transform> = keys.iter().map(|key| key.1).collect();
+        let times: Vec<Float> = keys.iter().map(|key| key.0).collect();
+        AnimatedTransform::new_with_keys(&transforms, &times)
+    }
+}
 
-        // we have only 2 transforms
-        self.t[0] != self.t[1]
+/// Applies *f* to all transformation keys selected by `ActiveTransform`.
+fn update_active_transforms<F>(api_state: &mut ApiState, f: F)
+where
+    F: Fn(&Transform) -> Transform,
+{
+    let bits: u8 = api_state.active_transform_bits;
+    for i in 0..MAX_TRANSFORMS {
+        if bits & (1_u8 << i) > 0_u8 {
+            api_state.cur_transform.t[i] = f(&api_state.cur_transform.t[i]);
+        }
+    }
+    if bits != ALL_TRANSFORMS_BITS {
+        // remember which intermediate keys were specified explicitly
+        api_state.cur_transform.keyed |= bits & !3_u8;
     }
 }
 
 pub struct RenderOptions {
     pub transform_start_time: Float,
     pub transform_end_time: Float,
+    /// times of the intermediate transformation keys (slots 2.. of
+    /// each `TransformSet`)
+    pub transform_key_times: Vec<Float>,
     pub filter_name: String, // "box"
     pub filter_params: ParamSet,
     pub film_name: String, // "image"
@@ -206,6 +283,11 @@ pub struct RenderOptions {
     pub current_instance: String,
     pub have_scattering_media: bool, // false
     pub crop_window: Bounds2f,
+    /// parameters of the film given on the command line, which take
+    /// precedence over the ones of the scene file
+    pub film_overrides: ParamSet,
+    /// the working color space of the scene (see `pbrt_color_space()`)
+    pub color_space: RGBColorSpace,
 }
 
 impl RenderOptions {
@@ -217,31 +299,13 @@ impl RenderOptions {
         let mut some_integrator: Option<Box<Integrator>> = None;
         let some_camera: Option<Arc<Camera>> = self.make_camera();
         if let Some(camera) = some_camera {
-            let some_sampler: Option<Box<Sampler>>;
-            if pixelsamples != 0_u32 {
-                // copy all bool and integer values, except pixelsamples
-                let mut new_sampler_params: ParamSet = ParamSet::default();
-                new_sampler_params.key_word = self.sampler_params.key_word.clone();
-                new_sampler_params.name = self.sampler_params.name.clone();
-                new_sampler_params.tex_type = self.sampler_params.tex_type.clone();
-                new_sampler_params.tex_name = self.sampler_params.tex_name.clone();
-                for b in &self.sampler_params.bools {
-                    new_sampler_params.add_bool(b.name.clone(), b.values[0]);
-                }
-                for i in &self.sampler_params.ints {
-                    if i.name == "pixelsamples" {
-                        new_sampler_params.add_int(i.name.clone(), pixelsamples as i32);
-                    } else {
-                        new_sampler_params.add_int(i.name.clone(), i.values[0]);
-                    }
-                }
+            let some_sampler: Option<Box<Sampler>> = if pixelsamples != 0_u32 {
+                let new_sampler_params: ParamSet = self.get_sampler_params(pixelsamples);
                 print_params(&new_sampler_params);
-                some_sampler =
-                    make_sampler(&self.sampler_name, &new_sampler_params, camera.get_film());
+                make_sampler(&self.sampler_name, &new_sampler_params, camera.get_film())
             } else {
-                some_sampler =
-                    make_sampler(&self.sampler_name, &self.sampler_params, camera.get_film());
-            }
+                make_sampler(&self.sampler_name, &self.sampler_params, camera.get_film())
+            };
             if let Some(sampler) = some_sampler {
                 // if let Some(integrator_name) = integrator_arg {
                 let integrator_name: String;
@@ -254,7 +318,7 @@ impl RenderOptions {
                 if integrator_name == "whitted" {
                     // CreateWhittedIntegrator
                     let max_depth: i32 = self.integrator_params.find_one_int("maxdepth", 5);
-                    let pixel_bounds: Bounds2i = camera.get_film().get_sample_bounds();
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
                     let integrator = Box::new(Integrator::Sampler(SamplerIntegrator::Whitted(
                         WhittedIntegrator::new(max_depth as u32, camera, sampler, pixel_bounds),
                     )));
@@ -273,13 +337,7 @@ impl RenderOptions {
                     } else {
                         panic!("Strategy \"{}\" for direct lighting unknown.", st);
                     }
-                    // TODO: const int *pb = params.FindInt("pixelbounds", &np);
-                    let xres: i32 = self.film_params.find_one_int("xresolution", 1280);
-                    let yres: i32 = self.film_params.find_one_int("yresolution", 720);
-                    let pixel_bounds: Bounds2i = Bounds2i {
-                        p_min: Point2i { x: 0, y: 0 },
-                        p_max: Point2i { x: xres, y: yres },
-                    };
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
                     let integrator = Box::new(Integrator::Sampler(
                         SamplerIntegrator::DirectLighting(DirectLightingIntegrator::new(
                             strategy,
@@ -293,29 +351,15 @@ impl RenderOptions {
                 } else if integrator_name == "path" {
                     // CreatePathIntegrator
                     let max_depth: i32 = self.integrator_params.find_one_int("maxdepth", 5);
-                    let pb: Vec<i32> = self.integrator_params.find_int("pixelbounds");
-                    let np: usize = pb.len();
-                    let pixel_bounds: Bounds2i = camera.get_film().get_sample_bounds();
-                    if np > 0 as usize {
-                        if np != 4 as usize {
-                            panic!(
-                                "Expected four values for \"pixelbounds\" parameter. Got {}.",
-                                np
-                            );
-                        } else {
-                            println!("TODO: pixelBounds = Intersect(...)");
-                            // pixelBounds = Intersect(pixelBounds,
-                            //                         Bounds2i{{pb[0], pb[2]}, {pb[1], pb[3]}});
-                            // if (pixelBounds.Area() == 0)
-                            //     Error("Degenerate \"pixelbounds\" specified.");
-                        }
-                    }
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
                     let rr_threshold: Float = self
                         .integrator_params
                         .find_one_float("rrthreshold", 1.0 as Float);
                     let light_strategy: String = self
                         .integrator_params
                         .find_one_string("lightsamplestrategy", String::from("spatial"));
+                    let guiding_passes: i32 =
+                        self.integrator_params.find_one_int("guidingpasses", 0);
                     let integrator = Box::new(Integrator::Sampler(SamplerIntegrator::Path(
                         PathIntegrator::new(
                             max_depth as u32,
@@ -324,35 +368,22 @@ impl RenderOptions {
                             pixel_bounds,
                             rr_threshold,
                             light_strategy,
+                            guiding_passes.max(0) as u32,
                         ),
                     )));
                     some_integrator = Some(integrator);
                 } else if integrator_name == "volpath" {
                     // CreateVolPathIntegrator
                     let max_depth: i32 = self.integrator_params.find_one_int("maxdepth", 5);
-                    let pb: Vec<i32> = self.integrator_params.find_int("pixelbounds");
-                    let np: usize = pb.len();
-                    let pixel_bounds: Bounds2i = camera.get_film().get_sample_bounds();
-                    if np > 0 as usize {
-                        if np != 4 as usize {
-                            panic!(
-                                "Expected four values for \"pixelbounds\" parameter. Got {}.",
-                                np
-                            );
-                        } else {
-                            println!("TODO: pixelBounds = Intersect(...)");
-                            // pixelBounds = Intersect(pixelBounds,
-                            //                         Bounds2i{{pb[0], pb[2]}, {pb[1], pb[3]}});
-                            // if (pixelBounds.Area() == 0)
-                            //     Error("Degenerate \"pixelbounds\" specified.");
-                        }
-                    }
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
                     let rr_threshold: Float = self
                         .integrator_params
                         .find_one_float("rrthreshold", 1.0 as Float);
                     let light_strategy: String = self
                         .integrator_params
                         .find_one_string("lightsamplestrategy", String::from("spatial"));
+                    let guiding_passes: i32 =
+                        self.integrator_params.find_one_int("guidingpasses", 0);
                     let integrator = Box::new(Integrator::Sampler(SamplerIntegrator::VolPath(
                         VolPathIntegrator::new(
                             max_depth as u32,
@@ -361,6 +392,7 @@ impl RenderOptions {
                             pixel_bounds,
                             rr_threshold,
                             light_strategy,
+                            guiding_passes.max(0) as u32,
                         ),
                     )));
                     some_integrator = Some(integrator);
@@ -378,7 +410,7 @@ impl RenderOptions {
                         println!(" limiting maxdepth to 5");
                         max_depth = 5;
                     }
-                    let pixel_bounds: Bounds2i = camera.get_film().get_sample_bounds();
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
                     let light_strategy: String = self
                         .integrator_params
                         .find_one_string("lightsamplestrategy", String::from("power"));
@@ -387,9 +419,37 @@ impl RenderOptions {
                         sampler,
                         pixel_bounds,
                         max_depth as u32,
+                        visualize_strategies,
+                        visualize_weights,
                         light_strategy,
                     )));
                     some_integrator = Some(integrator);
+                } else if integrator_name == "lighttracer" {
+                    // CreateLightTracerIntegrator
+                    let max_depth: i32 = self.integrator_params.find_one_int("maxdepth", 5);
+                    let light_paths_per_pixel: i32 =
+                        self.integrator_params.find_one_int("lightpathsperpixel", 0);
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
+                    // the light paths get their own sample budget
+                    let mut light_sampler: Box<Sampler> = sampler;
+                    if light_paths_per_pixel > 0 {
+                        let light_sampler_params: ParamSet =
+                            self.get_sampler_params(light_paths_per_pixel as u32);
+                        if let Some(new_sampler) = make_sampler(
+                            &self.sampler_name,
+                            &light_sampler_params,
+                            camera.get_film(),
+                        ) {
+                            light_sampler = new_sampler;
+                        }
+                    }
+                    let integrator = Box::new(Integrator::LightTracer(LightTracerIntegrator::new(
+                        camera,
+                        light_sampler,
+                        pixel_bounds,
+                        max_depth as u32,
+                    )));
+                    some_integrator = Some(integrator);
                 } else if integrator_name == "mlt" {
                     // CreateMLTIntegrator
                     let max_depth: i32 = self.integrator_params.find_one_int("maxdepth", 5);
@@ -406,8 +466,10 @@ impl RenderOptions {
                     let sigma: Float = self
                         .integrator_params
                         .find_one_float("sigma", 0.01 as Float);
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
                     let integrator = Box::new(Integrator::MLT(MLTIntegrator::new(
                         camera,
+                        pixel_bounds,
                         max_depth as u32,
                         n_bootstrap as u32,
                         n_chains as u32,
@@ -418,23 +480,7 @@ impl RenderOptions {
                     some_integrator = Some(integrator);
                 } else if integrator_name == "ao" || integrator_name == "ambientocclusion" {
                     // CreateAOIntegrator
-                    let pb: Vec<i32> = self.integrator_params.find_int("pixelbounds");
-                    let np: usize = pb.len();
-                    let pixel_bounds: Bounds2i = camera.get_film().get_sample_bounds();
-                    if np > 0 as usize {
-                        if np != 4 as usize {
-                            panic!(
-                                "Expected four values for \"pixelbounds\" parameter. Got {}.",
-                                np
-                            );
-                        } else {
-                            println!("TODO: pixelBounds = Intersect(...)");
-                            // pixelBounds = Intersect(pixelBounds,
-                            //                         Bounds2i{{pb[0], pb[2]}, {pb[1], pb[3]}});
-                            // if (pixelBounds.Area() == 0)
-                            //     Error("Degenerate \"pixelbounds\" specified.");
-                        }
-                    }
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
                     let cos_sample: bool = self.integrator_params.find_one_bool("cossample", true);
                     let n_samples: i32 = self.integrator_params.find_one_int("nsamples", 64 as i32);
                     let integrator = Box::new(Integrator::Sampler(SamplerIntegrator::AO(
@@ -459,8 +505,10 @@ impl RenderOptions {
                         .integrator_params
                         .find_one_float("radius", 1.0 as Float);
                     // TODO: if (PbrtOptions.quickRender) nIterations = std::max(1, nIterations / 16);
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
                     let integrator = Box::new(Integrator::SPPM(SPPMIntegrator::new(
                         camera,
+                        pixel_bounds,
                         n_iterations,
                         photons_per_iter,
                         max_depth as u32,
@@ -468,6 +516,62 @@ impl RenderOptions {
                         write_freq,
                     )));
                     some_integrator = Some(integrator);
+                } else if integrator_name == "vcm" {
+                    // CreateVCMIntegrator
+                    let max_depth: i32 = self.integrator_params.find_one_int("maxdepth", 5);
+                    // a non-positive radius is chosen relative to the scene size
+                    let radius: Float = self
+                        .integrator_params
+                        .find_one_float("radius", 0.0 as Float);
+                    let radius_alpha: Float = self
+                        .integrator_params
+                        .find_one_float("radiusalpha", 0.75 as Float);
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
+                    let integrator = Box::new(Integrator::VCM(VCMIntegrator::new(
+                        camera,
+                        sampler,
+                        pixel_bounds,
+                        max_depth as u32,
+                        radius,
+                        radius_alpha,
+                    )));
+                    some_integrator = Some(integrator);
+                } else if integrator_name == "gpt" {
+                    // CreateGPTIntegrator
+                    let max_depth: i32 = self.integrator_params.find_one_int("maxdepth", 5);
+                    let pixel_bounds: Bounds2i = self.get_pixel_bounds(&camera);
+                    let rr_threshold: Float = self
+                        .integrator_params
+                        .find_one_float("rrthreshold", 1.0 as Float);
+                    let light_strategy: String = self
+                        .integrator_params
+                        .find_one_string("lightsamplestrategy", String::from("spatial"));
+                    let reconstruction_name: String = self
+                        .integrator_params
+                        .find_one_string("reconstruction", String::from("L1"));
+                    let reconstruction: Reconstruction = match reconstruction_name.as_str() {
+                        "L1" | "l1" => Reconstruction::L1,
+                        "L2" | "l2" => Reconstruction::L2,
+                        _ => {
+                            println!(
+                                "WARNING: Reconstruction \"{}\" unknown. Using \"L1\".",
+                                reconstruction_name
+                            );
+                            Reconstruction::L1
+                        }
+                    };
+                    let alpha: Float = self.integrator_params.find_one_float("alpha", 0.2 as Float);
+                    let integrator = Box::new(Integrator::GPT(GPTIntegrator::new(
+                        camera,
+                        sampler,
+                        pixel_bounds,
+                        max_depth as u32,
+                        rr_threshold,
+                        light_strategy,
+                        reconstruction,
+                        alpha,
+                    )));
+                    some_integrator = Some(integrator);
                 } else {
                     println!("Integrator \"{}\" unknown.", integrator_name);
                 }
@@ -479,6 +583,64 @@ impl RenderOptions {
         }
         some_integrator
     }
+    /// Copies all bool and integer sampler values, but overrides
+    /// pixelsamples.
+    fn get_sampler_params(&self, pixelsamples: u32) -> ParamSet {
+        let mut new_sampler_params: ParamSet = ParamSet::default();
+        new_sampler_params.key_word = self.sampler_params.key_word.clone();
+        new_sampler_params.name = self.sampler_params.name.clone();
+        new_sampler_params.tex_type = self.sampler_params.tex_type.clone();
+        new_sampler_params.tex_name = self.sampler_params.tex_name.clone();
+        for b in &self.sampler_params.bools {
+            new_sampler_params.add_bool(b.name.clone(), b.values[0]);
+        }
+        let mut found: bool = false;
+        for i in &self.sampler_params.ints {
+            if i.name == "pixelsamples" {
+                new_sampler_params.add_int(i.name.clone(), pixelsamples as i32);
+                found = true;
+            } else {
+                new_sampler_params.add_int(i.name.clone(), i.values[0]);
+            }
+        }
+        if !found {
+            new_sampler_params.add_int(String::from("pixelsamples"), pixelsamples as i32);
+        }
+        new_sampler_params
+    }
+    /// Restricts the film's sample bounds to the integrator's
+    /// optional "pixelbounds" parameter (xmin xmax ymin ymax). Invalid
+    /// or degenerate bounds are reported and the full sample bounds are
+    /// used instead.
+    fn get_pixel_bounds(&self, camera: &Arc<Camera>) -> Bounds2i {
+        let sample_bounds: Bounds2i = camera.get_film().get_sample_bounds();
+        let pb: Vec<i32> = self.integrator_params.find_int("pixelbounds");
+        let np: usize = pb.len();
+        if np > 0 as usize {
+            if np != 4 as usize {
+                println!(
+                    "ERROR: Expected four values for \"pixelbounds\" parameter. Got {}.",
+                    np
+                );
+                return sample_bounds;
+            }
+            let pixel_bounds: Bounds2i = bnd2_intersect_bnd2i(
+                &sample_bounds,
+                &Bounds2i::new(
+                    Point2i { x: pb[0], y: pb[2] },
+                    Point2i { x: pb[1], y: pb[3] },
+                ),
+            );
+            if pixel_bounds.p_max.x <= pixel_bounds.p_min.x
+                || pixel_bounds.p_max.y <= pixel_bounds.p_min.y
+            {
+                println!("ERROR: Degenerate \"pixelbounds\" specified. Rendering the full image.");
+                return sample_bounds;
+            }
+            return pixel_bounds;
+        }
+        sample_bounds
+    }
     pub fn make_scene(&self) -> Scene {
         let some_accelerator = make_accelerator(
             &self.accelerator_name,
@@ -495,19 +657,30 @@ impl RenderOptions {
         let mut some_camera: Option<Arc<Camera>> = None;
         let some_filter = make_filter(&self.filter_name, &self.filter_params);
         if let Some(filter) = some_filter {
+            let mut film_params: ParamSet = ParamSet::default();
+            film_params.copy_from(&self.film_params);
+            for f in &self.film_overrides.floats {
+                film_params.erase_float(f.name.clone());
+                film_params.add_float(f.name.clone(), f.values[0]);
+            }
+            for s in &self.film_overrides.strings {
+                film_params.erase_string(s.name.clone());
+                film_params.add_string(s.name.clone(), s.values[0].clone());
+            }
             let some_film: Option<Arc<Film>> = make_film(
                 &self.film_name,
-                &self.film_params,
+                &film_params,
                 filter,
                 &self.crop_window,
+                self.color_space,
             );
             if let Some(film) = some_film {
-                let animated_cam_to_world: AnimatedTransform = AnimatedTransform::new(
-                    &self.camera_to_world.t[0],
-                    self.transform_start_time,
-                    &self.camera_to_world.t[1],
-                    self.transform_end_time,
-                );
+                let animated_cam_to_world: AnimatedTransform =
+                    self.camera_to_world.animated_transform(
+                        self.transform_start_time,
+                        self.transform_end_time,
+                        &self.transform_key_times,
+                    );
                 let clipping_start: Float = 0.0; // ADDED
                 some_camera = make_camera(
                     &self.camera_name,
@@ -527,6 +700,7 @@ impl Default for RenderOptions {
         RenderOptions {
             transform_start_time: 0.0 as Float,
             transform_end_time: 1.0 as Float,
+            transform_key_times: Vec::new(),
             filter_name: String::from("box"),
             filter_params: ParamSet::default(),
             film_name: String::from("image"),
@@ -557,7 +731,8 @@ impl Default for RenderOptions {
                             [0.0, 0.0, 0.0, 1.0],
                         ],
                     },
-                }; 2],
+                }; MAX_TRANSFORMS],
+                keyed: 0_u8,
             },
             named_media: HashMap::new(),
             lights: Vec::new(),
@@ -569,6 +744,8 @@ impl Default for RenderOptions {
                 p_min: Point2f { x: 0.0, y: 0.0 },
                 p_max: Point2f { x: 1.0, y: 1.0 },
             },
+            film_overrides: ParamSet::default(),
+            color_space: RGBColorSpace::SRGB,
         }
     }
 }
@@ -579,6 +756,8 @@ pub struct GraphicsState {
     pub current_outside_medium: String,
     pub float_textures: Arc<HashMap<String, Arc<dyn Texture<Float> + Send + Sync>>>,
     pub spectrum_textures: Arc<HashMap<String, Arc<dyn Texture<Spectrum> + Send + Sync>>>,
+    /// color converted image textures (by name), see create_material()
+    pub image_textures: Arc<HashMap<String, Arc<ImageTextureSource>>>,
     pub material_params: ParamSet,
     pub material: String,
     pub named_materials: Arc<HashMap<String, Option<Arc<Material>>>>,
@@ -610,6 +789,7 @@ impl GraphicsState {
             current_outside_medium: String::from(""),
             float_textures: float_textures.clone(),
             spectrum_textures: spectrum_textures.clone(),
+            image_textures: Arc::new(HashMap::new()),
             material_params: ParamSet::default(),
             material: String::from(""),
             named_materials,
@@ -649,6 +829,11 @@ fn create_material(api_state: &ApiState, bsdf_state: &mut BsdfState) -> Option<A
         geom_params: ParamSet::default(),
         material_params,
     };
+    // a normal map holds vectors, so use its texels without conversion
+    let normal_map: String = mp.material_params.find_texture("normalmap");
+    if let Some(source) = api_state.graphics_state.image_textures.get(&normal_map) {
+        Arc::make_mut(&mut mp.spectrum_textures).insert(normal_map, source.raw_texture());
+    }
     if api_state.graphics_state.current_material != String::new() {
         match api_state
             .graphics_state
@@ -710,7 +895,10 @@ fn create_material(api_state: &ApiState, bsdf_state: &mut BsdfState) -> Option<A
             }
             return None;
         } else if api_state.graphics_state.material == "metal" {
-            return Some(MetalMaterial::create(&mut mp));
+            return Some(MetalMaterial::create(
+                &mut mp,
+                api_state.render_options.color_space,
+            ));
         } else if api_state.graphics_state.material == "substrate" {
             return Some(SubstrateMaterial::create(&mut mp));
         } else if api_state.graphics_state.material == "uber" {
@@ -733,7 +921,7 @@ fn create_material(api_state: &ApiState, bsdf_state: &mut BsdfState) -> Option<A
     let kd = Arc::new(ConstantTexture::new(Spectrum::new(0.5)));
     let sigma = Arc::new(ConstantTexture::new(0.0 as Float));
     Some(Arc::new(Material::Matte(Box::new(MatteMaterial::new(
-        kd, sigma, None,
+        kd, sigma, None, None,
     )))))
 }
 
@@ -866,6 +1054,7 @@ fn make_light(api_state: &mut ApiState, medium_interface: &MediumInterface) {
                 medium_interface,
                 &(i * sc),
                 texname,
+                api_state.render_options.color_space,
             ),
         )));
         api_state.render_options.lights.push(projection_light);
@@ -887,6 +1076,7 @@ fn make_light(api_state: &mut ApiState, medium_interface: &MediumInterface) {
             &(i * sc),
             texname,
             fov,
+            api_state.render_options.color_space,
         ))));
         api_state.render_options.lights.push(projection_light);
     } else if api_state.param_set.name == "distant" {
@@ -949,6 +1139,7 @@ fn make_light(api_state: &mut ApiState, medium_interface: &MediumInterface) {
             &(l * sc),
             n_samples,
             texmap,
+            api_state.render_options.color_space,
         ))));
         api_state.render_options.lights.push(infinte_light);
     } else {
@@ -978,10 +1169,28 @@ fn make_medium(api_state: &mut ApiState) {
     let g: Float = api_state.param_set.find_one_float("g", 0.0 as Float);
     sig_a = api_state.param_set.find_one_spectrum("sigma_a", sig_a) * scale;
     sig_s = api_state.param_set.find_one_spectrum("sigma_s", sig_s) * scale;
+    // emission, either constant or given by a temperature grid
+    let le: Spectrum = api_state
+        .param_set
+        .find_one_spectrum("Le", Spectrum::new(0.0 as Float));
+    let le_scale: Float = api_state.param_set.find_one_float("Lescale", 1.0 as Float);
+    let temperature_offset: Float = api_state
+        .param_set
+        .find_one_float("temperatureoffset", 0.0 as Float);
+    let temperature_scale: Float = api_state
+        .param_set
+        .find_one_float("temperaturescale", 1.0 as Float);
+    let emission: MediumEmission = MediumEmission::new(
+        &le,
+        le_scale,
+        temperature_offset,
+        temperature_scale,
+        api_state.render_options.color_space,
+    );
     let some_medium: Option<Arc<Medium>>;
     if medium_type == "homogeneous" {
         some_medium = Some(Arc::new(Medium::Homogeneous(HomogeneousMedium::new(
-            &sig_a, &sig_s, g,
+            &sig_a, &sig_s, g, emission,
         ))));
     } else if medium_type == "heterogeneous" {
         let data: Arc<Vec<Float>> = Arc::new(api_state.param_set.find_float("density"));
@@ -1016,6 +1225,19 @@ fn make_medium(api_state: &mut ApiState) {
                 );
                 some_medium = None;
             } else {
+                let temperature: Vec<Float> = api_state.param_set.find_float("temperature");
+                let temperature: Option<Arc<Vec<Float>>> = if temperature.is_empty() {
+                    None
+                } else if temperature.len() != (nx * ny * nz) as usize {
+                    println!(
+                        "ERROR: GridDensityMedium has {} temperature values; expected nx*ny*nz = {}",
+                        temperature.len(),
+                        nx * ny * nz
+                    );
+                    None
+                } else {
+                    Some(Arc::new(temperature))
+                };
                 let data_2_medium: Transform = Transform::translate(&Vector3f::from(p0))
                     * Transform::scale(p1.x - p0.x, p1.y - p0.y, p1.z - p0.z);
                 let medium_2_world = api_state.cur_transform.t[0];
@@ -1028,9 +1250,78 @@ fn make_medium(api_state: &mut ApiState) {
                     nz,
                     &(medium_2_world * data_2_medium),
                     data,
+                    temperature,
+                    emission,
                 ))));
             }
         }
+    } else if medium_type == "sparsegrid" {
+        let mut filename: String = api_state
+            .param_set
+            .find_one_filename("filename", String::new());
+        if filename.is_empty() {
+            println!("ERROR: No \"filename\" provided for sparsegrid medium?");
+            some_medium = None;
+        } else {
+            if let Some(ref search_directory) = api_state.search_directory {
+                let mut path_buf: PathBuf = PathBuf::from("/");
+                path_buf.push(search_directory.as_ref());
+                path_buf.push(filename);
+                filename = String::from(path_buf.to_str().unwrap());
+            }
+            let density_name: String = api_state
+                .param_set
+                .find_one_string("densitychannel", String::from("density"));
+            let temperature_name: String = api_state
+                .param_set
+                .find_one_string("temperaturechannel", String::from("temperature"));
+            let majorant_res: i32 = api_state.param_set.find_one_int("majorantres", 16_i32);
+            let p0: Point3f = api_state.param_set.find_one_point3f(
+                "p0",
+                Point3f {
+                    x: 0.0 as Float,
+                    y: 0.0 as Float,
+                    z: 0.0 as Float,
+                },
+            );
+            let p1: Point3f = api_state.param_set.find_one_point3f(
+                "p1",
+                Point3f {
+                    x: 1.0 as Float,
+                    y: 1.0 as Float,
+                    z: 1.0 as Float,
+                },
+            );
+            some_medium = match read_brick_grids(&filename) {
+                Some(mut grids) => match grids.remove(&density_name) {
+                    Some(density) => {
+                        let temperature: Option<Arc<BrickGrid>> =
+                            grids.remove(&temperature_name).map(Arc::new);
+                        let data_2_medium: Transform = Transform::translate(&Vector3f::from(p0))
+                            * Transform::scale(p1.x - p0.x, p1.y - p0.y, p1.z - p0.z);
+                        let medium_2_world = api_state.cur_transform.t[0];
+                        Some(Arc::new(Medium::SparseGrid(SparseGridMedium::new(
+                            &sig_a,
+                            &sig_s,
+                            g,
+                            &(medium_2_world * data_2_medium),
+                            Arc::new(density),
+                            temperature,
+                            majorant_res,
+                            emission,
+                        ))))
+                    }
+                    None => {
+                        println!(
+                            "ERROR: No {:?} channel found in sparse grid file {:?}",
+                            density_name, filename
+                        );
+                        None
+                    }
+                },
+                None => None,
+            };
+        }
     } else {
         panic!("MakeMedium: unknown name {}", medium_type);
     }
@@ -1042,6 +1333,31 @@ fn make_medium(api_state: &mut ApiState) {
     }
 }
 
+/// The color space of an image texture is given by the
+/// **colorspace** parameter. Without it the older **gamma** parameter
+/// decides between sRGB encoded and linear texels (with 8-bit formats
+/// like PNG or TGA defaulting to sRGB).
+fn get_texture_color_space(tp: &mut TextureParams, filename: &str) -> TextureColorSpace {
+    let gamma: bool = tp.find_bool("gamma", is_srgb_encoded(filename));
+    let default: TextureColorSpace = if gamma {
+        TextureColorSpace::SRGB
+    } else {
+        TextureColorSpace::Linear
+    };
+    let name: String = tp.find_string("colorspace", String::new());
+    if name.is_empty() {
+        default
+    } else if let Some(colorspace) = TextureColorSpace::from_name(&name) {
+        colorspace
+    } else {
+        println!(
+            "WARNING: Texture color space \"{}\" unknown. Using {:?}.",
+            name, default
+        );
+        default
+    }
+}
+
 fn make_texture(api_state: &mut ApiState) {
     // pbrtTexture (api.cpp:1049)
     let mut geom_params: ParamSet = ParamSet::default();
@@ -1155,12 +1471,7 @@ fn make_texture(api_state: &mut ApiState) {
                 path_buf.push(filename);
                 filename = String::from(path_buf.to_str().unwrap());
             }
-            // TODO: default depends on:
-            // HasExtension(filename,
-            // ".tga") ||
-            // HasExtension(filename,
-            // ".png"));
-            let gamma: bool = tp.find_bool("gamma", true);
+            let colorspace: TextureColorSpace = get_texture_color_space(&mut tp, &filename);
 
             if let Some(mapping) = map {
                 let ft = Arc::new(ImageTexture::new(
@@ -1170,7 +1481,9 @@ fn make_texture(api_state: &mut ApiState) {
                     max_aniso,
                     wrap_mode,
                     scale,
-                    gamma,
+                    colorspace,
+                    // luminance is measured in sRGB (see Spectrum::y())
+                    RGBColorSpace::SRGB,
                     convert_to_float,
                 ));
                 Arc::make_mut(&mut api_state.graphics_state.float_textures)
@@ -1297,6 +1610,14 @@ fn make_texture(api_state: &mut ApiState) {
         {
             println!("Texture \"{}\" being redefined", api_state.param_set.name);
         }
+        if api_state
+            .graphics_state
+            .image_textures
+            .contains_key(api_state.param_set.name.as_str())
+        {
+            Arc::make_mut(&mut api_state.graphics_state.image_textures)
+                .remove(api_state.param_set.name.as_str());
+        }
         // TODO: WARN_IF_ANIMATED_TRANSFORM("Texture");
         // MakeSpectrumTexture(texname, curTransform[0], tp);
         if api_state.param_set.tex_name == "constant" {
@@ -1391,26 +1712,37 @@ fn make_texture(api_state: &mut ApiState) {
                 path_buf.push(filename);
                 filename = String::from(path_buf.to_str().unwrap());
             }
-            // TODO: default depends on:
-            // HasExtension(filename,
-            // ".tga") ||
-            // HasExtension(filename,
-            // ".png"));
-            let gamma: bool = tp.find_bool("gamma", true);
+            let colorspace: TextureColorSpace = get_texture_color_space(&mut tp, &filename);
 
             if let Some(mapping) = map {
-                let st = Arc::new(ImageTexture::new(
-                    mapping,
-                    filename,
+                let (texels, res) = read_texels(&filename);
+                let st = Arc::new(ImageTexture::from_texels(
+                    mapping.clone(),
+                    &texels,
+                    res,
                     do_trilinear,
                     max_aniso,
-                    wrap_mode,
+                    wrap_mode.clone(),
                     scale,
-                    gamma,
+                    colorspace,
+                    api_state.render_options.color_space,
                     convert_to_spectrum,
                 ));
                 Arc::make_mut(&mut api_state.graphics_state.spectrum_textures)
                     .insert(api_state.param_set.name.clone(), st);
+                if colorspace != TextureColorSpace::Raw {
+                    // keep the texels in case the texture is used as normal map
+                    let source = Arc::new(ImageTextureSource::new(
+                        &mapping,
+                        texels,
+                        res,
+                        do_trilinear,
+                        max_aniso,
+                        wrap_mode,
+                    ));
+                    Arc::make_mut(&mut api_state.graphics_state.image_textures)
+                        .insert(api_state.param_set.name.clone(), source);
+                }
             }
         } else if api_state.param_set.tex_name == "uv" {
             println!("TODO: CreateUVSpectrumTexture");
@@ -1617,6 +1949,22 @@ pub fn make_accelerator(
     accelerator_params: &ParamSet,
 ) -> Option<Arc<Primitive>> {
     let mut some_accelerator: Option<Arc<Primitive>> = None;
+    // optionally validate BVH against kd-tree before building the accelerator
+    let check_rays: i32 = accelerator_params.find_one_int("checkrays", 0);
+    if check_rays > 0 {
+        let report: ConsistencyReport =
+            check_accelerators(primitives, check_rays as usize, 1e-5 as Float);
+        println!(
+            "Accelerator check: {} rays, {} hits, {} mismatches ({} hit, {} distance, {} primitive, {} shadow)",
+            report.n_rays,
+            report.n_hits,
+            report.n_mismatches(),
+            report.hit_mismatches,
+            report.distance_mismatches,
+            report.primitive_mismatches,
+            report.shadow_mismatches
+        );
+    }
     if accelerator_name == "bvh" {
         // CreateBVHAccelerator
         some_accelerator = Some(Arc::new(BVHAccel::create(
@@ -1629,6 +1977,11 @@ pub fn make_accelerator(
             primitives.to_owned(),
             accelerator_params,
         )));
+    } else if accelerator_name == "widebvh" {
+        some_accelerator = Some(Arc::new(WideBVHAccel::create(
+            primitives.to_owned(),
+            accelerator_params,
+        )));
     }
     some_accelerator
 }
@@ -1750,9 +2103,10 @@ pub fn make_film(
     param_set: &ParamSet,
     filter: Box<Filter>,
     crop_window: &Bounds2f,
+    color_space: RGBColorSpace,
 ) -> Option<Arc<Film>> {
     if name == "image" {
-        Some(Film::create(param_set, filter, crop_window))
+        Some(Film::create(param_set, filter, crop_window, color_space))
     } else {
         println!("Film \"{}\" unknown.", name);
         None
@@ -1980,7 +2334,7 @@ fn get_shapes_and_materials(
         for item in &vi {
             vertex_indices.push(*item as u32);
         }
-        let mesh = Arc::new(TriangleMesh::new(
+        let mut mesh = TriangleMesh::new(
             obj_to_world,
             world_to_obj,
             api_state.graphics_state.reverse_orientation,
@@ -1993,7 +2347,60 @@ fn get_shapes_and_materials(
             uvs,
             alpha_tex,
             shadow_alpha_tex,
-        ));
+        );
+        // vertex motion: positions at the end of the shutter
+        // interval, given directly or as per-vertex velocities
+        let time_start: Float = api_state.render_options.transform_start_time;
+        let time_end: Float = api_state.render_options.transform_end_time;
+        let p1 = api_state.param_set.find_point3f("P1");
+        let velocity = api_state.param_set.find_vector3f("velocity");
+        let mut p_end: Vec<Point3f> = Vec::new();
+        if (!p1.is_empty() || !velocity.is_empty())
+            && !api_state.graphics_state.area_light.is_empty()
+        {
+            // light sampling uses the vertex positions at the start
+            println!(
+                "WARNING: vertex motion is not supported for area lights; ignoring \"P1\" and \"velocity\""
+            );
+        } else if !p1.is_empty() {
+            if !velocity.is_empty() {
+                println!(
+                    "WARNING: trianglemesh has both \"P1\" and \"velocity\"; ignoring \"velocity\""
+                );
+            }
+            if p1.len() == p.len() {
+                p_end = p1
+                    .iter()
+                    .map(|item| obj_to_world.transform_point(item))
+                    .collect();
+            } else {
+                println!(
+                    "WARNING: {} \"P1\" values provided for {} vertices; ignoring \"P1\"",
+                    p1.len(),
+                    p.len()
+                );
+            }
+        } else if !velocity.is_empty() {
+            if velocity.len() != p.len() {
+                println!(
+                    "WARNING: {} \"velocity\" values provided for {} vertices; ignoring \"velocity\"",
+                    velocity.len(),
+                    p.len()
+                );
+            } else {
+                p_end = p
+                    .iter()
+                    .zip(velocity.iter())
+                    .map(|(item, v)| {
+                        obj_to_world.transform_point(&(*item + *v * (time_end - time_start)))
+                    })
+                    .collect();
+            }
+        }
+        if !p_end.is_empty() {
+            mesh.set_vertex_motion(p_end, time_start, time_end);
+        }
+        let mesh = displace_mesh(api_state, Arc::new(mesh));
         let mtl: Option<Arc<Material>> = create_material(&api_state, bsdf_state);
         for id in 0..mesh.n_triangles {
             let triangle = Arc::new(Shape::Trngl(Triangle::new(
@@ -2014,6 +2421,15 @@ fn get_shapes_and_materials(
                 api_state.graphics_state.float_textures.clone(),
                 // additional parameters:
                 Some(search_directory),
+                // no vertex motion for area lights
+                if api_state.graphics_state.area_light.is_empty() {
+                    Some((
+                        api_state.render_options.transform_start_time,
+                        api_state.render_options.transform_end_time,
+                    ))
+                } else {
+                    None
+                },
             );
             for shape in ply_shapes {
                 shapes.push(shape.clone());
@@ -2050,6 +2466,7 @@ fn get_shapes_and_materials(
             &vertex_indices,
             &p,
         );
+        let mesh = displace_mesh(api_state, mesh);
         let mtl: Option<Arc<Material>> = create_material(&api_state, bsdf_state);
         for id in 0..mesh.n_triangles {
             let triangle = Arc::new(Shape::Trngl(Triangle::new(
@@ -2129,21 +2546,6 @@ fn get_shapes_and_materials(
                 npts
             );
         }
-        // compute NURBS dicing rates
-        let diceu: usize = 30;
-        let dicev: usize = 30;
-        let mut ueval: Vec<Float> = Vec::with_capacity(diceu);
-        let mut veval: Vec<Float> = Vec::with_capacity(dicev);
-        let mut eval_ps: Vec<Point3f> = Vec::with_capacity(diceu * dicev);
-        let mut eval_ns: Vec<Normal3f> = Vec::with_capacity(diceu * dicev);
-        for i in 0..diceu {
-            ueval.push(lerp(i as Float / (diceu - 1) as Float, u0, u1));
-        }
-        for i in 0..dicev {
-            veval.push(lerp(i as Float / (dicev - 1) as Float, v0, v1));
-        }
-        // evaluate NURBS over grid of points
-        let mut uvs: Vec<Point2f> = Vec::with_capacity(diceu * dicev);
         // turn NURBS into triangles
         let mut hom3: Vec<Homogeneous3> = Vec::with_capacity((nu * nv) as usize);
         if is_homogeneous {
@@ -2165,6 +2567,70 @@ fn get_shapes_and_materials(
                 });
             }
         }
+        // compute NURBS dicing rates
+        let max_error: Float = api_state.param_set.find_one_float("maxerror", 0.0 as Float);
+        let ueval: Vec<Float>;
+        let veval: Vec<Float>;
+        if max_error > 0.0 as Float {
+            // curvature-driven: refine the knot spans until the world
+            // space chord error drops below "maxerror" (or the grid
+            // would exceed "maxvertices"); each direction gets checked
+            // along the iso-lines of the other one, until both stop
+            // changing
+            let max_vertices: i32 = api_state
+                .param_set
+                .find_one_int("maxvertices", 1_000_000_i32);
+            let max_segments: usize = ((max_vertices.max(4) as f64).sqrt() as usize) - 1;
+            let eval = |u: Float, v: Float| -> Point3f {
+                obj_to_world.transform_point(&nurbs_evaluate_surface(
+                    uorder, &uknots, nu, u, vorder, &vknots, nv, v, &hom3, None, None,
+                ))
+            };
+            let n_cross: usize = 9;
+            let mut u_dice: Vec<Float> = (0..n_cross)
+                .map(|i| lerp(i as Float / (n_cross - 1) as Float, u0, u1))
+                .collect();
+            let mut v_dice: Vec<Float> =
+                nurbs_adaptive_dicing(&vknots, v0, v1, &u_dice, max_error, max_segments, |v, u| {
+                    eval(u, v)
+                });
+            for _ in 0..4 {
+                let u_next: Vec<Float> =
+                    nurbs_adaptive_dicing(&uknots, u0, u1, &v_dice, max_error, max_segments, eval);
+                let v_next: Vec<Float> = nurbs_adaptive_dicing(
+                    &vknots,
+                    v0,
+                    v1,
+                    &u_next,
+                    max_error,
+                    max_segments,
+                    |v, u| eval(u, v),
+                );
+                let converged: bool = u_next == u_dice && v_next == v_dice;
+                u_dice = u_next;
+                v_dice = v_next;
+                if converged {
+                    break;
+                }
+            }
+            ueval = u_dice;
+            veval = v_dice;
+        } else {
+            let diceu: usize = 30;
+            let dicev: usize = 30;
+            ueval = (0..diceu)
+                .map(|i| lerp(i as Float / (diceu - 1) as Float, u0, u1))
+                .collect();
+            veval = (0..dicev)
+                .map(|i| lerp(i as Float / (dicev - 1) as Float, v0, v1))
+                .collect();
+        }
+        let diceu: usize = ueval.len();
+        let dicev: usize = veval.len();
+        let mut eval_ps: Vec<Point3f> = Vec::with_capacity(diceu * dicev);
+        let mut eval_ns: Vec<Normal3f> = Vec::with_capacity(diceu * dicev);
+        // evaluate NURBS over grid of points
+        let mut uvs: Vec<Point2f> = Vec::with_capacity(diceu * dicev);
         for veval_item in veval.iter().take(dicev) {
             for ueval_item in ueval.iter().take(diceu) {
                 uvs.push(Point2f {
@@ -2249,6 +2715,16 @@ fn get_shapes_and_materials(
     (shapes, materials)
 }
 
+/// Tessellate and displace a triangle mesh if the shape has a
+/// "displacement" float texture.
+fn displace_mesh(api_state: &ApiState, mesh: Arc<TriangleMesh>) -> Arc<TriangleMesh> {
+    displace_mesh_from_params(
+        mesh,
+        &api_state.param_set,
+        &*api_state.graphics_state.float_textures,
+    )
+}
+
 fn print_params(params: &ParamSet) {
     for p in &params.strings {
         if p.n_values == 1_usize {
@@ -2346,6 +2822,7 @@ pub fn pbrt_init(
     cropx1: f32,
     cropy0: f32,
     cropy1: f32,
+    film_overrides: ParamSet,
 ) -> (ApiState, BsdfState) {
     let mut api_state: ApiState = ApiState::default();
     let bsdf_state: BsdfState = BsdfState::default();
@@ -2361,6 +2838,7 @@ pub fn pbrt_init(
             y: clamp_t(cropy1.max(cropy0), 0.0, 1.0),
         },
     };
+    api_state.render_options.film_overrides = film_overrides;
     (api_state, bsdf_state)
 }
 
@@ -2394,38 +2872,17 @@ pub fn pbrt_translate(api_state: &mut ApiState, dx: Float, dy: Float, dz: Float)
         y: dy,
         z: dz,
     });
-    if api_state.active_transform_bits & 1_u8 > 0_u8 {
-        // 0x?1
-        api_state.cur_transform.t[0] = api_state.cur_transform.t[0] * translate;
-    }
-    if api_state.active_transform_bits & 2_u8 > 0_u8 {
-        // 0x1?
-        api_state.cur_transform.t[1] = api_state.cur_transform.t[1] * translate;
-    }
+    update_active_transforms(api_state, |t| *t * translate);
 }
 
 pub fn pbrt_transform(api_state: &mut ApiState, tr: &Transform) {
     // println!("{:?}", tr);
-    if api_state.active_transform_bits & 1_u8 > 0_u8 {
-        // 0x?1
-        api_state.cur_transform.t[0] = *tr;
-    }
-    if api_state.active_transform_bits & 2_u8 > 0_u8 {
-        // 0x1?
-        api_state.cur_transform.t[1] = *tr;
-    }
+    update_active_transforms(api_state, |_t| *tr);
 }
 
 pub fn pbrt_concat_transform(api_state: &mut ApiState, tr: &Transform) {
     // println!("Concat{:?}", tr);
-    if api_state.active_transform_bits & 1_u8 > 0_u8 {
-        // 0x?1
-        api_state.cur_transform.t[0] = api_state.cur_transform.t[0] * *tr;
-    }
-    if api_state.active_transform_bits & 2_u8 > 0_u8 {
-        // 0x1?
-        api_state.cur_transform.t[1] = api_state.cur_transform.t[1] * *tr;
-    }
+    update_active_transforms(api_state, |t| *t * *tr);
 }
 
 pub fn pbrt_rotate(api_state: &mut ApiState, angle: Float, dx: Float, dy: Float, dz: Float) {
@@ -2438,27 +2895,13 @@ pub fn pbrt_rotate(api_state: &mut ApiState, angle: Float, dx: Float, dy: Float,
             z: dz,
         },
     );
-    if api_state.active_transform_bits & 1_u8 > 0_u8 {
-        // 0x?1
-        api_state.cur_transform.t[0] = api_state.cur_transform.t[0] * rotate;
-    }
-    if api_state.active_transform_bits & 2_u8 > 0_u8 {
-        // 0x1?
-        api_state.cur_transform.t[1] = api_state.cur_transform.t[1] * rotate;
-    }
+    update_active_transforms(api_state, |t| *t * rotate);
 }
 
 pub fn pbrt_scale(api_state: &mut ApiState, sx: Float, sy: Float, sz: Float) {
     // println!("Scale {} {} {}", sx, sy, sz);
     let scale: Transform = Transform::scale(sx, sy, sz);
-    if api_state.active_transform_bits & 1_u8 > 0_u8 {
-        // 0x?1
-        api_state.cur_transform.t[0] = api_state.cur_transform.t[0] * scale;
-    }
-    if api_state.active_transform_bits & 2_u8 > 0_u8 {
-        // 0x1?
-        api_state.cur_transform.t[1] = api_state.cur_transform.t[1] * scale;
-    }
+    update_active_transforms(api_state, |t| *t * scale);
 }
 
 pub fn pbrt_look_at(
@@ -2493,14 +2936,7 @@ pub fn pbrt_look_at(
         z: uz,
     };
     let look_at: Transform = Transform::look_at(&pos, &look, &up);
-    if api_state.active_transform_bits & 1_u8 > 0_u8 {
-        // 0x?1
-        api_state.cur_transform.t[0] = api_state.cur_transform.t[0] * look_at;
-    }
-    if api_state.active_transform_bits & 2_u8 > 0_u8 {
-        // 0x1?
-        api_state.cur_transform.t[1] = api_state.cur_transform.t[1] * look_at;
-    }
+    update_active_transforms(api_state, |t| *t * look_at);
 }
 
 pub fn pbrt_coord_sys_transform(api_state: &mut ApiState, params: ParamSet) {
@@ -2511,8 +2947,7 @@ pub fn pbrt_coord_sys_transform(api_state: &mut ApiState, params: ParamSet) {
         .get(api_state.param_set.name.as_str())
     {
         Some(transform_set) => {
-            api_state.cur_transform.t[0] = transform_set.t[0];
-            api_state.cur_transform.t[1] = transform_set.t[1];
+            api_state.cur_transform = *transform_set;
         }
         None => {
             println!(
@@ -2525,7 +2960,7 @@ pub fn pbrt_coord_sys_transform(api_state: &mut ApiState, params: ParamSet) {
 
 pub fn pbrt_active_transform_all(api_state: &mut ApiState) {
     // println!("ActiveTransform All");
-    api_state.active_transform_bits = 3_u8 // 0x11
+    api_state.active_transform_bits = ALL_TRANSFORMS_BITS
 }
 
 pub fn pbrt_active_transform_end_time(api_state: &mut ApiState) {
@@ -2538,6 +2973,45 @@ pub fn pbrt_active_transform_start_time(api_state: &mut ApiState) {
     api_state.active_transform_bits = 1_u8 // 0x01
 }
 
+/// Selects an intermediate transformation key at *time* (creating it
+/// if necessary) for the following transformation statements.
+pub fn pbrt_active_transform_time(api_state: &mut ApiState, time: Float) {
+    // println!("ActiveTransform Time {}", time);
+    let start_time: Float = api_state.render_options.transform_start_time;
+    let end_time: Float = api_state.render_options.transform_end_time;
+    if time == start_time {
+        api_state.active_transform_bits = 1_u8; // same as StartTime
+        return;
+    }
+    if time == end_time {
+        api_state.active_transform_bits = 2_u8; // same as EndTime
+        return;
+    }
+    if time < start_time || time > end_time {
+        println!(
+            "WARNING: ActiveTransform Time {} is outside of TransformTimes {} {}",
+            time, start_time, end_time
+        );
+    }
+    let key_times: &mut Vec<Float> = &mut api_state.render_options.transform_key_times;
+    let index: usize = match key_times.iter().position(|key_time| *key_time == time) {
+        Some(index) => index,
+        None => {
+            if key_times.len() + 2 >= MAX_TRANSFORMS {
+                println!(
+                    "WARNING: Only {} transformation keys are supported, ignoring ActiveTransform Time {}",
+                    MAX_TRANSFORMS, time
+                );
+                api_state.active_transform_bits = 0_u8;
+                return;
+            }
+            key_times.push(time);
+            key_times.len() - 1
+        }
+    };
+    api_state.active_transform_bits = 1_u8 << (index + 2);
+}
+
 pub fn pbrt_transform_times(api_state: &mut ApiState, start: Float, end: Float) {
     println!("TransformTimes {} {}", start, end);
     api_state.render_options.transform_start_time = start;
@@ -2604,19 +3078,10 @@ pub fn pbrt_camera(api_state: &mut ApiState, params: ParamSet) {
     // print_params(&params);
     api_state.render_options.camera_name = params.name.clone();
     api_state.param_set = params;
-    api_state.render_options.camera_to_world.t[0] =
-        Transform::inverse(&api_state.cur_transform.t[0]);
-    api_state.render_options.camera_to_world.t[1] =
-        Transform::inverse(&api_state.cur_transform.t[1]);
-    api_state.named_coordinate_systems.insert(
-        "camera",
-        TransformSet {
-            t: [
-                api_state.render_options.camera_to_world.t[0],
-                api_state.render_options.camera_to_world.t[1],
-            ],
-        },
-    );
+    api_state.render_options.camera_to_world = api_state.cur_transform.inverse();
+    api_state
+        .named_coordinate_systems
+        .insert("camera", api_state.render_options.camera_to_world);
     api_state
         .render_options
         .camera_params
@@ -2637,17 +3102,27 @@ pub fn pbrt_medium_interface(api_state: &mut ApiState, inside_name: &str, outsid
     api_state.render_options.have_scattering_media = true;
 }
 
+pub fn pbrt_color_space(api_state: &mut ApiState, params: ParamSet) {
+    println!("ColorSpace \"{}\"", params.name);
+    if let Some(color_space) = RGBColorSpace::from_name(&params.name) {
+        api_state.render_options.color_space = color_space;
+    } else {
+        println!(
+            "WARNING: Color space \"{}\" unknown. Using {:?}.",
+            params.name,
+            RGBColorSpace::SRGB
+        );
+        api_state.render_options.color_space = RGBColorSpace::SRGB;
+    }
+}
+
 pub fn pbrt_world_begin(api_state: &mut ApiState) {
     // println!("WorldBegin");
-    api_state.cur_transform.t[0] = Transform::default();
-    api_state.cur_transform.t[1] = Transform::default();
-    api_state.active_transform_bits = 3_u8; // 0x11
-    api_state.named_coordinate_systems.insert(
-        "world",
-        TransformSet {
-            t: [Transform::default(); 2],
-        },
-    );
+    api_state.cur_transform = TransformSet::default();
+    api_state.active_transform_bits = ALL_TRANSFORMS_BITS;
+    api_state
+        .named_coordinate_systems
+        .insert("world", TransformSet::default());
 }
 
 pub fn pbrt_attribute_begin(api_state: &mut ApiState) {
@@ -2661,6 +3136,7 @@ pub fn pbrt_attribute_begin(api_state: &mut ApiState) {
         current_outside_medium: api_state.graphics_state.current_outside_medium.clone(),
         float_textures: api_state.graphics_state.float_textures.clone(),
         spectrum_textures: api_state.graphics_state.spectrum_textures.clone(),
+        image_textures: api_state.graphics_state.image_textures.clone(),
         material_params: material_param_set,
         material: api_state.graphics_state.material.clone(),
         named_materials: api_state.graphics_state.named_materials.clone(),
@@ -2669,18 +3145,7 @@ pub fn pbrt_attribute_begin(api_state: &mut ApiState) {
         area_light: api_state.graphics_state.area_light.clone(),
         reverse_orientation: api_state.graphics_state.reverse_orientation,
     });
-    api_state.pushed_transforms.push(TransformSet {
-        t: [
-            Transform {
-                m: api_state.cur_transform.t[0].m,
-                m_inv: api_state.cur_transform.t[0].m_inv,
-            },
-            Transform {
-                m: api_state.cur_transform.t[1].m,
-                m_inv: api_state.cur_transform.t[1].m_inv,
-            },
-        ],
-    });
+    api_state.pushed_transforms.push(api_state.cur_transform);
     api_state
         .pushed_active_transform_bits
         .push(api_state.active_transform_bits);
@@ -2693,26 +3158,14 @@ pub fn pbrt_attribute_end(api_state: &mut ApiState) {
     }
     api_state.graphics_state = api_state.pushed_graphics_states.pop().unwrap();
     let popped_transform_set: TransformSet = api_state.pushed_transforms.pop().unwrap();
-    api_state.cur_transform.t[0] = popped_transform_set.t[0];
-    api_state.cur_transform.t[1] = popped_transform_set.t[1];
+    api_state.cur_transform = popped_transform_set;
     let active_transform_bits: u8 = api_state.pushed_active_transform_bits.pop().unwrap();
     api_state.active_transform_bits = active_transform_bits;
 }
 
 pub fn pbrt_transform_begin(api_state: &mut ApiState) {
     // println!("TransformBegin");
-    api_state.pushed_transforms.push(TransformSet {
-        t: [
-            Transform {
-                m: api_state.cur_transform.t[0].m,
-                m_inv: api_state.cur_transform.t[0].m_inv,
-            },
-            Transform {
-                m: api_state.cur_transform.t[1].m,
-                m_inv: api_state.cur_transform.t[1].m_inv,
-            },
-        ],
-    });
+    api_state.pushed_transforms.push(api_state.cur_transform);
     api_state
         .pushed_active_transform_bits
         .push(api_state.active_transform_bits);
@@ -2721,8 +3174,7 @@ pub fn pbrt_transform_begin(api_state: &mut ApiState) {
 pub fn pbrt_transform_end(api_state: &mut ApiState) {
     // println!("TransformEnd");
     let popped_transform_set: TransformSet = api_state.pushed_transforms.pop().unwrap();
-    api_state.cur_transform.t[0] = popped_transform_set.t[0];
-    api_state.cur_transform.t[1] = popped_transform_set.t[1];
+    api_state.cur_transform = popped_transform_set;
     let active_transform_bits: u8 = api_state.pushed_active_transform_bits.pop().unwrap();
     api_state.active_transform_bits = active_transform_bits;
 }
@@ -2872,30 +3324,41 @@ pub fn pbrt_shape(api_state: &mut ApiState, bsdf_state: &mut BsdfState, params:
         assert_eq!(shapes.len(), materials.len());
         // MediumInterface
         let mi: MediumInterface = create_medium_interface(&api_state);
-        for i in 0..shapes.len() {
-            let shape = &shapes[i];
-            let material = &materials[i];
-            let geo_prim = Arc::new(Primitive::Geometric(Box::new(GeometricPrimitive::new(
-                shape.clone(),
-                material.clone(),
-                None,
-                Some(Arc::new(mi.clone())),
-            ))));
-            prims.push(geo_prim.clone());
+        let compressed: Option<Arc<Primitive>> =
+            if api_state.param_set.find_one_bool("compress", false) {
+                create_compressed_mesh(&shapes, &materials, &mi)
+            } else {
+                None
+            };
+        if let Some(mesh_prim) = compressed {
+            prims.push(mesh_prim);
+        } else {
+            for i in 0..shapes.len() {
+                let shape = &shapes[i];
+                let material = &materials[i];
+                let geo_prim = Arc::new(Primitive::Geometric(Box::new(GeometricPrimitive::new(
+                    shape.clone(),
+                    material.clone(),
+                    None,
+                    Some(Arc::new(mi.clone())),
+                ))));
+                prims.push(geo_prim.clone());
+            }
         }
         // animated?
         if api_state.cur_transform.is_animated() {
-            let animated_object_to_world: AnimatedTransform = AnimatedTransform::new(
-                &api_state.cur_transform.t[0],
-                api_state.render_options.transform_start_time,
-                &api_state.cur_transform.t[1],
-                api_state.render_options.transform_end_time,
-            );
+            let animated_object_to_world: AnimatedTransform =
+                api_state.cur_transform.animated_transform(
+                    api_state.render_options.transform_start_time,
+                    api_state.render_options.transform_end_time,
+                    &api_state.render_options.transform_key_times,
+                );
             if prims.len() > 1 {
                 let bvh: Arc<Primitive> = Arc::new(Primitive::BVH(Box::new(BVHAccel::new(
                     prims.clone(),
                     4,
                     SplitMethod::SAH,
+                    12,
                 ))));
                 prims.clear();
                 prims.push(bvh);
@@ -2934,6 +3397,48 @@ pub fn pbrt_shape(api_state: &mut ApiState, bsdf_state: &mut BsdfState, params:
     }
 }
 
+/// Stores the triangles of a single mesh (see the "compress" shape
+/// parameter) as one **MeshPrimitive**. Returns *None* for all other
+/// shapes.
+fn create_compressed_mesh(
+    shapes: &[Arc<Shape>],
+    materials: &[Option<Arc<Material>>],
+    mi: &MediumInterface,
+) -> Option<Arc<Primitive>> {
+    let mesh: Arc<TriangleMesh> = match shapes.first().map(|shape| &**shape) {
+        Some(Shape::Trngl(triangle)) => triangle.get_mesh(),
+        _ => {
+            println!("WARNING: \"compress\" is only supported for triangle meshes");
+            return None;
+        }
+    };
+    // all triangles have to belong to the same mesh
+    let same_mesh = |shape: &Arc<Shape>| match &**shape {
+        Shape::Trngl(triangle) => Arc::ptr_eq(&triangle.get_mesh(), &mesh),
+        _ => false,
+    };
+    if shapes.len() != mesh.n_triangles as usize || !shapes.iter().all(same_mesh) {
+        println!("WARNING: \"compress\" is only supported for a single triangle mesh");
+        return None;
+    }
+    if mesh.has_vertex_motion() {
+        println!("WARNING: \"compress\" is not supported for deforming triangle meshes");
+        return None;
+    }
+    let compressed: Arc<CompressedTriangleMesh> = Arc::new(CompressedTriangleMesh::new(&mesh));
+    println!(
+        "Compressed triangle mesh with {} triangles: {:.3} MB (instead of {:.3} MB)",
+        compressed.n_triangles,
+        compressed.memory_usage() as f64 / (1024.0 * 1024.0),
+        CompressedTriangleMesh::uncompressed_memory_usage(&mesh) as f64 / (1024.0 * 1024.0)
+    );
+    Some(Arc::new(Primitive::Mesh(Box::new(MeshPrimitive::new(
+        compressed,
+        materials[0].clone(),
+        Some(Arc::new(mi.clone())),
+    )))))
+}
+
 // Attempt to determine if the ParamSet for a shape may provide a value for
 // its material's parameters. Unfortunately, materials don't provide an
 // explicit representation of their parameters that we can query and
@@ -3061,40 +3566,54 @@ pub fn pbrt_object_instance(api_state: &mut ApiState, params: ParamSet) {
                     .render_options
                     .accelerator_params
                     .find_one_string("splitmethod", String::from("sah"));
-                let split_method;
-                if split_method_name == "sah" {
-                    split_method = SplitMethod::SAH;
+                let split_method: SplitMethod = if split_method_name == "sah" {
+                    SplitMethod::SAH
                 } else if split_method_name == "hlbvh" {
-                    split_method = SplitMethod::HLBVH;
+                    SplitMethod::HLBVH
                 } else if split_method_name == "middle" {
-                    split_method = SplitMethod::Middle;
+                    SplitMethod::Middle
                 } else if split_method_name == "equal" {
-                    split_method = SplitMethod::EqualCounts;
+                    SplitMethod::EqualCounts
                 } else {
                     println!(
                         "WARNING: BVH split method \"{}\" unknown.  Using \"sah\".",
                         split_method_name
                     );
-                    split_method = SplitMethod::SAH;
-                }
+                    SplitMethod::SAH
+                };
                 let max_prims_in_node: i32 = api_state
                     .render_options
                     .accelerator_params
                     .find_one_int("maxnodeprims", 4);
+                let n_buckets: i32 = api_state
+                    .render_options
+                    .accelerator_params
+                    .find_one_int("nbuckets", 12);
                 let accelerator: Arc<Primitive> =
                     Arc::new(Primitive::BVH(Box::new(BVHAccel::new(
                         instance_vec.clone(),
                         max_prims_in_node as usize,
                         split_method,
+                        n_buckets as usize,
                     ))));
                 instance_vec.clear();
                 instance_vec.push(accelerator);
             } else if api_state.render_options.accelerator_name == "kdtree" {
-                // println!("TODO: CreateKdTreeAccelerator");
-                // WARNING: Use BVHAccel for now !!!
-                let accelerator: Arc<Primitive> = Arc::new(Primitive::BVH(Box::new(
-                    BVHAccel::new(instance_vec.clone(), 4, SplitMethod::SAH),
-                )));
+                // CreateKdTreeAccelerator
+                let accelerator: Arc<Primitive> = Arc::new(KdTreeAccel::create(
+                    instance_vec.clone(),
+                    &api_state.render_options.accelerator_params,
+                ));
+                instance_vec.clear();
+                instance_vec.push(accelerator);
+            } else if api_state.render_options.accelerator_name == "widebvh" {
+                let accelerator_params: &ParamSet = &api_state.render_options.accelerator_params;
+                let width: usize = WideBVHAccel::width_from_params(accelerator_params);
+                let accelerator: Arc<Primitive> =
+                    Arc::new(Primitive::WideBVH(Box::new(WideBVHAccel::new(
+                        BVHAccel::from_params(instance_vec.clone(), accelerator_params),
+                        width,
+                    ))));
                 instance_vec.clear();
                 instance_vec.push(accelerator);
             } else {
@@ -3105,12 +3624,12 @@ pub fn pbrt_object_instance(api_state: &mut ApiState, params: ParamSet) {
             }
         }
         // create _animatedInstanceToWorld_ transform for instance
-        let animated_instance_to_world: AnimatedTransform = AnimatedTransform::new(
-            &api_state.cur_transform.t[0],
-            api_state.render_options.transform_start_time,
-            &api_state.cur_transform.t[1],
-            api_state.render_options.transform_end_time,
-        );
+        let animated_instance_to_world: AnimatedTransform =
+            api_state.cur_transform.animated_transform(
+                api_state.render_options.transform_start_time,
+                api_state.render_options.transform_end_time,
+                &api_state.render_options.transform_key_times,
+            );
         let prim: Arc<Primitive> = Arc::new(Primitive::Transformed(Box::new(
             TransformedPrimitive::new(instance_vec[0].clone(), animated_instance_to_world),
         )));
@@ -0,0 +1,391 @@
+//! An RGB color space is defined by the chromaticities of its red,
+//! green and blue primaries and its white point. All RGB values the
+//! renderer works with (scene parameters, texels, light sources) are
+//! expressed in a single **working color space**, which can be
+//! changed per scene with the `ColorSpace` directive. It is part of
+//! the scene's render options and gets passed to everything that
+//! converts colors while the scene is created: image textures, which
+//! can declare the color space their texels are stored in, images of
+//! light sources, spectral parameters and the film.
+
+// others
+use lazy_static::lazy_static;
+// pbrt
+use crate::core::pbrt::{Float, Spectrum};
+use crate::core::spectrum::inverse_gamma_convert_float;
+
+pub type ColorMatrix = [[Float; 3]; 3];
+
+/// Linear RGB color spaces which can be used as working color space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RGBColorSpace {
+    /// sRGB and Rec.709 share the same primaries and D65 white point.
+    SRGB = 0,
+    /// ACES AP1 primaries with the (approximate) D60 white point.
+    ACEScg = 1,
+    /// Rec.2020 (UHDTV) primaries with a D65 white point.
+    Rec2020 = 2,
+}
+
+/// The color space (primaries and encoding) image texels are stored in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextureColorSpace {
+    /// sRGB transfer function with sRGB primaries (the usual 8-bit albedo map).
+    SRGB,
+    /// Linear values with sRGB/Rec.709 primaries.
+    Linear,
+    /// Rec.709 (BT.709 OETF) encoded values with Rec.709 primaries.
+    Rec709,
+    /// Linear values with ACES AP1 primaries.
+    ACEScg,
+    /// Data (roughness, normals, ...) which is used without any conversion.
+    Raw,
+}
+
+struct ColorSpaceMatrices {
+    rgb_to_xyz: ColorMatrix,
+    xyz_to_rgb: ColorMatrix,
+    white: [Float; 3],
+}
+
+const N_COLOR_SPACES: usize = 3;
+
+// xy chromaticities of the primaries (red, green, blue) and the white point
+const ACESCG_CHROMATICITIES: [[Float; 2]; 4] = [
+    [0.713, 0.293],
+    [0.165, 0.830],
+    [0.128, 0.044],
+    [0.321_68, 0.337_67],
+];
+const REC2020_CHROMATICITIES: [[Float; 2]; 4] = [
+    [0.708, 0.292],
+    [0.170, 0.797],
+    [0.131, 0.046],
+    [0.312_7, 0.329],
+];
+
+// keep the numbers pbrt always used for sRGB (see spectrum.h)
+const SRGB_RGB_TO_XYZ: ColorMatrix = [
+    [0.412_453, 0.357_580, 0.180_423],
+    [0.212_671, 0.715_160, 0.072_169],
+    [0.019_334, 0.119_193, 0.950_227],
+];
+const SRGB_XYZ_TO_RGB: ColorMatrix = [
+    [3.240_479, -1.537_15, -0.498_535],
+    [-0.969_256, 1.875_991, 0.041_556],
+    [0.055_648, -0.204_043, 1.057_311],
+];
+
+// Bradford chromatic adaptation (cone response) matrix
+const BRADFORD: ColorMatrix = [
+    [0.895_1, 0.266_4, -0.161_4],
+    [-0.750_2, 1.713_5, 0.036_7],
+    [0.038_9, -0.068_5, 1.029_6],
+];
+
+lazy_static! {
+    static ref COLOR_SPACES: [ColorSpaceMatrices; N_COLOR_SPACES] = {
+        let srgb_white: [Float; 3] = mat3_mul_vec3(&SRGB_RGB_TO_XYZ, &[1.0, 1.0, 1.0]);
+        let acescg: ColorMatrix = rgb_to_xyz_from_chromaticities(&ACESCG_CHROMATICITIES);
+        let rec2020: ColorMatrix = rgb_to_xyz_from_chromaticities(&REC2020_CHROMATICITIES);
+        [
+            ColorSpaceMatrices {
+                rgb_to_xyz: SRGB_RGB_TO_XYZ,
+                xyz_to_rgb: SRGB_XYZ_TO_RGB,
+                white: srgb_white,
+            },
+            ColorSpaceMatrices {
+                rgb_to_xyz: acescg,
+                xyz_to_rgb: mat3_inverse(&acescg),
+                white: mat3_mul_vec3(&acescg, &[1.0, 1.0, 1.0]),
+            },
+            ColorSpaceMatrices {
+                rgb_to_xyz: rec2020,
+                xyz_to_rgb: mat3_inverse(&rec2020),
+                white: mat3_mul_vec3(&rec2020, &[1.0, 1.0, 1.0]),
+            },
+        ]
+    };
+    /// RGB to RGB conversion matrices (including white point
+    /// adaptation), indexed by [from][to].
+    static ref CONVERSIONS: [[ColorMatrix; N_COLOR_SPACES]; N_COLOR_SPACES] = {
+        let mut conversions: [[ColorMatrix; N_COLOR_SPACES]; N_COLOR_SPACES] =
+            [[[[0.0 as Float; 3]; 3]; N_COLOR_SPACES]; N_COLOR_SPACES];
+        for (from, row) in conversions.iter_mut().enumerate() {
+            for (to, conversion) in row.iter_mut().enumerate() {
+                let src: &ColorSpaceMatrices = &COLOR_SPACES[from];
+                let dst: &ColorSpaceMatrices = &COLOR_SPACES[to];
+                let adapt: ColorMatrix = bradford_adaptation(&src.white, &dst.white);
+                *conversion = mat3_mul(&dst.xyz_to_rgb, &mat3_mul(&adapt, &src.rgb_to_xyz));
+            }
+        }
+        conversions
+    };
+}
+
+impl RGBColorSpace {
+    pub fn from_name(name: &str) -> Option<RGBColorSpace> {
+        match name.to_lowercase().as_str() {
+            "srgb" | "rec709" => Some(RGBColorSpace::SRGB),
+            "acescg" | "ap1" => Some(RGBColorSpace::ACEScg),
+            "rec2020" => Some(RGBColorSpace::Rec2020),
+            _ => None,
+        }
+    }
+    pub fn rgb_to_xyz(&self, rgb: &[Float; 3], xyz: &mut [Float; 3]) {
+        *xyz = mat3_mul_vec3(&COLOR_SPACES[*self as usize].rgb_to_xyz, rgb);
+    }
+    pub fn xyz_to_rgb(&self, xyz: &[Float; 3], rgb: &mut [Float; 3]) {
+        *rgb = mat3_mul_vec3(&COLOR_SPACES[*self as usize].xyz_to_rgb, xyz);
+    }
+    /// Converts a spectral distribution (see
+    /// [from_sampled()](../spectrum/struct.RGBSpectrum.html#method.from_sampled))
+    /// into RGB values of the color space.
+    pub fn from_sampled(&self, lambda: &[Float], v: &[Float], n: i32) -> Spectrum {
+        let s: Spectrum = Spectrum::from_sampled(lambda, v, n);
+        if *self == RGBColorSpace::SRGB {
+            s
+        } else {
+            let mut xyz: [Float; 3] = [0.0 as Float; 3];
+            s.to_xyz(&mut xyz);
+            let mut rgb: [Float; 3] = [0.0 as Float; 3];
+            self.xyz_to_rgb(&xyz, &mut rgb);
+            Spectrum::from_rgb(&rgb)
+        }
+    }
+    /// Luminance weights (the Y row of the RGB to XYZ matrix).
+    pub fn luminance_weights(&self) -> &'static [Float; 3] {
+        &COLOR_SPACES[*self as usize].rgb_to_xyz[1]
+    }
+    /// RGB to RGB matrix which adapts the white point of a black body
+    /// of the given color temperature (in Kelvin) to the white point
+    /// of the color space, i.e. the illuminant appears white.
+    pub fn white_balance(&self, temperature: Float) -> ColorMatrix {
+        let xy: [Float; 2] = planckian_xy(temperature);
+        let src_white: [Float; 3] = [xy[0] / xy[1], 1.0, (1.0 - xy[0] - xy[1]) / xy[1]];
+        let matrices: &ColorSpaceMatrices = &COLOR_SPACES[*self as usize];
+        let adapt: ColorMatrix = bradford_adaptation(&src_white, &matrices.white);
+        mat3_mul(
+            &matrices.xyz_to_rgb,
+            &mat3_mul(&adapt, &matrices.rgb_to_xyz),
+        )
+    }
+    /// Convert linear RGB values into another color space (using
+    /// Bradford adaptation if the white points differ).
+    pub fn convert_rgb(&self, rgb: &[Float; 3], to: RGBColorSpace) -> [Float; 3] {
+        if *self == to {
+            *rgb
+        } else {
+            mat3_mul_vec3(&CONVERSIONS[*self as usize][to as usize], rgb)
+        }
+    }
+}
+
+impl TextureColorSpace {
+    pub fn from_name(name: &str) -> Option<TextureColorSpace> {
+        match name.to_lowercase().as_str() {
+            "srgb" => Some(TextureColorSpace::SRGB),
+            "linear" => Some(TextureColorSpace::Linear),
+            "rec709" => Some(TextureColorSpace::Rec709),
+            "acescg" => Some(TextureColorSpace::ACEScg),
+            "raw" => Some(TextureColorSpace::Raw),
+            _ => None,
+        }
+    }
+    /// Undo the transfer function of the encoded value.
+    pub fn linearize(&self, v: Float) -> Float {
+        match self {
+            TextureColorSpace::SRGB => inverse_gamma_convert_float(v),
+            TextureColorSpace::Rec709 => {
+                if v < 0.081 {
+                    v / 4.5
+                } else {
+                    ((v + 0.099) / 1.099).powf(1.0 / 0.45)
+                }
+            }
+            _ => v,
+        }
+    }
+    /// The primaries of the stored values, **None** for raw data.
+    pub fn primaries(&self) -> Option<RGBColorSpace> {
+        match self {
+            TextureColorSpace::SRGB | TextureColorSpace::Linear | TextureColorSpace::Rec709 => {
+                Some(RGBColorSpace::SRGB)
+            }
+            TextureColorSpace::ACEScg => Some(RGBColorSpace::ACEScg),
+            TextureColorSpace::Raw => None,
+        }
+    }
+    /// Convert a stored texel into a linear RGB value of the given
+    /// working color space.
+    pub fn to_working(&self, texel: &Spectrum, working_color_space: RGBColorSpace) -> Spectrum {
+        if let Some(primaries) = self.primaries() {
+            let mut rgb: [Float; 3] = [0.0 as Float; 3];
+            texel.to_rgb(&mut rgb);
+            for c in rgb.iter_mut() {
+                *c = self.linearize(*c);
+            }
+            Spectrum::from_rgb(&primaries.convert_rgb(&rgb, working_color_space))
+        } else {
+            *texel
+        }
+    }
+}
+
+fn mat3_mul(a: &ColorMatrix, b: &ColorMatrix) -> ColorMatrix {
+    let mut r: ColorMatrix = [[0.0 as Float; 3]; 3];
+    for (i, row) in r.iter_mut().enumerate() {
+        for (j, item) in row.iter_mut().enumerate() {
+            *item = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+        }
+    }
+    r
+}
+
+pub fn mat3_mul_vec3(m: &ColorMatrix, v: &[Float; 3]) -> [Float; 3] {
+    [
+        m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+        m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+        m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+    ]
+}
+
+fn mat3_inverse(m: &ColorMatrix) -> ColorMatrix {
+    let det: Float = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+        - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+        + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+    assert!(det != 0.0 as Float, "Singular color space matrix");
+    let inv_det: Float = 1.0 as Float / det;
+    [
+        [
+            (m[1][1] * m[2][2] - m[1][2] * m[2][1]) * inv_det,
+            (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv_det,
+            (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv_det,
+        ],
+        [
+            (m[1][2] * m[2][0] - m[1][0] * m[2][2]) * inv_det,
+            (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv_det,
+            (m[0][2] * m[1][0] - m[0][0] * m[1][2]) * inv_det,
+        ],
+        [
+            (m[1][0] * m[2][1] - m[1][1] * m[2][0]) * inv_det,
+            (m[0][1] * m[2][0] - m[0][0] * m[2][1]) * inv_det,
+            (m[0][0] * m[1][1] - m[0][1] * m[1][0]) * inv_det,
+        ],
+    ]
+}
+
+/// Build the RGB to XYZ matrix from the xy chromaticities of the
+/// three primaries and the white point.
+fn rgb_to_xyz_from_chromaticities(xy: &[[Float; 2]; 4]) -> ColorMatrix {
+    let to_xyz = |c: &[Float; 2]| -> [Float; 3] { [c[0] / c[1], 1.0, (1.0 - c[0] - c[1]) / c[1]] };
+    let r: [Float; 3] = to_xyz(&xy[0]);
+    let g: [Float; 3] = to_xyz(&xy[1]);
+    let b: [Float; 3] = to_xyz(&xy[2]);
+    let w: [Float; 3] = to_xyz(&xy[3]);
+    let rgb: ColorMatrix = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+    // scale the primaries so that RGB (1, 1, 1) maps to the white point
+    let s: [Float; 3] = mat3_mul_vec3(&mat3_inverse(&rgb), &w);
+    [
+        [rgb[0][0] * s[0], rgb[0][1] * s[1], rgb[0][2] * s[2]],
+        [rgb[1][0] * s[0], rgb[1][1] * s[1], rgb[1][2] * s[2]],
+        [rgb[2][0] * s[0], rgb[2][1] * s[1], rgb[2][2] * s[2]],
+    ]
+}
+
+/// xy chromaticity of a black body of the given temperature (in
+/// Kelvin, clamped to [1667, 25000]), using the cubic spline
+/// approximation of the Planckian locus by Kim et al.
+fn planckian_xy(temperature: Float) -> [Float; 2] {
+    let t: Float = temperature.clamp(1667.0, 25000.0);
+    let (t2, t3): (Float, Float) = (t * t, t * t * t);
+    let x: Float = if t <= 4000.0 {
+        -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910
+    } else {
+        -3.025_846_9e9 / t3 + 2.107_038e6 / t2 + 0.222_634_7e3 / t + 0.240_390
+    };
+    let (x2, x3): (Float, Float) = (x * x, x * x * x);
+    let y: Float = if t <= 2222.0 {
+        -1.106_381_4 * x3 - 1.348_110_2 * x2 + 2.185_558_3 * x - 0.202_196_83
+    } else if t <= 4000.0 {
+        -0.954_947_6 * x3 - 1.374_185_9 * x2 + 2.091_37 * x - 0.167_488_67
+    } else {
+        3.081_758 * x3 - 5.873_387 * x2 + 3.751_13 * x - 0.370_014_83
+    };
+    [x, y]
+}
+
+/// Von Kries style adaptation in Bradford cone space from one white
+/// point (given in XYZ) to another.
+fn bradford_adaptation(src_white: &[Float; 3], dst_white: &[Float; 3]) -> ColorMatrix {
+    let src_lms: [Float; 3] = mat3_mul_vec3(&BRADFORD, src_white);
+    let dst_lms: [Float; 3] = mat3_mul_vec3(&BRADFORD, dst_white);
+    let scale: ColorMatrix = [
+        [dst_lms[0] / src_lms[0], 0.0, 0.0],
+        [0.0, dst_lms[1] / src_lms[1], 0.0],
+        [0.0, 0.0, dst_lms[2] / src_lms[2]],
+    ];
+    mat3_mul(&mat3_inverse(&BRADFORD), &mat3_mul(&scale, &BRADFORD))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &[Float; 3], b: &[Float; 3], eps: Float) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < eps, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn bradford_adaptation_maps_white_points() {
+        let d65: [Float; 3] = COLOR_SPACES[RGBColorSpace::SRGB as usize].white;
+        let d60: [Float; 3] = COLOR_SPACES[RGBColorSpace::ACEScg as usize].white;
+        let adapt: ColorMatrix = bradford_adaptation(&d65, &d60);
+        assert_close(&mat3_mul_vec3(&adapt, &d65), &d60, 1e-5);
+        let identity: ColorMatrix = bradford_adaptation(&d65, &d65);
+        assert_close(&identity[0], &[1.0, 0.0, 0.0], 1e-5);
+        assert_close(&identity[1], &[0.0, 1.0, 0.0], 1e-5);
+        assert_close(&identity[2], &[0.0, 0.0, 1.0], 1e-5);
+    }
+
+    #[test]
+    fn convert_rgb_keeps_white_and_round_trips() {
+        let spaces: [RGBColorSpace; 3] = [
+            RGBColorSpace::SRGB,
+            RGBColorSpace::ACEScg,
+            RGBColorSpace::Rec2020,
+        ];
+        let rgb: [Float; 3] = [0.8, 0.3, 0.1];
+        for from in spaces.iter() {
+            for to in spaces.iter() {
+                assert_close(
+                    &from.convert_rgb(&[1.0, 1.0, 1.0], *to),
+                    &[1.0, 1.0, 1.0],
+                    1e-4,
+                );
+                let back: [Float; 3] = to.convert_rgb(&from.convert_rgb(&rgb, *to), *from);
+                assert_close(&back, &rgb, 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_to_rec2020_matches_bt2087() {
+        // both share the D65 white point, see ITU-R BT.2087
+        let m: ColorMatrix =
+            CONVERSIONS[RGBColorSpace::SRGB as usize][RGBColorSpace::Rec2020 as usize];
+        assert_close(&m[0], &[0.6274, 0.3293, 0.0433], 2e-3);
+        assert_close(&m[1], &[0.0691, 0.9195, 0.0114], 2e-3);
+        assert_close(&m[2], &[0.0164, 0.0880, 0.8956], 2e-3);
+    }
+
+    #[test]
+    fn planckian_locus() {
+        let xy: [Float; 2] = planckian_xy(6500.0);
+        assert!((xy[0] - 0.3135).abs() < 1e-3 && (xy[1] - 0.3237).abs() < 1e-3);
+        let xy: [Float; 2] = planckian_xy(2856.0);
+        assert!((xy[0] - 0.4476).abs() < 1e-3 && (xy[1] - 0.4074).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,461 @@
+//! Denoising of the final image, before it gets written by the
+//! [Film](../film/struct.Film.html).
+//!
+//! Besides the (noisy) pixel values the denoisers use feature
+//! buffers, which are collected by the sampler integrators for the
+//! first surface hit of each camera ray: the albedo and the shading
+//! normal. They tell edges of the scene's geometry and textures from
+//! noise. The built-in denoiser is a joint non-local means filter
+//! (see Rousselle et al., "Robust Denoising using Feature and Color
+//! Information", 2013), which compares patches of pixels, normalized
+//! by the variance of the pixel estimates, and the features of the
+//! pixels. Alternatively an external program (e.g. the
+//! `oidnDenoise` example of Intel's Open Image Denoise) can be run as
+//! a local process on the buffers written to disk.
+//!
+//! The denoiser is chosen by the **Film** parameters:
+//!
+//! - **"string denoiser"**: `"none"` (default), `"nlmeans"` or
+//!   `"external"`
+//! - **"integer denoiseradius"** (7) and **"float denoisestrength"**
+//!   (0.45) of the non-local means filter
+//! - **"string denoisercommand"**: the command line of the external
+//!   denoiser, e.g. `"oidnDenoise --hdr {beauty} --alb {albedo} --nrm
+//!   {normal} -o {output}"`, whose placeholders are replaced by the
+//!   names of the portable float maps next to the film's output file
+//!   (for `"out.exr"` these are `out_beauty.pfm`, `out_albedo.pfm`,
+//!   `out_normal.pfm` and `out_denoised.pfm`). Arguments are split
+//!   at whitespace like a shell would, so quotes (`'...'` or `"..."`)
+//!   and backslashes can be used for paths containing spaces.
+//!
+//! Integrators which splat their samples onto the film (like BDPT)
+//! don't provide features or variances, which leaves their images
+//! untouched by the non-local means filter.
+
+// std
+use std::process::Command;
+// pbrt
+use crate::core::film::Film;
+use crate::core::geometry::{vec3_abs_dot_nrmf, Point2f, Point2i, Vector3f};
+use crate::core::imageio::{read_image, write_image};
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::paramset::ParamSet;
+use crate::core::pbrt::{Float, Spectrum};
+use crate::core::reflection::{Bsdf, BxdfType};
+
+/// Standard deviation of the albedo differences tolerated between
+/// pixels which get averaged.
+const ALBEDO_SIGMA: Float = 0.1;
+/// Same for the normals (roughly the angle in radians).
+const NORMAL_SIGMA: Float = 0.3;
+/// Number of stratified BSDF samples per dimension to estimate the
+/// albedo of the first hit.
+const ALBEDO_SAMPLES: usize = 4;
+
+/// The pixel values of the image and the feature buffers, top row
+/// first.
+pub struct FeatureBuffers {
+    pub resolution: Point2i,
+    /// the (noisy) pixel values
+    pub color: Vec<Spectrum>,
+    /// variance of the pixel values (zero if unknown)
+    pub variance: Vec<Spectrum>,
+    pub albedo: Vec<Spectrum>,
+    pub normal: Vec<Vector3f>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Denoiser {
+    /// joint non-local means filter, with a search window of
+    /// (2 * radius + 1)^2 pixels
+    NLMeans { radius: i32, strength: Float },
+    /// an external program, whose arguments contain the placeholders
+    /// `{beauty}`, `{albedo}`, `{normal}` and `{output}`
+    External { args: Vec<String> },
+}
+
+impl Denoiser {
+    /// Reads the denoiser parameters of the **Film**, returns _None_
+    /// unless **"string denoiser"** is `"nlmeans"` or `"external"`.
+    pub fn create(params: &ParamSet) -> Option<Denoiser> {
+        let name: String = params.find_one_string("denoiser", String::from("none"));
+        match name.as_str() {
+            "none" => None,
+            "nlmeans" => {
+                let radius: i32 = params.find_one_int("denoiseradius", 7);
+                let strength: Float = params.find_one_float("denoisestrength", 0.45 as Float);
+                Some(Denoiser::NLMeans {
+                    radius: radius.max(1),
+                    strength,
+                })
+            }
+            "external" => {
+                let command: String = params.find_one_string("denoisercommand", String::new());
+                match split_command_line(&command) {
+                    Some(ref args) if args.is_empty() => {
+                        println!("WARNING: No \"denoisercommand\" given. Not denoising.");
+                        None
+                    }
+                    Some(args) => Some(Denoiser::External { args }),
+                    None => {
+                        println!(
+                            "WARNING: Unterminated quote in \"denoisercommand\" {:?}. \
+                             Not denoising.",
+                            command
+                        );
+                        None
+                    }
+                }
+            }
+            _ => {
+                println!("WARNING: Denoiser \"{}\" unknown. Not denoising.", name);
+                None
+            }
+        }
+    }
+    /// Returns the denoised pixel values, or _None_ if denoising
+    /// failed. The files exchanged with an external denoiser are
+    /// named after the film's output file.
+    pub fn denoise(&self, buffers: &FeatureBuffers, film: &Film) -> Option<Vec<Spectrum>> {
+        match self {
+            Denoiser::NLMeans { radius, strength } => {
+                println!(
+                    "Denoising image (non-local means, radius {}, strength {}) ...",
+                    radius, strength
+                );
+                Some(nl_means(buffers, *radius, *strength))
+            }
+            Denoiser::External { args } => run_external(args, buffers, film),
+        }
+    }
+}
+
+/// The albedo and shading normal of the first surface hit by a
+/// camera ray (both zero if nothing was hit), recorded by the
+/// sampler integrators while they evaluate the radiance.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FirstHitFeatures {
+    pub albedo: Spectrum,
+    pub normal: Vector3f,
+}
+
+impl FirstHitFeatures {
+    /// Takes the features from an intersection whose scattering
+    /// functions have been computed already.
+    pub fn new(isect: &SurfaceInteraction) -> Self {
+        let albedo: Spectrum = if let Some(ref bsdf) = isect.bsdf {
+            estimate_albedo(bsdf, &isect.common.wo)
+        } else {
+            Spectrum::default()
+        };
+        FirstHitFeatures {
+            albedo,
+            normal: Vector3f::from(isect.shading.n),
+        }
+    }
+}
+
+/// Estimates the directional albedo (the fraction of light scattered
+/// towards _wo_) with stratified BSDF samples.
+fn estimate_albedo(bsdf: &Bsdf, wo: &Vector3f) -> Spectrum {
+    let mut albedo: Spectrum = Spectrum::default();
+    for i in 0..ALBEDO_SAMPLES {
+        for j in 0..ALBEDO_SAMPLES {
+            let u: Point2f = Point2f {
+                x: (i as Float + 0.5 as Float) / ALBEDO_SAMPLES as Float,
+                y: (j as Float + 0.5 as Float) / ALBEDO_SAMPLES as Float,
+            };
+            let mut wi: Vector3f = Vector3f::default();
+            let mut pdf: Float = 0.0 as Float;
+            let mut sampled_type: u8 = 0_u8;
+            let f: Spectrum = bsdf.sample_f(
+                wo,
+                &mut wi,
+                &u,
+                &mut pdf,
+                BxdfType::BsdfAll as u8,
+                &mut sampled_type,
+            );
+            if pdf > 0.0 as Float {
+                albedo += f * (vec3_abs_dot_nrmf(&wi, &bsdf.ns) / pdf);
+            }
+        }
+    }
+    (albedo / (ALBEDO_SAMPLES * ALBEDO_SAMPLES) as Float).clamp(0.0 as Float, 1.0 as Float)
+}
+
+fn nl_means(buffers: &FeatureBuffers, radius: i32, strength: Float) -> Vec<Spectrum> {
+    let width: usize = buffers.resolution.x as usize;
+    let height: usize = buffers.resolution.y as usize;
+    let mut output: Vec<Spectrum> = vec![Spectrum::default(); width * height];
+    if width == 0 || height == 0 {
+        return output;
+    }
+    // filter bands of rows in parallel
+    let num_cores: usize = num_cpus::get();
+    let band_rows: usize = height.div_ceil(num_cores);
+    crossbeam::scope(|scope| {
+        for (band, rows) in output.chunks_mut(band_rows * width).enumerate() {
+            scope.spawn(move |_| {
+                nl_means_rows(buffers, radius, strength, band * band_rows, rows);
+            });
+        }
+    })
+    .unwrap();
+    output
+}
+
+/// Filters the rows starting at _y0_, which fill _output_. For each
+/// offset within the search window, the distances of all pixel pairs
+/// are calculated first and then averaged over 3x3 patches with a
+/// (separable) box filter.
+fn nl_means_rows(
+    buffers: &FeatureBuffers,
+    radius: i32,
+    strength: Float,
+    y0: usize,
+    output: &mut [Spectrum],
+) {
+    let width: usize = buffers.resolution.x as usize;
+    let height: usize = buffers.resolution.y as usize;
+    let y1: usize = y0 + output.len() / width;
+    // the patches reach one row above and below the band
+    let py0: usize = y0.saturating_sub(1);
+    let py1: usize = (y1 + 1).min(height);
+    let k2: Float = strength * strength;
+    let mut distance: Vec<Float> = vec![0.0 as Float; (py1 - py0) * width];
+    let mut blurred: Vec<Float> = vec![0.0 as Float; (py1 - py0) * width];
+    let mut weight_sum: Vec<Float> = vec![0.0 as Float; output.len()];
+    for dy in -radius..=radius {
+        for dx in -radius..=radius {
+            // distances of the pixels to their (clamped) neighbors
+            for y in py0..py1 {
+                let qy: usize = (y as i32 + dy).max(0).min(height as i32 - 1) as usize;
+                for x in 0..width {
+                    let qx: usize = (x as i32 + dx).max(0).min(width as i32 - 1) as usize;
+                    distance[(y - py0) * width + x] =
+                        pixel_distance(buffers, y * width + x, qy * width + qx, k2);
+                }
+            }
+            // box filter (horizontal)
+            for y in 0..(py1 - py0) {
+                let row: &[Float] = &distance[y * width..(y + 1) * width];
+                for x in 0..width {
+                    let left: Float = row[x.saturating_sub(1)];
+                    let right: Float = row[(x + 1).min(width - 1)];
+                    blurred[y * width + x] = (left + row[x] + right) / 3.0 as Float;
+                }
+            }
+            // box filter (vertical) and weighted sum of the neighbors
+            for y in y0..y1 {
+                let qy: i32 = y as i32 + dy;
+                if qy < 0 || qy >= height as i32 {
+                    continue;
+                }
+                let above: usize = y.saturating_sub(1).max(py0) - py0;
+                let below: usize = (y + 1).min(py1 - 1) - py0;
+                for x in 0..width {
+                    let qx: i32 = x as i32 + dx;
+                    if qx < 0 || qx >= width as i32 {
+                        continue;
+                    }
+                    let d: Float = (blurred[above * width + x]
+                        + blurred[(y - py0) * width + x]
+                        + blurred[below * width + x])
+                        / 3.0 as Float;
+                    let p: usize = y * width + x;
+                    let q: usize = qy as usize * width + qx as usize;
+                    let w_color: Float = (-d.max(0.0 as Float)).exp();
+                    let w: Float = w_color.min(feature_weight(buffers, p, q));
+                    output[p - y0 * width] += buffers.color[q] * w;
+                    weight_sum[p - y0 * width] += w;
+                }
+            }
+        }
+    }
+    for (i, pixel) in output.iter_mut().enumerate() {
+        if weight_sum[i] > 0.0 as Float {
+            *pixel /= weight_sum[i];
+        } else {
+            *pixel = buffers.color[y0 * width + i];
+        }
+    }
+}
+
+/// Squared difference of the pixels _p_ and _q_, reduced by the
+/// expected difference due to noise and normalized by the variances.
+fn pixel_distance(buffers: &FeatureBuffers, p: usize, q: usize, k2: Float) -> Float {
+    let mut d: Float = 0.0 as Float;
+    for c in 0..3 {
+        let var_p: Float = buffers.variance[p].c[c];
+        let var_q: Float = buffers.variance[q].c[c];
+        let diff: Float = buffers.color[p].c[c] - buffers.color[q].c[c];
+        d += (diff * diff - (var_p + var_p.min(var_q))) / (1e-10 as Float + k2 * (var_p + var_q));
+    }
+    d / 3.0 as Float
+}
+
+fn feature_weight(buffers: &FeatureBuffers, p: usize, q: usize) -> Float {
+    let da: Spectrum = buffers.albedo[p] - buffers.albedo[q];
+    let da2: Float = da.c[0] * da.c[0] + da.c[1] * da.c[1] + da.c[2] * da.c[2];
+    let dn2: Float = (buffers.normal[p] - buffers.normal[q]).length_squared();
+    (-da2 / (2.0 as Float * ALBEDO_SIGMA * ALBEDO_SIGMA)
+        - dn2 / (2.0 as Float * NORMAL_SIGMA * NORMAL_SIGMA))
+        .exp()
+}
+
+/// Splits a command line into arguments like a POSIX shell would
+/// (without any expansions): whitespace separates arguments unless
+/// it's quoted by `'...'`, `"..."` or a backslash. Returns _None_ for
+/// an unterminated quote.
+fn split_command_line(command: &str) -> Option<Vec<String>> {
+    let mut args: Vec<String> = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                let word: &mut String = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => word.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let word: &mut String = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ '"' | c @ '\\' | c @ '$' | c @ '`' => word.push(c),
+                            '\n' => {}
+                            c => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let word: &mut String = arg.get_or_insert_with(String::new);
+                match chars.next() {
+                    Some('\n') => {}
+                    Some(c) => word.push(c),
+                    None => word.push('\\'),
+                }
+            }
+            c if c.is_whitespace() => {
+                if let Some(word) = arg.take() {
+                    args.push(word);
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(word) = arg {
+        args.push(word);
+    }
+    Some(args)
+}
+
+/// Writes the buffers as portable float maps, runs the external
+/// denoiser and reads its output back in.
+fn run_external(args: &[String], buffers: &FeatureBuffers, film: &Film) -> Option<Vec<Spectrum>> {
+    let beauty_file: String = film.auxiliary_filename("_beauty", "pfm");
+    let albedo_file: String = film.auxiliary_filename("_albedo", "pfm");
+    let normal_file: String = film.auxiliary_filename("_normal", "pfm");
+    let denoised_file: String = film.auxiliary_filename("_denoised", "pfm");
+    let normal: Vec<Spectrum> = buffers
+        .normal
+        .iter()
+        .map(|n| Spectrum::rgb(n.x, n.y, n.z))
+        .collect();
+    for (name, pixels) in [
+        (&beauty_file, &buffers.color),
+        (&albedo_file, &buffers.albedo),
+        (&normal_file, &normal),
+    ]
+    .iter()
+    {
+        let rgb: Vec<Float> = pixels.iter().flat_map(|s| s.c.iter().copied()).collect();
+        write_image(name, &rgb, buffers.resolution, film.color_space);
+    }
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            arg.replace("{beauty}", &beauty_file)
+                .replace("{albedo}", &albedo_file)
+                .replace("{normal}", &normal_file)
+                .replace("{output}", &denoised_file)
+        })
+        .collect();
+    println!("Denoising image with {:?} ...", args);
+    match Command::new(&args[0]).args(&args[1..]).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            println!("ERROR: Denoiser {:?} failed ({})", args[0], status);
+            return None;
+        }
+        Err(err) => {
+            println!("ERROR: Unable to run denoiser {:?}: {}", args[0], err);
+            return None;
+        }
+    }
+    let mut resolution: Point2i = Point2i::default();
+    let denoised: Vec<Spectrum> = read_image(&denoised_file, &mut resolution)?;
+    if resolution.x != buffers.resolution.x || resolution.y != buffers.resolution.y {
+        println!(
+            "ERROR: Denoised image {:?} has resolution {:?}, expected {:?}",
+            denoised_file, resolution, buffers.resolution
+        );
+        return None;
+    }
+    Some(denoised)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Option<Vec<String>> {
+        Some(args.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn split_words() {
+        assert_eq!(split_command_line(""), args(&[]));
+        assert_eq!(
+            split_command_line("  oidnDenoise  --hdr\t-o out.pfm\n"),
+            args(&["oidnDenoise", "--hdr", "-o", "out.pfm"])
+        );
+    }
+
+    #[test]
+    fn split_quoted_words() {
+        assert_eq!(
+            split_command_line(r#"run 'a b' "c d" e\ f x'y'"z""#),
+            args(&["run", "a b", "c d", "e f", "xyz"])
+        );
+        // empty quotes are an (empty) argument of their own
+        assert_eq!(split_command_line("a '' \"\""), args(&["a", "", ""]));
+        // backslashes only escape some characters within double quotes
+        assert_eq!(
+            split_command_line(r#""\"\\\$\`" "\n" '\'"#),
+            args(&[r#""\$`"#, r"\n", r"\"])
+        );
+        // escaped newlines continue the line
+        assert_eq!(split_command_line("a\\\nb \"c\\\nd\""), args(&["ab", "cd"]));
+        assert_eq!(split_command_line("trailing\\"), args(&["trailing\\"]));
+    }
+
+    #[test]
+    fn reject_unterminated_quotes() {
+        assert_eq!(split_command_line("'open"), None);
+        assert_eq!(split_command_line("\"open"), None);
+        assert_eq!(split_command_line("\"escaped\\\""), None);
+    }
+}
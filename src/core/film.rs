@@ -19,16 +19,20 @@ use std::sync::{Arc, RwLock, RwLockWriteGuard};
 use openexr::{FrameBuffer, Header, PixelType, ScanlineOutputFile};
 use smallvec::SmallVec;
 // pbrt
+use crate::core::colorspace::RGBColorSpace;
+use crate::core::denoiser::{Denoiser, FeatureBuffers};
 use crate::core::filter::Filter;
 use crate::core::geometry::{
     bnd2_intersect_bnd2i, pnt2_ceil, pnt2_floor, pnt2_inside_exclusivei, pnt2_max_pnt2i,
     pnt2_min_pnt2i,
 };
-use crate::core::geometry::{Bounds2f, Bounds2i, Point2f, Point2i, Vector2f};
+use crate::core::geometry::{Bounds2f, Bounds2i, Point2f, Point2i, Vector2f, Vector3f};
+use crate::core::imageio::{is_srgb_encoded, write_image};
 use crate::core::paramset::ParamSet;
 use crate::core::pbrt::{clamp_t, gamma_correct};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::spectrum::xyz_to_rgb;
+use crate::core::tonemap::DisplayTransform;
 
 // see film.h
 
@@ -59,6 +63,28 @@ pub struct FilmTilePixel {
     filter_weight_sum: Float,
 }
 
+/// Sums over the camera samples of a pixel, which the
+/// [Denoiser](../denoiser/enum.Denoiser.html) uses to estimate the
+/// variance and the features of the pixel.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FeaturePixel {
+    l_sum: Spectrum,
+    l2_sum: Spectrum,
+    albedo_sum: Spectrum,
+    normal_sum: Vector3f,
+    n_samples: u32,
+}
+
+impl FeaturePixel {
+    fn add(&mut self, other: &FeaturePixel) {
+        self.l_sum += other.l_sum;
+        self.l2_sum += other.l2_sum;
+        self.albedo_sum += other.albedo_sum;
+        self.normal_sum += other.normal_sum;
+        self.n_samples += other.n_samples;
+    }
+}
+
 pub struct FilmTile<'a> {
     pub pixel_bounds: Bounds2i,
     filter_radius: Vector2f,
@@ -67,6 +93,8 @@ pub struct FilmTile<'a> {
     filter_table_size: usize,
     pixels: Vec<FilmTilePixel>,
     max_sample_luminance: Float,
+    /// empty, unless the film collects features
+    features: Vec<FeaturePixel>,
 }
 
 impl<'a> FilmTile<'a> {
@@ -89,6 +117,7 @@ impl<'a> FilmTile<'a> {
             // TODO: pixels = std::vector<FilmTilePixel>(std::max(0, pixelBounds.Area()));
             pixels: vec![FilmTilePixel::default(); pixel_bounds.area() as usize],
             max_sample_luminance,
+            features: Vec::new(),
         }
     }
     pub fn add_sample(&mut self, p_film: Point2f, l: &mut Spectrum, sample_weight: Float) {
@@ -145,6 +174,35 @@ impl<'a> FilmTile<'a> {
             }
         }
     }
+    /// Records the radiance (as clamped by
+    /// [add_sample()](#method.add_sample)) and the features of a
+    /// camera sample for the pixel containing _p_film_, if the film
+    /// [collects features](struct.Film.html#method.collects_features).
+    pub fn add_features(
+        &mut self,
+        p_film: Point2f,
+        l: &Spectrum,
+        albedo: &Spectrum,
+        normal: &Vector3f,
+    ) {
+        if self.features.is_empty() {
+            return;
+        }
+        let pi: Point2i = Point2i {
+            x: p_film.x.floor() as i32,
+            y: p_film.y.floor() as i32,
+        };
+        if !pnt2_inside_exclusivei(pi, &self.pixel_bounds) {
+            return;
+        }
+        let idx = self.get_pixel_index(pi.x, pi.y);
+        let feature = &mut self.features[idx];
+        feature.l_sum += *l;
+        feature.l2_sum += *l * *l;
+        feature.albedo_sum += *albedo;
+        feature.normal_sum += *normal;
+        feature.n_samples += 1;
+    }
     fn get_pixel_index(&self, x: i32, y: i32) -> usize {
         let width: i32 = self.pixel_bounds.p_max.x - self.pixel_bounds.p_min.x;
         let pidx = (y - self.pixel_bounds.p_min.y) * width + (x - self.pixel_bounds.p_min.x);
@@ -164,12 +222,19 @@ pub struct Film {
     pub filename: String,
     /// A crop window that may specify a subset of the image to render
     pub cropped_pixel_bounds: Bounds2i,
+    /// The working color space of the RGB values stored in the pixels
+    pub color_space: RGBColorSpace,
 
     // Film Private Data
     pub pixels: RwLock<Vec<Pixel>>,
     filter_table: [Float; FILTER_TABLE_WIDTH * FILTER_TABLE_WIDTH],
     scale: Float,
     max_sample_luminance: Float,
+    features: RwLock<Vec<FeaturePixel>>,
+    denoiser: Option<Denoiser>,
+    display: DisplayTransform,
+    /// optional file for the linear (HDR) image
+    hdr_filename: String,
 }
 
 impl Film {
@@ -215,13 +280,23 @@ impl Film {
             filter,
             filename,
             cropped_pixel_bounds,
+            color_space: RGBColorSpace::SRGB,
             pixels: RwLock::new(vec![Pixel::default(); cropped_pixel_bounds.area() as usize]),
             filter_table,
             scale,
             max_sample_luminance,
+            features: RwLock::new(Vec::new()),
+            denoiser: None,
+            display: DisplayTransform::default(),
+            hdr_filename: String::new(),
         }
     }
-    pub fn create(params: &ParamSet, filter: Box<Filter>, crop_window: &Bounds2f) -> Arc<Film> {
+    pub fn create(
+        params: &ParamSet,
+        filter: Box<Filter>,
+        crop_window: &Bounds2f,
+        color_space: RGBColorSpace,
+    ) -> Arc<Film> {
         let filename: String = params.find_one_string("filename", String::new());
         let xres: i32 = params.find_one_int("xresolution", 1280);
         let yres: i32 = params.find_one_int("yresolution", 720);
@@ -250,7 +325,7 @@ impl Film {
         let diagonal: Float = params.find_one_float("diagonal", 35.0);
         let max_sample_luminance: Float =
             params.find_one_float("maxsampleluminance", std::f32::INFINITY);
-        Arc::new(Film::new(
+        let mut film: Film = Film::new(
             resolution,
             crop,
             filter,
@@ -258,7 +333,35 @@ impl Film {
             filename,
             scale,
             max_sample_luminance,
-        ))
+        );
+        if let Some(denoiser) = Denoiser::create(params) {
+            film.set_denoiser(denoiser);
+        }
+        film.color_space = color_space;
+        film.display = DisplayTransform::create(params, color_space);
+        film.hdr_filename = params.find_one_string("hdrfilename", String::new());
+        if !film.hdr_filename.is_empty() && is_srgb_encoded(&film.hdr_filename) {
+            println!(
+                "WARNING: HDR image {:?} is not an OpenEXR file or a portable float map.",
+                film.hdr_filename
+            );
+        }
+        Arc::new(film)
+    }
+    /// Denoise the image before writing it, which makes the film
+    /// collect the features of the camera samples.
+    pub fn set_denoiser(&mut self, denoiser: Denoiser) {
+        self.denoiser = Some(denoiser);
+        self.features = RwLock::new(vec![
+            FeaturePixel::default();
+            self.cropped_pixel_bounds.area() as usize
+        ]);
+    }
+    /// Returns true if the integrator should call
+    /// [FilmTile::add_features()](struct.FilmTile.html#method.add_features)
+    /// for its camera samples.
+    pub fn collects_features(&self) -> bool {
+        self.denoiser.is_some()
     }
     pub fn get_cropped_pixel_bounds(&self) -> Bounds2i {
         self.cropped_pixel_bounds
@@ -335,13 +438,17 @@ impl Film {
             },
             &self.cropped_pixel_bounds,
         );
-        FilmTile::new(
+        let mut tile: FilmTile = FilmTile::new(
             tile_pixel_bounds,
             self.filter.get_radius(),
             &self.filter_table,
             FILTER_TABLE_WIDTH,
             self.max_sample_luminance,
-        )
+        );
+        if self.collects_features() {
+            tile.features = vec![FeaturePixel::default(); tile_pixel_bounds.area() as usize];
+        }
+        tile
     }
     pub fn merge_film_tile(&self, tile: &FilmTile) {
         // TODO: ProfilePhase p(Prof::MergeFilmTile);
@@ -365,6 +472,10 @@ impl Film {
                 merge_pixel.xyz[i] += item;
             }
             merge_pixel.filter_weight_sum += tile_pixel.filter_weight_sum;
+            if !tile.features.is_empty() {
+                let mut features_write = self.features.write().unwrap();
+                features_write[offset as usize].add(&tile.features[idx]);
+            }
             // write pixel back
             // pixels_write[offset as usize] = *merge_pixel;
         }
@@ -434,6 +545,133 @@ impl Film {
         splat_xyz[1] += xyz[1];
         splat_xyz[2] += xyz[2];
     }
+    /// Returns the final RGB values (of the working color space) of
+    /// all pixels inside the crop window, top row first, including
+    /// the splats scaled by _splat_scale_.
+    pub fn get_rgb(&self, splat_scale: Float) -> Vec<Float> {
+        let mut rgb: Vec<Float> =
+            vec![0.0 as Float; (3 * self.cropped_pixel_bounds.area()) as usize];
+        let pixels = self.pixels.read().unwrap();
+        for (offset, pixel) in pixels.iter().enumerate() {
+            let start: usize = 3 * offset;
+            let mut rgb_array: [Float; 3] = [0.0 as Float; 3];
+            xyz_to_rgb(&pixel.xyz, &mut rgb_array);
+            // normalize pixel with weight sum
+            let filter_weight_sum: Float = pixel.filter_weight_sum;
+            if filter_weight_sum != 0.0 as Float {
+                let inv_wt: Float = 1.0 as Float / filter_weight_sum;
+                for c in rgb_array.iter_mut() {
+                    *c = (*c * inv_wt).max(0.0 as Float);
+                }
+            }
+            // add splat value at pixel
+            let mut splat_rgb: [Float; 3] = [0.0 as Float; 3];
+            xyz_to_rgb(&pixel.splat_xyz, &mut splat_rgb);
+            for c in 0..3 {
+                // scale pixel value by _scale_
+                rgb[start + c] = (rgb_array[c] + splat_scale * splat_rgb[c]) * self.scale;
+            }
+        }
+        rgb
+    }
+    /// Returns the features collected for the pixels (see
+    /// [get_rgb()](#method.get_rgb)) together with their values
+    /// _rgb_.
+    fn get_feature_buffers(&self, rgb: &[Float]) -> FeatureBuffers {
+        let features = self.features.read().unwrap();
+        let n_pixels: usize = self.cropped_pixel_bounds.area() as usize;
+        let mut buffers: FeatureBuffers = FeatureBuffers {
+            resolution: Point2i {
+                x: self.cropped_pixel_bounds.p_max.x - self.cropped_pixel_bounds.p_min.x,
+                y: self.cropped_pixel_bounds.p_max.y - self.cropped_pixel_bounds.p_min.y,
+            },
+            color: rgb
+                .chunks(3)
+                .map(|c| Spectrum::rgb(c[0], c[1], c[2]))
+                .collect(),
+            variance: vec![Spectrum::default(); n_pixels],
+            albedo: vec![Spectrum::default(); n_pixels],
+            normal: vec![Vector3f::default(); n_pixels],
+        };
+        for (i, feature) in features.iter().enumerate() {
+            if feature.n_samples == 0 {
+                continue;
+            }
+            let n: Float = feature.n_samples as Float;
+            if feature.n_samples > 1 {
+                // variance of the mean of the samples
+                let mean: Spectrum = feature.l_sum / n;
+                buffers.variance[i] = ((feature.l2_sum - feature.l_sum * mean)
+                    / ((n - 1.0 as Float) * n)
+                    * (self.scale * self.scale))
+                    .clamp(0.0 as Float, Float::INFINITY);
+            }
+            buffers.albedo[i] = feature.albedo_sum / n;
+            buffers.normal[i] = feature.normal_sum / n;
+        }
+        buffers
+    }
+    /// Replaces the pixel values _rgb_ (see
+    /// [get_rgb()](#method.get_rgb)) by the denoised ones, if the
+    /// film has a denoiser.
+    fn denoise(&self, rgb: &mut [Float]) {
+        if let Some(ref denoiser) = self.denoiser {
+            let buffers: FeatureBuffers = self.get_feature_buffers(rgb);
+            if let Some(denoised) = denoiser.denoise(&buffers, self) {
+                for (texel, pixel) in rgb.chunks_mut(3).zip(denoised) {
+                    texel.copy_from_slice(&pixel.c);
+                }
+            }
+        }
+    }
+    /// Returns the name of an auxiliary image, which gets written
+    /// next to the film's output image, e.g. `scene_albedo.pfm` for
+    /// `scene.png`, *suffix* `"_albedo"` and *extension* `"pfm"`.
+    pub fn auxiliary_filename(&self, suffix: &str, extension: &str) -> String {
+        let path: &Path = if self.filename.is_empty() {
+            Path::new("pbrt.png")
+        } else {
+            Path::new(&self.filename)
+        };
+        let stem: &str = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("pbrt");
+        path.with_file_name(format!("{}{}.{}", stem, suffix, extension))
+            .to_string_lossy()
+            .into_owned()
+    }
+    /// Writes the linear (white balanced and exposed) pixel values to
+    /// the film's _hdr_filename_, if there is one.
+    fn write_hdr_image(&self, rgb: &[Float]) {
+        if self.hdr_filename.is_empty() {
+            return;
+        }
+        println!(
+            "Writing image {:?} with bounds {:?}",
+            self.hdr_filename, self.cropped_pixel_bounds
+        );
+        let resolution: Point2i = Point2i {
+            x: self.cropped_pixel_bounds.p_max.x - self.cropped_pixel_bounds.p_min.x,
+            y: self.cropped_pixel_bounds.p_max.y - self.cropped_pixel_bounds.p_min.y,
+        };
+        write_image(&self.hdr_filename, rgb, resolution, self.color_space);
+    }
+    /// Writes the image to the file given by the film's _filename_,
+    /// see [`write_image`](crate::core::imageio::write_image) for the
+    /// supported formats.
+    pub fn write_image_to_file(&self, splat_scale: Float) {
+        let rgb: Vec<Float> = self.get_rgb(splat_scale);
+        println!(
+            "Writing image {:?} with bounds {:?}",
+            self.filename, self.cropped_pixel_bounds
+        );
+        let resolution: Point2i = Point2i {
+            x: self.cropped_pixel_bounds.p_max.x - self.cropped_pixel_bounds.p_min.x,
+            y: self.cropped_pixel_bounds.p_max.y - self.cropped_pixel_bounds.p_min.y,
+        };
+        write_image(&self.filename, &rgb, resolution, self.color_space);
+    }
     #[cfg(not(feature = "openexr"))]
     pub fn write_image(&self, splat_scale: Float) {
         let mut rgb: Vec<Float> =
@@ -478,6 +716,14 @@ impl Film {
             rgb[start + 1] *= self.scale;
             rgb[start + 2] *= self.scale;
         }
+        self.denoise(&mut rgb);
+        self.display.adjust(&mut rgb);
+        self.write_hdr_image(&rgb);
+        for texel in rgb.chunks_mut(3) {
+            // 8-bit images are displayed as (tone mapped) sRGB
+            let display_rgb: [Float; 3] = self.display.to_display(&[texel[0], texel[1], texel[2]]);
+            texel.copy_from_slice(&display_rgb);
+        }
         let filename = "pbrt.png";
         println!(
             "Writing image {:?} with bounds {:?}",
@@ -570,10 +816,18 @@ impl Film {
             rgb[start] *= self.scale;
             rgb[start + 1] *= self.scale;
             rgb[start + 2] *= self.scale;
+        }
+        self.denoise(&mut rgb);
+        self.display.adjust(&mut rgb);
+        self.write_hdr_image(&rgb);
+        for (offset, texel) in rgb.chunks_mut(3).enumerate() {
             // copy data for OpenEXR image
-            exr[offset].0 = rgb[start];
-            exr[offset].1 = rgb[start + 1];
-            exr[offset].2 = rgb[start + 2];
+            exr[offset].0 = texel[0];
+            exr[offset].1 = texel[1];
+            exr[offset].2 = texel[2];
+            // 8-bit images are displayed as (tone mapped) sRGB
+            let display_rgb: [Float; 3] = self.display.to_display(&[texel[0], texel[1], texel[2]]);
+            texel.copy_from_slice(&display_rgb);
         }
         let filename = "pbrt.png";
         println!(
@@ -0,0 +1,515 @@
+//! Path guiding learns the distribution of incident radiance in the
+//! scene from the paths of a few training passes and uses it to
+//! importance sample directions for the path tracers.
+//!
+//! The distribution is stored in a spatial-directional tree (SD-tree,
+//! see Müller et al., "Practical Path Guiding for Efficient
+//! Light-Transport Simulation", 2017): a binary tree subdividing the
+//! scene bounds, with a quadtree over the (cylindrically mapped)
+//! sphere of directions in each of its leaves. After each training
+//! pass, leaves with many recorded samples are split and the
+//! quadtrees are refined where they hold a lot of energy.
+
+// std
+use std::f32::consts::PI;
+// pbrt
+use crate::core::geometry::{spherical_direction, spherical_phi, vec3_dot_nrmf};
+use crate::core::geometry::{Bounds3f, Point2f, Point3f, Vector3f, XYZEnum};
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::parallel::AtomicFloat;
+use crate::core::pbrt::clamp_t;
+use crate::core::pbrt::{Float, Spectrum};
+use crate::core::reflection::{Bsdf, BxdfType};
+use crate::core::rng::FLOAT_ONE_MINUS_EPSILON;
+use crate::core::sampler::Sampler;
+use crate::core::sampling::{uniform_sample_sphere, uniform_sphere_pdf};
+
+/// Maximum depth of the directional quadtrees.
+const DTREE_MAX_DEPTH: u32 = 20;
+/// A quadtree node gets subdivided if its share of the energy of the
+/// whole quadtree exceeds this fraction.
+const DTREE_THRESHOLD: Float = 0.01;
+/// A spatial leaf gets split if more samples than this (times the
+/// square root of the samples per pixel of the last pass) were
+/// recorded in it.
+const STREE_THRESHOLD: Float = 12000.0;
+/// Probability of sampling the BSDF instead of the learned
+/// distribution.
+const BSDF_SAMPLING_FRACTION: Float = 0.5;
+
+/// Maps a direction to the unit square, preserving areas.
+fn dir_to_canonical(d: &Vector3f) -> Point2f {
+    let cos_theta: Float = clamp_t(d.z, -1.0 as Float, 1.0 as Float);
+    let phi: Float = spherical_phi(d);
+    Point2f {
+        x: ((cos_theta + 1.0 as Float) * 0.5 as Float).min(FLOAT_ONE_MINUS_EPSILON),
+        y: (phi / (2.0 as Float * PI)).min(FLOAT_ONE_MINUS_EPSILON),
+    }
+}
+
+/// Inverse of [dir_to_canonical()](fn.dir_to_canonical.html).
+fn canonical_to_dir(p: &Point2f) -> Vector3f {
+    let cos_theta: Float = 2.0 as Float * p.x - 1.0 as Float;
+    let sin_theta: Float = (1.0 as Float - cos_theta * cos_theta).max(0.0).sqrt();
+    spherical_direction(sin_theta, cos_theta, 2.0 as Float * PI * p.y)
+}
+
+#[derive(Debug, Default, Clone)]
+struct QuadNode {
+    /// energy recorded in the four quadrants
+    sums: [AtomicFloat; 4],
+    /// index of the node subdividing a quadrant, 0 for leaves
+    children: [usize; 4],
+}
+
+impl QuadNode {
+    fn sum(&self, quadrant: usize) -> Float {
+        Float::from(&self.sums[quadrant])
+    }
+    fn sums(&self) -> [Float; 4] {
+        [self.sum(0), self.sum(1), self.sum(2), self.sum(3)]
+    }
+    /// Returns the quadrant containing *p* and remaps *p* to the
+    /// quadrant's unit square.
+    fn quadrant(p: &mut Point2f) -> usize {
+        let mut quadrant: usize = 0;
+        if p.x >= 0.5 as Float {
+            quadrant |= 1;
+            p.x = p.x * 2.0 as Float - 1.0 as Float;
+        } else {
+            p.x *= 2.0 as Float;
+        }
+        if p.y >= 0.5 as Float {
+            quadrant |= 2;
+            p.y = p.y * 2.0 as Float - 1.0 as Float;
+        } else {
+            p.y *= 2.0 as Float;
+        }
+        quadrant
+    }
+}
+
+/// Directional quadtree.
+#[derive(Debug, Clone)]
+struct DTree {
+    nodes: Vec<QuadNode>,
+    /// number of recorded samples
+    weight: AtomicFloat,
+}
+
+impl DTree {
+    fn new() -> Self {
+        DTree {
+            nodes: vec![QuadNode::default()],
+            weight: AtomicFloat::default(),
+        }
+    }
+    fn total(&self) -> Float {
+        self.nodes[0].sums().iter().sum()
+    }
+    fn record(&self, d: &Vector3f, irradiance: Float) {
+        self.weight.add(1.0 as Float);
+        if irradiance <= 0.0 as Float {
+            return;
+        }
+        let mut p: Point2f = dir_to_canonical(d);
+        let mut node_index: usize = 0;
+        loop {
+            let node: &QuadNode = &self.nodes[node_index];
+            let quadrant: usize = QuadNode::quadrant(&mut p);
+            node.sums[quadrant].add(irradiance);
+            if node.children[quadrant] == 0 {
+                break;
+            }
+            node_index = node.children[quadrant];
+        }
+    }
+    /// Returns the solid angle density of sampling *d*.
+    fn pdf(&self, d: &Vector3f) -> Float {
+        if self.total() <= 0.0 as Float {
+            return uniform_sphere_pdf();
+        }
+        let mut p: Point2f = dir_to_canonical(d);
+        let mut pdf: Float = 1.0 as Float;
+        let mut node_index: usize = 0;
+        loop {
+            let node: &QuadNode = &self.nodes[node_index];
+            let node_total: Float = node.sums().iter().sum();
+            let quadrant: usize = QuadNode::quadrant(&mut p);
+            if node_total <= 0.0 as Float {
+                return 0.0 as Float;
+            }
+            pdf *= 4.0 as Float * node.sum(quadrant) / node_total;
+            if pdf == 0.0 as Float || node.children[quadrant] == 0 {
+                break;
+            }
+            node_index = node.children[quadrant];
+        }
+        pdf / (4.0 as Float * PI)
+    }
+    /// Samples a direction proportionally to the recorded energy.
+    fn sample(&self, u: &Point2f) -> Vector3f {
+        if self.total() <= 0.0 as Float {
+            return uniform_sample_sphere(*u);
+        }
+        let mut u: Point2f = *u;
+        let mut origin: Point2f = Point2f::default();
+        let mut size: Float = 1.0 as Float;
+        let mut node_index: usize = 0;
+        loop {
+            let node: &QuadNode = &self.nodes[node_index];
+            let s: [Float; 4] = node.sums();
+            // choose the left or right half first, then the quadrant
+            let left: Float = s[0] + s[2];
+            let node_total: Float = left + s[1] + s[3];
+            let p_left: Float = if node_total > 0.0 as Float {
+                left / node_total
+            } else {
+                0.5 as Float
+            };
+            let qx: usize = if u.x < p_left {
+                u.x /= p_left;
+                0
+            } else {
+                u.x = (u.x - p_left) / (1.0 as Float - p_left);
+                1
+            };
+            let column: Float = s[qx] + s[qx + 2];
+            let p_bottom: Float = if column > 0.0 as Float {
+                s[qx] / column
+            } else {
+                0.5 as Float
+            };
+            let qy: usize = if u.y < p_bottom {
+                u.y /= p_bottom;
+                0
+            } else {
+                u.y = (u.y - p_bottom) / (1.0 as Float - p_bottom);
+                1
+            };
+            u.x = u.x.min(FLOAT_ONE_MINUS_EPSILON);
+            u.y = u.y.min(FLOAT_ONE_MINUS_EPSILON);
+            size *= 0.5 as Float;
+            origin.x += qx as Float * size;
+            origin.y += qy as Float * size;
+            let quadrant: usize = qx | (qy << 1);
+            if node.children[quadrant] == 0 {
+                break;
+            }
+            node_index = node.children[quadrant];
+        }
+        canonical_to_dir(&Point2f {
+            x: (origin.x + u.x * size).min(FLOAT_ONE_MINUS_EPSILON),
+            y: (origin.y + u.y * size).min(FLOAT_ONE_MINUS_EPSILON),
+        })
+    }
+    /// Returns an empty quadtree, which is subdivided where this one
+    /// holds more than *DTREE_THRESHOLD* of the energy.
+    fn refined(&self) -> DTree {
+        let mut refined: DTree = DTree::new();
+        let total: Float = self.total();
+        if total > 0.0 as Float {
+            self.refine_node(
+                Some(0),
+                self.nodes[0].sums(),
+                0,
+                1,
+                total,
+                &mut refined.nodes,
+            );
+        }
+        refined
+    }
+    fn refine_node(
+        &self,
+        old_index: Option<usize>,
+        sums: [Float; 4],
+        new_index: usize,
+        depth: u32,
+        total: Float,
+        nodes: &mut Vec<QuadNode>,
+    ) {
+        for (quadrant, sum) in sums.iter().enumerate() {
+            if depth < DTREE_MAX_DEPTH && *sum / total > DTREE_THRESHOLD {
+                let old_child: Option<usize> = old_index
+                    .map(|i| self.nodes[i].children[quadrant])
+                    .filter(|c| *c != 0);
+                // spread the energy of former leaves evenly
+                let child_sums: [Float; 4] = match old_child {
+                    Some(c) => self.nodes[c].sums(),
+                    None => [*sum * 0.25 as Float; 4],
+                };
+                let child: usize = nodes.len();
+                nodes.push(QuadNode::default());
+                nodes[new_index].children[quadrant] = child;
+                self.refine_node(old_child, child_sums, child, depth + 1, total, nodes);
+            }
+        }
+    }
+}
+
+/// The quadtree used for sampling (learned in the previous pass) and
+/// the one recording the current pass.
+#[derive(Debug, Clone)]
+struct DTreeWrapper {
+    sampling: DTree,
+    building: DTree,
+}
+
+#[derive(Debug, Clone)]
+struct STreeNode {
+    /// index of the first child (the second one follows it), 0 for
+    /// leaves
+    children: usize,
+    axis: XYZEnum,
+    d_tree: Option<DTreeWrapper>,
+}
+
+/// Spatial binary tree with a
+/// [DTreeWrapper](struct.DTreeWrapper.html) in each leaf.
+struct SdTree {
+    nodes: Vec<STreeNode>,
+    bounds: Bounds3f,
+}
+
+impl SdTree {
+    fn new(bounds: &Bounds3f) -> Self {
+        // use a cube, so the leaves stay (roughly) cubical as well
+        let diag: Vector3f = bounds.diagonal();
+        let extent: Float = diag.x.max(diag.y).max(diag.z);
+        SdTree {
+            nodes: vec![STreeNode {
+                children: 0,
+                axis: XYZEnum::X,
+                d_tree: Some(DTreeWrapper {
+                    sampling: DTree::new(),
+                    building: DTree::new(),
+                }),
+            }],
+            bounds: Bounds3f {
+                p_min: bounds.p_min,
+                p_max: bounds.p_min
+                    + Vector3f {
+                        x: extent,
+                        y: extent,
+                        z: extent,
+                    },
+            },
+        }
+    }
+    fn leaf_index(&self, p: &Point3f) -> usize {
+        let o: Vector3f = self.bounds.offset(p);
+        let mut o: [Float; 3] = [
+            clamp_t(o.x, 0.0 as Float, 1.0 as Float),
+            clamp_t(o.y, 0.0 as Float, 1.0 as Float),
+            clamp_t(o.z, 0.0 as Float, 1.0 as Float),
+        ];
+        let mut node_index: usize = 0;
+        loop {
+            let node: &STreeNode = &self.nodes[node_index];
+            if node.children == 0 {
+                return node_index;
+            }
+            let a: usize = node.axis as usize;
+            if o[a] < 0.5 as Float {
+                o[a] *= 2.0 as Float;
+                node_index = node.children;
+            } else {
+                o[a] = o[a] * 2.0 as Float - 1.0 as Float;
+                node_index = node.children + 1;
+            }
+        }
+    }
+    fn d_tree(&self, leaf_index: usize) -> &DTreeWrapper {
+        self.nodes[leaf_index].d_tree.as_ref().unwrap()
+    }
+    /// Splits all leaves with more than *threshold* recorded samples.
+    fn subdivide(&mut self, threshold: Float) {
+        let mut stack: Vec<usize> = vec![0];
+        while let Some(node_index) = stack.pop() {
+            if self.nodes[node_index].children != 0 {
+                let children: usize = self.nodes[node_index].children;
+                stack.push(children);
+                stack.push(children + 1);
+                continue;
+            }
+            let weight: Float = Float::from(&self.d_tree(node_index).building.weight);
+            if weight <= threshold {
+                continue;
+            }
+            // both children start with a copy of the quadtrees and
+            // half of the samples
+            let mut d_tree: DTreeWrapper = self.nodes[node_index].d_tree.take().unwrap();
+            d_tree.building.weight = AtomicFloat::new(weight * 0.5 as Float);
+            let axis: XYZEnum = match self.nodes[node_index].axis {
+                XYZEnum::X => XYZEnum::Y,
+                XYZEnum::Y => XYZEnum::Z,
+                XYZEnum::Z => XYZEnum::X,
+            };
+            let children: usize = self.nodes.len();
+            for _ in 0..2 {
+                self.nodes.push(STreeNode {
+                    children: 0,
+                    axis,
+                    d_tree: Some(d_tree.clone()),
+                });
+            }
+            self.nodes[node_index].children = children;
+            stack.push(children);
+            stack.push(children + 1);
+        }
+    }
+}
+
+/// A path vertex sampled from the guiding distribution. The radiance
+/// arriving along *wi* is accumulated while tracing the rest of the
+/// path and finally recorded in the SD-tree.
+pub struct GuideVertex {
+    leaf_index: usize,
+    wi: Vector3f,
+    /// path throughput after scattering at the vertex
+    throughput: Spectrum,
+    radiance: Spectrum,
+    pdf: Float,
+}
+
+impl GuideVertex {
+    /// Adds a contribution *l* to the path's radiance, which was
+    /// found after this vertex.
+    fn add_radiance(&mut self, l: &Spectrum) {
+        for c in 0..3 {
+            if self.throughput.c[c] > 0.0 as Float {
+                self.radiance.c[c] += l.c[c] / self.throughput.c[c];
+            }
+        }
+    }
+}
+
+/// Adds a contribution *l* to the radiance of all *vertices*.
+pub fn add_guide_radiance(vertices: &mut [GuideVertex], l: &Spectrum) {
+    for v in vertices.iter_mut() {
+        v.add_radiance(l);
+    }
+}
+
+/// Online-learned guiding distribution for the path tracers.
+pub struct PathGuide {
+    sd_tree: SdTree,
+    /// number of training passes
+    pub passes: u32,
+    /// record the radiance of traced paths (during training passes)
+    pub recording: bool,
+}
+
+impl PathGuide {
+    pub fn new(bounds: &Bounds3f, passes: u32) -> Self {
+        PathGuide {
+            sd_tree: SdTree::new(bounds),
+            passes,
+            recording: false,
+        }
+    }
+    /// Only BSDFs without specular components are guided.
+    pub fn guides(&self, bsdf: &Bsdf) -> bool {
+        let bsdf_flags: u8 = BxdfType::BsdfAll as u8 & !(BxdfType::BsdfSpecular as u8);
+        let n_components: u8 = bsdf.num_components(bsdf_flags);
+        n_components > 0 && n_components == bsdf.num_components(BxdfType::BsdfAll as u8)
+    }
+    /// Samples a direction at *isect* from its BSDF or the learned
+    /// distribution, otherwise like
+    /// [Bsdf::sample_f()](../reflection/struct.Bsdf.html#method.sample_f).
+    /// The returned *pdf* is the one of the mixture of both
+    /// strategies (one-sample MIS with the balance heuristic).
+    pub fn sample_f(
+        &self,
+        isect: &SurfaceInteraction,
+        wi: &mut Vector3f,
+        sampler: &mut Sampler,
+        pdf: &mut Float,
+        sampled_type: &mut u8,
+    ) -> Spectrum {
+        let bsdf: &Bsdf = isect.bsdf.as_ref().unwrap();
+        let wo: &Vector3f = &isect.common.wo;
+        let leaf_index: usize = self.sd_tree.leaf_index(&isect.common.p);
+        let d_tree: &DTree = &self.sd_tree.d_tree(leaf_index).sampling;
+        let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
+        let u_choice: Float = sampler.get_1d();
+        let u: Point2f = sampler.get_2d();
+        let f: Spectrum;
+        let mut bsdf_pdf: Float = 0.0 as Float;
+        if u_choice < BSDF_SAMPLING_FRACTION {
+            f = bsdf.sample_f(wo, wi, &u, &mut bsdf_pdf, bsdf_flags, sampled_type);
+            if f.is_black() || bsdf_pdf == 0.0 as Float {
+                *pdf = 0.0 as Float;
+                return f;
+            }
+        } else {
+            *wi = d_tree.sample(&u);
+            f = bsdf.f(wo, wi, bsdf_flags);
+            bsdf_pdf = bsdf.pdf(wo, wi, bsdf_flags);
+            *sampled_type =
+                if vec3_dot_nrmf(wi, &bsdf.ng) * vec3_dot_nrmf(wo, &bsdf.ng) > 0.0 as Float {
+                    BxdfType::BsdfReflection as u8
+                } else {
+                    BxdfType::BsdfTransmission as u8
+                };
+        }
+        *pdf = BSDF_SAMPLING_FRACTION * bsdf_pdf
+            + (1.0 as Float - BSDF_SAMPLING_FRACTION) * d_tree.pdf(wi);
+        f
+    }
+    /// The density with which [sample_f()](#method.sample_f) samples
+    /// *wi* at *p*.
+    pub fn pdf(&self, bsdf: &Bsdf, p: &Point3f, wo: &Vector3f, wi: &Vector3f) -> Float {
+        let leaf_index: usize = self.sd_tree.leaf_index(p);
+        let d_tree: &DTree = &self.sd_tree.d_tree(leaf_index).sampling;
+        let bsdf_pdf: Float = bsdf.pdf(wo, wi, BxdfType::BsdfAll as u8);
+        BSDF_SAMPLING_FRACTION * bsdf_pdf + (1.0 as Float - BSDF_SAMPLING_FRACTION) * d_tree.pdf(wi)
+    }
+    /// Creates the vertex recording the radiance arriving at *p* along
+    /// *wi*, which was sampled with [sample_f()](#method.sample_f).
+    pub fn new_vertex(
+        &self,
+        p: &Point3f,
+        wi: &Vector3f,
+        throughput: &Spectrum,
+        pdf: Float,
+    ) -> GuideVertex {
+        GuideVertex {
+            leaf_index: self.sd_tree.leaf_index(p),
+            wi: *wi,
+            throughput: *throughput,
+            radiance: Spectrum::default(),
+            pdf,
+        }
+    }
+    /// Records the incident radiance of the *vertices* of a path.
+    pub fn record(&self, vertices: &[GuideVertex]) {
+        for v in vertices {
+            let radiance: Float = v.radiance.y();
+            if v.pdf > 0.0 as Float && radiance.is_finite() {
+                self.sd_tree
+                    .d_tree(v.leaf_index)
+                    .building
+                    .record(&v.wi, radiance.max(0.0 as Float) / v.pdf);
+            }
+        }
+    }
+    /// Makes the distribution recorded in the last pass (which traced
+    /// *spp* samples per pixel) available for sampling and prepares
+    /// the recording of the next one.
+    pub fn refine(&mut self, spp: i64) {
+        for node in self.sd_tree.nodes.iter_mut() {
+            if let Some(ref mut d_tree) = node.d_tree {
+                d_tree.sampling = d_tree.building.clone();
+            }
+        }
+        self.sd_tree
+            .subdivide(STREE_THRESHOLD * (spp as Float).sqrt());
+        for node in self.sd_tree.nodes.iter_mut() {
+            if let Some(ref mut d_tree) = node.d_tree {
+                d_tree.building = d_tree.sampling.refined();
+            }
+        }
+    }
+}
@@ -0,0 +1,316 @@
+//! Read images from disk and return their texels as RGB spectra.
+//!
+//! The file format is chosen by looking at the extension of the file
+//! name: OpenEXR (`.exr`), Radiance (`.hdr`), portable float maps
+//! (`.pfm`), Targa (`.tga`) and PNG (`.png`) are supported. Any other
+//! extension is handed to the `image` crate, which will try to guess
+//! the format from the file contents.
+//!
+//! Images are written either as OpenEXR files (`.exr`) or portable
+//! float maps (`.pfm`) with 32-bit floating point values or in an
+//! 8-bit format chosen by the `image` crate from the extension.
+
+// std
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+// others
+use byteorder::{BigEndian, ByteOrder, LittleEndian, WriteBytesExt};
+use image::{ColorType, DynamicImage, ImageError, ImageResult, Rgb32FImage};
+// pbrt
+use crate::core::colorspace::{RGBColorSpace, TextureColorSpace};
+use crate::core::geometry::Point2i;
+use crate::core::pbrt::{clamp_t, gamma_correct};
+use crate::core::pbrt::{Float, Spectrum};
+
+// see fileutil.cpp
+
+/// Case-insensitive check if the file name ends with the given
+/// extension (without the leading dot).
+pub fn has_extension(name: &str, ext: &str) -> bool {
+    match Path::new(name).extension() {
+        Some(found) => found.to_string_lossy().eq_ignore_ascii_case(ext),
+        None => false,
+    }
+}
+
+/// Low dynamic range formats store sRGB encoded values, all floating
+/// point formats store linear values.
+pub fn is_srgb_encoded(name: &str) -> bool {
+    !(has_extension(name, "exr") || has_extension(name, "hdr") || has_extension(name, "pfm"))
+}
+
+// see imageio.cpp
+
+/// Read an image and return its texels (top row first) as stored in
+/// the file, e.g. 8-bit values are scaled to [0, 1], but no inverse
+/// gamma correction is applied. Use [`read_linear_image`] if the
+/// texels are used as radiance values.
+pub fn read_image(name: &str, resolution: &mut Point2i) -> Option<Vec<Spectrum>> {
+    if has_extension(name, "pfm") {
+        return read_image_pfm(name, resolution);
+    }
+    // EXR, HDR, TGA, PNG (and everything else the image crate knows)
+    let img_result: ImageResult<DynamicImage> = image::open(Path::new(name));
+    match img_result {
+        Ok(buf) => {
+            let rgb = buf.to_rgb32f();
+            resolution.x = rgb.width() as i32;
+            resolution.y = rgb.height() as i32;
+            let texels: Vec<Spectrum> = rgb
+                .pixels()
+                .map(|p| Spectrum::rgb(p[0] as Float, p[1] as Float, p[2] as Float))
+                .collect();
+            Some(texels)
+        }
+        Err(err) => {
+            println!("ERROR: Unable to read image file {:?}: {}", name, err);
+            None
+        }
+    }
+}
+
+/// Read an image like [`read_image`] does, but undo the sRGB encoding
+/// of low dynamic range formats, so all returned texels are linear
+/// values of the given working color space.
+pub fn read_linear_image(
+    name: &str,
+    resolution: &mut Point2i,
+    working_color_space: RGBColorSpace,
+) -> Option<Vec<Spectrum>> {
+    let texels: Vec<Spectrum> = read_image(name, resolution)?;
+    let colorspace: TextureColorSpace = if is_srgb_encoded(name) {
+        TextureColorSpace::SRGB
+    } else {
+        TextureColorSpace::Linear
+    };
+    Some(
+        texels
+            .iter()
+            .map(|p| colorspace.to_working(p, working_color_space))
+            .collect(),
+    )
+}
+
+/// Write RGB values (top row first) of the working color space to
+/// disk. OpenEXR files and portable float maps store the linear
+/// values, all other formats are converted to sRGB and gamma
+/// corrected.
+pub fn write_image(
+    name: &str,
+    rgb: &[Float],
+    resolution: Point2i,
+    working_color_space: RGBColorSpace,
+) {
+    let width: u32 = resolution.x as u32;
+    let height: u32 = resolution.y as u32;
+    assert_eq!(rgb.len(), (3 * width * height) as usize);
+    let result: ImageResult<()> = if has_extension(name, "exr") {
+        let data: Vec<f32> = rgb.to_vec();
+        let buffer: Rgb32FImage = Rgb32FImage::from_raw(width, height, data).unwrap();
+        buffer.save(Path::new(name))
+    } else if has_extension(name, "pfm") {
+        write_image_pfm(name, rgb, resolution).map_err(ImageError::from)
+    } else {
+        let mut buffer: Vec<u8> = Vec::with_capacity(rgb.len());
+        for texel in rgb.chunks(3) {
+            let display_rgb: [Float; 3] = working_color_space
+                .convert_rgb(&[texel[0], texel[1], texel[2]], RGBColorSpace::SRGB);
+            for value in display_rgb.iter() {
+                buffer.push(clamp_t(
+                    255.0 as Float * gamma_correct(*value) + 0.5,
+                    0.0 as Float,
+                    255.0 as Float,
+                ) as u8);
+            }
+        }
+        image::save_buffer(Path::new(name), &buffer, width, height, ColorType::Rgb8)
+    };
+    if let Err(err) = result {
+        println!("ERROR: Unable to write image file {:?}: {}", name, err);
+    }
+}
+
+fn read_pfm_word<R: BufRead>(reader: &mut R) -> Option<String> {
+    let mut word: String = String::new();
+    loop {
+        let mut byte: [u8; 1] = [0_u8; 1];
+        if reader.read_exact(&mut byte).is_err() {
+            break;
+        }
+        let c: char = byte[0] as char;
+        if c.is_ascii_whitespace() {
+            if word.is_empty() {
+                continue;
+            }
+            // exactly one whitespace character separates the header
+            // from the (binary) pixel data
+            break;
+        }
+        word.push(c);
+    }
+    if word.is_empty() {
+        None
+    } else {
+        Some(word)
+    }
+}
+
+/// Read a portable float map (color "PF" or grayscale "Pf"). The
+/// rows are stored bottom to top and get flipped on the way.
+fn read_image_pfm(name: &str, resolution: &mut Point2i) -> Option<Vec<Spectrum>> {
+    let file = match File::open(name) {
+        Ok(f) => f,
+        Err(_) => {
+            println!("ERROR: Unable to open file {:?}", name);
+            return None;
+        }
+    };
+    let mut reader = BufReader::new(file);
+    // read either "Pf" or "PF"
+    let n_channels: usize = match read_pfm_word(&mut reader).as_deref() {
+        Some("PF") => 3,
+        Some("Pf") => 1,
+        _ => {
+            println!("ERROR: Reading PFM file {:?} failed (bad header)", name);
+            return None;
+        }
+    };
+    // read the rest of the header: width, height, and scale
+    let width: Option<i32> = read_pfm_word(&mut reader).and_then(|w| w.parse().ok());
+    let height: Option<i32> = read_pfm_word(&mut reader).and_then(|w| w.parse().ok());
+    let scale: Option<f32> = read_pfm_word(&mut reader).and_then(|w| w.parse().ok());
+    let (width, height, scale) = match (width, height, scale) {
+        (Some(w), Some(h), Some(s)) if w > 0 && h > 0 => (w, h, s),
+        _ => {
+            println!("ERROR: Reading PFM file {:?} failed (bad header)", name);
+            return None;
+        }
+    };
+    // read the data
+    let n_floats: usize = n_channels * width as usize * height as usize;
+    let mut bytes: Vec<u8> = vec![0_u8; n_floats * 4];
+    if reader.read_exact(&mut bytes).is_err() {
+        println!("ERROR: Reading PFM file {:?} failed (premature end)", name);
+        return None;
+    }
+    let mut data: Vec<f32> = vec![0.0_f32; n_floats];
+    // a negative scale indicates little endian data
+    if scale < 0.0 {
+        LittleEndian::read_f32_into(&bytes, &mut data);
+    } else {
+        BigEndian::read_f32_into(&bytes, &mut data);
+    }
+    // apply endian conversion and scale if appropriate
+    let abs_scale: f32 = scale.abs();
+    if abs_scale != 1.0 {
+        for v in data.iter_mut() {
+            *v *= abs_scale;
+        }
+    }
+    // create RGBs (flip in Y, as P*M has the origin at the lower left corner)
+    resolution.x = width;
+    resolution.y = height;
+    let mut texels: Vec<Spectrum> = Vec::with_capacity((width * height) as usize);
+    for y in (0..height as usize).rev() {
+        for x in 0..width as usize {
+            let offset: usize = (y * width as usize + x) * n_channels;
+            if n_channels == 1 {
+                texels.push(Spectrum::new(data[offset] as Float));
+            } else {
+                texels.push(Spectrum::rgb(
+                    data[offset] as Float,
+                    data[offset + 1] as Float,
+                    data[offset + 2] as Float,
+                ));
+            }
+        }
+    }
+    Some(texels)
+}
+
+/// Write a color portable float map ("PF") with little endian data,
+/// flipping the rows (see [`read_image_pfm`]).
+fn write_image_pfm(name: &str, rgb: &[Float], resolution: Point2i) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(name)?);
+    // a negative scale indicates little endian data
+    write!(writer, "PF\n{} {}\n-1\n", resolution.x, resolution.y)?;
+    let width: usize = resolution.x as usize;
+    for row in rgb.chunks(3 * width.max(1)).rev() {
+        for value in row {
+            writer.write_f32::<LittleEndian>(*value)?;
+        }
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file in the temporary directory, which is removed again.
+    struct TempFile(String);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("rs_pbrt_{}_{}", std::process::id(), name));
+            TempFile(path.to_string_lossy().into_owned())
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn read_grayscale_big_endian_pfm() {
+        // 2 x 2 pixels, big endian data scaled by 2, bottom row first
+        let file: TempFile = TempFile::new("gray.pfm");
+        let mut bytes: Vec<u8> = b"Pf\n2 2\n2.0\n".to_vec();
+        for v in [1.0_f32, 2.0, 3.0, 4.0].iter() {
+            bytes.write_f32::<BigEndian>(*v).unwrap();
+        }
+        std::fs::write(&file.0, &bytes).unwrap();
+        let mut resolution: Point2i = Point2i::default();
+        let texels: Vec<Spectrum> = read_image_pfm(&file.0, &mut resolution).unwrap();
+        assert_eq!((resolution.x, resolution.y), (2, 2));
+        let values: Vec<Float> = texels.iter().map(|s| s.c[0]).collect();
+        assert_eq!(values, vec![6.0, 8.0, 2.0, 4.0]);
+        assert!(texels.iter().all(|s| s.c[0] == s.c[1] && s.c[0] == s.c[2]));
+    }
+
+    #[test]
+    fn write_and_read_pfm() {
+        // 3 x 2 pixels, top row first
+        let file: TempFile = TempFile::new("rgb.pfm");
+        let rgb: Vec<Float> = (0..18).map(|i| i as Float * 0.5 - 1.0).collect();
+        write_image_pfm(&file.0, &rgb, Point2i { x: 3, y: 2 }).unwrap();
+        let mut resolution: Point2i = Point2i::default();
+        let texels: Vec<Spectrum> = read_image_pfm(&file.0, &mut resolution).unwrap();
+        assert_eq!((resolution.x, resolution.y), (3, 2));
+        let read: Vec<Float> = texels.iter().flat_map(|s| s.c.iter().copied()).collect();
+        assert_eq!(read, rgb);
+    }
+
+    #[test]
+    fn reject_bad_pfm_files() {
+        let file: TempFile = TempFile::new("bad.pfm");
+        let mut resolution: Point2i = Point2i::default();
+        for bytes in [
+            &b"P6\n2 2\n-1\n"[..],
+            &b"PF\n2 -2\n-1\n"[..],
+            &b"PF\n2 2\n"[..],
+            // premature end of the data
+            &b"PF\n1 1\n-1\n\0\0\0\0"[..],
+        ]
+        .iter()
+        {
+            std::fs::write(&file.0, bytes).unwrap();
+            assert!(read_image_pfm(&file.0, &mut resolution).is_none());
+        }
+        assert!(read_image_pfm("does_not_exist.pfm", &mut resolution).is_none());
+    }
+}
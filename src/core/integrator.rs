@@ -4,13 +4,17 @@
 // std
 use std::sync::Arc;
 // pbrt
+use crate::accelerators::bvh::MAX_PACKET_SIZE;
 use crate::blockqueue::BlockQueue;
 use crate::core::camera::{Camera, CameraSample};
+use crate::core::denoiser::FirstHitFeatures;
 use crate::core::geometry::{pnt2_inside_exclusivei, vec3_abs_dot_nrmf};
 use crate::core::geometry::{Bounds2i, Point2f, Point2i, Ray, Vector2i, Vector3f};
+use crate::core::guiding::PathGuide;
 use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
 use crate::core::light::is_delta_light;
 use crate::core::light::{Light, VisibilityTester};
+use crate::core::lightdistrib::LocalLightDistribution;
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::reflection::BxdfType;
 use crate::core::sampler::Sampler;
@@ -20,9 +24,12 @@ use crate::core::scene::Scene;
 use crate::integrators::ao::AOIntegrator;
 use crate::integrators::bdpt::BDPTIntegrator;
 use crate::integrators::directlighting::DirectLightingIntegrator;
+use crate::integrators::gpt::GPTIntegrator;
+use crate::integrators::lighttracer::LightTracerIntegrator;
 use crate::integrators::mlt::MLTIntegrator;
 use crate::integrators::path::PathIntegrator;
 use crate::integrators::sppm::SPPMIntegrator;
+use crate::integrators::vcm::VCMIntegrator;
 use crate::integrators::volpath::VolPathIntegrator;
 use crate::integrators::whitted::WhittedIntegrator;
 
@@ -30,18 +37,24 @@ use crate::integrators::whitted::WhittedIntegrator;
 
 pub enum Integrator {
     BDPT(BDPTIntegrator),
+    GPT(GPTIntegrator),
+    LightTracer(LightTracerIntegrator),
     MLT(MLTIntegrator),
     SPPM(SPPMIntegrator),
     Sampler(SamplerIntegrator),
+    VCM(VCMIntegrator),
 }
 
 impl Integrator {
     pub fn render(&mut self, scene: &Scene, num_threads: u8) {
         match self {
             Integrator::BDPT(integrator) => integrator.render(scene, num_threads),
+            Integrator::GPT(integrator) => integrator.render(scene, num_threads),
+            Integrator::LightTracer(integrator) => integrator.render(scene, num_threads),
             Integrator::MLT(integrator) => integrator.render(scene, num_threads),
             Integrator::SPPM(integrator) => integrator.render(scene, num_threads),
             Integrator::Sampler(integrator) => integrator.render(scene, num_threads),
+            Integrator::VCM(integrator) => integrator.render(scene, num_threads),
         }
     }
 }
@@ -71,153 +84,206 @@ impl SamplerIntegrator {
         let film = self.get_camera().get_film();
         let sample_bounds: Bounds2i = film.get_sample_bounds();
         self.preprocess(scene);
-        let sample_extent: Vector2i = sample_bounds.diagonal();
-        let tile_size: i32 = 16;
-        let x: i32 = (sample_extent.x + tile_size - 1) / tile_size;
-        let y: i32 = (sample_extent.y + tile_size - 1) / tile_size;
-        let n_tiles: Point2i = Point2i { x, y };
-        // TODO: ProgressReporter reporter(nTiles.x * nTiles.y, "Rendering");
         let num_cores = if num_threads == 0_u8 {
             num_cpus::get()
         } else {
             num_threads as usize
         };
+        self.train_path_guide(scene, num_cores);
         println!("Rendering with {:?} thread(s) ...", num_cores);
-        {
-            let block_queue = BlockQueue::new(
-                (
-                    (n_tiles.x * tile_size) as u32,
-                    (n_tiles.y * tile_size) as u32,
-                ),
-                (tile_size as u32, tile_size as u32),
-                (0, 0),
-            );
-            let integrator = &self;
-            let bq = &block_queue;
-            let sampler = &self.get_sampler();
-            let camera = &self.get_camera();
-            let film = &film;
-            let pixel_bounds = &self.get_pixel_bounds();
-            crossbeam::scope(|scope| {
-                let (pixel_tx, pixel_rx) = crossbeam_channel::bounded(num_cores);
-                // spawn worker threads
-                for _ in 0..num_cores {
-                    let pixel_tx = pixel_tx.clone();
-                    let mut tile_sampler: Box<Sampler> = sampler.clone_with_seed(0_u64);
-                    scope.spawn(move |_| {
-                        while let Some((x, y)) = bq.next() {
-                            let tile: Point2i = Point2i {
-                                x: x as i32,
-                                y: y as i32,
-                            };
-                            let seed: i32 = tile.y * n_tiles.x + tile.x;
-                            tile_sampler.reseed(seed as u64);
-                            let x0: i32 = sample_bounds.p_min.x + tile.x * tile_size;
-                            let x1: i32 = std::cmp::min(x0 + tile_size, sample_bounds.p_max.x);
-                            let y0: i32 = sample_bounds.p_min.y + tile.y * tile_size;
-                            let y1: i32 = std::cmp::min(y0 + tile_size, sample_bounds.p_max.y);
-                            let tile_bounds: Bounds2i =
-                                Bounds2i::new(Point2i { x: x0, y: y0 }, Point2i { x: x1, y: y1 });
-                            // println!("Starting image tile {:?}", tile_bounds);
-                            let mut film_tile = film.get_film_tile(&tile_bounds);
-                            for pixel in &tile_bounds {
-                                tile_sampler.start_pixel(pixel);
-                                if !pnt2_inside_exclusivei(pixel, &pixel_bounds) {
-                                    continue;
-                                }
-                                let mut done: bool = false;
-                                while !done {
-                                    // let's use the copy_arena crate instead of pbrt's MemoryArena
-                                    // let mut arena: Arena = Arena::with_capacity(262144); // 256kB
-
-                                    // initialize _CameraSample_ for current sample
-                                    let camera_sample: CameraSample =
-                                        tile_sampler.get_camera_sample(pixel);
-                                    // generate camera ray for current sample
-                                    let mut ray: Ray = Ray::default();
-                                    let ray_weight: Float =
-                                        camera.generate_ray_differential(&camera_sample, &mut ray);
-                                    ray.scale_differentials(
-                                        1.0 as Float
-                                            / (tile_sampler.get_samples_per_pixel() as Float)
-                                                .sqrt(),
-                                    );
-                                    // TODO: ++nCameraRays;
-                                    // evaluate radiance along camera ray
-                                    let mut l: Spectrum = Spectrum::new(0.0 as Float);
-                                    let y: Float = l.y();
-                                    if ray_weight > 0.0 {
-                                        // ADDED
-                                        let clipping_start: Float = camera.get_clipping_start();
-                                        if clipping_start > 0.0 as Float {
-                                            // adjust ray origin for near clipping
-                                            camera
-                                                .adjust_to_clipping_start(&camera_sample, &mut ray);
-                                        }
-                                        // ADDED
-                                        l = integrator.li(
-                                            &mut ray,
-                                            scene,
-                                            &mut tile_sampler, // &mut arena,
-                                            0_i32,
-                                        );
-                                    }
-                                    if l.has_nans() {
-                                        println!(
-                                            "Not-a-number radiance value returned for pixel \
-                                                     ({:?}, {:?}), sample {:?}. Setting to black.",
-                                            pixel.x,
-                                            pixel.y,
-                                            tile_sampler.get_current_sample_number()
-                                        );
-                                        l = Spectrum::new(0.0);
-                                    } else if y < -10.0e-5 as Float {
-                                        println!(
-                                            "Negative luminance value, {:?}, returned for pixel \
-                                                 ({:?}, {:?}), sample {:?}. Setting to black.",
-                                            y,
-                                            pixel.x,
-                                            pixel.y,
-                                            tile_sampler.get_current_sample_number()
-                                        );
-                                        l = Spectrum::new(0.0);
-                                    } else if y.is_infinite() {
-                                        println!(
-                                            "Infinite luminance value returned for pixel ({:?}, \
-                                                 {:?}), sample {:?}. Setting to black.",
-                                            pixel.x,
-                                            pixel.y,
-                                            tile_sampler.get_current_sample_number()
-                                        );
-                                        l = Spectrum::new(0.0);
-                                    }
-                                    // println!("Camera sample: {:?} -> ray: {:?} -> L = {:?}",
-                                    //          camera_sample, ray, l);
-                                    // add camera ray's contribution to image
-                                    film_tile.add_sample(camera_sample.p_film, &mut l, ray_weight);
-                                    done = !tile_sampler.start_next_sample();
-                                } // arena is dropped here !
+        let integrator = &self;
+        let camera = &self.get_camera();
+        let film = &film;
+        let pixel_bounds = &self.get_pixel_bounds();
+        let collect_features: bool = film.collects_features();
+        render_tiles(
+            &sample_bounds,
+            self.get_sampler(),
+            num_cores,
+            0_u32,
+            |tile_bounds: &Bounds2i, tile_sampler: &mut Sampler| {
+                let mut film_tile = film.get_film_tile(tile_bounds);
+                for pixel in tile_bounds {
+                    tile_sampler.start_pixel(pixel);
+                    if !pnt2_inside_exclusivei(pixel, &pixel_bounds) {
+                        continue;
+                    }
+                    let mut done: bool = false;
+                    while !done {
+                        // generate the camera rays of the next
+                        // samples of the pixel (several at once
+                        // to trace them as a ray packet)
+                        let first_sample: i64 = tile_sampler.get_current_sample_number();
+                        let mut camera_samples: Vec<CameraSample> = Vec::new();
+                        let mut rays: Vec<Ray> = Vec::new();
+                        let mut ray_weights: Vec<Float> = Vec::new();
+                        loop {
+                            // initialize _CameraSample_ for current sample
+                            let camera_sample: CameraSample = tile_sampler.get_camera_sample(pixel);
+                            // generate camera ray for current sample
+                            let mut ray: Ray = Ray::default();
+                            let ray_weight: Float = generate_camera_ray(
+                                camera,
+                                &camera_sample,
+                                &mut ray,
+                                tile_sampler.get_samples_per_pixel(),
+                            );
+                            camera_samples.push(camera_sample);
+                            rays.push(ray);
+                            ray_weights.push(ray_weight);
+                            if rays.len() == MAX_PACKET_SIZE || !tile_sampler.start_next_sample() {
+                                break;
                             }
-                            // send the tile through the channel to main thread
-                            pixel_tx
-                                .send(film_tile)
-                                .unwrap_or_else(|_| panic!("Failed to send tile"));
                         }
-                    });
-                }
-                // spawn thread to collect pixels and render image to file
-                scope.spawn(move |_| {
-                    for _ in pbr::PbIter::new(0..bq.len()) {
-                        let film_tile = pixel_rx.recv().unwrap();
-                        // merge image tile into _Film_
-                        film.merge_film_tile(&film_tile);
+                        let mut isects: Vec<SurfaceInteraction> =
+                            rays.iter().map(|_| SurfaceInteraction::default()).collect();
+                        let mut hits: Vec<bool> = vec![false; rays.len()];
+                        // rays with zero weight might be invalid
+                        let traced: bool = ray_weights.iter().all(|w| *w > 0.0 as Float);
+                        if traced {
+                            scene.intersect_packet(&rays, &mut isects, &mut hits);
+                        }
+                        for (k, (mut ray, mut isect)) in rays.into_iter().zip(isects).enumerate() {
+                            let camera_sample: CameraSample = camera_samples[k];
+                            let ray_weight: Float = ray_weights[k];
+                            // continue the sample where its camera
+                            // sample left off
+                            tile_sampler.resume_after_camera_sample(first_sample + k as i64);
+                            // TODO: ++nCameraRays;
+                            // features of the first hit for the denoiser
+                            let mut features: FirstHitFeatures = FirstHitFeatures::default();
+                            // evaluate radiance along camera ray
+                            let mut l: Spectrum = Spectrum::new(0.0 as Float);
+                            let y: Float = l.y();
+                            if ray_weight > 0.0 {
+                                let hit: bool = if traced {
+                                    hits[k]
+                                } else {
+                                    scene.intersect(&ray, &mut isect)
+                                };
+                                l = integrator.li_with_isect(
+                                    &mut ray,
+                                    hit,
+                                    isect,
+                                    scene,
+                                    tile_sampler, // &mut arena,
+                                    0_i32,
+                                    if collect_features {
+                                        Some(&mut features)
+                                    } else {
+                                        None
+                                    },
+                                );
+                            }
+                            if l.has_nans() {
+                                println!(
+                                    "Not-a-number radiance value returned for pixel \
+                                     ({:?}, {:?}), sample {:?}. Setting to black.",
+                                    pixel.x,
+                                    pixel.y,
+                                    tile_sampler.get_current_sample_number()
+                                );
+                                l = Spectrum::new(0.0);
+                            } else if y < -10.0e-5 as Float {
+                                println!(
+                                    "Negative luminance value, {:?}, returned for pixel \
+                                     ({:?}, {:?}), sample {:?}. Setting to black.",
+                                    y,
+                                    pixel.x,
+                                    pixel.y,
+                                    tile_sampler.get_current_sample_number()
+                                );
+                                l = Spectrum::new(0.0);
+                            } else if y.is_infinite() {
+                                println!(
+                                    "Infinite luminance value returned for pixel ({:?}, \
+                                     {:?}), sample {:?}. Setting to black.",
+                                    pixel.x,
+                                    pixel.y,
+                                    tile_sampler.get_current_sample_number()
+                                );
+                                l = Spectrum::new(0.0);
+                            }
+                            // println!("Camera sample: {:?} -> ray: {:?} -> L = {:?}",
+                            //          camera_sample, ray, l);
+                            // add camera ray's contribution to image
+                            film_tile.add_sample(camera_sample.p_film, &mut l, ray_weight);
+                            if collect_features {
+                                film_tile.add_features(
+                                    camera_sample.p_film,
+                                    &l,
+                                    &features.albedo,
+                                    &features.normal,
+                                );
+                            }
+                            done = !tile_sampler.start_next_sample();
+                        }
                     }
-                });
-            })
-            .unwrap();
-        }
+                }
+                film_tile
+            },
+            // merge image tile into _Film_
+            |film_tile| film.merge_film_tile(&film_tile),
+        );
         film.write_image(1.0 as Float);
     }
+    /// Runs the training passes of the
+    /// [PathGuide](../guiding/struct.PathGuide.html), if the
+    /// integrator uses one. Pass *k* traces 2^k samples per pixel,
+    /// whose radiance is recorded, but not added to the image.
+    fn train_path_guide(&mut self, scene: &Scene, num_cores: usize) {
+        let passes: u32 = match self.get_path_guide() {
+            Some(guide) => guide.passes,
+            None => return,
+        };
+        let max_spp: i64 = self.get_sampler().get_samples_per_pixel();
+        for pass in 0..passes {
+            let spp: i64 = (1_i64 << pass.min(30)).min(max_spp);
+            println!(
+                "Training path guide, pass {} of {} ({} spp) ...",
+                pass + 1,
+                passes,
+                spp
+            );
+            if let Some(guide) = self.get_path_guide_mut() {
+                guide.recording = true;
+            }
+            self.trace_training_pass(scene, num_cores, pass, spp);
+            if let Some(guide) = self.get_path_guide_mut() {
+                guide.recording = false;
+                guide.refine(spp);
+            }
+        }
+    }
+    fn trace_training_pass(&self, scene: &Scene, num_cores: usize, pass: u32, spp: i64) {
+        let integrator = &self;
+        let camera = &self.get_camera();
+        // different seeds for every pass
+        render_tiles(
+            &self.get_pixel_bounds(),
+            self.get_sampler(),
+            num_cores,
+            pass + 1,
+            |tile_bounds: &Bounds2i, tile_sampler: &mut Sampler| {
+                for pixel in tile_bounds {
+                    tile_sampler.start_pixel(pixel);
+                    for _ in 0..spp {
+                        let camera_sample: CameraSample = tile_sampler.get_camera_sample(pixel);
+                        let mut ray: Ray = Ray::default();
+                        let ray_weight: Float =
+                            generate_camera_ray(camera, &camera_sample, &mut ray, spp);
+                        if ray_weight > 0.0 {
+                            integrator.li(&mut ray, scene, tile_sampler, 0_i32);
+                        }
+                        if !tile_sampler.start_next_sample() {
+                            break;
+                        }
+                    }
+                }
+            },
+            |_| {},
+        );
+    }
     pub fn li(&self, ray: &mut Ray, scene: &Scene, sampler: &mut Sampler, depth: i32) -> Spectrum {
         match self {
             SamplerIntegrator::AO(integrator) => integrator.li(ray, scene, sampler, depth),
@@ -229,6 +295,34 @@ impl SamplerIntegrator {
             SamplerIntegrator::Whitted(integrator) => integrator.li(ray, scene, sampler, depth),
         }
     }
+    pub fn li_with_isect(
+        &self,
+        ray: &mut Ray,
+        hit: bool,
+        isect: SurfaceInteraction,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        depth: i32,
+        features: Option<&mut FirstHitFeatures>,
+    ) -> Spectrum {
+        match self {
+            SamplerIntegrator::AO(integrator) => {
+                integrator.li_with_isect(ray, hit, isect, scene, sampler, depth, features)
+            }
+            SamplerIntegrator::DirectLighting(integrator) => {
+                integrator.li_with_isect(ray, hit, isect, scene, sampler, depth, features)
+            }
+            SamplerIntegrator::Path(integrator) => {
+                integrator.li_with_isect(ray, hit, isect, scene, sampler, depth, features)
+            }
+            SamplerIntegrator::VolPath(integrator) => {
+                integrator.li_with_isect(ray, hit, isect, scene, sampler, depth, features)
+            }
+            SamplerIntegrator::Whitted(integrator) => {
+                integrator.li_with_isect(ray, hit, isect, scene, sampler, depth, features)
+            }
+        }
+    }
     pub fn get_camera(&self) -> Arc<Camera> {
         match self {
             SamplerIntegrator::AO(integrator) => integrator.get_camera(),
@@ -247,6 +341,20 @@ impl SamplerIntegrator {
             SamplerIntegrator::Whitted(integrator) => integrator.get_sampler(),
         }
     }
+    pub fn get_path_guide(&self) -> Option<&PathGuide> {
+        match self {
+            SamplerIntegrator::Path(integrator) => integrator.get_path_guide(),
+            SamplerIntegrator::VolPath(integrator) => integrator.get_path_guide(),
+            _ => None,
+        }
+    }
+    pub fn get_path_guide_mut(&mut self) -> Option<&mut PathGuide> {
+        match self {
+            SamplerIntegrator::Path(integrator) => integrator.get_path_guide_mut(),
+            SamplerIntegrator::VolPath(integrator) => integrator.get_path_guide_mut(),
+            _ => None,
+        }
+    }
     pub fn get_pixel_bounds(&self) -> Bounds2i {
         match self {
             SamplerIntegrator::AO(integrator) => integrator.get_pixel_bounds(),
@@ -294,9 +402,108 @@ impl SamplerIntegrator {
     }
 }
 
+/// Generates the camera ray for a camera sample, as used by
+/// [render()](enum.SamplerIntegrator.html#method.render).
+pub fn generate_camera_ray(
+    camera: &Camera,
+    camera_sample: &CameraSample,
+    ray: &mut Ray,
+    samples_per_pixel: i64,
+) -> Float {
+    let ray_weight: Float = camera.generate_ray_differential(camera_sample, ray);
+    ray.scale_differentials(1.0 as Float / (samples_per_pixel as Float).sqrt());
+    if ray_weight > 0.0 {
+        // ADDED
+        let clipping_start: Float = camera.get_clipping_start();
+        if clipping_start > 0.0 as Float {
+            // adjust ray origin for near clipping
+            camera.adjust_to_clipping_start(camera_sample, ray);
+        }
+        // ADDED
+    }
+    ray_weight
+}
+
+/// Side length (in pixels) of the square image tiles, which are
+/// rendered by one thread at a time.
+const TILE_SIZE: i32 = 16;
+
+/// The tiled render loop of all integrators which trace the samples
+/// of each pixel independently. Worker threads take the tiles covering
+/// *bounds* from a [BlockQueue](../../blockqueue/struct.BlockQueue.html)
+/// and pass each of them to *render_tile* together with their copy of
+/// *sampler*, which is reseeded for every tile (and *pass*). The
+/// results are handed to *merge_tile* by a separate thread, which
+/// reports the progress.
+pub fn render_tiles<T, R, M>(
+    bounds: &Bounds2i,
+    sampler: &Sampler,
+    num_cores: usize,
+    pass: u32,
+    render_tile: R,
+    mut merge_tile: M,
+) where
+    T: Send,
+    R: Fn(&Bounds2i, &mut Sampler) -> T + Sync,
+    M: FnMut(T) + Send,
+{
+    let extent: Vector2i = bounds.diagonal();
+    let n_tiles: Point2i = Point2i {
+        x: (extent.x + TILE_SIZE - 1) / TILE_SIZE,
+        y: (extent.y + TILE_SIZE - 1) / TILE_SIZE,
+    };
+    // TODO: ProgressReporter reporter(nTiles.x * nTiles.y, "Rendering");
+    let block_queue = BlockQueue::new(
+        (
+            (n_tiles.x * TILE_SIZE) as u32,
+            (n_tiles.y * TILE_SIZE) as u32,
+        ),
+        (TILE_SIZE as u32, TILE_SIZE as u32),
+        (0, 0),
+    );
+    let bq = &block_queue;
+    let render_tile = &render_tile;
+    crossbeam::scope(|scope| {
+        let (tile_tx, tile_rx) = crossbeam_channel::bounded(num_cores);
+        // spawn worker threads
+        for _ in 0..num_cores {
+            let tile_tx = tile_tx.clone();
+            let mut tile_sampler: Box<Sampler> = sampler.clone_with_seed(0_u64);
+            scope.spawn(move |_| {
+                while let Some((x, y)) = bq.next() {
+                    let seed: i32 =
+                        pass as i32 * n_tiles.x * n_tiles.y + y as i32 * n_tiles.x + x as i32;
+                    tile_sampler.reseed(seed as u64);
+                    let x0: i32 = bounds.p_min.x + x as i32 * TILE_SIZE;
+                    let x1: i32 = std::cmp::min(x0 + TILE_SIZE, bounds.p_max.x);
+                    let y0: i32 = bounds.p_min.y + y as i32 * TILE_SIZE;
+                    let y1: i32 = std::cmp::min(y0 + TILE_SIZE, bounds.p_max.y);
+                    let tile_bounds: Bounds2i =
+                        Bounds2i::new(Point2i { x: x0, y: y0 }, Point2i { x: x1, y: y1 });
+                    // println!("Starting image tile {:?}", tile_bounds);
+                    let result: T = render_tile(&tile_bounds, &mut tile_sampler);
+                    // send the tile through the channel to main thread
+                    tile_tx
+                        .send(result)
+                        .unwrap_or_else(|_| panic!("Failed to send tile"));
+                }
+            });
+        }
+        // spawn thread to collect the rendered tiles
+        scope.spawn(move |_| {
+            for _ in pbr::PbIter::new(0..bq.len()) {
+                merge_tile(tile_rx.recv().unwrap());
+            }
+        });
+    })
+    .unwrap();
+}
+
 // see integrator.cpp
 
-/// Most basic direct lighting strategy.
+/// Most basic direct lighting strategy. Without participating media
+/// the shadow rays of all light samples are collected and traced
+/// together as a packet.
 pub fn uniform_sample_all_lights(
     it: &SurfaceInteraction,
     scene: &Scene,
@@ -306,6 +513,8 @@ pub fn uniform_sample_all_lights(
 ) -> Spectrum {
     // TODO: ProfilePhase p(Prof::DirectLighting);
     let mut l: Spectrum = Spectrum::new(0.0);
+    let mut shadow_rays: Vec<Ray> = Vec::new();
+    let mut unoccluded_ld: Vec<Spectrum> = Vec::new();
     for (j, n_samples) in n_light_samples.iter().enumerate().take(scene.lights.len()) {
         // accumulate contribution of _j_th light to _L_
         let light = &scene.lights[j];
@@ -317,7 +526,7 @@ pub fn uniform_sample_all_lights(
             // use a single sample for illumination from _light_
             let u_light: Point2f = sampler.get_2d();
             let u_scattering: Point2f = sampler.get_2d();
-            l += estimate_direct(
+            l += estimate_direct_deferred(
                 it,
                 u_scattering,
                 light,
@@ -325,7 +534,9 @@ pub fn uniform_sample_all_lights(
                 scene,
                 sampler,
                 handle_media,
-                false,
+                1.0 as Float,
+                &mut shadow_rays,
+                &mut unoccluded_ld,
             );
         } else {
             // estimate direct lighting using sample arrays
@@ -337,7 +548,7 @@ pub fn uniform_sample_all_lights(
                 );
                 let u_light_array_sample: Point2f =
                     sampler.get_2d_sample(u_light_array_idx, u_light_array_start + k as usize);
-                ld += estimate_direct(
+                ld += estimate_direct_deferred(
                     it,
                     u_scattering_array_sample,
                     light,
@@ -345,15 +556,65 @@ pub fn uniform_sample_all_lights(
                     scene,
                     sampler,
                     handle_media,
-                    false,
+                    *n_samples as Float,
+                    &mut shadow_rays,
+                    &mut unoccluded_ld,
                 );
             }
             l += ld / *n_samples as Float;
         }
     }
+    if !shadow_rays.is_empty() {
+        let mut occluded: Vec<bool> = vec![false; shadow_rays.len()];
+        scene.intersect_p_packet(&shadow_rays, &mut occluded);
+        for (ld, occluded) in unoccluded_ld.iter().zip(occluded.iter()) {
+            if !occluded {
+                l += *ld;
+            }
+        }
+    }
     l
 }
 
+/// Like [estimate_direct()](fn.estimate_direct.html), but (without
+/// media) the shadow ray of the light sample is only recorded in
+/// *shadow_rays* and its (scaled down by *n_samples*) contribution in
+/// *unoccluded_ld*. Returns the remaining part of the estimate.
+fn estimate_direct_deferred(
+    it: &SurfaceInteraction,
+    u_scattering: Point2f,
+    light: &Light,
+    u_light: Point2f,
+    scene: &Scene,
+    sampler: &mut Sampler,
+    handle_media: bool,
+    n_samples: Float,
+    shadow_rays: &mut Vec<Ray>,
+    unoccluded_ld: &mut Vec<Spectrum>,
+) -> Spectrum {
+    if handle_media {
+        return estimate_direct(
+            it,
+            u_scattering,
+            light,
+            u_light,
+            scene,
+            sampler,
+            handle_media,
+            false,
+        );
+    }
+    let mut visibility: VisibilityTester = VisibilityTester::default();
+    let mut light_intr: InteractionCommon = InteractionCommon::default();
+    let ld: Spectrum =
+        estimate_direct_light(it, light, u_light, false, &mut light_intr, &mut visibility);
+    if !ld.is_black() {
+        shadow_rays.push(visibility.shadow_ray());
+        unoccluded_ld.push(ld / n_samples);
+    }
+    estimate_direct_bsdf(it, u_scattering, light, scene, sampler, false, false)
+}
+
 /// Estimate direct lighting for only one randomly chosen light and
 /// multiply the result by the number of lights to compensate.
 pub fn uniform_sample_one_light(
@@ -361,7 +622,7 @@ pub fn uniform_sample_one_light(
     scene: &Scene,
     sampler: &mut Sampler,
     handle_media: bool,
-    light_distrib: Option<&Distribution1D>,
+    light_distrib: Option<&LocalLightDistribution>,
 ) -> Spectrum {
     // TODO: ProfilePhase p(Prof::DirectLighting);
 
@@ -414,26 +675,68 @@ pub fn estimate_direct(
     handle_media: bool,
     specular: bool,
 ) -> Spectrum {
-    let bsdf_flags = if !specular {
+    // sample light source with multiple importance sampling
+    let mut visibility: VisibilityTester = VisibilityTester::default();
+    let mut light_intr: InteractionCommon = InteractionCommon::default();
+    let mut ld: Spectrum = estimate_direct_light(
+        it,
+        light,
+        u_light,
+        specular,
+        &mut light_intr,
+        &mut visibility,
+    );
+    if !ld.is_black() {
+        // compute effect of visibility for light source sample
+        if handle_media {
+            ld *= visibility.tr(scene, sampler);
+        } else if !visibility.unoccluded(scene) {
+            ld = Spectrum::new(0.0 as Float);
+        }
+    }
+    ld + estimate_direct_bsdf(
+        it,
+        u_scattering,
+        light,
+        scene,
+        sampler,
+        handle_media,
+        specular,
+    )
+}
+
+fn direct_bsdf_flags(specular: bool) -> u8 {
+    if !specular {
         // bitwise not in Rust is ! (not the ~ operator like in C)
         BxdfType::BsdfAll as u8 & !(BxdfType::BsdfSpecular as u8)
     } else {
         BxdfType::BsdfAll as u8
-    };
-    let mut ld: Spectrum = Spectrum::new(0.0);
-    // sample light source with multiple importance sampling
+    }
+}
+
+/// The light sampling half of
+/// [estimate_direct()](fn.estimate_direct.html). Returns the
+/// contribution of the light sample *assuming it is visible*; the
+/// caller has to trace the shadow ray set up in *visibility*.
+pub fn estimate_direct_light<'a, 'b>(
+    it: &'a dyn Interaction,
+    light: &'b Light,
+    u_light: Point2f,
+    specular: bool,
+    light_intr: &'b mut InteractionCommon,
+    visibility: &mut VisibilityTester<'a, 'b>,
+) -> Spectrum {
+    let bsdf_flags: u8 = direct_bsdf_flags(specular);
     let mut wi: Vector3f = Vector3f::default();
     let mut light_pdf: Float = 0.0 as Float;
     let mut scattering_pdf: Float = 0.0 as Float;
-    let mut visibility: VisibilityTester = VisibilityTester::default();
-    let mut light_intr: InteractionCommon = InteractionCommon::default();
-    let mut li: Spectrum = light.sample_li(
+    let li: Spectrum = light.sample_li(
         it.get_common(),
-        &mut light_intr,
+        light_intr,
         u_light,
         &mut wi,
         &mut light_pdf,
-        &mut visibility,
+        visibility,
     );
     // TODO: println!("EstimateDirect uLight: {:?} -> Li: {:?}, wi:
     // {:?}, pdf: {:?}", u_light, li, wi, light_pdf);
@@ -459,23 +762,33 @@ pub fn estimate_direct(
             }
         }
         if !f.is_black() {
-            // compute effect of visibility for light source sample
-            if handle_media {
-                li *= visibility.tr(scene, sampler);
-            } else if !visibility.unoccluded(scene) {
-                li = Spectrum::new(0.0 as Float);
-            }
-            // add light's contribution to reflected radiance
-            if !li.is_black() {
-                if is_delta_light(light.get_flags()) {
-                    ld += f * li / light_pdf;
-                } else {
-                    let weight: Float = power_heuristic(1_u8, light_pdf, 1_u8, scattering_pdf);
-                    ld += f * li * Spectrum::new(weight) / light_pdf;
-                }
+            // light's contribution to reflected radiance (if unoccluded)
+            if is_delta_light(light.get_flags()) {
+                return f * li / light_pdf;
+            } else {
+                let weight: Float = power_heuristic(1_u8, light_pdf, 1_u8, scattering_pdf);
+                return f * li * Spectrum::new(weight) / light_pdf;
             }
         }
     }
+    Spectrum::new(0.0)
+}
+
+/// The BSDF sampling half of
+/// [estimate_direct()](fn.estimate_direct.html).
+pub fn estimate_direct_bsdf(
+    it: &dyn Interaction,
+    u_scattering: Point2f,
+    light: &Light,
+    scene: &Scene,
+    sampler: &mut Sampler,
+    handle_media: bool,
+    specular: bool,
+) -> Spectrum {
+    let bsdf_flags: u8 = direct_bsdf_flags(specular);
+    let mut ld: Spectrum = Spectrum::new(0.0);
+    let mut wi: Vector3f = Vector3f::default();
+    let mut scattering_pdf: Float = 0.0 as Float;
     // sample BSDF with multiple importance sampling
     if !is_delta_light(light.get_flags()) {
         let mut f: Spectrum = Spectrum::new(0.0);
@@ -512,7 +825,7 @@ pub fn estimate_direct(
         if !f.is_black() && scattering_pdf > 0.0 {
             // account for light contributions along sampled direction _wi_
             let weight = if !sampled_specular {
-                light_pdf = light.pdf_li(it, &wi);
+                let light_pdf: Float = light.pdf_li(it, &wi);
                 if light_pdf == 0.0 {
                     return ld;
                 }
@@ -122,6 +122,9 @@ pub struct MediumInteraction {
     pub common: InteractionCommon,
     // MediumInteraction Public Data
     pub phase: Option<Arc<HenyeyGreenstein>>,
+    /// emitted radiance estimate of the medium for this interaction
+    /// (already divided by the sampling density)
+    pub le: Spectrum,
 }
 
 impl MediumInteraction {
@@ -142,10 +145,18 @@ impl MediumInteraction {
             let inside: Option<Arc<Medium>> = Some(medium_arc.clone());
             let outside: Option<Arc<Medium>> = Some(medium_arc);
             common.medium_interface = Some(Arc::new(MediumInterface::new(inside, outside)));
-            MediumInteraction { common, phase }
+            MediumInteraction {
+                common,
+                phase,
+                le: Spectrum::default(),
+            }
         } else {
             common.medium_interface = None;
-            MediumInteraction { common, phase }
+            MediumInteraction {
+                common,
+                phase,
+                le: Spectrum::default(),
+            }
         }
     }
     pub fn get_medium(&self, w: &Vector3f) -> Option<Arc<Medium>> {
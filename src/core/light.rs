@@ -2,12 +2,19 @@
 //! source of illumination so that some light is reflected from them
 //! to the camera sensor.
 
+// std
+use std::f32::consts::PI;
 // pbrt
-use crate::core::geometry::{Normal3f, Point2f, Ray, Vector3f};
+use crate::core::geometry::{
+    bnd3_union_bnd3f, pnt3_distance_squaredf, vec3_cross_vec3, vec3_dot_vec3f,
+};
+use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
-use crate::core::pbrt::{Float, Spectrum};
+use crate::core::pbrt::clamp_t;
+use crate::core::pbrt::{degrees, Float, Spectrum};
 use crate::core::sampler::Sampler;
 use crate::core::scene::Scene;
+use crate::core::transform::Transform;
 use crate::lights::diffuse::DiffuseAreaLight;
 use crate::lights::distant::DistantLight;
 use crate::lights::goniometric::GonioPhotometricLight;
@@ -164,6 +171,19 @@ impl Light {
             Light::Spot(light) => light.get_n_samples(),
         }
     }
+    /// Returns the bounds of the light's emission, or *None* for
+    /// lights at infinity (which can't be bounded).
+    pub fn bounds(&self) -> Option<LightBounds> {
+        match self {
+            Light::DiffuseArea(light) => Some(light.bounds()),
+            Light::Distant(_light) => None,
+            Light::GonioPhotometric(light) => Some(light.bounds()),
+            Light::InfiniteArea(_light) => None,
+            Light::Point(light) => Some(light.bounds()),
+            Light::Projection(light) => Some(light.bounds()),
+            Light::Spot(light) => Some(light.bounds()),
+        }
+    }
     // AreaLight
     pub fn l(&self, intr: &InteractionCommon, w: &Vector3f) -> Spectrum {
         match self {
@@ -197,12 +217,17 @@ pub struct VisibilityTester<'a, 'b> {
 
 impl<'a, 'b> VisibilityTester<'a, 'b> {
     pub fn unoccluded(&self, scene: &Scene) -> bool {
-        let mut ray: Ray = self
-            .p0
+        let mut ray: Ray = self.shadow_ray();
+        !scene.intersect_p(&mut ray)
+    }
+    /// The ray between both end points, e.g. to be tested as part of
+    /// a packet by
+    /// [intersect_p_packet()](../scene/struct.Scene.html#method.intersect_p_packet).
+    pub fn shadow_ray(&self) -> Ray {
+        self.p0
             .as_ref()
             .unwrap()
-            .spawn_ray_to(&self.p1.as_ref().unwrap());
-        !scene.intersect_p(&mut ray)
+            .spawn_ray_to(&self.p1.as_ref().unwrap())
     }
     pub fn tr(&self, scene: &Scene, sampler: &mut Sampler) -> Spectrum {
         let mut ray: Ray = self
@@ -246,3 +271,148 @@ impl<'a, 'b> VisibilityTester<'a, 'b> {
 // pub trait AreaLight: Light {
 //     fn l(&self, intr: &InteractionCommon, w: &Vector3f) -> Spectrum;
 // }
+
+/// Bounds of the light a light source (or a group of them) emits:
+/// the spatial bounds, the total emitted power *phi* and a cone of
+/// normals (around *w* with *cos_theta_o*), from which light is
+/// emitted into directions up to an additional angle *cos_theta_e*.
+#[derive(Debug, Copy, Clone)]
+pub struct LightBounds {
+    pub bounds: Bounds3f,
+    pub phi: Float,
+    pub w: Vector3f,
+    pub cos_theta_o: Float,
+    pub cos_theta_e: Float,
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    /// Bounds for a light emitting into all directions.
+    pub fn new_omnidirectional(bounds: Bounds3f, phi: Float) -> Self {
+        LightBounds {
+            bounds,
+            phi,
+            w: Vector3f {
+                x: 0.0 as Float,
+                y: 0.0 as Float,
+                z: 1.0 as Float,
+            },
+            cos_theta_o: -1.0 as Float,
+            cos_theta_e: 0.0 as Float,
+            two_sided: false,
+        }
+    }
+    pub fn centroid(&self) -> Point3f {
+        (self.bounds.p_min + self.bounds.p_max) * 0.5 as Float
+    }
+    /// Merges two light bounds, widening the cone of normals as
+    /// little as possible.
+    pub fn union(&self, other: &LightBounds) -> LightBounds {
+        if self.phi == 0.0 as Float {
+            return *other;
+        }
+        if other.phi == 0.0 as Float {
+            return *self;
+        }
+        let (w, cos_theta_o) = cone_union(&self.w, self.cos_theta_o, &other.w, other.cos_theta_o);
+        LightBounds {
+            bounds: bnd3_union_bnd3f(&self.bounds, &other.bounds),
+            phi: self.phi + other.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+    /// A conservative estimate of the light arriving at *p*: the power
+    /// over the squared distance, scaled by the cosine of the smallest
+    /// angle between the cone of normals and the directions to *p*.
+    pub fn importance(&self, p: &Point3f) -> Float {
+        let pc: Point3f = self.centroid();
+        let d2: Float =
+            pnt3_distance_squaredf(p, &pc).max(self.bounds.diagonal().length() * 0.5 as Float);
+        let wp: Vector3f = *p - pc;
+        let mut cos_theta_w: Float = if wp.length_squared() > 0.0 as Float {
+            vec3_dot_vec3f(&self.w, &wp.normalize())
+        } else {
+            1.0 as Float
+        };
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w: Float = safe_sqrt(1.0 as Float - cos_theta_w * cos_theta_w);
+        // bound the directions from p to the bounds by a cone
+        let radius: Float = self.bounds.diagonal().length() * 0.5 as Float;
+        let dist2: Float = pnt3_distance_squaredf(p, &pc);
+        let cos_theta_b: Float = if dist2 < radius * radius {
+            -1.0 as Float
+        } else {
+            safe_sqrt(1.0 as Float - radius * radius / dist2)
+        };
+        let sin_theta_b: Float = safe_sqrt(1.0 as Float - cos_theta_b * cos_theta_b);
+        let sin_theta_o: Float = safe_sqrt(1.0 as Float - self.cos_theta_o * self.cos_theta_o);
+        // cosine of the angle between w and p - pc, reduced by theta_o and theta_b
+        let (sin_theta_x, cos_theta_x) =
+            sin_cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let (_sin_theta_p, cos_theta_p) =
+            sin_cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p < self.cos_theta_e {
+            return 0.0 as Float;
+        }
+        self.phi * cos_theta_p / d2
+    }
+}
+
+fn safe_sqrt(x: Float) -> Float {
+    x.max(0.0 as Float).sqrt()
+}
+
+/// Returns sine and cosine of *a - b* (clamped to zero if *b* > *a*).
+fn sin_cos_sub_clamped(sin_a: Float, cos_a: Float, sin_b: Float, cos_b: Float) -> (Float, Float) {
+    if cos_a > cos_b {
+        (0.0 as Float, 1.0 as Float)
+    } else {
+        (sin_a * cos_b - cos_a * sin_b, cos_a * cos_b + sin_a * sin_b)
+    }
+}
+
+/// Numerically robust angle between two normalized vectors.
+fn angle_between(v1: &Vector3f, v2: &Vector3f) -> Float {
+    if vec3_dot_vec3f(v1, v2) < 0.0 as Float {
+        PI - 2.0 as Float * ((*v1 + *v2).length() * 0.5 as Float).min(1.0).asin()
+    } else {
+        2.0 as Float * ((*v2 - *v1).length() * 0.5 as Float).min(1.0).asin()
+    }
+}
+
+/// Returns the smallest cone (axis and cosine of its spread angle)
+/// containing two other cones.
+fn cone_union(
+    wa: &Vector3f,
+    cos_theta_a: Float,
+    wb: &Vector3f,
+    cos_theta_b: Float,
+) -> (Vector3f, Float) {
+    let entire_sphere: (Vector3f, Float) = (*wa, -1.0 as Float);
+    let theta_a: Float = clamp_t(cos_theta_a, -1.0, 1.0).acos();
+    let theta_b: Float = clamp_t(cos_theta_b, -1.0, 1.0).acos();
+    let theta_d: Float = angle_between(wa, wb);
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (*wa, cos_theta_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (*wb, cos_theta_b);
+    }
+    let theta_o: Float = (theta_a + theta_d + theta_b) * 0.5 as Float;
+    if theta_o >= PI {
+        return entire_sphere;
+    }
+    // rotate wa towards wb, so that the new cone contains both
+    let theta_r: Float = theta_o - theta_a;
+    let wr: Vector3f = vec3_cross_vec3(wa, wb);
+    if wr.length_squared() == 0.0 as Float {
+        return entire_sphere;
+    }
+    let w: Vector3f = Transform::rotate(degrees(theta_r), &wr).transform_vector(wa);
+    (w.normalize(), theta_o.cos())
+}
@@ -1,6 +1,8 @@
 //! Various probability distributions for sampling light sources.
 
 // std
+use std::collections::HashMap;
+use std::f32::consts::PI;
 use std::rc::Rc;
 use std::sync::Arc;
 // others
@@ -8,13 +10,15 @@ use atom::AtomSetOnce;
 use atomic::{Atomic, Ordering};
 use strum::IntoEnumIterator;
 // pbrt
+use crate::core::geometry::{bnd3_union_bnd3f, bnd3_union_pnt3f};
 use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Point3i, Vector3f, XYZEnum};
 use crate::core::integrator::compute_light_power_distribution;
 use crate::core::interaction::InteractionCommon;
-use crate::core::light::VisibilityTester;
+use crate::core::light::{LightBounds, VisibilityTester};
 use crate::core::lowdiscrepancy::radical_inverse;
 use crate::core::pbrt::clamp_t;
 use crate::core::pbrt::{Float, Spectrum};
+use crate::core::rng::FLOAT_ONE_MINUS_EPSILON;
 use crate::core::sampling::Distribution1D;
 use crate::core::scene::Scene;
 
@@ -27,14 +31,85 @@ pub enum LightDistribution {
     Uniform(UniformLightDistribution),
     Power(PowerLightDistribution),
     Spatial(SpatialLightDistribution),
+    Bvh(Arc<BvhLightDistribution>),
 }
 
 impl LightDistribution {
-    pub fn lookup(&self, p: &Point3f) -> Arc<Distribution1D> {
+    pub fn lookup(&self, p: &Point3f) -> LocalLightDistribution {
+        match self {
+            LightDistribution::Uniform(distribution) => {
+                LocalLightDistribution::Tabulated(distribution.lookup(p))
+            }
+            LightDistribution::Power(distribution) => {
+                LocalLightDistribution::Tabulated(distribution.lookup(p))
+            }
+            LightDistribution::Spatial(distribution) => {
+                LocalLightDistribution::Tabulated(distribution.lookup(p))
+            }
+            LightDistribution::Bvh(distribution) => {
+                LocalLightDistribution::Bvh(distribution.clone(), *p)
+            }
+        }
+    }
+    /// Returns the distribution for choosing the light a path starts
+    /// on, e.g. for the light subpaths of BDPT. The BVH can't know
+    /// where the path will be connected to and ignores *p*, all other
+    /// distributions are the same as for
+    /// [lookup()](enum.LightDistribution.html#method.lookup).
+    pub fn lookup_origin(&self, p: &Point3f) -> LocalLightDistribution {
+        match self {
+            LightDistribution::Bvh(distribution) => {
+                LocalLightDistribution::BvhRoot(distribution.clone())
+            }
+            _ => self.lookup(p),
+        }
+    }
+}
+
+/// The distribution for sampling a light source at one point in
+/// space, as returned by
+/// [LightDistribution::lookup()](enum.LightDistribution.html#method.lookup).
+/// The BVH variants evaluate probabilities lazily, without tabulating
+/// them for all lights. *BvhRoot* uses the position-independent
+/// importance of the lights (see
+/// [LightDistribution::lookup_origin()](enum.LightDistribution.html#method.lookup_origin)).
+#[derive(Clone)]
+pub enum LocalLightDistribution {
+    Tabulated(Arc<Distribution1D>),
+    Bvh(Arc<BvhLightDistribution>, Point3f),
+    BvhRoot(Arc<BvhLightDistribution>),
+}
+
+impl LocalLightDistribution {
+    /// Returns the index of the sampled light in *scene.lights*. The
+    /// discrete probability is returned in *pdf* and is zero if no
+    /// light could be sampled.
+    pub fn sample_discrete(&self, u: Float, pdf: Option<&mut Float>) -> usize {
+        match self {
+            LocalLightDistribution::Tabulated(distrib) => distrib.sample_discrete(u, pdf),
+            LocalLightDistribution::Bvh(distrib, p) => distrib.sample_discrete(p, u, pdf),
+            LocalLightDistribution::BvhRoot(distrib) => distrib.sample_discrete_root(u, pdf),
+        }
+    }
+    /// Returns the discrete probability of sampling the light with
+    /// index *light_index* in *scene.lights*.
+    pub fn discrete_pdf(&self, light_index: usize) -> Float {
+        match self {
+            LocalLightDistribution::Tabulated(distrib) => distrib.discrete_pdf(light_index),
+            LocalLightDistribution::Bvh(distrib, p) => distrib.discrete_pdf(p, light_index),
+            LocalLightDistribution::BvhRoot(distrib) => distrib.discrete_pdf_root(light_index),
+        }
+    }
+    /// Returns the distribution for choosing a light to connect the
+    /// point *p* to, refining a position-independent BVH distribution
+    /// to a lookup at *p*. Returns *None* if the distribution would
+    /// be the same for all points.
+    pub fn connection_distribution(&self, p: &Point3f) -> Option<LocalLightDistribution> {
         match self {
-            LightDistribution::Uniform(distribution) => distribution.lookup(p),
-            LightDistribution::Power(distribution) => distribution.lookup(p),
-            LightDistribution::Spatial(distribution) => distribution.lookup(p),
+            LocalLightDistribution::BvhRoot(distrib) => {
+                Some(LocalLightDistribution::Bvh(distrib.clone(), *p))
+            }
+            _ => None,
         }
     }
 }
@@ -139,17 +214,15 @@ impl SpatialLightDistribution {
         let bmax: Float = diag[bmax_i];
         let mut n_voxels: [i32; 3] = [0_i32; 3];
         for i in XYZEnum::iter() {
-            n_voxels[i as usize] = std::cmp::max(
-                1 as i32,
-                (diag[i] / bmax * max_voxels as Float).round() as i32,
-            );
+            n_voxels[i as usize] =
+                std::cmp::max(1_i32, (diag[i] / bmax * max_voxels as Float).round() as i32);
             // in the Lookup() method, we require that 20 or fewer
             // bits be sufficient to represent each coordinate
             // value. It's fairly hard to imagine that this would ever
             // be a problem.
             assert!(n_voxels[i as usize] < (1 << 20));
         }
-        let hash_table_size: usize = (4 as i32 * n_voxels[0] * n_voxels[1] * n_voxels[2]) as usize;
+        let hash_table_size: usize = (4_i32 * n_voxels[0] * n_voxels[1] * n_voxels[2]) as usize;
         let mut hash_table: Vec<HashEntry> = Vec::with_capacity(hash_table_size);
         // let null: *mut Distribution1D = std::ptr::null_mut();
         for _i in 0..hash_table_size {
@@ -281,7 +354,7 @@ impl SpatialLightDistribution {
 
         // first, compute integer voxel coordinates for the given
         // point |p| with respect to the overall voxel grid.
-        let offset: Vector3f = self.scene.world_bound().offset(&p); // offset in [0,1].
+        let offset: Vector3f = self.scene.world_bound().offset(p); // offset in [0,1].
         let mut pi: Point3i = Point3i::default();
         for i in XYZEnum::iter() {
             // the clamp should almost never be necessary, but is
@@ -328,7 +401,10 @@ impl SpatialLightDistribution {
                 // Yes! Most of the time, there should already by a light
                 // sampling distribution available.
                 let option: Option<Arc<Distribution1D>> = entry.distribution.dup(Ordering::Acquire);
-                if option.is_none() {
+                if let Some(dist) = option {
+                    // We have a valid sampling distribution.
+                    return dist;
+                } else {
                     // Rarely, another thread will have already done a
                     // lookup at this point, found that there isn't a
                     // sampling distribution, and will already be
@@ -347,9 +423,6 @@ impl SpatialLightDistribution {
                             }
                         }
                     }
-                } else {
-                    // We have a valid sampling distribution.
-                    return option.as_ref().unwrap().clone();
                 }
             } else if entry_packed_pos != INVALID_PACKED_POS {
                 // The hash table entry we're checking has already
@@ -386,6 +459,347 @@ impl SpatialLightDistribution {
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+struct LightBvhNode {
+    light_bounds: LightBounds,
+    /// index of the second child for interior nodes (the first child
+    /// directly follows its parent), index into *scene.lights* for
+    /// leaves
+    child_or_light_index: usize,
+    is_leaf: bool,
+}
+
+/// Samples lights by traversing a bounding volume hierarchy over the
+/// emission bounds of all lights (see
+/// [LightBounds](../light/struct.LightBounds.html)). At each interior
+/// node a child is chosen proportionally to its estimated importance
+/// for the lookup point, which takes the power, distance and
+/// orientation of the lights into account. Lights at infinity can't
+/// be bounded and are sampled uniformly with a fixed probability.
+pub struct BvhLightDistribution {
+    nodes: Vec<LightBvhNode>,
+    infinite_lights: Vec<usize>,
+    /// the path from the root to each bounded light's leaf; bit *i*
+    /// is set if the second child was taken at depth *i*
+    light_to_bit_trail: HashMap<usize, u64>,
+    n_lights: usize,
+}
+
+impl BvhLightDistribution {
+    pub fn new(scene: &Scene) -> Self {
+        let mut infinite_lights: Vec<usize> = Vec::new();
+        let mut bvh_lights: Vec<(usize, LightBounds)> = Vec::new();
+        for (i, light) in scene.lights.iter().enumerate() {
+            if let Some(light_bounds) = light.bounds() {
+                // lights without power can't contribute
+                if light_bounds.phi > 0.0 as Float {
+                    bvh_lights.push((i, light_bounds));
+                }
+            } else {
+                infinite_lights.push(i);
+            }
+        }
+        let mut distribution = BvhLightDistribution {
+            nodes: Vec::with_capacity(2 * bvh_lights.len()),
+            infinite_lights,
+            light_to_bit_trail: HashMap::new(),
+            n_lights: scene.lights.len(),
+        };
+        if !bvh_lights.is_empty() {
+            let n_bvh_lights: usize = bvh_lights.len();
+            distribution.build(&mut bvh_lights, 0, n_bvh_lights, 0_u64, 0_u32);
+        }
+        distribution
+    }
+    /// Recursively builds the BVH for *bvh_lights[start..end]* and
+    /// returns the index of the subtree's root node.
+    fn build(
+        &mut self,
+        bvh_lights: &mut Vec<(usize, LightBounds)>,
+        start: usize,
+        end: usize,
+        bit_trail: u64,
+        depth: u32,
+    ) -> usize {
+        assert!(start < end);
+        if end - start == 1 || depth == 63 {
+            // create a leaf node (a single light, or the union of the
+            // remaining lights' bounds if the tree got too deep)
+            let node_index: usize = self.nodes.len();
+            let (light_index, light_bounds) = bvh_lights[start];
+            if end - start > 1 {
+                println!(
+                    "WARNING: light BVH too deep, ignoring {} light(s)",
+                    end - start - 1
+                );
+            }
+            self.nodes.push(LightBvhNode {
+                light_bounds,
+                child_or_light_index: light_index,
+                is_leaf: true,
+            });
+            self.light_to_bit_trail.insert(light_index, bit_trail);
+            return node_index;
+        }
+        // compute bounds of the light bounds and their centroids
+        let mut bounds: Bounds3f = bvh_lights[start].1.bounds;
+        let c: Point3f = bvh_lights[start].1.centroid();
+        let mut centroid_bounds: Bounds3f = Bounds3f { p_min: c, p_max: c };
+        for (_i, lb) in bvh_lights.iter().take(end).skip(start + 1) {
+            bounds = bnd3_union_bnd3f(&bounds, &lb.bounds);
+            centroid_bounds = bnd3_union_pnt3f(&centroid_bounds, &lb.centroid());
+        }
+        // choose the split with the lowest cost over all dimensions
+        let mut min_cost: Float = Float::INFINITY;
+        let mut min_cost_split_bucket: Option<(usize, usize)> = None;
+        const N_BUCKETS: usize = 12;
+        for dim in XYZEnum::iter() {
+            let d: usize = dim as usize;
+            if centroid_bounds.p_max[dim] == centroid_bounds.p_min[dim] {
+                continue;
+            }
+            let mut bucket_light_bounds: [Option<LightBounds>; N_BUCKETS] = [None; N_BUCKETS];
+            for (_i, lb) in bvh_lights.iter().take(end).skip(start) {
+                let b: usize = bucket_index(&centroid_bounds, &lb.centroid(), dim, N_BUCKETS);
+                bucket_light_bounds[b] = Some(match bucket_light_bounds[b] {
+                    Some(bucket) => bucket.union(lb),
+                    None => *lb,
+                });
+            }
+            for split in 0..(N_BUCKETS - 1) {
+                let below: Option<LightBounds> = union_buckets(&bucket_light_bounds[..=split]);
+                let above: Option<LightBounds> = union_buckets(&bucket_light_bounds[(split + 1)..]);
+                if let (Some(below), Some(above)) = (below, above) {
+                    let cost: Float =
+                        split_cost(&below, &bounds, d) + split_cost(&above, &bounds, d);
+                    if cost > 0.0 as Float && cost < min_cost {
+                        min_cost = cost;
+                        min_cost_split_bucket = Some((d, split));
+                    }
+                }
+            }
+        }
+        // partition the lights according to the chosen split
+        let mut mid: usize;
+        if let Some((d, split)) = min_cost_split_bucket {
+            let dim: XYZEnum = match d {
+                0 => XYZEnum::X,
+                1 => XYZEnum::Y,
+                _ => XYZEnum::Z,
+            };
+            let (mut left, mut right): (Vec<_>, Vec<_>) =
+                bvh_lights[start..end].iter().partition(|(_i, lb)| {
+                    bucket_index(&centroid_bounds, &lb.centroid(), dim, N_BUCKETS) <= split
+                });
+            mid = start + left.len();
+            left.append(&mut right);
+            bvh_lights.splice(start..end, left);
+        } else {
+            mid = (start + end) / 2;
+        }
+        if mid == start || mid == end {
+            mid = (start + end) / 2;
+        }
+        // allocate the interior node and build its children
+        let node_index: usize = self.nodes.len();
+        self.nodes.push(LightBvhNode {
+            light_bounds: bvh_lights[start].1,
+            child_or_light_index: 0,
+            is_leaf: false,
+        });
+        let child0: usize = self.build(bvh_lights, start, mid, bit_trail, depth + 1);
+        assert_eq!(child0, node_index + 1);
+        let child1: usize = self.build(
+            bvh_lights,
+            mid,
+            end,
+            bit_trail | (1_u64 << depth),
+            depth + 1,
+        );
+        let light_bounds: LightBounds = self.nodes[child0]
+            .light_bounds
+            .union(&self.nodes[child1].light_bounds);
+        self.nodes[node_index].light_bounds = light_bounds;
+        self.nodes[node_index].child_or_light_index = child1;
+        node_index
+    }
+    /// Probability of sampling one of the lights at infinity.
+    fn p_infinite(&self) -> Float {
+        let n_infinite: usize = self.infinite_lights.len();
+        let n_bvh: usize = if self.nodes.is_empty() { 0 } else { 1 };
+        if n_infinite + n_bvh == 0 {
+            0.0 as Float
+        } else {
+            n_infinite as Float / (n_infinite + n_bvh) as Float
+        }
+    }
+
+    // LocalLightDistribution
+
+    /// Samples a light with probabilities depending on the lookup
+    /// point *p*.
+    pub fn sample_discrete(&self, p: &Point3f, u: Float, pdf: Option<&mut Float>) -> usize {
+        self.sample_discrete_by(|lb| lb.importance(p), u, pdf)
+    }
+    /// Returns the probability of sampling *light_index* with
+    /// [sample_discrete()](#method.sample_discrete).
+    pub fn discrete_pdf(&self, p: &Point3f, light_index: usize) -> Float {
+        self.discrete_pdf_by(|lb| lb.importance(p), light_index)
+    }
+    /// Samples a light without a lookup point. The importance of
+    /// each node is its power alone, as seen from the root of the
+    /// BVH, which makes bounded lights proportional to their power.
+    pub fn sample_discrete_root(&self, u: Float, pdf: Option<&mut Float>) -> usize {
+        self.sample_discrete_by(|lb| lb.phi, u, pdf)
+    }
+    /// Returns the probability of sampling *light_index* with
+    /// [sample_discrete_root()](#method.sample_discrete_root).
+    pub fn discrete_pdf_root(&self, light_index: usize) -> Float {
+        self.discrete_pdf_by(|lb| lb.phi, light_index)
+    }
+    fn sample_discrete_by<F>(&self, importance: F, u: Float, pdf: Option<&mut Float>) -> usize
+    where
+        F: Fn(&LightBounds) -> Float,
+    {
+        let mut light_pdf: Float = 0.0 as Float;
+        let mut light_index: usize = 0;
+        let p_infinite: Float = self.p_infinite();
+        if u < p_infinite {
+            // sample one of the lights at infinity
+            let n_infinite: usize = self.infinite_lights.len();
+            let index: usize =
+                ((u / p_infinite * n_infinite as Float) as usize).min(n_infinite - 1);
+            light_index = self.infinite_lights[index];
+            light_pdf = p_infinite / n_infinite as Float;
+        } else if !self.nodes.is_empty() {
+            // traverse the BVH, choosing children by their importance
+            let mut u: Float =
+                ((u - p_infinite) / (1.0 as Float - p_infinite)).min(FLOAT_ONE_MINUS_EPSILON);
+            let mut node_index: usize = 0;
+            let mut node_pdf: Float = 1.0 as Float - p_infinite;
+            loop {
+                let node: &LightBvhNode = &self.nodes[node_index];
+                if node.is_leaf {
+                    if node_index > 0 || importance(&node.light_bounds) > 0.0 as Float {
+                        light_index = node.child_or_light_index;
+                        light_pdf = node_pdf;
+                    }
+                    break;
+                }
+                let ci: [Float; 2] = [
+                    importance(&self.nodes[node_index + 1].light_bounds),
+                    importance(&self.nodes[node.child_or_light_index].light_bounds),
+                ];
+                if ci[0] == 0.0 as Float && ci[1] == 0.0 as Float {
+                    break;
+                }
+                let p0: Float = ci[0] / (ci[0] + ci[1]);
+                if u < p0 {
+                    node_pdf *= p0;
+                    u = (u / p0).min(FLOAT_ONE_MINUS_EPSILON);
+                    node_index += 1;
+                } else {
+                    node_pdf *= 1.0 as Float - p0;
+                    u = ((u - p0) / (1.0 as Float - p0)).min(FLOAT_ONE_MINUS_EPSILON);
+                    node_index = node.child_or_light_index;
+                }
+            }
+        }
+        if let Some(pdf) = pdf {
+            *pdf = light_pdf;
+        }
+        light_index
+    }
+    fn discrete_pdf_by<F>(&self, importance: F, light_index: usize) -> Float
+    where
+        F: Fn(&LightBounds) -> Float,
+    {
+        assert!(light_index < self.n_lights);
+        if self.infinite_lights.contains(&light_index) {
+            return self.p_infinite() / self.infinite_lights.len() as Float;
+        }
+        let mut bit_trail: u64 = match self.light_to_bit_trail.get(&light_index) {
+            Some(bit_trail) => *bit_trail,
+            None => return 0.0 as Float,
+        };
+        // follow the light's path from the root to its leaf
+        let mut pdf: Float = 1.0 as Float - self.p_infinite();
+        let mut node_index: usize = 0;
+        loop {
+            let node: &LightBvhNode = &self.nodes[node_index];
+            if node.is_leaf {
+                if node.child_or_light_index != light_index {
+                    // dropped from a leaf of a too deep tree
+                    return 0.0 as Float;
+                }
+                if node_index == 0 && importance(&node.light_bounds) == 0.0 as Float {
+                    return 0.0 as Float;
+                }
+                return pdf;
+            }
+            let ci: [Float; 2] = [
+                importance(&self.nodes[node_index + 1].light_bounds),
+                importance(&self.nodes[node.child_or_light_index].light_bounds),
+            ];
+            let child: usize = (bit_trail & 1) as usize;
+            if ci[child] == 0.0 as Float {
+                return 0.0 as Float;
+            }
+            pdf *= ci[child] / (ci[0] + ci[1]);
+            node_index = if child == 0 {
+                node_index + 1
+            } else {
+                node.child_or_light_index
+            };
+            bit_trail >>= 1;
+        }
+    }
+}
+
+/// Maps a centroid to one of *n_buckets* buckets along dimension
+/// *dim* of *centroid_bounds*.
+fn bucket_index(centroid_bounds: &Bounds3f, c: &Point3f, dim: XYZEnum, n_buckets: usize) -> usize {
+    let offset: Float = centroid_bounds.offset(c)[dim];
+    ((offset * n_buckets as Float) as usize).min(n_buckets - 1)
+}
+
+fn union_buckets(buckets: &[Option<LightBounds>]) -> Option<LightBounds> {
+    let mut result: Option<LightBounds> = None;
+    for lb in buckets.iter().flatten() {
+        result = Some(match result {
+            Some(r) => r.union(lb),
+            None => *lb,
+        });
+    }
+    result
+}
+
+/// Surface area heuristic extended by the power and the solid angle
+/// of the emission cone of a group of lights.
+fn split_cost(lb: &LightBounds, node_bounds: &Bounds3f, dim: usize) -> Float {
+    let theta_o: Float = clamp_t(lb.cos_theta_o, -1.0, 1.0).acos();
+    let theta_e: Float = clamp_t(lb.cos_theta_e, -1.0, 1.0).acos();
+    let theta_w: Float = (theta_o + theta_e).min(PI);
+    let sin_theta_o: Float = (1.0 as Float - lb.cos_theta_o * lb.cos_theta_o)
+        .max(0.0)
+        .sqrt();
+    let m_omega: Float = 2.0 as Float * PI * (1.0 as Float - lb.cos_theta_o)
+        + PI / 2.0 as Float
+            * (2.0 as Float * theta_w * sin_theta_o
+                - (theta_o - 2.0 as Float * theta_w).cos()
+                - 2.0 as Float * theta_o * sin_theta_o
+                + lb.cos_theta_o);
+    // penalize thin nodes along the split dimension
+    let diag: Vector3f = node_bounds.diagonal();
+    let extent: Float = [diag.x, diag.y, diag.z][dim];
+    let kr: Float = if extent > 0.0 as Float {
+        diag.x.max(diag.y).max(diag.z) / extent
+    } else {
+        1.0 as Float
+    };
+    lb.phi * m_omega * kr * lb.bounds.surface_area()
+}
+
 // see lightdistrib.cpp
 
 const INVALID_PACKED_POS: u64 = 0xffff_ffff_ffff_ffff;
@@ -404,6 +818,10 @@ pub fn create_light_sample_distribution(
         Some(Arc::new(LightDistribution::Power(
             PowerLightDistribution::new(scene),
         )))
+    } else if name == "bvh" {
+        Some(Arc::new(LightDistribution::Bvh(Arc::new(
+            BvhLightDistribution::new(scene),
+        ))))
     } else if name == "spatial" {
         Some(Arc::new(LightDistribution::Spatial(
             SpatialLightDistribution::new(scene, 64),
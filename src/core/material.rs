@@ -5,7 +5,7 @@
 use std::cell::Cell;
 use std::sync::Arc;
 // pbrt
-use crate::core::geometry::vec3_cross_vec3;
+use crate::core::geometry::{vec3_cross_vec3, vec3_dot_vec3f};
 use crate::core::geometry::{Normal3f, Vector2f, Vector3f};
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::pbrt::{Float, Spectrum};
@@ -216,4 +216,51 @@ impl Material {
         let dndv = si.shading.dndv;
         si.set_shading_geometry(&dpdu, &dpdv, &dndu, &dndv, false);
     }
+    /// Perturb the shading frame with a tangent-space normal map. The
+    /// RGB values of the texture encode the normal in the frame built
+    /// from the shading normal and *dpdu* (image textures used as
+    /// **"normalmap"** are therefore read without any color space
+    /// conversion). Materials with a **"bumpmap"** as well apply the
+    /// normal map after bump mapping, so it perturbs the bumped frame.
+    pub fn normal_map(
+        normal_map: &Arc<dyn Texture<Spectrum> + Send + Sync>,
+        si: &mut SurfaceInteraction,
+    ) where
+        Self: Sized,
+    {
+        // get normalized normal vector from normal map
+        let mut rgb: [Float; 3] = [0.0 as Float; 3];
+        normal_map.evaluate(si).to_rgb(&mut rgb);
+        let ns: Vector3f = Vector3f {
+            x: 2.0 as Float * rgb[0] - 1.0 as Float,
+            y: 2.0 as Float * rgb[1] - 1.0 as Float,
+            z: 2.0 as Float * rgb[2] - 1.0 as Float,
+        };
+        if ns.length_squared() == 0.0 as Float {
+            return;
+        }
+        let ns: Vector3f = ns.normalize();
+        // transform tangent-space normal to rendering space
+        let z: Vector3f = Vector3f::from(si.shading.n).normalize();
+        let x: Vector3f = gram_schmidt(&si.shading.dpdu, &z);
+        if x.length_squared() == 0.0 as Float {
+            return;
+        }
+        let x: Vector3f = x.normalize();
+        let y: Vector3f = vec3_cross_vec3(&z, &x);
+        let ns: Vector3f = (x * ns.x + y * ns.y + z * ns.z).normalize();
+        // find $\dpdu$ and $\dpdv$ that give shading normal
+        let ulen: Float = si.shading.dpdu.length();
+        let vlen: Float = si.shading.dpdv.length();
+        let dpdu: Vector3f = gram_schmidt(&si.shading.dpdu, &ns).normalize() * ulen;
+        let dpdv: Vector3f = vec3_cross_vec3(&ns, &dpdu).normalize() * vlen;
+        let dndu = si.shading.dndu;
+        let dndv = si.shading.dndv;
+        si.set_shading_geometry(&dpdu, &dpdv, &dndu, &dndv, false);
+    }
+}
+
+/// Remove the component of *v* along the normalized vector *w*.
+fn gram_schmidt(v: &Vector3f, w: &Vector3f) -> Vector3f {
+    *v - *w * vec3_dot_vec3f(v, w)
 }
@@ -2,17 +2,21 @@
 //! compute the beam transmittance along a given ray.
 
 // std
+use std::cell::Cell;
 use std::f32::consts::PI;
 use std::sync::Arc;
 // pbrt
+use crate::core::colorspace::RGBColorSpace;
 use crate::core::geometry::{spherical_direction_vec3, vec3_coordinate_system, vec3_dot_vec3f};
-use crate::core::geometry::{Point2f, Ray, Vector3f, XYEnum};
+use crate::core::geometry::{Point2f, Point3f, Ray, Vector3f, XYEnum};
 use crate::core::interaction::MediumInteraction;
 use crate::core::pbrt::INV_4_PI;
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::sampler::Sampler;
+use crate::core::spectrum::{blackbody_normalized, CIE_LAMBDA, N_CIE_SAMPLES};
 use crate::media::grid::GridDensityMedium;
 use crate::media::homogeneous::HomogeneousMedium;
+use crate::media::sparse::SparseGridMedium;
 
 pub const SUBSURFACE_PARAMETER_TABLE: [MeasuredSS; 47] = [
     // From "A Practical Model for Subsurface Light Transport"
@@ -271,14 +275,42 @@ pub enum Medium {
     Empty(NoMedium),
     GridDensity(GridDensityMedium),
     Homogeneous(HomogeneousMedium),
+    SparseGrid(SparseGridMedium),
+}
+
+/// Scattering properties of a medium at a point, see
+/// [Medium::sample_t_maj()](enum.Medium.html#method.sample_t_maj).
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MediumProperties {
+    pub sigma_a: Spectrum,
+    pub sigma_s: Spectrum,
+    /// emitted radiance (black for non-emissive media)
+    pub le: Spectrum,
+    /// asymmetry parameter of the Henyey-Greenstein phase function
+    pub g: Float,
+}
+
+/// A parametric range of a ray (with a normalized direction) over
+/// which the extinction coefficient is bounded by *sigma_maj*.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RayMajorantSegment {
+    pub t_min: Float,
+    pub t_max: Float,
+    pub sigma_maj: Spectrum,
+}
+
+/// Uniformly choose the (hero) channel whose majorant is used to
+/// sample distances along a ray.
+pub fn sample_channel(u: Float) -> usize {
+    ((u * 3.0 as Float) as usize).min(2_usize)
 }
 
 impl Medium {
     pub fn tr(&self, r_world: &Ray, sampler: &mut Sampler) -> Spectrum {
         match self {
             Medium::Empty(_medium) => Spectrum::default(),
-            Medium::GridDensity(medium) => medium.tr(r_world, sampler),
             Medium::Homogeneous(medium) => medium.tr(r_world, sampler),
+            _ => self.ratio_tracking(r_world, sampler),
         }
     }
     pub fn sample(
@@ -288,9 +320,289 @@ impl Medium {
     ) -> (Spectrum, Option<MediumInteraction>) {
         match self {
             Medium::Empty(_medium) => (Spectrum::default(), None),
-            Medium::GridDensity(medium) => medium.sample(r_world, sampler),
             Medium::Homogeneous(medium) => medium.sample(r_world, sampler),
+            _ => self.delta_tracking(r_world, sampler),
+        }
+    }
+    /// Calls *segment* for the majorant segments of *ray* (with a
+    /// normalized direction) up to *ray.t_max*, until it returns
+    /// false.
+    pub fn traverse_majorants<F>(&self, ray: &Ray, segment: F)
+    where
+        F: FnMut(RayMajorantSegment) -> bool,
+    {
+        match self {
+            Medium::Empty(_medium) => {}
+            Medium::GridDensity(medium) => medium.traverse_majorants(ray, segment),
+            Medium::Homogeneous(medium) => medium.traverse_majorants(ray, segment),
+            Medium::SparseGrid(medium) => medium.traverse_majorants(ray, segment),
+        }
+    }
+    /// Scattering properties at the world space point *p*.
+    pub fn sample_point(&self, p: &Point3f) -> MediumProperties {
+        match self {
+            Medium::Empty(_medium) => MediumProperties::default(),
+            Medium::GridDensity(medium) => {
+                medium.sample_point(&medium.world_to_medium.transform_point(p))
+            }
+            Medium::Homogeneous(medium) => medium.sample_point(),
+            Medium::SparseGrid(medium) => {
+                medium.sample_point(&medium.world_to_medium.transform_point(p))
+            }
+        }
+    }
+    /// Samples tentative collisions along *ray* up to the parametric
+    /// distance *t_max* (of *ray*) with exponential steps according
+    /// to the majorant of the given *channel*. At each of them
+    /// *callback* gets the world space position, the medium
+    /// properties there, the majorant and the majorant transmittance
+    /// since the previous collision, and returns false to stop the
+    /// sampling. Returns the majorant transmittance from the last
+    /// collision to *t_max*, or one if the sampling was stopped.
+    pub fn sample_t_maj<F>(
+        &self,
+        ray: &Ray,
+        t_max: Float,
+        channel: usize,
+        sampler: &mut Sampler,
+        mut callback: F,
+    ) -> Spectrum
+    where
+        F: FnMut(&Point3f, &MediumProperties, &Spectrum, &Spectrum, &mut Sampler) -> bool,
+    {
+        // work with a normalized ray direction
+        let length: Float = ray.d.length();
+        let ray: Ray = Ray {
+            o: ray.o,
+            d: ray.d / length,
+            t_max: Cell::new(t_max * length),
+            time: ray.time,
+            differential: None,
+            medium: None,
+        };
+        let mut t_maj: Spectrum = Spectrum::new(1.0 as Float);
+        let mut done: bool = false;
+        self.traverse_majorants(&ray, |segment: RayMajorantSegment| {
+            let sigma_maj: Spectrum = segment.sigma_maj;
+            if sigma_maj.c[channel] == 0.0 as Float {
+                // no collisions can be sampled within the segment
+                let dt: Float = (segment.t_max - segment.t_min).min(Float::MAX);
+                t_maj *= (-sigma_maj * dt).exp();
+                return true;
+            }
+            let mut t_min: Float = segment.t_min;
+            loop {
+                // sample the next tentative collision
+                let u: Float = sampler.get_1d();
+                let t: Float = t_min - (1.0 as Float - u).ln() / sigma_maj.c[channel];
+                if t >= segment.t_max {
+                    let dt: Float = (segment.t_max - t_min).min(Float::MAX);
+                    t_maj *= (-sigma_maj * dt).exp();
+                    return true;
+                }
+                t_maj *= (-sigma_maj * (t - t_min)).exp();
+                let p: Point3f = ray.position(t);
+                let mp: MediumProperties = self.sample_point(&p);
+                if !callback(&p, &mp, &sigma_maj, &t_maj, sampler) {
+                    done = true;
+                    return false;
+                }
+                t_maj = Spectrum::new(1.0 as Float);
+                t_min = t;
+            }
+        });
+        if done {
+            Spectrum::new(1.0 as Float)
+        } else {
+            t_maj
+        }
+    }
+    /// Chromatic ratio tracking: distances are sampled with the
+    /// majorant of a randomly chosen hero channel and the estimates
+    /// of all channels are combined with spectral MIS.
+    fn ratio_tracking(&self, r_world: &Ray, sampler: &mut Sampler) -> Spectrum {
+        let channel: usize = sample_channel(sampler.get_1d());
+        let mut t_ray: Spectrum = Spectrum::new(1.0 as Float);
+        // ratios of the channels' sampling densities to the hero's one
+        let mut r_l: Spectrum = Spectrum::new(1.0 as Float);
+        let t_maj: Spectrum = self.sample_t_maj(
+            r_world,
+            r_world.t_max.get(),
+            channel,
+            sampler,
+            |_p, mp, sigma_maj, t_maj, sampler| {
+                let sigma_n: Spectrum =
+                    (*sigma_maj - mp.sigma_a - mp.sigma_s).clamp(0.0 as Float, Float::INFINITY);
+                let pdf: Float = t_maj.c[channel] * sigma_maj.c[channel];
+                t_ray *= *t_maj * sigma_n / pdf;
+                r_l *= *t_maj * *sigma_maj / pdf;
+                // when transmittance gets low, start applying Russian
+                // roulette to terminate sampling
+                let tr: Spectrum = t_ray / r_l.average();
+                if tr.max_component_value() < 0.05 as Float {
+                    if sampler.get_1d() < 0.75 as Float {
+                        t_ray = Spectrum::default();
+                    } else {
+                        t_ray /= 0.25 as Float;
+                    }
+                }
+                !t_ray.is_black()
+            },
+        );
+        if t_ray.is_black() || t_maj.c[channel] == 0.0 as Float {
+            return Spectrum::default();
+        }
+        t_ray *= t_maj / t_maj.c[channel];
+        r_l *= t_maj / t_maj.c[channel];
+        t_ray / r_l.average()
+    }
+    /// Chromatic delta tracking: like [ratio_tracking()](#method.ratio_tracking)
+    /// the hero channel's majorant is used to sample tentative
+    /// collisions, which are real ones with the probability of the
+    /// hero's extinction. Absorption is accounted for by the returned
+    /// weight (and the re-emitted light).
+    fn delta_tracking(
+        &self,
+        r_world: &Ray,
+        sampler: &mut Sampler,
+    ) -> (Spectrum, Option<MediumInteraction>) {
+        let channel: usize = sample_channel(sampler.get_1d());
+        let mut beta: Spectrum = Spectrum::new(1.0 as Float);
+        let mut r_u: Spectrum = Spectrum::new(1.0 as Float);
+        let mut le: Spectrum = Spectrum::default();
+        let mut collision: Option<(Point3f, Float)> = None;
+        let t_maj: Spectrum = self.sample_t_maj(
+            r_world,
+            r_world.t_max.get(),
+            channel,
+            sampler,
+            |p, mp, sigma_maj, t_maj, sampler| {
+                let sigma_t: Spectrum = mp.sigma_a + mp.sigma_s;
+                if sampler.get_1d() < sigma_t.c[channel] / sigma_maj.c[channel] {
+                    // real collision
+                    let pdf: Float = t_maj.c[channel] * sigma_t.c[channel];
+                    // absorbed light is re-emitted as _Le_
+                    le = beta * *t_maj * mp.sigma_a * mp.le / pdf;
+                    beta *= *t_maj * mp.sigma_s / pdf;
+                    r_u *= *t_maj * sigma_t / pdf;
+                    collision = Some((*p, mp.g));
+                    false
+                } else {
+                    // null collision
+                    let sigma_n: Spectrum =
+                        (*sigma_maj - sigma_t).clamp(0.0 as Float, Float::INFINITY);
+                    let pdf: Float = t_maj.c[channel] * sigma_n.c[channel];
+                    if pdf == 0.0 as Float {
+                        beta = Spectrum::default();
+                    } else {
+                        beta *= *t_maj * sigma_n / pdf;
+                        r_u *= *t_maj * sigma_n / pdf;
+                    }
+                    !beta.is_black() && !r_u.is_black()
+                }
+            },
+        );
+        if let Some((p, g)) = collision {
+            // populate _mi_ with medium interaction information and
+            // return, the ray's medium is this one
+            let mut mi: MediumInteraction = MediumInteraction::new(
+                &p,
+                &(-r_world.d),
+                r_world.time,
+                r_world.medium.clone(),
+                Some(Arc::new(HenyeyGreenstein { g })),
+            );
+            mi.le = le / r_u.average();
+            return (beta / r_u.average(), Some(mi));
+        }
+        if beta.is_black() || r_u.is_black() || t_maj.c[channel] == 0.0 as Float {
+            return (Spectrum::default(), None);
+        }
+        beta *= t_maj / t_maj.c[channel];
+        r_u *= t_maj / t_maj.c[channel];
+        (beta / r_u.average(), None)
+    }
+    pub fn is_emissive(&self) -> bool {
+        match self {
+            Medium::Empty(_medium) => false,
+            Medium::GridDensity(medium) => medium.is_emissive(),
+            Medium::Homogeneous(medium) => medium.is_emissive(),
+            Medium::SparseGrid(medium) => medium.is_emissive(),
+        }
+    }
+    /// Emitted radiance at the world space point *p*.
+    pub fn le(&self, p: &Point3f) -> Spectrum {
+        match self {
+            Medium::Empty(_medium) => Spectrum::default(),
+            Medium::GridDensity(medium) => medium.le(&medium.world_to_medium.transform_point(p)),
+            Medium::Homogeneous(medium) => medium.le(),
+            Medium::SparseGrid(medium) => medium.le(&medium.world_to_medium.transform_point(p)),
+        }
+    }
+}
+
+/// Temperatures of the precomputed blackbody table are spaced this
+/// far apart (in Kelvin).
+const BLACKBODY_TABLE_STEP: Float = 100.0;
+const BLACKBODY_TABLE_SIZE: usize = 201;
+
+/// Emission of a medium. Without a temperature the emitted radiance
+/// is the constant *le*, otherwise the temperature (after applying
+/// offset and scale) is mapped through the normalized blackbody
+/// spectrum. In both cases the result gets multiplied by *le_scale*.
+/// Media emit in proportion to their absorption coefficient.
+#[derive(Clone)]
+pub struct MediumEmission {
+    pub le: Spectrum,
+    pub le_scale: Float,
+    pub temperature_offset: Float,
+    pub temperature_scale: Float,
+    blackbody: Arc<Vec<Spectrum>>,
+}
+
+impl MediumEmission {
+    pub fn new(
+        le: &Spectrum,
+        le_scale: Float,
+        temperature_offset: Float,
+        temperature_scale: Float,
+        color_space: RGBColorSpace,
+    ) -> Self {
+        // tabulate the blackbody spectrum from 0 to 20000 Kelvin
+        let mut blackbody: Vec<Spectrum> = Vec::with_capacity(BLACKBODY_TABLE_SIZE);
+        blackbody.push(Spectrum::default());
+        for i in 1..BLACKBODY_TABLE_SIZE {
+            let mut v: Vec<Float> = Vec::with_capacity(N_CIE_SAMPLES as usize);
+            let t: Float = i as Float * BLACKBODY_TABLE_STEP;
+            blackbody_normalized(&CIE_LAMBDA, N_CIE_SAMPLES as usize, t, &mut v);
+            blackbody.push(color_space.from_sampled(&CIE_LAMBDA, &v, N_CIE_SAMPLES as i32));
+        }
+        MediumEmission {
+            le: *le,
+            le_scale,
+            temperature_offset,
+            temperature_scale,
+            blackbody: Arc::new(blackbody),
+        }
+    }
+    pub fn is_emissive(&self, has_temperature: bool) -> bool {
+        self.le_scale > 0.0 as Float && (has_temperature || !self.le.is_black())
+    }
+    /// Emitted radiance for a constant emission.
+    pub fn le(&self) -> Spectrum {
+        self.le * self.le_scale
+    }
+    /// Emitted radiance for a (grid) temperature value.
+    pub fn le_temperature(&self, temperature: Float) -> Spectrum {
+        let t: Float = (temperature - self.temperature_offset) * self.temperature_scale;
+        if t <= 100.0 as Float {
+            return Spectrum::default();
         }
+        // interpolate the blackbody table
+        let x: Float = t / BLACKBODY_TABLE_STEP;
+        let i: usize = (x as usize).min(BLACKBODY_TABLE_SIZE - 2);
+        let dx: Float = (x - i as Float).min(1.0 as Float);
+        (self.blackbody[i] * (1.0 as Float - dx) + self.blackbody[i + 1] * dx) * self.le_scale
     }
 }
 
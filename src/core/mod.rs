@@ -3,11 +3,15 @@
 pub mod api;
 pub mod bssrdf;
 pub mod camera;
+pub mod colorspace;
+pub mod denoiser;
 pub mod efloat;
 pub mod film;
 pub mod filter;
 pub mod floatfile;
 pub mod geometry;
+pub mod guiding;
+pub mod imageio;
 pub mod integrator;
 pub mod interaction;
 pub mod interpolation;
@@ -33,4 +37,5 @@ pub mod shape;
 pub mod sobolmatrices;
 pub mod spectrum;
 pub mod texture;
+pub mod tonemap;
 pub mod transform;
@@ -7,6 +7,7 @@ use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 // pbrt
+use crate::core::colorspace::RGBColorSpace;
 use crate::core::floatfile::read_float_file;
 use crate::core::geometry::{Normal3f, Point2f, Point3f, Vector2f, Vector3f};
 use crate::core::pbrt::{Float, Spectrum};
@@ -164,7 +165,12 @@ impl ParamSet {
             looked_up: false,
         });
     }
-    pub fn add_sampled_spectrum_files(&mut self, name: String, names: Vec<String>) {
+    pub fn add_sampled_spectrum_files(
+        &mut self,
+        name: String,
+        names: Vec<String>,
+        color_space: RGBColorSpace,
+    ) {
         // TODO: cachedSpectra
         self.erase_spectrum(name.clone());
         let mut s: Vec<Spectrum> = Vec::with_capacity(names.len());
@@ -201,7 +207,7 @@ impl ParamSet {
                         wls.push(vals[2 * j]);
                         v.push(vals[2 * j + 1]);
                     }
-                    s.push(Spectrum::from_sampled(&wls[..], &v[..], wls.len() as i32));
+                    s.push(color_space.from_sampled(&wls[..], &v[..], wls.len() as i32));
                 }
             }
         }
@@ -289,7 +295,12 @@ impl ParamSet {
             looked_up: false,
         });
     }
-    pub fn add_blackbody_spectrum(&mut self, name: String, values: Vec<Float>) {
+    pub fn add_blackbody_spectrum(
+        &mut self,
+        name: String,
+        values: Vec<Float>,
+        color_space: RGBColorSpace,
+    ) {
         assert!(values.len() % 2 == 0);
         // temperature (K), scale, ...
         let n_values: usize = values.len() / 2_usize;
@@ -298,7 +309,7 @@ impl ParamSet {
         for i in 0..n_values {
             blackbody_normalized(&CIE_LAMBDA, N_CIE_SAMPLES as usize, values[2 * i], &mut v);
             s.push(
-                Spectrum::from_sampled(&CIE_LAMBDA, &v, N_CIE_SAMPLES as i32) * values[2 * i + 1],
+                color_space.from_sampled(&CIE_LAMBDA, &v, N_CIE_SAMPLES as i32) * values[2 * i + 1],
             );
         }
         self.spectra.push(ParamSetItem::<Spectrum> {
@@ -407,6 +418,24 @@ impl ParamSet {
             });
         }
     }
+    pub fn erase_float(&mut self, name: String) -> bool {
+        for i in 0..self.floats.len() {
+            if self.floats[i].name == name {
+                self.floats.remove(i);
+                return true;
+            }
+        }
+        false
+    }
+    pub fn erase_string(&mut self, name: String) -> bool {
+        for i in 0..self.strings.len() {
+            if self.strings[i].name == name {
+                self.strings.remove(i);
+                return true;
+            }
+        }
+        false
+    }
     pub fn erase_spectrum(&mut self, name: String) -> bool {
         for i in 0..self.spectra.len() {
             if self.spectra[i].name == name {
@@ -4,8 +4,9 @@
 // std
 use std::sync::Arc;
 // pbrt
-use crate::accelerators::bvh::BVHAccel;
+use crate::accelerators::bvh::{traverse_bvh, BVHAccel, BVHBuildStats, LinearBVHNode, SplitMethod};
 use crate::accelerators::kdtreeaccel::KdTreeAccel;
+use crate::accelerators::widebvh::WideBVHAccel;
 use crate::core::geometry::nrm_dot_nrmf;
 use crate::core::geometry::{Bounds3f, Ray};
 use crate::core::interaction::SurfaceInteraction;
@@ -15,23 +16,28 @@ use crate::core::medium::{Medium, MediumInterface};
 use crate::core::pbrt::Float;
 use crate::core::shape::Shape;
 use crate::core::transform::{AnimatedTransform, Transform};
+use crate::shapes::compressedmesh::CompressedTriangleMesh;
 
 // see primitive.h
 
 pub enum Primitive {
     Geometric(Box<GeometricPrimitive>),
+    Mesh(Box<MeshPrimitive>),
     Transformed(Box<TransformedPrimitive>),
     BVH(Box<BVHAccel>),
     KdTree(Box<KdTreeAccel>),
+    WideBVH(Box<WideBVHAccel>),
 }
 
 impl Primitive {
     pub fn world_bound(&self) -> Bounds3f {
         match self {
             Primitive::Geometric(primitive) => primitive.world_bound(),
+            Primitive::Mesh(primitive) => primitive.world_bound(),
             Primitive::Transformed(primitive) => primitive.world_bound(),
             Primitive::BVH(primitive) => primitive.world_bound(),
             Primitive::KdTree(primitive) => primitive.world_bound(),
+            Primitive::WideBVH(primitive) => primitive.world_bound(),
         }
     }
     pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
@@ -43,33 +49,74 @@ impl Primitive {
                 }
                 hit_surface
             }
+            Primitive::Mesh(primitive) => {
+                let hit_surface: bool = primitive.intersect(ray, isect);
+                if hit_surface {
+                    isect.primitive = Some(self);
+                }
+                hit_surface
+            }
             Primitive::Transformed(primitive) => primitive.intersect(ray, isect),
             Primitive::BVH(primitive) => primitive.intersect(ray, isect),
             Primitive::KdTree(primitive) => primitive.intersect(ray, isect),
+            Primitive::WideBVH(primitive) => primitive.intersect(ray, isect),
         }
     }
     pub fn intersect_p(&self, ray: &Ray) -> bool {
         match self {
             Primitive::Geometric(primitive) => primitive.intersect_p(ray),
+            Primitive::Mesh(primitive) => primitive.intersect_p(ray),
             Primitive::Transformed(primitive) => primitive.intersect_p(ray),
             Primitive::BVH(primitive) => primitive.intersect_p(ray),
             Primitive::KdTree(primitive) => primitive.intersect_p(ray),
+            Primitive::WideBVH(primitive) => primitive.intersect_p(ray),
+        }
+    }
+    /// Intersects several rays at once. Only the BVH traverses the
+    /// rays as a packet, all other primitives test them one by one.
+    pub fn intersect_packet(
+        &self,
+        rays: &[Ray],
+        isects: &mut [SurfaceInteraction],
+        hits: &mut [bool],
+    ) {
+        match self {
+            Primitive::BVH(primitive) => primitive.intersect_packet(rays, isects, hits),
+            _ => {
+                for ((ray, isect), hit) in rays.iter().zip(isects.iter_mut()).zip(hits.iter_mut()) {
+                    *hit = self.intersect(ray, isect);
+                }
+            }
+        }
+    }
+    pub fn intersect_p_packet(&self, rays: &[Ray], occluded: &mut [bool]) {
+        match self {
+            Primitive::BVH(primitive) => primitive.intersect_p_packet(rays, occluded),
+            _ => {
+                for (ray, o) in rays.iter().zip(occluded.iter_mut()) {
+                    *o = self.intersect_p(ray);
+                }
+            }
         }
     }
     pub fn get_area_light(&self) -> Option<Arc<Light>> {
         match self {
             Primitive::Geometric(primitive) => primitive.get_area_light(),
+            Primitive::Mesh(primitive) => primitive.get_area_light(),
             Primitive::Transformed(primitive) => primitive.get_area_light(),
             Primitive::BVH(primitive) => primitive.get_area_light(),
             Primitive::KdTree(primitive) => primitive.get_area_light(),
+            Primitive::WideBVH(primitive) => primitive.get_area_light(),
         }
     }
     pub fn get_material(&self) -> Option<Arc<Material>> {
         match self {
             Primitive::Geometric(primitive) => primitive.get_material(),
+            Primitive::Mesh(primitive) => primitive.get_material(),
             Primitive::Transformed(primitive) => primitive.get_material(),
             Primitive::BVH(primitive) => primitive.get_material(),
             Primitive::KdTree(primitive) => primitive.get_material(),
+            Primitive::WideBVH(primitive) => primitive.get_material(),
         }
     }
     pub fn compute_scattering_functions(
@@ -203,6 +250,94 @@ impl GeometricPrimitive {
     }
 }
 
+/// A whole triangle mesh (stored as a **CompressedTriangleMesh**)
+/// with its own BVH, which references the triangles by index instead
+/// of needing one **Shape** and **GeometricPrimitive** per triangle.
+pub struct MeshPrimitive {
+    pub mesh: Arc<CompressedTriangleMesh>,
+    pub material: Option<Arc<Material>>,
+    pub medium_interface: Option<Arc<MediumInterface>>,
+    pub nodes: Vec<LinearBVHNode>,
+    /// triangle indices in the order the BVH leaves refer to them
+    pub triangles: Vec<u32>,
+    pub stats: BVHBuildStats,
+}
+
+impl MeshPrimitive {
+    pub fn new(
+        mesh: Arc<CompressedTriangleMesh>,
+        material: Option<Arc<Material>>,
+        medium_interface: Option<Arc<MediumInterface>>,
+    ) -> Self {
+        let triangle_bounds: Vec<Bounds3f> =
+            (0..mesh.n_triangles).map(|i| mesh.world_bound(i)).collect();
+        let mut bvh: BVHAccel = BVHAccel::new(Vec::new(), 4, SplitMethod::SAH, 12);
+        let order: Vec<usize> = bvh.build_nodes(&triangle_bounds);
+        MeshPrimitive {
+            mesh,
+            material,
+            medium_interface,
+            nodes: bvh.nodes,
+            triangles: order.iter().map(|i| *i as u32).collect(),
+            stats: bvh.stats,
+        }
+    }
+    // Primitive
+    pub fn world_bound(&self) -> Bounds3f {
+        if !self.nodes.is_empty() {
+            self.nodes[0].bounds
+        } else {
+            Bounds3f::default()
+        }
+    }
+    pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        let mut hit: bool = false;
+        traverse_bvh(&self.nodes, ray, |leaf| {
+            // intersect ray with triangles in leaf BVH node
+            for triangle in &self.triangles[leaf] {
+                let mut t_hit: Float = 0.0;
+                if self.mesh.intersect(*triangle, ray, &mut t_hit, isect) {
+                    ray.t_max.set(t_hit);
+                    hit = true;
+                }
+            }
+            false
+        });
+        if hit {
+            assert!(nrm_dot_nrmf(&isect.common.n, &isect.shading.n) >= 0.0 as Float);
+            // initialize _SurfaceInteraction::mediumInterface_ after
+            // _Shape_ intersection
+            if let Some(ref medium_interface) = self.medium_interface {
+                if medium_interface.is_medium_transition() {
+                    isect.common.medium_interface = Some(medium_interface.clone());
+                } else if let Some(ref medium_arc) = ray.medium {
+                    let inside: Option<Arc<Medium>> = Some(medium_arc.clone());
+                    let outside: Option<Arc<Medium>> = Some(medium_arc.clone());
+                    isect.common.medium_interface =
+                        Some(Arc::new(MediumInterface::new(inside, outside)));
+                }
+            }
+        }
+        hit
+    }
+    pub fn intersect_p(&self, ray: &Ray) -> bool {
+        let mut occluded: bool = false;
+        traverse_bvh(&self.nodes, ray, |leaf| {
+            occluded = self.triangles[leaf]
+                .iter()
+                .any(|triangle| self.mesh.intersect_p(*triangle, ray));
+            occluded
+        });
+        occluded
+    }
+    pub fn get_material(&self) -> Option<Arc<Material>> {
+        self.material.clone()
+    }
+    pub fn get_area_light(&self) -> Option<Arc<Light>> {
+        None
+    }
+}
+
 pub struct TransformedPrimitive {
     pub primitive: Arc<Primitive>,
     pub primitive_to_world: AnimatedTransform,
@@ -256,9 +391,8 @@ impl TransformedPrimitive {
                 // is.shading.dpdv = new_isect.shading.dpdv;
                 // is.shading.dndu = new_isect.shading.dndu;
                 // is.shading.dndv = new_isect.shading.dndv;
-                return true;
             }
-            false
+            true
         } else {
             false
         }
@@ -16,6 +16,7 @@ use crate::samplers::zerotwosequence::ZeroTwoSequenceSampler;
 
 // see sampler.h
 
+#[derive(Clone)]
 pub enum Sampler {
     Halton(HaltonSampler),
     MaxMinDist(MaxMinDistSampler),
@@ -92,6 +93,17 @@ impl Sampler {
         cs.p_lens = self.get_2d();
         cs
     }
+    /// Continues sample *sample_num* of the current pixel after the
+    /// dimensions of its camera sample (see
+    /// [get_camera_sample()](#method.get_camera_sample)), e.g. once
+    /// the camera rays of several samples were traced as a packet.
+    pub fn resume_after_camera_sample(&mut self, sample_num: i64) -> bool {
+        let valid: bool = self.set_sample_number(sample_num);
+        self.get_2d();
+        self.get_1d();
+        self.get_2d();
+        valid
+    }
     pub fn request_2d_array(&mut self, n: i32) {
         match self {
             Sampler::Halton(sampler) => sampler.request_2d_array(n),
@@ -191,12 +203,15 @@ impl Sampler {
             Sampler::ZeroTwoSequence(sampler) => sampler.get_samples_per_pixel(),
         }
     }
-    // GlobalSampler
     pub fn set_sample_number(&mut self, sample_num: i64) -> bool {
         match self {
             Sampler::Halton(sampler) => sampler.set_sample_number(sample_num),
+            Sampler::MaxMinDist(sampler) => sampler.set_sample_number(sample_num),
+            Sampler::MLT(_sampler) => false,
+            Sampler::Random(sampler) => sampler.set_sample_number(sample_num),
             Sampler::Sobol(sampler) => sampler.set_sample_number(sample_num),
-            _ => false,
+            Sampler::Stratified(sampler) => sampler.set_sample_number(sample_num),
+            Sampler::ZeroTwoSequence(sampler) => sampler.set_sample_number(sample_num),
         }
     }
 }
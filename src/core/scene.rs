@@ -76,6 +76,42 @@ impl Scene {
         );
         self.aggregate.intersect_p(ray)
     }
+    /// Finds the closest intersection for each ray of a packet of
+    /// (coherent) rays, e.g. camera rays through the same pixel.
+    pub fn intersect_packet(
+        &self,
+        rays: &[Ray],
+        isects: &mut [SurfaceInteraction],
+        hits: &mut [bool],
+    ) {
+        // TODO: nIntersectionTests += rays.len();
+        for ray in rays {
+            assert_ne!(
+                ray.d,
+                Vector3f {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                }
+            );
+        }
+        self.aggregate.intersect_packet(rays, isects, hits)
+    }
+    /// Tests a packet of shadow rays for occlusion.
+    pub fn intersect_p_packet(&self, rays: &[Ray], occluded: &mut [bool]) {
+        // TODO: nShadowTests += rays.len();
+        for ray in rays {
+            assert_ne!(
+                ray.d,
+                Vector3f {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 0.0,
+                }
+            );
+        }
+        self.aggregate.intersect_p_packet(rays, occluded)
+    }
     pub fn intersect_tr(
         &self,
         ray: &mut Ray,
@@ -109,8 +109,11 @@ impl Shape {
             Shape::Trngl(shape) => shape.sample(u, pdf),
         }
     }
-    pub fn pdf(&self, _iref: &InteractionCommon) -> Float {
-        1.0 as Float / self.area()
+    pub fn pdf(&self, iref: &InteractionCommon) -> Float {
+        match self {
+            Shape::Crv(shape) => shape.pdf(iref),
+            _ => 1.0 as Float / self.area(),
+        }
     }
     pub fn sample_with_ref_point(
         &self,
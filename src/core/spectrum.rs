@@ -1640,6 +1640,9 @@ impl RGBSpectrum {
         }
         m
     }
+    pub fn average(&self) -> Float {
+        (self.c[0] + self.c[1] + self.c[2]) / 3.0 as Float
+    }
     pub fn has_nans(&self) -> bool {
         for i in 0..3 {
             if self.c[i].is_nan() {
@@ -48,6 +48,7 @@ pub const NOISE_PERM: [u8; 2 * NOISE_PERM_SIZE] = [
     222, 114, 67, 29, 24, 72, 243, 141, 128, 195, 78, 66, 215, 61, 156, 180,
 ];
 
+#[derive(Clone)]
 pub enum TextureMapping2D {
     UV(UVMapping2D),
     Spherical(SphericalMapping2D),
@@ -0,0 +1,283 @@
+//! Before the **Film** writes an 8-bit image, the linear RGB values
+//! of the working color space go through a display transform:
+//!
+//! 1. **White balance**: a von Kries adaptation (in Bradford cone
+//!    space) from the white point of a color temperature
+//!    (**"float whitebalance"** in Kelvin, 0 disables it) to the white
+//!    point of the working color space, so that an illuminant of that
+//!    temperature appears white.
+//! 2. **Exposure**: the values are scaled by 2^EV (**"float
+//!    exposure"**, in stops), on top of the film's **"float scale"**.
+//! 3. **Tone mapping** of the (linear sRGB) values towards the
+//!    displayable range [0, 1], chosen by **"string tonemap"** (values
+//!    still outside of it get clipped when the 8-bit image is written):
+//!    - `"clamp"` (default): values above 1 are clipped
+//!    - `"reinhard"`: L / (1 + L) on the luminance, keeping the hue
+//!    - `"aces"`: a fit of the ACES reference rendering and output
+//!      device transforms (by Stephen Hill)
+//!    - `"agx"`: a polynomial approximation of Troy Sobotka's AgX
+//!      curve, which desaturates bright colors towards white
+//!
+//! The first two steps also apply to the linear HDR image, which can
+//! be written in addition to the 8-bit one (**"string hdrfilename"**,
+//! an OpenEXR file or a portable float map).
+
+// pbrt
+use crate::core::colorspace::{mat3_mul_vec3, ColorMatrix, RGBColorSpace};
+use crate::core::paramset::ParamSet;
+use crate::core::pbrt::Float;
+
+// ACES fitted (sRGB => ACES AP1 with the RRT saturation, and back)
+const ACES_INPUT: ColorMatrix = [
+    [0.597_19, 0.354_58, 0.048_23],
+    [0.076_00, 0.908_34, 0.015_66],
+    [0.028_40, 0.133_83, 0.837_77],
+];
+const ACES_OUTPUT: ColorMatrix = [
+    [1.604_75, -0.531_08, -0.073_67],
+    [-0.102_08, 1.108_13, -0.006_05],
+    [-0.003_27, -0.072_76, 1.076_02],
+];
+
+// AgX (inset and outset matrices, log2 range in stops)
+const AGX_INSET: ColorMatrix = [
+    [0.842_479, 0.078_434, 0.079_224],
+    [0.042_328, 0.878_469, 0.079_166],
+    [0.042_376, 0.078_434, 0.879_143],
+];
+const AGX_OUTSET: ColorMatrix = [
+    [1.196_879, -0.098_021, -0.099_03],
+    [-0.052_897, 1.151_903, -0.098_961],
+    [-0.052_972, -0.098_043, 1.151_074],
+];
+const AGX_MIN_EV: Float = -12.473_93;
+const AGX_MAX_EV: Float = 4.026_069;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMap {
+    Clamp,
+    Reinhard,
+    ACES,
+    AgX,
+}
+
+impl ToneMap {
+    pub fn from_name(name: &str) -> Option<ToneMap> {
+        match name.to_lowercase().as_str() {
+            "clamp" | "none" => Some(ToneMap::Clamp),
+            "reinhard" => Some(ToneMap::Reinhard),
+            "aces" | "filmic" => Some(ToneMap::ACES),
+            "agx" => Some(ToneMap::AgX),
+            _ => None,
+        }
+    }
+    /// Maps linear sRGB values to linear display values. They aren't
+    /// clipped, so they can still leave [0, 1]: `Clamp` passes them
+    /// through, `Reinhard` only bounds the luminance (single channels
+    /// of saturated colors can exceed 1) and the color matrices of
+    /// `ACES` and `AgX` can push channels slightly out of range.
+    pub fn apply(&self, rgb: &[Float; 3]) -> [Float; 3] {
+        match self {
+            ToneMap::Clamp => *rgb,
+            ToneMap::Reinhard => {
+                let weights: &[Float; 3] = RGBColorSpace::SRGB.luminance_weights();
+                let l: Float = weights[0] * rgb[0] + weights[1] * rgb[1] + weights[2] * rgb[2];
+                if l <= 0.0 as Float {
+                    return [0.0 as Float; 3];
+                }
+                let s: Float = 1.0 as Float / (1.0 as Float + l);
+                [rgb[0] * s, rgb[1] * s, rgb[2] * s]
+            }
+            ToneMap::ACES => {
+                let v: [Float; 3] = mat3_mul_vec3(&ACES_INPUT, rgb);
+                // RRT and ODT fit
+                let fit = |v: Float| -> Float {
+                    let v: Float = v.max(0.0 as Float);
+                    let a: Float = v * (v + 0.024_578_6) - 0.000_090_537;
+                    let b: Float = v * (0.983_729 * v + 0.432_951) + 0.238_081;
+                    a / b
+                };
+                mat3_mul_vec3(&ACES_OUTPUT, &[fit(v[0]), fit(v[1]), fit(v[2])])
+            }
+            ToneMap::AgX => {
+                let v: [Float; 3] = mat3_mul_vec3(&AGX_INSET, rgb);
+                // log2 encoding and sigmoid contrast curve
+                let curve = |v: Float| -> Float {
+                    let x: Float = (v.max(1e-10 as Float).log2().clamp(AGX_MIN_EV, AGX_MAX_EV)
+                        - AGX_MIN_EV)
+                        / (AGX_MAX_EV - AGX_MIN_EV);
+                    let x2: Float = x * x;
+                    let x4: Float = x2 * x2;
+                    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x
+                        + 0.4298 * x2
+                        + 0.1191 * x
+                        - 0.002_32
+                };
+                let v: [Float; 3] =
+                    mat3_mul_vec3(&AGX_OUTSET, &[curve(v[0]), curve(v[1]), curve(v[2])]);
+                // the curve's output is display encoded
+                [
+                    v[0].max(0.0 as Float).powf(2.2),
+                    v[1].max(0.0 as Float).powf(2.2),
+                    v[2].max(0.0 as Float).powf(2.2),
+                ]
+            }
+        }
+    }
+}
+
+/// White balance, exposure and tone mapping of the film's pixels.
+#[derive(Debug, Copy, Clone)]
+pub struct DisplayTransform {
+    /// white balance and exposure (in the working color space)
+    adjust: ColorMatrix,
+    pub tone_map: ToneMap,
+    /// the working color space of the film's pixels
+    pub color_space: RGBColorSpace,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        DisplayTransform {
+            adjust: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            tone_map: ToneMap::Clamp,
+            color_space: RGBColorSpace::SRGB,
+        }
+    }
+}
+
+impl DisplayTransform {
+    pub fn new(
+        exposure: Float,
+        white_balance: Float,
+        tone_map: ToneMap,
+        color_space: RGBColorSpace,
+    ) -> Self {
+        let mut adjust: ColorMatrix = if white_balance > 0.0 as Float {
+            color_space.white_balance(white_balance)
+        } else {
+            DisplayTransform::default().adjust
+        };
+        let scale: Float = exposure.exp2();
+        for row in adjust.iter_mut() {
+            for item in row.iter_mut() {
+                *item *= scale;
+            }
+        }
+        DisplayTransform {
+            adjust,
+            tone_map,
+            color_space,
+        }
+    }
+    pub fn create(params: &ParamSet, color_space: RGBColorSpace) -> DisplayTransform {
+        let exposure: Float = params.find_one_float("exposure", 0.0 as Float);
+        let white_balance: Float = params.find_one_float("whitebalance", 0.0 as Float);
+        let name: String = params.find_one_string("tonemap", String::from("clamp"));
+        let tone_map: ToneMap = ToneMap::from_name(&name).unwrap_or_else(|| {
+            println!(
+                "WARNING: Tone mapping \"{}\" unknown. Using \"clamp\".",
+                name
+            );
+            ToneMap::Clamp
+        });
+        DisplayTransform::new(exposure, white_balance, tone_map, color_space)
+    }
+    /// Applies white balance and exposure to the RGB values (of the
+    /// working color space) of all pixels.
+    pub fn adjust(&self, rgb: &mut [Float]) {
+        for texel in rgb.chunks_mut(3) {
+            let adjusted: [Float; 3] = mat3_mul_vec3(&self.adjust, &[texel[0], texel[1], texel[2]]);
+            texel.copy_from_slice(&adjusted);
+        }
+    }
+    /// Converts adjusted RGB values (see [adjust()](#method.adjust))
+    /// into tone mapped, linear sRGB values.
+    pub fn to_display(&self, rgb: &[Float; 3]) -> [Float; 3] {
+        let srgb: [Float; 3] = self.color_space.convert_rgb(rgb, RGBColorSpace::SRGB);
+        self.tone_map.apply(&srgb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMap; 4] = [
+        ToneMap::Clamp,
+        ToneMap::Reinhard,
+        ToneMap::ACES,
+        ToneMap::AgX,
+    ];
+
+    fn gray(tone_map: ToneMap, v: Float) -> [Float; 3] {
+        tone_map.apply(&[v, v, v])
+    }
+
+    #[test]
+    fn operator_names() {
+        assert_eq!(ToneMap::from_name("none"), Some(ToneMap::Clamp));
+        assert_eq!(ToneMap::from_name("Reinhard"), Some(ToneMap::Reinhard));
+        assert_eq!(ToneMap::from_name("filmic"), Some(ToneMap::ACES));
+        assert_eq!(ToneMap::from_name("AGX"), Some(ToneMap::AgX));
+        assert_eq!(ToneMap::from_name("hable"), None);
+    }
+
+    #[test]
+    fn gray_stays_gray_and_increases() {
+        for tone_map in OPERATORS.iter() {
+            let mut previous: Float = -1.0;
+            for i in 0..=40 {
+                let v: Float = (2.0 as Float).powf(i as Float * 0.5 - 10.0);
+                let rgb: [Float; 3] = gray(*tone_map, v);
+                // (the rows of the AgX matrices sum to 1 +- 1e-4)
+                assert!(
+                    (rgb[0] - rgb[1]).abs() < 1e-3 && (rgb[0] - rgb[2]).abs() < 1e-3,
+                    "{:?}({}) = {:?}",
+                    tone_map,
+                    v,
+                    rgb
+                );
+                assert!(rgb[0] >= previous, "{:?}({}) = {:?}", tone_map, v, rgb);
+                previous = rgb[0];
+            }
+        }
+    }
+
+    #[test]
+    fn curves_compress_highlights() {
+        assert_eq!(gray(ToneMap::Clamp, 4.0), [4.0, 4.0, 4.0]);
+        assert!((gray(ToneMap::Reinhard, 3.0)[0] - 0.75).abs() < 1e-5);
+        for tone_map in [ToneMap::ACES, ToneMap::AgX].iter() {
+            assert!(gray(*tone_map, 0.0)[0].abs() < 1e-3, "{:?}", tone_map);
+            let white: Float = gray(*tone_map, 1e6)[0];
+            assert!(white > 0.95 && white < 1.02, "{:?}: {}", tone_map, white);
+        }
+    }
+
+    #[test]
+    fn reinhard_keeps_hue() {
+        let rgb: [Float; 3] = ToneMap::Reinhard.apply(&[4.0, 2.0, 1.0]);
+        assert!((rgb[0] / rgb[1] - 2.0).abs() < 1e-5 && (rgb[1] / rgb[2] - 2.0).abs() < 1e-5);
+        assert_eq!(ToneMap::Reinhard.apply(&[-1.0, 0.0, 0.0]), [0.0; 3]);
+    }
+
+    #[test]
+    fn agx_desaturates_bright_colors() {
+        let rgb: [Float; 3] = ToneMap::AgX.apply(&[8.0, 0.0, 0.0]);
+        assert!(rgb[1] > 0.1 && rgb[2] > 0.1, "{:?}", rgb);
+        assert!(rgb[0] > rgb[1] && rgb[0] > rgb[2], "{:?}", rgb);
+        // and very bright ones all the way to white
+        let rgb: [Float; 3] = ToneMap::AgX.apply(&[1e3, 0.0, 0.0]);
+        assert!(rgb.iter().all(|v| *v > 0.99), "{:?}", rgb);
+    }
+
+    #[test]
+    fn exposure_scales_by_stops() {
+        let transform: DisplayTransform =
+            DisplayTransform::new(-2.0, 0.0, ToneMap::Clamp, RGBColorSpace::SRGB);
+        let mut rgb: [Float; 6] = [4.0, 2.0, 1.0, 0.0, 8.0, 0.5];
+        transform.adjust(&mut rgb);
+        assert_eq!(rgb, [1.0, 0.5, 0.25, 0.0, 2.0, 0.125]);
+    }
+}
@@ -862,7 +862,8 @@ impl Transform {
         //     }
         // }
         // ret.bssrdf = si.bssrdf.clone();
-        ret.primitive = None; // TODO? si.primitive;
+        // keep the primitive (e.g. of a transformed primitive's hit)
+        ret.primitive = si.primitive;
         ret.shading.n = nrm_faceforward_nrm(&ret.shading.n, &ret.common.n);
         // TODO: ret.faceIndex = si.faceIndex;
         *si = ret;
@@ -899,8 +900,10 @@ impl DerivativeTerm {
     }
 }
 
+/// Interpolates between two transformation keys by decomposing them
+/// into translation, rotation and scale.
 #[derive(Debug, Default, Copy, Clone)]
-pub struct AnimatedTransform {
+pub struct AnimatedTransformSegment {
     start_transform: Transform,
     end_transform: Transform,
     start_time: Float,
@@ -917,21 +920,31 @@ pub struct AnimatedTransform {
     c5: [DerivativeTerm; 3],
 }
 
-impl AnimatedTransform {
+impl AnimatedTransformSegment {
     pub fn new(
         start_transform: &Transform,
         start_time: Float,
         end_transform: &Transform,
         end_time: Float,
     ) -> Self {
-        let mut at: AnimatedTransform = AnimatedTransform::default();
+        let mut at: AnimatedTransformSegment = AnimatedTransformSegment::default();
         at.start_transform = *start_transform;
         at.end_transform = *end_transform;
         at.start_time = start_time;
         at.end_time = end_time;
         at.actually_animated = *start_transform != *end_transform;
-        AnimatedTransform::decompose(&start_transform.m, &mut at.t[0], &mut at.r[0], &mut at.s[0]);
-        AnimatedTransform::decompose(&end_transform.m, &mut at.t[1], &mut at.r[1], &mut at.s[1]);
+        AnimatedTransformSegment::decompose(
+            &start_transform.m,
+            &mut at.t[0],
+            &mut at.r[0],
+            &mut at.s[0],
+        );
+        AnimatedTransformSegment::decompose(
+            &end_transform.m,
+            &mut at.t[1],
+            &mut at.r[1],
+            &mut at.s[1],
+        );
         // flip _r[1]_ if needed to select shortest path
         if quat_dot_quat(&at.r[0], &at.r[1]) < 0.0 {
             at.r[1] = -at.r[1];
@@ -2210,6 +2223,95 @@ impl AnimatedTransform {
     }
 }
 
+/// A transformation which changes over the shutter interval. It is
+/// given by two or more keys (sorted by time) and interpolates
+/// piecewise between consecutive keys.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    segments: Vec<AnimatedTransformSegment>,
+}
+
+impl Default for AnimatedTransform {
+    fn default() -> Self {
+        let identity: Transform = Transform::default();
+        AnimatedTransform::new(&identity, 0.0 as Float, &identity, 1.0 as Float)
+    }
+}
+
+impl AnimatedTransform {
+    pub fn new(
+        start_transform: &Transform,
+        start_time: Float,
+        end_transform: &Transform,
+        end_time: Float,
+    ) -> Self {
+        AnimatedTransform {
+            segments: vec![AnimatedTransformSegment::new(
+                start_transform,
+                start_time,
+                end_transform,
+                end_time,
+            )],
+        }
+    }
+    /// Creates an animated transform from *transforms* at increasing
+    /// *times* (at least one key).
+    pub fn new_with_keys(transforms: &[Transform], times: &[Float]) -> Self {
+        assert_eq!(transforms.len(), times.len());
+        assert!(!transforms.is_empty());
+        if transforms.len() == 1 {
+            return AnimatedTransform::new(&transforms[0], times[0], &transforms[0], times[0]);
+        }
+        let mut segments: Vec<AnimatedTransformSegment> = Vec::with_capacity(transforms.len() - 1);
+        for i in 0..(transforms.len() - 1) {
+            assert!(times[i] <= times[i + 1]);
+            segments.push(AnimatedTransformSegment::new(
+                &transforms[i],
+                times[i],
+                &transforms[i + 1],
+                times[i + 1],
+            ));
+        }
+        AnimatedTransform { segments }
+    }
+    pub fn decompose(m: &Matrix4x4, t: &mut Vector3f, rquat: &mut Quaternion, s: &mut Matrix4x4) {
+        AnimatedTransformSegment::decompose(m, t, rquat, s)
+    }
+    /// Returns the segment which is active at *time* (the first or
+    /// last one for times outside of the keys).
+    fn segment(&self, time: Float) -> &AnimatedTransformSegment {
+        let last: usize = self.segments.len() - 1;
+        let index: usize = self.segments[..last].partition_point(|seg| seg.end_time < time);
+        &self.segments[index]
+    }
+    pub fn interpolate(&self, time: Float, t: &mut Transform) {
+        self.segment(time).interpolate(time, t)
+    }
+    pub fn transform_ray(&self, r: &Ray) -> Ray {
+        self.segment(r.time).transform_ray(r)
+    }
+    pub fn transform_point(&self, time: Float, p: &Point3f) -> Point3f {
+        self.segment(time).transform_point(time, p)
+    }
+    pub fn transform_vector(&self, time: Float, v: &Vector3f) -> Vector3f {
+        self.segment(time).transform_vector(time, v)
+    }
+    pub fn motion_bounds(&self, b: &Bounds3f) -> Bounds3f {
+        let mut bounds: Bounds3f = self.segments[0].motion_bounds(b);
+        for segment in self.segments.iter().skip(1) {
+            bounds = bnd3_union_bnd3f(&bounds, &segment.motion_bounds(b));
+        }
+        bounds
+    }
+    pub fn bound_point_motion(&self, p: &Point3f) -> Bounds3f {
+        let mut bounds: Bounds3f = self.segments[0].bound_point_motion(p);
+        for segment in self.segments.iter().skip(1) {
+            bounds = bnd3_union_bnd3f(&bounds, &segment.bound_point_motion(p));
+        }
+        bounds
+    }
+}
+
 #[derive(Debug, Default, Copy, Clone)]
 pub struct Interval {
     pub low: Float,
@@ -2,6 +2,7 @@
 use std::sync::Arc;
 // pbrt
 use crate::core::camera::Camera;
+use crate::core::denoiser::FirstHitFeatures;
 use crate::core::geometry::{nrm_cross_vec3, nrm_faceforward_vec3, vec3_dot_nrmf};
 use crate::core::geometry::{Bounds2i, Normal3f, Point2f, Ray, Vector3f};
 use crate::core::interaction::{Interaction, SurfaceInteraction};
@@ -53,14 +54,34 @@ impl AOIntegrator {
         scene: &Scene,
         sampler: &mut Sampler,
         // arena: &mut Arena,
+        depth: i32,
+    ) -> Spectrum {
+        let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+        let hit: bool = scene.intersect(ray, &mut isect);
+        self.li_with_isect(ray, hit, isect, scene, sampler, depth, None)
+    }
+    /// Same as [li()](#method.li), but the closest intersection of
+    /// the camera ray is already known (e.g. from tracing a ray
+    /// packet). If _features_ is given, it receives the albedo and
+    /// shading normal of the first surface hit (for the denoiser).
+    pub fn li_with_isect(
+        &self,
+        ray: &mut Ray,
+        hit: bool,
+        mut isect: SurfaceInteraction,
+        scene: &Scene,
+        sampler: &mut Sampler,
         _depth: i32,
+        features: Option<&mut FirstHitFeatures>,
     ) -> Spectrum {
         // TODO: ProfilePhase p(Prof::SamplerIntegratorLi);
         let mut l: Spectrum = Spectrum::default();
-        let mut isect: SurfaceInteraction = SurfaceInteraction::default();
-        if scene.intersect(ray, &mut isect) {
+        if hit {
             let mode: TransportMode = TransportMode::Radiance;
             isect.compute_scattering_functions(&ray, true, mode);
+            if let Some(features) = features {
+                *features = FirstHitFeatures::new(&isect);
+            }
             // if (!isect.bsdf) {
             //     VLOG(2) << "Skipping intersection due to null bsdf";
             //     ray = isect.SpawnRay(ray.d);
@@ -5,12 +5,13 @@ use std::sync::Arc;
 // pbrt
 use crate::blockqueue::BlockQueue;
 use crate::core::camera::{Camera, CameraSample};
+use crate::core::film::Film;
 use crate::core::geometry::{
     nrm_abs_dot_vec3f, pnt2_inside_exclusivei, pnt3_offset_ray_origin, vec3_abs_dot_nrmf,
     vec3_dot_nrmf,
 };
 use crate::core::geometry::{
-    Bounds2i, Bounds3f, Normal3f, Point2f, Point2i, Point3f, Ray, Vector2i, Vector3f,
+    Bounds2f, Bounds2i, Bounds3f, Normal3f, Point2f, Point2i, Point3f, Ray, Vector2i, Vector3f,
 };
 use crate::core::interaction::{
     Interaction, InteractionCommon, MediumInteraction, SurfaceInteraction,
@@ -18,14 +19,16 @@ use crate::core::interaction::{
 use crate::core::light::is_delta_light;
 use crate::core::light::{Light, LightFlags, VisibilityTester};
 use crate::core::lightdistrib::create_light_sample_distribution;
+use crate::core::lightdistrib::{LightDistribution, LocalLightDistribution};
 use crate::core::material::TransportMode;
 use crate::core::medium::{HenyeyGreenstein, Medium, MediumInterface};
+use crate::core::paramset::ParamSet;
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::reflection::Bsdf;
 use crate::core::reflection::BxdfType;
 use crate::core::sampler::Sampler;
-use crate::core::sampling::Distribution1D;
 use crate::core::scene::Scene;
+use crate::filters::boxfilter::BoxFilter;
 
 // see bdpt.h
 
@@ -487,6 +490,26 @@ impl<'a> Vertex<'a> {
             }
         }
     }
+    pub fn beta(&self) -> Spectrum {
+        self.beta
+    }
+    pub fn is_delta(&self) -> bool {
+        self.delta
+    }
+    pub fn pdf_fwd(&self) -> Float {
+        self.pdf_fwd
+    }
+    pub fn pdf_rev(&self) -> Float {
+        self.pdf_rev
+    }
+    pub fn surface_interaction(&self) -> Option<&SurfaceInteraction<'a>> {
+        self.si.as_ref()
+    }
+    /// Photons can only be merged at surface vertices which were
+    /// scattered by a non-specular BSDF component.
+    pub fn is_mergeable(&self) -> bool {
+        self.vertex_type == VertexType::Surface && !self.delta && self.is_connectible()
+    }
     pub fn is_light(&self) -> bool {
         if self.vertex_type == VertexType::Light {
             return true;
@@ -712,7 +735,7 @@ impl<'a> Vertex<'a> {
         &self,
         scene: &Scene,
         v: &Vertex,
-        light_distr: Arc<Distribution1D>,
+        light_distr: LocalLightDistribution,
     ) -> Float {
         let mut w: Vector3f = v.p() - self.p();
         if w.length_squared() == 0.0 as Float {
@@ -722,86 +745,52 @@ impl<'a> Vertex<'a> {
         if self.is_infinite_light() {
             // return solid angle density for infinite light sources
             return infinite_light_density(scene, light_distr, &w);
-        } else {
-            // return solid angle density for non-infinite light sources
-            //         Float pdf_pos, pdf_dir, pdf_choice = 0;
-            let mut pdf_pos: Float = 0.0;
-            let mut pdf_dir: Float = 0.0;
-            let mut pdf_choice: Float = 0.0;
-            // get pointer _light_ to the light source at the vertex
-            assert!(self.is_light());
-            if self.vertex_type == VertexType::Light {
-                // a real light source (not geometry emitting light)
-                if let Some(ref ei) = self.ei {
-                    if let Some(ref light_ref) = ei.light {
-                        // find light in light vector
-                        for i in 0..scene.lights.len() {
-                            let light = &scene.lights[i];
-                            // use ** (alloc::arc::Arc<Light> **)
-                            let pr = &**light_ref as *const _ as *const usize;
-                            let pl = &*light as *const _ as *const usize;
-                            if pr == pl {
-                                // compute the discrete probability of
-                                // sampling _light_, _pdf_choice_
-                                pdf_choice = light_distr.discrete_pdf(i);
-                                light.pdf_le(
-                                    &Ray {
-                                        o: self.p(),
-                                        d: w,
-                                        t_max: Cell::new(std::f32::INFINITY),
-                                        time: self.time(),
-                                        differential: None,
-                                        medium: None,
-                                    },
-                                    &self.ng(),
-                                    &mut pdf_pos,
-                                    &mut pdf_dir,
-                                );
-                                break;
-                            }
-                        }
-                        return pdf_pos * pdf_choice;
-                    }
-                }
-            } else {
-                // area light from primitive
-                if let Some(ref si) = self.si {
-                    if let Some(primitive_raw) = si.primitive {
-                        let primitive = unsafe { &*primitive_raw };
-                        if let Some(area_light) = primitive.get_area_light() {
-                            // find area light in light vector
-                            for i in 0..scene.lights.len() {
-                                let light = &scene.lights[i];
-                                let pa = &*area_light as *const _ as *const usize;
-                                let pl = &**light as *const _ as *const usize;
-                                if pa == pl {
-                                    // compute the discrete
-                                    // probability of sampling
-                                    // _light_, _pdf_choice_
-                                    pdf_choice = light_distr.discrete_pdf(i);
-                                    light.pdf_le(
-                                        &Ray {
-                                            o: self.p(),
-                                            d: w,
-                                            t_max: Cell::new(std::f32::INFINITY),
-                                            time: self.time(),
-                                            differential: None,
-                                            medium: None,
-                                        },
-                                        &self.ng(),
-                                        &mut pdf_pos,
-                                        &mut pdf_dir,
-                                    );
-                                    break;
-                                }
-                            }
-                            return pdf_pos * pdf_choice;
-                        }
-                    }
-                }
-            }
         }
-        0.0 as Float
+        // return solid angle density for non-infinite light sources
+        let mut pdf_pos: Float = 0.0;
+        let mut pdf_dir: Float = 0.0;
+        let mut pdf_choice: Float = 0.0;
+        // get pointer _light_ to the light source at the vertex
+        assert!(self.is_light());
+        if let Some(index) = self.light_index(scene) {
+            // compute the discrete probability of sampling _light_,
+            // _pdf_choice_
+            pdf_choice = light_distr.discrete_pdf(index);
+            scene.lights[index].pdf_le(
+                &Ray {
+                    o: self.p(),
+                    d: w,
+                    t_max: Cell::new(std::f32::INFINITY),
+                    time: self.time(),
+                    differential: None,
+                    medium: None,
+                },
+                &self.ng(),
+                &mut pdf_pos,
+                &mut pdf_dir,
+            );
+        }
+        pdf_pos * pdf_choice
+    }
+    /// Returns the index in *scene.lights* of the light source at the
+    /// vertex, which is either a real light source or geometry
+    /// emitting light.
+    pub fn light_index(&self, scene: &Scene) -> Option<usize> {
+        let light_ptr: *const Light = if self.vertex_type == VertexType::Light {
+            // a real light source (not geometry emitting light)
+            let light_ref = self.ei.as_ref()?.light?;
+            &**light_ref
+        } else {
+            // area light from primitive
+            let primitive_raw = self.si.as_ref()?.primitive?;
+            let primitive = unsafe { &*primitive_raw };
+            Arc::as_ptr(&primitive.get_area_light()?)
+        };
+        // find light in light vector
+        scene
+            .lights
+            .iter()
+            .position(|light| std::ptr::eq(&**light, light_ptr))
     }
 }
 
@@ -812,8 +801,8 @@ pub struct BDPTIntegrator {
     pub pixel_bounds: Bounds2i,
     // see bdpt.h
     pub max_depth: u32,
-    // visualize_strategies: bool,
-    // visualize_weights: bool,
+    pub visualize_strategies: bool,
+    pub visualize_weights: bool,
     pub light_sample_strategy: String, // "power"
 }
 
@@ -823,8 +812,8 @@ impl BDPTIntegrator {
         sampler: Box<Sampler>,
         pixel_bounds: Bounds2i,
         max_depth: u32,
-        // visualize_strategies: bool,
-        // visualize_weights: bool,
+        visualize_strategies: bool,
+        visualize_weights: bool,
         light_sample_strategy: String,
     ) -> Self {
         BDPTIntegrator {
@@ -832,8 +821,8 @@ impl BDPTIntegrator {
             sampler,
             pixel_bounds,
             max_depth,
-            // visualize_strategies,
-            // visualize_weights,
+            visualize_strategies,
+            visualize_weights,
             light_sample_strategy,
         }
     }
@@ -859,8 +848,18 @@ impl BDPTIntegrator {
         let n_x_tiles: i32 = (sample_extent.x + tile_size - 1) / tile_size;
         let n_y_tiles: i32 = (sample_extent.y + tile_size - 1) / tile_size;
         // TODO: ProgressReporter reporter(nXTiles * nYTiles, "Rendering");
-        // TODO: Allocate buffers for debug visualization
-        // ...
+        // allocate buffers for debug visualization, the MIS weighted
+        // images get a "_weighted" suffix
+        let strategy_films: Vec<Option<Film>> = if self.visualize_strategies {
+            create_strategy_films(&film, self.max_depth, "")
+        } else {
+            Vec::new()
+        };
+        let weight_films: Vec<Option<Film>> = if self.visualize_weights {
+            create_strategy_films(&film, self.max_depth, "_weighted")
+        } else {
+            Vec::new()
+        };
         // render and write the output image to disk
         if !scene.lights.is_empty() {
             let samples_per_pixel: i64 = self.sampler.get_samples_per_pixel();
@@ -884,7 +883,18 @@ impl BDPTIntegrator {
                 let sampler = &self.get_sampler();
                 let camera = &self.get_camera();
                 let film = &film;
-                // let pixel_bounds = integrator.get_pixel_bounds().clone();
+                let strategy_films = &strategy_films;
+                let weight_films = &weight_films;
+                // compensate light tracing splats for the pixels
+                // outside of _pixel_bounds_, which start no light paths
+                let splat_scale: Float =
+                    sample_bounds.area() as Float / integrator.pixel_bounds.area() as Float;
+                // one light distribution is shared by all samples
+                let light_distribution: &Option<Arc<LightDistribution>> =
+                    &create_light_sample_distribution(
+                        integrator.get_light_sample_strategy(),
+                        scene,
+                    );
                 crossbeam::scope(|scope| {
                     let (pixel_tx, pixel_rx) = crossbeam_channel::bounded(num_cores);
                     // spawn worker threads
@@ -927,14 +937,13 @@ impl BDPTIntegrator {
                                         // strategy. We use the
                                         // PowerLightDistribution by
                                         // default here, which doesn't use
-                                        // the point passed to it. Now
-                                        // trace the light subpath
-                                        if let Some(light_distribution) =
-                                            create_light_sample_distribution(
-                                                integrator.get_light_sample_strategy(),
-                                                scene,
-                                            )
-                                        {
+                                        // the point passed to it, and the
+                                        // light BVH ignores it as well
+                                        // (connections to single light
+                                        // vertices look it up per vertex,
+                                        // see connect_bdpt()). Now trace
+                                        // the light subpath
+                                        if let Some(light_distribution) = light_distribution {
                                             // generate a single sample using BDPT
                                             let p_film: Point2f = Point2f {
                                                 x: p_pixel.x as Float,
@@ -948,8 +957,9 @@ impl BDPTIntegrator {
                                             let n_camera;
                                             let p;
                                             let time;
+                                            let le_medium;
                                             {
-                                                let (n_camera_new, p_new, time_new) =
+                                                let (n_camera_new, p_new, time_new, le_new) =
                                                     generate_camera_subpath(
                                                         scene,
                                                         &mut tile_sampler,
@@ -961,9 +971,10 @@ impl BDPTIntegrator {
                                                 n_camera = n_camera_new;
                                                 p = p_new;
                                                 time = time_new;
+                                                le_medium = le_new;
                                             }
-                                            let light_distr: Arc<Distribution1D> =
-                                                light_distribution.lookup(&p);
+                                            let light_distr: LocalLightDistribution =
+                                                light_distribution.lookup_origin(&p);
                                             let mut light_vertices: Vec<Vertex> =
                                                 Vec::with_capacity(
                                                     (integrator.max_depth + 1) as usize,
@@ -981,7 +992,9 @@ impl BDPTIntegrator {
                                                 );
                                             }
                                             // Execute all BDPT connection strategies
-                                            let mut l: Spectrum = Spectrum::new(0.0 as Float);
+                                            let mut l: Spectrum = le_medium
+                                                .iter()
+                                                .fold(Spectrum::default(), |l, le| l + *le);
                                             // println!("n_camera = {:?}", n_camera);
                                             // println!("n_light = {:?}", n_light);
                                             for t in 1..=n_camera {
@@ -1012,24 +1025,71 @@ impl BDPTIntegrator {
                                                         &mut tile_sampler,
                                                         &mut p_film_new,
                                                         mis_weight.as_mut(),
+                                                        0.0 as Float,
                                                     );
                                                     // if let Some(mis_weight_flt) = mis_weight {
                                                     //     println!("Connect bdpt s: {:?}, t: {:?}, lpath: {:?}, mis_weight: {:?}",
                                                     //              s, t, lpath, mis_weight_flt);
                                                     // }
-                                                    // if (visualizeStrategies || visualizeWeights) {
-                                                    //     Spectrum value;
-                                                    //     if (visualizeStrategies)
-                                                    //         value =
-                                                    //             mis_weight == 0 ? 0 : lpath / mis_weight;
-                                                    //     if (visualizeWeights) value = lpath;
-                                                    //     weightFilms[BufferIndex(s, t)]->AddSplat(
-                                                    //         pFilmNew, value);
-                                                    // }
+                                                    if !strategy_films.is_empty()
+                                                        || !weight_films.is_empty()
+                                                    {
+                                                        // light tracing splats are
+                                                        // treated like above
+                                                        let p_splat: Point2i = Point2i {
+                                                            x: p_film_new.x.floor() as i32,
+                                                            y: p_film_new.y.floor() as i32,
+                                                        };
+                                                        let scale: Float = if t != 1 {
+                                                            1.0 as Float
+                                                        } else if pnt2_inside_exclusivei(
+                                                            p_splat,
+                                                            &integrator.pixel_bounds,
+                                                        ) {
+                                                            splat_scale
+                                                        } else {
+                                                            0.0 as Float
+                                                        };
+                                                        let index: usize = buffer_index(s, t);
+                                                        if let Some(Some(strategy_film)) =
+                                                            strategy_films.get(index)
+                                                        {
+                                                            let weight: Float =
+                                                                mis_weight.unwrap_or(0.0);
+                                                            if weight != 0.0 as Float {
+                                                                strategy_film.add_splat(
+                                                                    p_film_new,
+                                                                    &(lpath * (scale / weight)),
+                                                                );
+                                                            }
+                                                        }
+                                                        if let Some(Some(weight_film)) =
+                                                            weight_films.get(index)
+                                                        {
+                                                            weight_film.add_splat(
+                                                                p_film_new,
+                                                                &(lpath * scale),
+                                                            );
+                                                        }
+                                                    }
                                                     if t != 1 {
                                                         l += lpath;
                                                     } else if !lpath.is_black() {
-                                                        film.add_splat(p_film_new, &lpath);
+                                                        // light paths only start from
+                                                        // pixels inside _pixel_bounds_
+                                                        let p_splat: Point2i = Point2i {
+                                                            x: p_film_new.x.floor() as i32,
+                                                            y: p_film_new.y.floor() as i32,
+                                                        };
+                                                        if pnt2_inside_exclusivei(
+                                                            p_splat,
+                                                            &integrator.pixel_bounds,
+                                                        ) {
+                                                            film.add_splat(
+                                                                p_film_new,
+                                                                &(lpath * splat_scale),
+                                                            );
+                                                        }
                                                     }
                                                 }
                                             }
@@ -1063,7 +1123,10 @@ impl BDPTIntegrator {
                 .unwrap();
             }
             film.write_image(1.0 as Float / samples_per_pixel as Float);
-            // TODO: Write buffers for debug visualization
+            // write buffers for debug visualization
+            for strategy_film in strategy_films.iter().chain(weight_films.iter()).flatten() {
+                strategy_film.write_image_to_file(1.0 as Float / samples_per_pixel as Float);
+            }
         }
     }
     pub fn get_camera(&self) -> Arc<Camera> {
@@ -1076,6 +1139,45 @@ impl BDPTIntegrator {
 
 // BDPT Utility Functions
 
+/// Index of the $(s, t)$ strategy in the debug visualization buffers,
+/// which reserve $depth + 3$ entries for each path depth.
+fn buffer_index(s: usize, t: usize) -> usize {
+    let above: usize = s + t - 2;
+    s + above * (5 + above) / 2
+}
+
+/// Allocate one film per $(s, t)$ strategy up to _max_depth_, named
+/// like the files written by **pbrt**, e.g. "bdpt_d01_s02_t01.exr".
+fn create_strategy_films(film: &Film, max_depth: u32, suffix: &str) -> Vec<Option<Film>> {
+    let n_buffers: usize = buffer_index(0, max_depth as usize + 3);
+    let mut films: Vec<Option<Film>> = Vec::with_capacity(n_buffers);
+    films.resize_with(n_buffers, || None);
+    for depth in 0..=max_depth as usize {
+        for s in 0..=depth + 2 {
+            let t: usize = depth + 2 - s;
+            if t == 0 || (s == 1 && t == 1) {
+                continue;
+            }
+            let filename: String = format!("bdpt_d{:02}_s{:02}_t{:02}{}.exr", depth, s, t, suffix);
+            let mut strategy_film: Film = Film::new(
+                film.full_resolution,
+                Bounds2f {
+                    p_min: Point2f { x: 0.0, y: 0.0 },
+                    p_max: Point2f { x: 1.0, y: 1.0 },
+                },
+                BoxFilter::create(&ParamSet::default()),
+                film.diagonal * 1000.0,
+                filename,
+                1.0 as Float,
+                Float::INFINITY,
+            );
+            strategy_film.color_space = film.color_space;
+            films[buffer_index(s, t)] = Some(strategy_film);
+        }
+    }
+    films
+}
+
 pub fn correct_shading_normal(
     isect: &SurfaceInteraction,
     wo: &Vector3f,
@@ -1106,9 +1208,9 @@ pub fn generate_camera_subpath<'a>(
     camera: &'a Arc<Camera>,
     p_film: Point2f,
     path: &mut Vec<Vertex<'a>>,
-) -> (usize, Point3f, Float) {
+) -> (usize, Point3f, Float, Vec<Spectrum>) {
     if max_depth == 0 {
-        return (0_usize, Point3f::default(), Float::default());
+        return (0_usize, Point3f::default(), Float::default(), Vec::new());
     }
     // TODO: ProfilePhase _(Prof::BDPTGenerateSubpath);
     // sample initial ray for camera subpath
@@ -1135,20 +1237,22 @@ pub fn generate_camera_subpath<'a>(
     // store vertex
     path.push(vertex);
     let (_pdf_pos, pdf_dir) = camera.pdf_we(&ray);
-    (
-        random_walk(
-            scene,
-            &ray,
-            sampler,
-            &mut beta,
-            pdf_dir,
-            max_depth - 1_u32,
-            TransportMode::Radiance,
-            path,
-        ) + 1_usize,
-        p,
-        time,
-    )
+    // light emitted by media along the camera subpath can't be found
+    // by any other strategy and is returned separately (by the index
+    // of the camera vertex it was found at)
+    let mut le_medium: Vec<Spectrum> = Vec::new();
+    let n_vertices: usize = random_walk(
+        scene,
+        &ray,
+        sampler,
+        &mut beta,
+        pdf_dir,
+        max_depth - 1_u32,
+        TransportMode::Radiance,
+        path,
+        &mut le_medium,
+    );
+    (n_vertices + 1_usize, p, time, le_medium)
 }
 
 pub fn generate_light_subpath<'a>(
@@ -1156,7 +1260,7 @@ pub fn generate_light_subpath<'a>(
     sampler: &mut Sampler,
     max_depth: u32,
     time: Float,
-    light_distr: Arc<Distribution1D>,
+    light_distr: LocalLightDistribution,
     // TODO: light_to_index
     path: &mut Vec<Vertex<'a>>,
 ) -> usize {
@@ -1184,7 +1288,11 @@ pub fn generate_light_subpath<'a>(
         &mut pdf_pos,
         &mut pdf_dir,
     );
-    if pdf_pos == 0.0 as Float || pdf_dir == 0.0 as Float || le.is_black() {
+    if pdf_pos == 0.0 as Float
+        || pdf_dir == 0.0 as Float
+        || le.is_black()
+        || light_pdf == Some(0.0 as Float)
+    {
         return 0_usize;
     }
     if let Some(light_pdf) = light_pdf {
@@ -1201,6 +1309,7 @@ pub fn generate_light_subpath<'a>(
 
         // set spatial density of _path[1]_ for infinite area
         // light is done in random_walk !!!
+        let mut le_medium: Vec<Spectrum> = Vec::new(); // not used for light subpaths
         n_vertices = random_walk(
             scene,
             &ray,
@@ -1210,6 +1319,7 @@ pub fn generate_light_subpath<'a>(
             max_depth - 1,
             TransportMode::Importance,
             path,
+            &mut le_medium,
         );
         // correct subpath sampling densities for infinite area lights
         if is_infinite_light {
@@ -1236,6 +1346,7 @@ pub fn random_walk<'a>(
     max_depth: u32,
     mode: TransportMode,
     path: &mut Vec<Vertex<'a>>,
+    le_medium: &mut Vec<Spectrum>,
 ) -> usize {
     // create a copy of the ray which can be mutated
     let mut ray: Ray = ray.clone();
@@ -1263,10 +1374,18 @@ pub fn random_walk<'a>(
         }
         if let Some(ref medium) = ray.medium {
             let (spectrum, option) = medium.sample(&ray, sampler);
-            *beta *= spectrum;
             if let Some(mi) = option {
+                if mode == TransportMode::Radiance {
+                    // light emitted by the medium (at the next vertex)
+                    let index: usize = path.len();
+                    if le_medium.len() <= index {
+                        le_medium.resize(index + 1, Spectrum::default());
+                    }
+                    le_medium[index] += *beta * mi.le;
+                }
                 mi_opt = Some(mi);
             }
+            *beta *= spectrum;
         }
         if beta.is_black() {
             break;
@@ -1500,15 +1619,21 @@ pub fn g<'a>(scene: &'a Scene, sampler: &mut Sampler, v0: &Vertex, v1: &Vertex)
     vis.tr(scene, sampler) * g
 }
 
+/// Computes the MIS weight of the strategy connecting the last
+/// vertices of _light_vertices_ and _camera_vertices_, which are
+/// expected to hold exactly $s$ and $t$ vertices. With a non-zero
+/// _eta_vm_ (see [vcm](../vcm/index.html)) the strategies merging
+/// photons at any of the vertices are taken into account as well.
 pub fn mis_weight<'a>(
     scene: &'a Scene,
     light_vertices: &[Vertex<'a>],
     camera_vertices: &[Vertex<'a>],
     sampled: &Vertex,
-    s: usize,
-    t: usize,
-    light_pdf: Arc<Distribution1D>,
+    light_pdf: LocalLightDistribution,
+    eta_vm: Float,
 ) -> Float {
+    let s: usize = light_vertices.len();
+    let t: usize = camera_vertices.len();
     if s + t == 2 as usize {
         return 1.0 as Float;
     }
@@ -1577,7 +1702,11 @@ pub fn mis_weight<'a>(
             common.wo = lv_mi.common.wo;
             common.n = lv_mi.common.n;
             common.medium_interface = medium_interface;
-            let new_mi: MediumInteraction = MediumInteraction { common, phase };
+            let new_mi: MediumInteraction = MediumInteraction {
+                common,
+                phase,
+                le: lv_mi.le,
+            };
             mi = Some(new_mi);
         }
         if let Some(ref lv_si) = sampled.si {
@@ -1668,7 +1797,11 @@ pub fn mis_weight<'a>(
             common.wo = lv_mi.common.wo;
             common.n = lv_mi.common.n;
             common.medium_interface = medium_interface;
-            let new_mi: MediumInteraction = MediumInteraction { common, phase };
+            let new_mi: MediumInteraction = MediumInteraction {
+                common,
+                phase,
+                le: lv_mi.le,
+            };
             mi = Some(new_mi);
         }
         if let Some(ref lv_si) = sampled.si {
@@ -1763,7 +1896,11 @@ pub fn mis_weight<'a>(
             common.wo = cv_mi.common.wo;
             common.n = cv_mi.common.n;
             common.medium_interface = medium_interface;
-            let new_mi: MediumInteraction = MediumInteraction { common, phase };
+            let new_mi: MediumInteraction = MediumInteraction {
+                common,
+                phase,
+                le: cv_mi.le,
+            };
             mi = Some(new_mi);
         }
         if let Some(ref cv_si) = camera_vertices[t - 1].si {
@@ -1857,7 +1994,11 @@ pub fn mis_weight<'a>(
             common.wo = lv_mi.common.wo;
             common.n = lv_mi.common.n;
             common.medium_interface = medium_interface;
-            let new_mi: MediumInteraction = MediumInteraction { common, phase };
+            let new_mi: MediumInteraction = MediumInteraction {
+                common,
+                phase,
+                le: lv_mi.le,
+            };
             mi = Some(new_mi);
         }
         if let Some(ref lv_si) = light_vertices[s - 1].si {
@@ -1915,7 +2056,7 @@ pub fn mis_weight<'a>(
             }
         } else if t > 1 {
             overwrite.pdf_rev =
-                overwrite.pdf_light_origin(scene, &camera_vertices[t - 2], light_pdf);
+                overwrite.pdf_light_origin(scene, &camera_vertices[t - 2], light_pdf.clone());
         }
     }
     // update reverse density of vertex $\pt{}_{t-2}$
@@ -1967,7 +2108,11 @@ pub fn mis_weight<'a>(
                 common.wo = cv_mi.common.wo;
                 common.n = cv_mi.common.n;
                 common.medium_interface = medium_interface;
-                let new_mi: MediumInteraction = MediumInteraction { common, phase };
+                let new_mi: MediumInteraction = MediumInteraction {
+                    common,
+                    phase,
+                    le: cv_mi.le,
+                };
                 mi = Some(new_mi);
             }
             if let Some(ref cv_si) = camera_vertices[t - 2].si {
@@ -2070,7 +2215,11 @@ pub fn mis_weight<'a>(
                 common.wo = lv_mi.common.wo;
                 common.n = lv_mi.common.n;
                 common.medium_interface = medium_interface;
-                let new_mi: MediumInteraction = MediumInteraction { common, phase };
+                let new_mi: MediumInteraction = MediumInteraction {
+                    common,
+                    phase,
+                    le: lv_mi.le,
+                };
                 mi = Some(new_mi);
             }
             if let Some(ref lv_si) = light_vertices[s - 2].si {
@@ -2111,6 +2260,26 @@ pub fn mis_weight<'a>(
         }
     }
 
+    // the densities of the light subpath origin are the ones for
+    // starting a light subpath; scale the $s=1$ strategy by the
+    // probability of choosing the light for the connected vertex
+    let s1_ratio: Float = if s == 0 {
+        if camera_vertices[t - 1].is_light() {
+            connection_light_pdf_ratio(
+                scene,
+                &camera_vertices[t - 1],
+                &camera_vertices[t - 2],
+                &light_pdf,
+            )
+        } else {
+            1.0 as Float
+        }
+    } else if s == 1 {
+        connection_light_pdf_ratio(scene, sampled, &camera_vertices[t - 1], &light_pdf)
+    } else {
+        connection_light_pdf_ratio(scene, &light_vertices[0], &light_vertices[1], &light_pdf)
+    };
+
     // consider hypothetical connection strategies along the camera subpath
     let mut ri: Float = 1.0;
     let mut i: usize = t - 1;
@@ -2135,13 +2304,21 @@ pub fn mis_weight<'a>(
         if numerator == 0.0 {
             numerator = 1.0;
         }
+        // merging at the light source itself is impossible
+        if eta_vm > 0.0 as Float && !(s == 0 && i == t - 1) && cv1.is_mergeable() {
+            sum_ri += ri * numerator * eta_vm;
+        }
         let mut denominator: Float = cv1.pdf_fwd;
         if denominator == 0.0 {
             denominator = 1.0;
         }
         ri *= numerator / denominator;
         if !cv1.delta && !cv0.delta {
-            sum_ri += ri;
+            if s == 0 && i == t - 1 {
+                sum_ri += ri * s1_ratio;
+            } else {
+                sum_ri += ri;
+            }
         }
         i -= 1;
     }
@@ -2166,6 +2343,9 @@ pub fn mis_weight<'a>(
         if numerator == 0.0 {
             numerator = 1.0;
         }
+        if eta_vm > 0.0 as Float && i > 0 && lv1.is_mergeable() {
+            sum_ri += ri * numerator * eta_vm;
+        }
         let mut denominator: Float = lv1.pdf_fwd;
         if denominator == 0.0 {
             denominator = 1.0;
@@ -2188,24 +2368,140 @@ pub fn mis_weight<'a>(
             delta_lightvertex = lv1.is_delta_light();
         }
         if !lv1.delta && !delta_lightvertex {
-            sum_ri += ri;
+            if i == 1 {
+                sum_ri += ri * s1_ratio;
+            } else {
+                sum_ri += ri;
+            }
         }
         i -= 1;
     }
+    if s == 1 && s1_ratio > 0.0 as Float {
+        // all other strategies relative to the $s=1$ one
+        sum_ri /= s1_ratio;
+    }
     1.0 as Float / (1.0 as Float + sum_ri)
 }
 
+/// Returns the ratio of the discrete probabilities of choosing the
+/// light at the light vertex _x0_ for a connection to vertex _x1_
+/// and for starting a light subpath (see
+/// [LocalLightDistribution::connection_distribution()](../../core/lightdistrib/enum.LocalLightDistribution.html#method.connection_distribution)).
+fn connection_light_pdf_ratio(
+    scene: &Scene,
+    x0: &Vertex,
+    x1: &Vertex,
+    light_distr: &LocalLightDistribution,
+) -> Float {
+    // lights at infinity are chosen with the same probability
+    if x0.is_infinite_light() {
+        return 1.0 as Float;
+    }
+    if let Some(connection_distr) = light_distr.connection_distribution(&x1.p()) {
+        if let Some(index) = x0.light_index(scene) {
+            let pdf_origin: Float = light_distr.discrete_pdf(index);
+            if pdf_origin > 0.0 as Float {
+                return connection_distr.discrete_pdf(index) / pdf_origin;
+            }
+        }
+    }
+    1.0 as Float
+}
+
+/// Samples a point on the camera lens and connects it to the light
+/// subpath vertex _qs_ (the $t=1$ strategy) without applying a MIS
+/// weight. Returns the contribution and the sampled camera vertex;
+/// _p_raster_ receives the raster position to splat to. Vertices on
+/// area lights contribute their emitted radiance divided by the
+/// density they were sampled with.
+pub fn connect_to_camera<'a>(
+    scene: &'a Scene,
+    qs: &Vertex<'a>,
+    camera: &'a Arc<Camera>,
+    sampler: &mut Sampler,
+    p_raster: &mut Point2f,
+) -> (Spectrum, Option<Vertex<'a>>) {
+    let mut l: Spectrum = Spectrum::default();
+    if !qs.is_connectible() {
+        return (l, None);
+    }
+    let mut iref: InteractionCommon = InteractionCommon::default();
+    let mut lens_intr: InteractionCommon = InteractionCommon::default();
+    // qs.GetInteraction()
+    match qs.vertex_type {
+        VertexType::Medium => {
+            if let Some(ref mi) = qs.mi {
+                iref = mi.common.clone();
+            }
+        }
+        VertexType::Surface => {
+            if let Some(ref si) = qs.si {
+                iref = si.common.clone();
+            }
+        }
+        _ => {
+            if let Some(ref ei) = qs.ei {
+                iref = ei.common.clone();
+            }
+        }
+    }
+    let mut wi: Vector3f = Vector3f::default();
+    let mut pdf: Float = 0.0 as Float;
+    let mut vis: VisibilityTester = VisibilityTester::default();
+    let wi_color: Spectrum = camera.sample_wi(
+        &iref,
+        &mut lens_intr,
+        sampler.get_2d(),
+        &mut wi,
+        &mut pdf,
+        p_raster,
+        &mut vis,
+    );
+    if pdf == 0.0 as Float || wi_color.is_black() {
+        return (l, None);
+    }
+    // initialize dynamically sampled vertex and _L_ for $t=1$ case
+    let sampled: Vertex = Vertex::create_camera_from_interaction(
+        camera,
+        vis.p1.as_ref().unwrap().clone(),
+        &(wi_color / pdf),
+    );
+    if qs.vertex_type == VertexType::Light {
+        // only area lights can be seen directly by the camera
+        if let Some(ref ei) = qs.ei {
+            if let Some(light) = ei.light {
+                if light.get_flags() & LightFlags::Area as u8 != 0 && qs.pdf_fwd > 0.0 as Float {
+                    l = light.l(&ei.common, &wi) * sampled.beta / qs.pdf_fwd;
+                }
+            }
+        }
+    } else {
+        l = qs.beta * qs.f(&sampled, TransportMode::Importance) * sampled.beta;
+    }
+    if qs.is_on_surface() {
+        l *= Spectrum::new(vec3_abs_dot_nrmf(&wi, &qs.ns()));
+    }
+    assert!(!l.has_nans());
+    // only check visibility after we know that the path would
+    // make a non-zero contribution.
+    if !l.is_black() {
+        l *= vis.tr(scene, sampler);
+    }
+    (l, Some(sampled))
+}
+
 pub fn connect_bdpt<'a>(
     scene: &'a Scene,
     light_vertices: &[Vertex<'a>],
     camera_vertices: &[Vertex<'a>],
     s: usize,
     t: usize,
-    light_distr: Arc<Distribution1D>,
+    light_distr: LocalLightDistribution,
     camera: &'a Arc<Camera>,
     sampler: &mut Sampler,
     p_raster: &mut Point2f,
     mis_weight_opt: Option<&mut Float>,
+    eta_vm: Float,
 ) -> Spectrum {
     // TODO: ProfilePhase _(Prof::BDPTConnectSubpaths);
     let mut l: Spectrum = Spectrum::default();
@@ -2239,63 +2535,11 @@ pub fn connect_bdpt<'a>(
             (s - 1),
             light_vertices.len()
         );
-        if light_vertices[s - 1].is_connectible() {
-            let mut iref: InteractionCommon = InteractionCommon::default();
-            let mut lens_intr: InteractionCommon = InteractionCommon::default();
-            // qs.GetInteraction()
-            match light_vertices[s - 1].vertex_type {
-                VertexType::Medium => {
-                    if let Some(ref mi) = light_vertices[s - 1].mi {
-                        iref = mi.common.clone();
-                    } else {
-                    }
-                }
-                VertexType::Surface => {
-                    if let Some(ref si) = light_vertices[s - 1].si {
-                        iref = si.common.clone();
-                    } else {
-                    }
-                }
-                _ => {
-                    if let Some(ref ei) = light_vertices[s - 1].ei {
-                        iref = ei.common.clone();
-                    } else {
-                    }
-                }
-            }
-            let mut wi: Vector3f = Vector3f::default();
-            let mut pdf: Float = 0.0 as Float;
-            let mut vis: VisibilityTester = VisibilityTester::default();
-            let wi_color: Spectrum = camera.sample_wi(
-                &iref,
-                &mut lens_intr,
-                sampler.get_2d(),
-                &mut wi,
-                &mut pdf,
-                p_raster,
-                &mut vis,
-            );
-            if pdf > 0.0 as Float && !wi_color.is_black() {
-                // initialize dynamically sampled vertex and _L_ for $t=1$ case
-                sampled = Vertex::create_camera_from_interaction(
-                    camera,
-                    vis.p1.as_ref().unwrap().clone(),
-                    &(wi_color / pdf),
-                );
-                l = light_vertices[s - 1].beta
-                    * light_vertices[s - 1].f(&sampled, TransportMode::Importance)
-                    * sampled.beta;
-                // println!("l = {:?}", l);
-                if light_vertices[s - 1].is_on_surface() {
-                    l *= Spectrum::new(vec3_abs_dot_nrmf(&wi, &light_vertices[s - 1].ns()));
-                }
-                assert!(!l.has_nans());
-                // only check visibility after we know that the path
-                // would make a non-zero contribution.
-                if !l.is_black() {
-                    l *= vis.tr(scene, sampler);
-                }
-            }
+        let (l_camera, sampled_camera) =
+            connect_to_camera(scene, &light_vertices[s - 1], camera, sampler, p_raster);
+        l = l_camera;
+        if let Some(sampled_camera) = sampled_camera {
+            sampled = sampled_camera;
         }
     } else if s == 1 {
         // sample a point on a light and connect it to the camera subpath
@@ -2305,8 +2549,12 @@ pub fn connect_bdpt<'a>(
             let mut pdf: Float = 0.0 as Float;
             let mut light_pdf: Option<Float> = Some(0.0 as Float);
             let mut vis: VisibilityTester = VisibilityTester::default();
+            // the light BVH chooses the light for the vertex at hand
+            let connection_distr: LocalLightDistribution = light_distr
+                .connection_distribution(&camera_vertices[t - 1].p())
+                .unwrap_or_else(|| light_distr.clone());
             let light_num: usize =
-                light_distr.sample_discrete(sampler.get_1d(), light_pdf.as_mut());
+                connection_distr.sample_discrete(sampler.get_1d(), light_pdf.as_mut());
             //         const std::shared_ptr<Light> &light = scene.lights[light_num];
             let mut iref: InteractionCommon = InteractionCommon::default();
             // pt.GetInteraction()
@@ -2353,7 +2601,7 @@ pub fn connect_bdpt<'a>(
                 &mut pdf,
                 &mut vis,
             );
-            if pdf > 0.0 as Float && !light_weight.is_black() {
+            if pdf > 0.0 as Float && !light_weight.is_black() && light_pdf.unwrap() > 0.0 as Float {
                 let ei: EndpointInteraction = EndpointInteraction::new_interaction_from_light(
                     vis.p1.as_ref().unwrap().clone(),
                     &scene.lights[light_num],
@@ -2363,6 +2611,8 @@ pub fn connect_bdpt<'a>(
                     &(light_weight / (pdf * light_pdf.unwrap())),
                     0.0 as Float,
                 );
+                // the density of starting a light subpath here,
+                // mis_weight() accounts for _connection_distr_
                 sampled.pdf_fwd =
                     sampled.pdf_light_origin(scene, &camera_vertices[t - 1], light_distr.clone());
                 l = camera_vertices[t - 1].beta
@@ -2431,12 +2681,11 @@ pub fn connect_bdpt<'a>(
     let mis_weight_flt = if !l.is_black() {
         mis_weight(
             scene,
-            light_vertices,
-            camera_vertices,
+            &light_vertices[..s],
+            &camera_vertices[..t],
             &sampled,
-            s,
-            t,
             light_distr,
+            eta_vm,
         )
     } else {
         0.0 as Float
@@ -2457,22 +2706,16 @@ pub fn connect_bdpt<'a>(
 
 pub fn infinite_light_density<'a>(
     scene: &'a Scene,
-    light_distr: Arc<Distribution1D>,
+    light_distr: LocalLightDistribution,
     // const std::unordered_map<const Light *, size_t> &lightToDistrIndex,
     w: &Vector3f,
 ) -> Float {
     let mut pdf: Float = 0.0 as Float;
-    for light in &scene.infinite_lights {
-        // for i in 0..scene.infinite_lights.len() {
-        //     CHECK(lightToDistrIndex.find(light.get()) != lightToDistrIndex.end());
-        //     size_t index = lightToDistrIndex.find(light.get())->second;
-        let index: usize = 0; // TODO: calculate index (see above)
-        pdf += light.pdf_li(&SurfaceInteraction::default(), &-(*w)) * light_distr.func[index];
-    }
-    // TODO: Old loop (without cache) !!!
-    // for (size_t i = 0; i < scene.lights.size(); ++i)
-    //     if (scene.lights[i]->flags & (int)LightFlags::Infinite)
-    //         pdf +=
-    //             scene.lights[i]->Pdf_Li(Interaction(), -w) * light_distr.func[i];
-    pdf / (light_distr.func_int * light_distr.count() as Float)
+    for (index, light) in scene.lights.iter().enumerate() {
+        if (light.get_flags() & LightFlags::Infinite as u8) != 0_u8 {
+            pdf += light.pdf_li(&SurfaceInteraction::default(), &-(*w))
+                * light_distr.discrete_pdf(index);
+        }
+    }
+    pdf
 }
@@ -2,6 +2,7 @@
 use std::sync::Arc;
 // pbrt
 use crate::core::camera::Camera;
+use crate::core::denoiser::FirstHitFeatures;
 use crate::core::geometry::{vec3_abs_dot_nrmf, vec3_dot_nrmf};
 use crate::core::geometry::{Bounds2i, Normal3f, Ray, RayDifferential, Vector3f};
 use crate::core::integrator::{uniform_sample_all_lights, uniform_sample_one_light};
@@ -76,16 +77,39 @@ impl DirectLightingIntegrator {
         // arena: &mut Arena,
         depth: i32,
     ) -> Spectrum {
-        // TODO: ProfilePhase p(Prof::SamplerIntegratorLi);
-        let mut l: Spectrum = Spectrum::new(0.0 as Float);
         // find closest ray intersection or return background radiance
         let mut isect: SurfaceInteraction = SurfaceInteraction::default();
-        if scene.intersect(ray, &mut isect) {
+        let hit: bool = scene.intersect(ray, &mut isect);
+        self.li_with_isect(ray, hit, isect, scene, sampler, depth, None)
+    }
+    /// Same as [li()](#method.li), but the closest intersection of
+    /// the camera ray is already known (e.g. from tracing a ray
+    /// packet). If _features_ is given, it receives the albedo and
+    /// shading normal of the first surface hit (for the denoiser).
+    pub fn li_with_isect(
+        &self,
+        ray: &Ray,
+        hit: bool,
+        mut isect: SurfaceInteraction,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        depth: i32,
+        features: Option<&mut FirstHitFeatures>,
+    ) -> Spectrum {
+        // TODO: ProfilePhase p(Prof::SamplerIntegratorLi);
+        let mut l: Spectrum = Spectrum::new(0.0 as Float);
+        if hit {
             // compute scattering functions for surface interaction
             let mode: TransportMode = TransportMode::Radiance;
             isect.compute_scattering_functions(ray, false, mode);
             if isect.bsdf.is_none() {
-                return self.li(&mut isect.spawn_ray(&ray.d), scene, sampler, depth);
+                let ray: Ray = isect.spawn_ray(&ray.d);
+                let mut next: SurfaceInteraction = SurfaceInteraction::default();
+                let hit: bool = scene.intersect(&ray, &mut next);
+                return self.li_with_isect(&ray, hit, next, scene, sampler, depth, features);
+            }
+            if let Some(features) = features {
+                *features = FirstHitFeatures::new(&isect);
             }
             let wo: Vector3f = isect.common.wo;
             l += isect.le(&wo);
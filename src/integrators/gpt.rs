@@ -0,0 +1,1140 @@
+//! Gradient-Domain Path Tracing (Kettunen et al. 2015)
+//!
+//! Every base path of a pixel is accompanied by four offset paths
+//! for the horizontally and vertically neighboring pixels, and the
+//! differences of their contributions estimate the image gradients.
+//! The final image is reconstructed from the primal (pixel) and
+//! gradient buffers by solving a screened Poisson equation.
+//!
+//! An offset path starts with the camera sample moved by one pixel
+//! and reuses the random numbers of the base path, until both paths
+//! reach a vertex without specular components and the next vertex of
+//! the base path has none either. There the offset path is
+//! reconnected to the base path (the reconnection shift) and shares
+//! all further vertices (and light samples) with it. The densities of
+//! the base path and the shifted path (including the Jacobian of the
+//! shift) weight their contributions with the balance heuristic, so
+//! that each pixel and each gradient is estimated from the base paths
+//! of both pixels involved. If the shifted path cannot be sampled
+//! (e.g. because the reconnection is occluded), the shift fails and
+//! the base path takes all the weight.
+//!
+//! Unlike the paper, glossy vertices are treated like diffuse ones
+//! and specular chains are shifted by reusing random numbers instead
+//! of copying half-vectors. Participating media and subsurface
+//! scattering are ignored. The primal and gradient buffers are written
+//! next to the output image (with the suffixes `_gpt_primal`,
+//! `_gpt_dx` and `_gpt_dy`).
+
+// std
+use std::sync::Arc;
+// pbrt
+use crate::core::camera::{Camera, CameraSample};
+use crate::core::geometry::{pnt2_inside_exclusivei, vec3_abs_dot_nrmf, vec3_dot_nrmf};
+use crate::core::geometry::{Bounds2i, Normal3f, Point2f, Point2i, Ray, Vector2i, Vector3f};
+use crate::core::imageio::write_image;
+use crate::core::integrator::{generate_camera_ray, render_tiles};
+use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
+use crate::core::light::{is_delta_light, Light, VisibilityTester};
+use crate::core::lightdistrib::create_light_sample_distribution;
+use crate::core::lightdistrib::{LightDistribution, LocalLightDistribution};
+use crate::core::material::TransportMode;
+use crate::core::pbrt::{Float, Spectrum};
+use crate::core::reflection::{Bsdf, BxdfType};
+use crate::core::sampler::Sampler;
+use crate::core::sampling::power_heuristic;
+use crate::core::scene::Scene;
+
+// see Kettunen et al., "Gradient-Domain Path Tracing", SIGGRAPH 2015
+
+/// Pixel offsets of the shifted paths: right, left, down, up.
+const OFFSETS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+
+/// Norm of the residuals minimized by the screened Poisson
+/// reconstruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reconstruction {
+    /// unbiased, but fails to remove some of the noise
+    L2,
+    /// iteratively reweighted least squares, slightly biased but
+    /// robust to outliers in the primal and gradient buffers
+    L1,
+}
+
+/// Sums of the samples traced for one (base) pixel. The
+/// contributions shared with the offset paths are weighted by the
+/// balance heuristic, separately for each offset.
+#[derive(Debug, Default, Clone, Copy)]
+struct GradientPixel {
+    base: Spectrum,
+    weighted_base: [Spectrum; 4],
+    weighted_offsets: [Spectrum; 4],
+    n_samples: u32,
+}
+
+impl GradientPixel {
+    /// Adds the contribution of a base path and of its *k*th offset
+    /// path, whose density (times the Jacobian of the shift) is
+    /// *ratio* times the density of the base path.
+    fn add_shifted(&mut self, k: usize, base: Spectrum, offset: Spectrum, ratio: Float) {
+        let weight: Float = 1.0 as Float / (1.0 as Float + ratio);
+        self.weighted_base[k] += base * weight;
+        self.weighted_offsets[k] += offset * weight;
+    }
+    fn add(&mut self, other: &GradientPixel) {
+        self.base += other.base;
+        for k in 0..4 {
+            self.weighted_base[k] += other.weighted_base[k];
+            self.weighted_offsets[k] += other.weighted_offsets[k];
+        }
+    }
+    /// Average of *sum* over the samples of the pixel.
+    fn mean(&self, sum: &Spectrum) -> Spectrum {
+        if self.n_samples > 0 {
+            *sum / self.n_samples as Float
+        } else {
+            Spectrum::default()
+        }
+    }
+    /// Estimate of the difference to the neighbor at offset *k*.
+    fn difference(&self, k: usize) -> Spectrum {
+        self.mean(&(self.weighted_offsets[k] - self.weighted_base[k]))
+    }
+    fn is_valid(&self) -> bool {
+        std::iter::once(&self.base)
+            .chain(self.weighted_base.iter())
+            .chain(self.weighted_offsets.iter())
+            .all(|s| !s.has_nans() && !s.y().is_infinite())
+    }
+}
+
+/// How an offset path relates to its base path at the current vertex.
+enum Shift<'a> {
+    /// The offset path reuses the random numbers of the base path at
+    /// its own vertex (if _ray_ hit anything).
+    Replay {
+        isect: Option<SurfaceInteraction<'a>>,
+        ray: Ray,
+        specular_bounce: bool,
+    },
+    /// A replaying offset path sampled direction _wi_, which it only
+    /// follows if it can't be reconnected to the next vertex of the
+    /// base path.
+    Sampled {
+        isect: SurfaceInteraction<'a>,
+        wi: Vector3f,
+        weight: Spectrum,
+        specular: bool,
+        eta_scale: Float,
+    },
+    /// The offset path shares the vertex with the base path, but at
+    /// the reconnection vertex itself it leaves in direction _wo_.
+    Reconnected { wo: Option<Vector3f> },
+    /// The shifted path can't be sampled.
+    Failed,
+}
+
+/// An offset path, traced in lockstep with its base path.
+struct OffsetPath<'a> {
+    shift: Shift<'a>,
+    /// contribution so far, times the Jacobian of the shift, divided
+    /// by the density of the base path
+    beta: Spectrum,
+    /// density so far, times the Jacobian of the shift, divided by the
+    /// density of the base path
+    ratio: Float,
+    eta_scale: Float,
+}
+
+impl<'a> OffsetPath<'a> {
+    fn new(scene: &Scene, mut ray: Ray, ray_weight: Float) -> Self {
+        if ray_weight <= 0.0 as Float {
+            return OffsetPath::failed();
+        }
+        OffsetPath {
+            shift: Shift::Replay {
+                isect: intersect_surface(scene, &mut ray),
+                ray,
+                specular_bounce: false,
+            },
+            beta: Spectrum::new(ray_weight),
+            ratio: 1.0 as Float,
+            eta_scale: 1.0 as Float,
+        }
+    }
+    fn failed() -> Self {
+        OffsetPath {
+            shift: Shift::Failed,
+            beta: Spectrum::default(),
+            ratio: 0.0 as Float,
+            eta_scale: 1.0 as Float,
+        }
+    }
+    fn fail(&mut self) {
+        *self = OffsetPath::failed();
+    }
+    /// Emitted radiance at the current vertex of the offset path (or
+    /// from the environment), if it is accounted for by hitting the
+    /// emitter. The base path is at vertex *isect*, reached by *ray*.
+    fn emitted(
+        &self,
+        scene: &Scene,
+        isect: Option<&SurfaceInteraction>,
+        ray: &Ray,
+        base_emits: bool,
+        bounces: u32,
+    ) -> Spectrum {
+        match self.shift {
+            Shift::Replay {
+                ref isect,
+                ref ray,
+                specular_bounce,
+            } if bounces == 0 || specular_bounce => self.beta * emitted(scene, isect.as_ref(), ray),
+            Shift::Reconnected { wo: None } if base_emits => self.beta * emitted(scene, isect, ray),
+            _ => Spectrum::default(),
+        }
+    }
+    /// Samples a direction at the current vertex of the offset path,
+    /// given the direction *wi* sampled by the base path at *x* with
+    /// *pdf* and the resulting *weight* (f * cos / pdf).
+    fn sample_direction(
+        &mut self,
+        x: &SurfaceInteraction,
+        wi: &Vector3f,
+        pdf: Float,
+        weight: &Spectrum,
+        eta_scale: Float,
+        u: &Point2f,
+    ) {
+        match std::mem::replace(&mut self.shift, Shift::Failed) {
+            Shift::Replay {
+                isect: Some(isect), ..
+            } => {
+                if let Some(ref bsdf) = isect.bsdf {
+                    let mut wi_y: Vector3f = Vector3f::default();
+                    let mut pdf_y: Float = 0.0 as Float;
+                    let mut sampled_type: u8 = u8::MAX; // != 0
+                    let f: Spectrum = bsdf.sample_f(
+                        &isect.common.wo,
+                        &mut wi_y,
+                        u,
+                        &mut pdf_y,
+                        BxdfType::BsdfAll as u8,
+                        &mut sampled_type,
+                    );
+                    if !f.is_black() && pdf_y > 0.0 as Float {
+                        self.shift = Shift::Sampled {
+                            weight: f * vec3_abs_dot_nrmf(&wi_y, &isect.shading.n) / pdf_y,
+                            specular: (sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8,
+                            eta_scale: refraction_scale(
+                                bsdf,
+                                &isect.common.wo,
+                                &isect.common.n,
+                                sampled_type,
+                            ),
+                            isect,
+                            wi: wi_y,
+                        };
+                        return;
+                    }
+                }
+                self.fail();
+            }
+            Shift::Reconnected { wo: Some(wo) } => {
+                // same direction, but from a different outgoing one
+                if let Some(ref bsdf) = x.bsdf {
+                    let f: Spectrum = bsdf.f(&wo, wi, BxdfType::BsdfAll as u8)
+                        * vec3_abs_dot_nrmf(wi, &x.shading.n);
+                    let pdf_y: Float = bsdf.pdf(&wo, wi, BxdfType::BsdfAll as u8);
+                    if !f.is_black() && pdf_y > 0.0 as Float {
+                        self.beta = self.beta * f / pdf;
+                        self.ratio *= pdf_y / pdf;
+                        self.eta_scale *= eta_scale;
+                        self.shift = Shift::Reconnected { wo: None };
+                        return;
+                    }
+                }
+                self.fail();
+            }
+            Shift::Reconnected { wo: None } => {
+                self.beta *= *weight;
+                self.eta_scale *= eta_scale;
+                self.shift = Shift::Reconnected { wo: None };
+            }
+            _ => self.fail(),
+        }
+    }
+    /// Moves a replaying offset path to its next vertex, once the base
+    /// path went from *x* (with density *pdf* of the direction) to
+    /// *next* along *ray*. If the vertices at both ends are free of
+    /// specular components, the offset path is reconnected instead.
+    fn follow(
+        &mut self,
+        scene: &Scene,
+        x: &SurfaceInteraction,
+        pdf: Float,
+        next: Option<&SurfaceInteraction>,
+        ray: &Ray,
+    ) {
+        let (isect, wi, weight, specular, eta_scale) =
+            match std::mem::replace(&mut self.shift, Shift::Failed) {
+                Shift::Sampled {
+                    isect,
+                    wi,
+                    weight,
+                    specular,
+                    eta_scale,
+                } => (isect, wi, weight, specular, eta_scale),
+                shift => {
+                    self.shift = shift;
+                    return;
+                }
+            };
+        let connectable: bool = is_rough(x) && is_rough(&isect);
+        if connectable {
+            if let Some(next) = next.filter(|next| is_rough(next)) {
+                self.reconnect(scene, &isect, x, pdf, next, ray);
+                return;
+            }
+        }
+        self.beta *= weight;
+        self.eta_scale *= eta_scale;
+        let mut ray_y: Ray = isect.spawn_ray(&wi);
+        let next_y: Option<SurfaceInteraction> = intersect_surface(scene, &mut ray_y);
+        if connectable && next_y.as_ref().is_some_and(|next_y| is_rough(next_y)) {
+            // the inverse shift would reconnect
+            self.fail();
+            return;
+        }
+        self.shift = Shift::Replay {
+            isect: next_y,
+            ray: ray_y,
+            specular_bounce: specular,
+        };
+    }
+    /// Connects vertex *y* of the offset path to vertex *next* of the
+    /// base path, which was sampled from *x* with (solid angle)
+    /// density *pdf*. Both paths share *next*, so the Jacobian of the
+    /// shift is one in area measure.
+    fn reconnect(
+        &mut self,
+        scene: &Scene,
+        y: &SurfaceInteraction,
+        x: &SurfaceInteraction,
+        pdf: Float,
+        next: &SurfaceInteraction,
+        ray: &Ray,
+    ) {
+        let d: Vector3f = next.common.p - y.common.p;
+        let dist2_y: Float = d.length_squared();
+        let dist2_x: Float = (next.common.p - x.common.p).length_squared();
+        if let Some(ref bsdf) = y.bsdf {
+            if dist2_y > 0.0 as Float && dist2_x > 0.0 as Float {
+                let wi: Vector3f = d / dist2_y.sqrt();
+                let f: Spectrum = bsdf.f(&y.common.wo, &wi, BxdfType::BsdfAll as u8)
+                    * vec3_abs_dot_nrmf(&wi, &y.shading.n);
+                let pdf_y: Float = bsdf.pdf(&y.common.wo, &wi, BxdfType::BsdfAll as u8);
+                // densities of the shared vertex per unit area
+                let g_y: Float = vec3_abs_dot_nrmf(&wi, &next.common.n) / dist2_y;
+                let area_pdf_x: Float = pdf * vec3_abs_dot_nrmf(&ray.d, &next.common.n) / dist2_x;
+                let mut shadow_ray: Ray = y.common.spawn_ray_to(&next.common);
+                if !f.is_black()
+                    && pdf_y > 0.0 as Float
+                    && area_pdf_x > 0.0 as Float
+                    && !scene.intersect_p(&mut shadow_ray)
+                {
+                    self.beta = self.beta * f * g_y / area_pdf_x;
+                    self.ratio *= pdf_y * g_y / area_pdf_x;
+                    self.shift = Shift::Reconnected { wo: Some(-wi) };
+                    return;
+                }
+            }
+        }
+        self.fail();
+    }
+    /// Accounts for the Russian roulette of the base path, which
+    /// survived with probability 1 - *q*. The offset path would have
+    /// survived with a probability of its own.
+    fn roulette(&mut self, q: Float, rr_threshold: Float) {
+        if self.ratio > 0.0 as Float {
+            let rr_beta: Spectrum = self.beta / self.ratio * self.eta_scale;
+            let q_y: Float = termination_probability(&rr_beta, rr_threshold);
+            self.beta /= 1.0 as Float - q;
+            self.ratio *= (1.0 as Float - q_y) / (1.0 as Float - q);
+        }
+    }
+}
+
+/// Next event estimation at a vertex: one light sample and one BSDF
+/// sample, with everything which does not depend on the outgoing
+/// direction. Evaluated for the base path and for an offset path
+/// reconnected to the vertex (see
+/// [uniform_sample_one_light()](../../core/integrator/fn.uniform_sample_one_light.html)).
+struct DirectSample {
+    choice_pdf: Float,
+    delta: bool,
+    light_wi: Vector3f,
+    /// radiance of the light sample (black if occluded)
+    light_li: Spectrum,
+    light_pdf: Float,
+    bsdf_wi: Vector3f,
+    /// radiance of the chosen light along the BSDF sample
+    bsdf_li: Spectrum,
+    bsdf_pdf: Float,
+    bsdf_light_pdf: Float,
+}
+
+impl DirectSample {
+    fn new(
+        it: &SurfaceInteraction,
+        scene: &Scene,
+        light_distribution: &LightDistribution,
+        u_choice: Float,
+        u_light: Point2f,
+        u_scattering: Point2f,
+    ) -> Option<Self> {
+        let bsdf: &Bsdf = it.bsdf.as_ref()?;
+        if scene.lights.is_empty() || bsdf.num_components(non_specular()) == 0 {
+            return None;
+        }
+        let distrib: LocalLightDistribution = light_distribution.lookup(&it.common.p);
+        let mut choice_pdf: Float = 0.0 as Float;
+        let light_num: usize = distrib.sample_discrete(u_choice, Some(&mut choice_pdf));
+        if choice_pdf == 0.0 as Float {
+            return None;
+        }
+        let light: &Arc<Light> = &scene.lights[light_num];
+        // sample light source
+        let mut light_intr: InteractionCommon = InteractionCommon::default();
+        let mut visibility: VisibilityTester = VisibilityTester::default();
+        let mut light_wi: Vector3f = Vector3f::default();
+        let mut light_pdf: Float = 0.0 as Float;
+        let mut light_li: Spectrum = light.sample_li(
+            &it.common,
+            &mut light_intr,
+            u_light,
+            &mut light_wi,
+            &mut light_pdf,
+            &mut visibility,
+        );
+        if light_pdf > 0.0 as Float && !light_li.is_black() && !visibility.unoccluded(scene) {
+            light_li = Spectrum::default();
+        }
+        // sample BSDF
+        let delta: bool = is_delta_light(light.get_flags());
+        let mut bsdf_wi: Vector3f = Vector3f::default();
+        let mut bsdf_li: Spectrum = Spectrum::default();
+        let mut bsdf_pdf: Float = 0.0 as Float;
+        let mut bsdf_light_pdf: Float = 0.0 as Float;
+        if !delta {
+            let mut sampled_type: u8 = 0_u8;
+            bsdf.sample_f(
+                &it.common.wo,
+                &mut bsdf_wi,
+                &u_scattering,
+                &mut bsdf_pdf,
+                non_specular(),
+                &mut sampled_type,
+            );
+            if bsdf_pdf > 0.0 as Float {
+                bsdf_light_pdf = light.pdf_li(it, &bsdf_wi);
+                if bsdf_light_pdf > 0.0 as Float {
+                    bsdf_li = light_radiance(scene, it, light, &bsdf_wi);
+                }
+            }
+        }
+        Some(DirectSample {
+            choice_pdf,
+            delta,
+            light_wi,
+            light_li,
+            light_pdf,
+            bsdf_wi,
+            bsdf_li,
+            bsdf_pdf,
+            bsdf_light_pdf,
+        })
+    }
+    /// Contributions of the light and the BSDF sample for outgoing
+    /// direction *wo*, and the density of the BSDF sample for *wo*
+    /// relative to the one it was sampled with.
+    fn contributions(
+        &self,
+        bsdf: &Bsdf,
+        n: &Normal3f,
+        wo: &Vector3f,
+    ) -> (Spectrum, Spectrum, Float) {
+        let mut light_ld: Spectrum = Spectrum::default();
+        if self.light_pdf > 0.0 as Float && !self.light_li.is_black() {
+            let f: Spectrum =
+                bsdf.f(wo, &self.light_wi, non_specular()) * vec3_abs_dot_nrmf(&self.light_wi, n);
+            light_ld = if self.delta {
+                f * self.light_li / self.light_pdf
+            } else {
+                let scattering_pdf: Float = bsdf.pdf(wo, &self.light_wi, non_specular());
+                let weight: Float = power_heuristic(1, self.light_pdf, 1, scattering_pdf);
+                f * self.light_li * weight / self.light_pdf
+            };
+        }
+        let mut bsdf_ld: Spectrum = Spectrum::default();
+        let mut pdf_ratio: Float = 0.0 as Float;
+        if self.bsdf_pdf > 0.0 as Float && !self.bsdf_li.is_black() {
+            let scattering_pdf: Float = bsdf.pdf(wo, &self.bsdf_wi, non_specular());
+            let f: Spectrum =
+                bsdf.f(wo, &self.bsdf_wi, non_specular()) * vec3_abs_dot_nrmf(&self.bsdf_wi, n);
+            let weight: Float = power_heuristic(1, scattering_pdf, 1, self.bsdf_light_pdf);
+            bsdf_ld = f * self.bsdf_li * weight / self.bsdf_pdf;
+            pdf_ratio = scattering_pdf / self.bsdf_pdf;
+        }
+        (
+            light_ld / self.choice_pdf,
+            bsdf_ld / self.choice_pdf,
+            pdf_ratio,
+        )
+    }
+}
+
+/// Gradient-Domain Path Tracing with the same parameters as the
+/// [PathIntegrator](../path/struct.PathIntegrator.html)
+pub struct GPTIntegrator {
+    pub camera: Arc<Camera>,
+    pub sampler: Box<Sampler>,
+    pub pixel_bounds: Bounds2i,
+    max_depth: u32,
+    rr_threshold: Float,           // 1.0
+    light_sample_strategy: String, // "spatial"
+    light_distribution: Option<Arc<LightDistribution>>,
+    pub reconstruction: Reconstruction,
+    /// weight of the primal image relative to the gradients
+    pub alpha: Float,
+}
+
+impl GPTIntegrator {
+    pub fn new(
+        camera: Arc<Camera>,
+        sampler: Box<Sampler>,
+        pixel_bounds: Bounds2i,
+        max_depth: u32,
+        rr_threshold: Float,
+        light_sample_strategy: String,
+        reconstruction: Reconstruction,
+        alpha: Float,
+    ) -> Self {
+        GPTIntegrator {
+            camera,
+            sampler,
+            pixel_bounds,
+            max_depth,
+            rr_threshold,
+            light_sample_strategy,
+            light_distribution: None,
+            reconstruction,
+            alpha,
+        }
+    }
+    pub fn preprocess(&mut self, scene: &Scene) {
+        self.light_distribution =
+            create_light_sample_distribution(self.light_sample_strategy.clone(), scene);
+    }
+    pub fn render(&mut self, scene: &Scene, num_threads: u8) {
+        let film = self.get_camera().get_film();
+        self.preprocess(scene);
+        let pixel_bounds: Bounds2i = self.pixel_bounds;
+        let extent: Vector2i = pixel_bounds.diagonal();
+        let num_cores = if num_threads == 0_u8 {
+            num_cpus::get()
+        } else {
+            num_threads as usize
+        };
+        println!("Rendering with {:?} thread(s) ...", num_cores);
+        let integrator: &GPTIntegrator = self;
+        let camera = &self.get_camera();
+        let mut pixels: Vec<GradientPixel> =
+            vec![GradientPixel::default(); pixel_bounds.area() as usize];
+        render_tiles(
+            &pixel_bounds,
+            self.get_sampler(),
+            num_cores,
+            0_u32,
+            |tile_bounds: &Bounds2i, tile_sampler: &mut Sampler| {
+                let tile_pixels: Vec<GradientPixel> = tile_bounds
+                    .into_iter()
+                    .map(|pixel| {
+                        tile_sampler.start_pixel(pixel);
+                        integrator.trace_pixel(pixel, camera, scene, tile_sampler)
+                    })
+                    .collect();
+                (*tile_bounds, tile_pixels)
+            },
+            |(tile_bounds, tile_pixels): (Bounds2i, Vec<GradientPixel>)| {
+                for (pixel, gradient_pixel) in (&tile_bounds).into_iter().zip(tile_pixels) {
+                    pixels[pixel_index(&pixel_bounds, pixel)] = gradient_pixel;
+                }
+            },
+        );
+        // assemble and write the primal and gradient buffers
+        let (primal, dx, dy) = assemble_buffers(&pixels, &pixel_bounds);
+        let resolution: Point2i = Point2i {
+            x: extent.x,
+            y: extent.y,
+        };
+        for (suffix, buffer) in
+            [("_gpt_primal", &primal), ("_gpt_dx", &dx), ("_gpt_dy", &dy)].iter()
+        {
+            let name: String = film.auxiliary_filename(suffix, "exr");
+            println!("Writing image {:?} with bounds {:?}", name, pixel_bounds);
+            let rgb: Vec<Float> = buffer.iter().flat_map(|s| s.c.iter().copied()).collect();
+            write_image(&name, &rgb, resolution, film.color_space);
+        }
+        // reconstruct the final image
+        println!(
+            "Solving screened Poisson equation ({:?}, alpha = {}) ...",
+            self.reconstruction, self.alpha
+        );
+        let image: Vec<Spectrum> =
+            reconstruct(&primal, &dx, &dy, &extent, self.alpha, self.reconstruction);
+        for (pixel, value) in (&pixel_bounds).into_iter().zip(image) {
+            let p_film: Point2f = Point2f {
+                x: pixel.x as Float + 0.5 as Float,
+                y: pixel.y as Float + 0.5 as Float,
+            };
+            film.add_splat(p_film, &value.clamp(0.0 as Float, Float::INFINITY));
+        }
+        film.write_image(1.0 as Float);
+    }
+    /// Traces all base paths of *pixel* and the offset paths of each
+    /// of them.
+    fn trace_pixel(
+        &self,
+        pixel: Point2i,
+        camera: &Camera,
+        scene: &Scene,
+        sampler: &mut Sampler,
+    ) -> GradientPixel {
+        let mut gradient_pixel: GradientPixel = GradientPixel::default();
+        let mut done: bool = false;
+        while !done {
+            let mut sample: GradientPixel = GradientPixel::default();
+            self.trace_sample(pixel, camera, scene, sampler, &mut sample);
+            // the same path counts as black for all neighbors
+            if sample.is_valid() {
+                gradient_pixel.add(&sample);
+            }
+            gradient_pixel.n_samples += 1;
+            done = !sampler.start_next_sample();
+        }
+        gradient_pixel
+    }
+    /// Traces one base path of *pixel* and its offset paths in
+    /// lockstep, and adds their contributions to *sample*.
+    fn trace_sample(
+        &self,
+        pixel: Point2i,
+        camera: &Camera,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        sample: &mut GradientPixel,
+    ) {
+        let samples_per_pixel: i64 = sampler.get_samples_per_pixel();
+        let camera_sample: CameraSample = sampler.get_camera_sample(pixel);
+        let mut ray: Ray = Ray::default();
+        let ray_weight: Float =
+            generate_camera_ray(camera, &camera_sample, &mut ray, samples_per_pixel);
+        if ray_weight <= 0.0 as Float {
+            return;
+        }
+        // the offset paths move the camera sample to the neighbors
+        let mut offsets: Vec<Option<OffsetPath>> = OFFSETS
+            .iter()
+            .map(|offset| {
+                let neighbor: Point2i = Point2i {
+                    x: pixel.x + offset.0,
+                    y: pixel.y + offset.1,
+                };
+                if !pnt2_inside_exclusivei(neighbor, &self.pixel_bounds) {
+                    return None;
+                }
+                let mut shifted_sample: CameraSample = camera_sample;
+                shifted_sample.p_film.x += offset.0 as Float;
+                shifted_sample.p_film.y += offset.1 as Float;
+                let mut shifted_ray: Ray = Ray::default();
+                let shifted_weight: Float = generate_camera_ray(
+                    camera,
+                    &shifted_sample,
+                    &mut shifted_ray,
+                    samples_per_pixel,
+                );
+                Some(OffsetPath::new(scene, shifted_ray, shifted_weight))
+            })
+            .collect();
+        let mut beta: Spectrum = Spectrum::new(ray_weight);
+        let mut isect: Option<SurfaceInteraction> = intersect_surface(scene, &mut ray);
+        let mut specular_bounce: bool = false;
+        let mut bounces: u32 = 0_u32;
+        let mut eta_scale: Float = 1.0;
+        loop {
+            // possibly add emitted light at path vertex (or from the
+            // environment)
+            let base_emits: bool = bounces == 0 || specular_bounce;
+            let le: Spectrum = if base_emits {
+                beta * emitted(scene, isect.as_ref(), &ray)
+            } else {
+                Spectrum::default()
+            };
+            add_contribution(sample, &offsets, le, |offset| {
+                (
+                    offset.emitted(scene, isect.as_ref(), &ray, base_emits, bounces),
+                    offset.ratio,
+                )
+            });
+            // terminate path if ray escaped or _maxDepth_ was reached
+            let x: SurfaceInteraction = match isect.take() {
+                Some(x) => x,
+                None => break,
+            };
+            if bounces >= self.max_depth {
+                break;
+            }
+            let bsdf: &Bsdf = match x.bsdf {
+                Some(ref bsdf) => bsdf,
+                None => break,
+            };
+            // the random numbers of this bounce are shared by all paths
+            let u_choice: Float = sampler.get_1d();
+            let u_light: Point2f = sampler.get_2d();
+            let u_scattering: Point2f = sampler.get_2d();
+            let u_bsdf: Point2f = sampler.get_2d();
+            let u_rr: Float = sampler.get_1d();
+            // sample illumination from lights
+            if let Some(ref light_distribution) = self.light_distribution {
+                let direct: Option<DirectSample> = DirectSample::new(
+                    &x,
+                    scene,
+                    light_distribution,
+                    u_choice,
+                    u_light,
+                    u_scattering,
+                );
+                let (light_ld, bsdf_ld, _) = match direct {
+                    Some(ref direct) => direct.contributions(bsdf, &x.shading.n, &x.common.wo),
+                    None => (Spectrum::default(), Spectrum::default(), 0.0 as Float),
+                };
+                // light sampling (and all of replaying offset paths)
+                add_contribution(sample, &offsets, beta * light_ld, |offset| {
+                    let ld: Spectrum = match offset.shift {
+                        Shift::Replay {
+                            isect: Some(ref y), ..
+                        } => match (
+                            &y.bsdf,
+                            DirectSample::new(
+                                y,
+                                scene,
+                                light_distribution,
+                                u_choice,
+                                u_light,
+                                u_scattering,
+                            ),
+                        ) {
+                            (Some(bsdf_y), Some(direct_y)) => {
+                                let (light_ld, bsdf_ld, _) =
+                                    direct_y.contributions(bsdf_y, &y.shading.n, &y.common.wo);
+                                light_ld + bsdf_ld
+                            }
+                            _ => Spectrum::default(),
+                        },
+                        Shift::Reconnected { wo: Some(wo) } => match direct {
+                            Some(ref direct) => direct.contributions(bsdf, &x.shading.n, &wo).0,
+                            None => Spectrum::default(),
+                        },
+                        Shift::Reconnected { wo: None } => light_ld,
+                        _ => Spectrum::default(),
+                    };
+                    (offset.beta * ld, offset.ratio)
+                });
+                // BSDF sampling of reconnected offset paths
+                add_contribution(sample, &offsets, beta * bsdf_ld, |offset| {
+                    match offset.shift {
+                        Shift::Reconnected { wo: Some(wo) } => match direct {
+                            Some(ref direct) => {
+                                let (_, ld, pdf_ratio) =
+                                    direct.contributions(bsdf, &x.shading.n, &wo);
+                                (offset.beta * ld, offset.ratio * pdf_ratio)
+                            }
+                            None => (Spectrum::default(), offset.ratio),
+                        },
+                        Shift::Reconnected { wo: None } => (offset.beta * bsdf_ld, offset.ratio),
+                        _ => (Spectrum::default(), offset.ratio),
+                    }
+                });
+            }
+            // sample BSDF to get new path direction
+            let wo: Vector3f = x.common.wo;
+            let mut wi: Vector3f = Vector3f::default();
+            let mut pdf: Float = 0.0 as Float;
+            let mut sampled_type: u8 = u8::MAX; // != 0
+            let f: Spectrum = bsdf.sample_f(
+                &wo,
+                &mut wi,
+                &u_bsdf,
+                &mut pdf,
+                BxdfType::BsdfAll as u8,
+                &mut sampled_type,
+            );
+            if f.is_black() || pdf == 0.0 as Float {
+                break;
+            }
+            let weight: Spectrum = f * vec3_abs_dot_nrmf(&wi, &x.shading.n) / pdf;
+            beta *= weight;
+            specular_bounce = (sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8;
+            let refraction: Float = refraction_scale(bsdf, &wo, &x.common.n, sampled_type);
+            eta_scale *= refraction;
+            for offset in offsets.iter_mut().flatten() {
+                offset.sample_direction(&x, &wi, pdf, &weight, refraction, &u_bsdf);
+            }
+            // find the next vertex of all paths
+            ray = x.spawn_ray(&wi);
+            isect = intersect_surface(scene, &mut ray);
+            for offset in offsets.iter_mut().flatten() {
+                offset.follow(scene, &x, pdf, isect.as_ref(), &ray);
+            }
+            // possibly terminate the path with Russian roulette,
+            // factor out radiance scaling due to refraction
+            if bounces > 3 {
+                let q: Float = termination_probability(&(beta * eta_scale), self.rr_threshold);
+                if u_rr < q {
+                    break;
+                }
+                beta /= 1.0 as Float - q;
+                for offset in offsets.iter_mut().flatten() {
+                    offset.roulette(q, self.rr_threshold);
+                }
+            }
+            bounces += 1_u32;
+        }
+    }
+    pub fn get_camera(&self) -> Arc<Camera> {
+        self.camera.clone()
+    }
+    pub fn get_sampler(&self) -> &Sampler {
+        &self.sampler
+    }
+}
+
+/// Adds the contribution *base* of the base path to *sample*, with
+/// the ones of the offset paths (and their density ratios) returned
+/// by *shifted*.
+fn add_contribution<F>(
+    sample: &mut GradientPixel,
+    offsets: &[Option<OffsetPath>],
+    base: Spectrum,
+    shifted: F,
+) where
+    F: Fn(&OffsetPath) -> (Spectrum, Float),
+{
+    sample.base += base;
+    for (k, offset) in offsets.iter().enumerate() {
+        if let Some(offset) = offset {
+            let (contribution, ratio) = shifted(offset);
+            sample.add_shifted(k, base, contribution, ratio);
+        }
+    }
+}
+
+/// Finds the next surface along *ray* with scattering functions,
+/// skipping over medium boundaries.
+fn intersect_surface<'a>(scene: &Scene, ray: &mut Ray) -> Option<SurfaceInteraction<'a>> {
+    loop {
+        let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+        if !scene.intersect(ray, &mut isect) {
+            return None;
+        }
+        isect.compute_scattering_functions(ray, true, TransportMode::Radiance);
+        if isect.bsdf.is_some() {
+            return Some(isect);
+        }
+        *ray = isect.spawn_ray(&ray.d);
+    }
+}
+
+/// Emitted radiance at *isect*, or from the environment if *ray*
+/// escaped.
+fn emitted(scene: &Scene, isect: Option<&SurfaceInteraction>, ray: &Ray) -> Spectrum {
+    match isect {
+        Some(isect) => isect.le(&-ray.d),
+        None => scene
+            .infinite_lights
+            .iter()
+            .fold(Spectrum::default(), |le, light| le + light.le(ray)),
+    }
+}
+
+/// Radiance arriving at *it* from *light* along *wi*.
+fn light_radiance(
+    scene: &Scene,
+    it: &SurfaceInteraction,
+    light: &Arc<Light>,
+    wi: &Vector3f,
+) -> Spectrum {
+    let ray: Ray = it.spawn_ray(wi);
+    let mut light_isect: SurfaceInteraction = SurfaceInteraction::default();
+    if !scene.intersect(&ray, &mut light_isect) {
+        return light.le(&ray);
+    }
+    if let Some(primitive_raw) = light_isect.primitive {
+        let primitive = unsafe { &*primitive_raw };
+        if let Some(area_light) = primitive.get_area_light() {
+            if Arc::ptr_eq(&area_light, light) {
+                return light_isect.le(&-*wi);
+            }
+        }
+    }
+    Spectrum::default()
+}
+
+fn non_specular() -> u8 {
+    BxdfType::BsdfAll as u8 & !(BxdfType::BsdfSpecular as u8)
+}
+
+/// Whether the reconnection shift may connect to or from *isect*,
+/// i.e. its BSDF has no specular components.
+fn is_rough(isect: &SurfaceInteraction) -> bool {
+    let specular: u8 = BxdfType::BsdfSpecular as u8
+        | BxdfType::BsdfReflection as u8
+        | BxdfType::BsdfTransmission as u8;
+    isect
+        .bsdf
+        .as_ref()
+        .is_some_and(|bsdf| bsdf.num_components(specular) == 0)
+}
+
+/// Radiance scaling of a specular refraction (see
+/// [PathIntegrator](../path/struct.PathIntegrator.html)).
+fn refraction_scale(bsdf: &Bsdf, wo: &Vector3f, n: &Normal3f, sampled_type: u8) -> Float {
+    if (sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8
+        && (sampled_type & BxdfType::BsdfTransmission as u8) != 0_u8
+    {
+        if vec3_dot_nrmf(wo, n) > 0.0 as Float {
+            bsdf.eta * bsdf.eta
+        } else {
+            1.0 as Float / (bsdf.eta * bsdf.eta)
+        }
+    } else {
+        1.0 as Float
+    }
+}
+
+/// Probability of the Russian roulette to terminate a path with
+/// throughput *rr_beta*.
+fn termination_probability(rr_beta: &Spectrum, rr_threshold: Float) -> Float {
+    if rr_beta.max_component_value() < rr_threshold {
+        (0.05 as Float).max(1.0 as Float - rr_beta.max_component_value())
+    } else {
+        0.0 as Float
+    }
+}
+
+fn pixel_index(bounds: &Bounds2i, p: Point2i) -> usize {
+    let width: i32 = bounds.p_max.x - bounds.p_min.x;
+    ((p.y - bounds.p_min.y) * width + (p.x - bounds.p_min.x)) as usize
+}
+
+/// Combines the samples of the base and offset paths into the primal
+/// buffer and the forward differences to the right (_dx_) and bottom
+/// (_dy_) neighbors. The weighted base paths of two neighboring pixels
+/// and their offset paths estimate both pixel values and their
+/// difference; the primal buffer averages over all neighbors.
+fn assemble_buffers(
+    pixels: &[GradientPixel],
+    bounds: &Bounds2i,
+) -> (Vec<Spectrum>, Vec<Spectrum>, Vec<Spectrum>) {
+    let n_pixels: usize = pixels.len();
+    let mut primal: Vec<Spectrum> = vec![Spectrum::default(); n_pixels];
+    let mut dx: Vec<Spectrum> = vec![Spectrum::default(); n_pixels];
+    let mut dy: Vec<Spectrum> = vec![Spectrum::default(); n_pixels];
+    for p in bounds {
+        let i: usize = pixel_index(bounds, p);
+        // the offset paths of the neighbors in the opposite direction
+        // (k ^ 1) sample this pixel
+        let mut sum: Spectrum = Spectrum::default();
+        let mut n_neighbors: u32 = 0;
+        for (k, offset) in OFFSETS.iter().enumerate() {
+            let neighbor: Point2i = Point2i {
+                x: p.x + offset.0,
+                y: p.y + offset.1,
+            };
+            if pnt2_inside_exclusivei(neighbor, bounds) {
+                let j: usize = pixel_index(bounds, neighbor);
+                sum += pixels[i].mean(&pixels[i].weighted_base[k])
+                    + pixels[j].mean(&pixels[j].weighted_offsets[k ^ 1]);
+                n_neighbors += 1;
+            }
+        }
+        primal[i] = if n_neighbors > 0 {
+            sum / n_neighbors as Float
+        } else {
+            pixels[i].mean(&pixels[i].base)
+        };
+        // right and bottom neighbors (offsets 0 and 2), whose left
+        // and top offsets (1 and 3) estimate the negated gradient
+        for (gradient, k) in [(&mut dx, 0_usize), (&mut dy, 2_usize)].iter_mut() {
+            let neighbor: Point2i = Point2i {
+                x: p.x + OFFSETS[*k].0,
+                y: p.y + OFFSETS[*k].1,
+            };
+            if pnt2_inside_exclusivei(neighbor, bounds) {
+                let j: usize = pixel_index(bounds, neighbor);
+                gradient[i] = pixels[i].difference(*k) - pixels[j].difference(*k + 1);
+            }
+        }
+    }
+    (primal, dx, dy)
+}
+
+/// Solves the screened Poisson equation, i.e. finds the image whose
+/// forward differences are closest to _dx_ and _dy_, while staying
+/// close to the _primal_ image (weighted by _alpha_). The L2 version
+/// solves the normal equations with conjugate gradients, the L1
+/// version reweights the residuals of the L2 problem iteratively.
+fn reconstruct(
+    primal: &[Spectrum],
+    dx: &[Spectrum],
+    dy: &[Spectrum],
+    extent: &Vector2i,
+    alpha: Float,
+    reconstruction: Reconstruction,
+) -> Vec<Spectrum> {
+    let n_pixels: usize = primal.len();
+    let mut image: Vec<Spectrum> = vec![Spectrum::default(); n_pixels];
+    for channel in 0..3 {
+        let p: Vec<Float> = primal.iter().map(|s| s.c[channel]).collect();
+        let gx: Vec<Float> = dx.iter().map(|s| s.c[channel]).collect();
+        let gy: Vec<Float> = dy.iter().map(|s| s.c[channel]).collect();
+        let mut problem: PoissonProblem = PoissonProblem {
+            width: extent.x as usize,
+            height: extent.y as usize,
+            alpha2: alpha * alpha,
+            w_p: vec![1.0 as Float; n_pixels],
+            w_x: vec![1.0 as Float; n_pixels],
+            w_y: vec![1.0 as Float; n_pixels],
+        };
+        let mut x: Vec<Float> = p.clone();
+        problem.solve(&mut x, &p, &gx, &gy, 50);
+        if reconstruction == Reconstruction::L1 {
+            // iteratively reweighted least squares
+            let epsilon: Float = 1e-3 as Float;
+            for _ in 0..20 {
+                for i in 0..n_pixels {
+                    problem.w_p[i] = 1.0 as Float / (x[i] - p[i]).abs().max(epsilon);
+                    let (ddx, ddy) = problem.differences(&x, i);
+                    problem.w_x[i] = 1.0 as Float / (ddx - gx[i]).abs().max(epsilon);
+                    problem.w_y[i] = 1.0 as Float / (ddy - gy[i]).abs().max(epsilon);
+                }
+                problem.solve(&mut x, &p, &gx, &gy, 20);
+            }
+        }
+        for (pixel, value) in image.iter_mut().zip(x) {
+            pixel.c[channel] = value;
+        }
+    }
+    image
+}
+
+/// Weighted least squares problem of the (single channel) screened
+/// Poisson reconstruction.
+struct PoissonProblem {
+    width: usize,
+    height: usize,
+    alpha2: Float,
+    w_p: Vec<Float>,
+    w_x: Vec<Float>,
+    w_y: Vec<Float>,
+}
+
+impl PoissonProblem {
+    /// Forward differences of *x* at pixel *i* (zero at the borders).
+    fn differences(&self, x: &[Float], i: usize) -> (Float, Float) {
+        let (px, py) = (i % self.width, i / self.width);
+        let ddx: Float = if px + 1 < self.width {
+            x[i + 1] - x[i]
+        } else {
+            0.0 as Float
+        };
+        let ddy: Float = if py + 1 < self.height {
+            x[i + self.width] - x[i]
+        } else {
+            0.0 as Float
+        };
+        (ddx, ddy)
+    }
+    /// Applies $D^T W v$ for the forward difference operators, where
+    /// *vx* and *vy* live on the (weighted) edges.
+    fn add_transposed(&self, vx: &[Float], vy: &[Float], result: &mut [Float]) {
+        for i in 0..result.len() {
+            let (px, py) = (i % self.width, i / self.width);
+            if px + 1 < self.width {
+                let v: Float = self.w_x[i] * vx[i];
+                result[i] -= v;
+                result[i + 1] += v;
+            }
+            if py + 1 < self.height {
+                let v: Float = self.w_y[i] * vy[i];
+                result[i] -= v;
+                result[i + self.width] += v;
+            }
+        }
+    }
+    /// Applies the normal equations' matrix
+    /// $\alpha^2 W_p + D^T W_g D$ to *x*.
+    fn apply(&self, x: &[Float], result: &mut [Float]) {
+        let n: usize = x.len();
+        let mut ddx: Vec<Float> = vec![0.0 as Float; n];
+        let mut ddy: Vec<Float> = vec![0.0 as Float; n];
+        for i in 0..n {
+            let (dx, dy) = self.differences(x, i);
+            ddx[i] = dx;
+            ddy[i] = dy;
+            result[i] = self.alpha2 * self.w_p[i] * x[i];
+        }
+        self.add_transposed(&ddx, &ddy, result);
+    }
+    /// Improves *x* with (at most) *iterations* conjugate gradient
+    /// steps.
+    fn solve(&self, x: &mut [Float], p: &[Float], gx: &[Float], gy: &[Float], iterations: u32) {
+        let n: usize = x.len();
+        // right hand side $\alpha^2 W_p p + D^T W_g g$
+        let mut b: Vec<Float> = (0..n).map(|i| self.alpha2 * self.w_p[i] * p[i]).collect();
+        self.add_transposed(gx, gy, &mut b);
+        let mut ax: Vec<Float> = vec![0.0 as Float; n];
+        self.apply(x, &mut ax);
+        let mut r: Vec<Float> = b.iter().zip(&ax).map(|(b, ax)| b - ax).collect();
+        let mut d: Vec<Float> = r.clone();
+        let mut r_dot_r: f64 = dot(&r, &r);
+        let b_dot_b: f64 = dot(&b, &b);
+        let mut ad: Vec<Float> = vec![0.0 as Float; n];
+        for _ in 0..iterations {
+            if r_dot_r <= 1e-12 * b_dot_b {
+                break;
+            }
+            self.apply(&d, &mut ad);
+            let d_dot_ad: f64 = dot(&d, &ad);
+            if d_dot_ad <= 0.0 {
+                break;
+            }
+            let step: Float = (r_dot_r / d_dot_ad) as Float;
+            for i in 0..n {
+                x[i] += step * d[i];
+                r[i] -= step * ad[i];
+            }
+            let r_dot_r_new: f64 = dot(&r, &r);
+            let beta: Float = (r_dot_r_new / r_dot_r) as Float;
+            for i in 0..n {
+                d[i] = r[i] + beta * d[i];
+            }
+            r_dot_r = r_dot_r_new;
+        }
+    }
+}
+
+fn dot(a: &[Float], b: &[Float]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| *a as f64 * *b as f64).sum()
+}
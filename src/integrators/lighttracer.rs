@@ -0,0 +1,190 @@
+//! Light tracing (also called particle tracing) follows paths from
+//! the light sources only and connects every vertex to the camera.
+
+// std
+use std::sync::Arc;
+// pbrt
+use crate::blockqueue::BlockQueue;
+use crate::core::camera::Camera;
+use crate::core::geometry::pnt2_inside_exclusivei;
+use crate::core::geometry::{Bounds2i, Point2f, Point2i, Point3f, Vector2i};
+use crate::core::lightdistrib::create_light_sample_distribution;
+use crate::core::lightdistrib::{LightDistribution, LocalLightDistribution};
+use crate::core::pbrt::lerp;
+use crate::core::pbrt::{Float, Spectrum};
+use crate::core::sampler::Sampler;
+use crate::core::scene::Scene;
+use crate::integrators::bdpt::{connect_to_camera, generate_light_subpath, Vertex};
+
+/// Light Tracing (Particle Tracing)
+pub struct LightTracerIntegrator {
+    pub camera: Arc<Camera>,
+    pub sampler: Box<Sampler>,
+    pub pixel_bounds: Bounds2i,
+    pub max_depth: u32,
+}
+
+impl LightTracerIntegrator {
+    pub fn new(
+        camera: Arc<Camera>,
+        sampler: Box<Sampler>,
+        pixel_bounds: Bounds2i,
+        max_depth: u32,
+    ) -> Self {
+        LightTracerIntegrator {
+            camera,
+            sampler,
+            pixel_bounds,
+            max_depth,
+        }
+    }
+    pub fn render(&self, scene: &Scene, num_threads: u8) {
+        let film = self.get_camera().get_film();
+        let sample_bounds: Bounds2i = film.get_sample_bounds();
+        let sample_extent: Vector2i = sample_bounds.diagonal();
+        let tile_size: i32 = 16;
+        let n_x_tiles: i32 = (sample_extent.x + tile_size - 1) / tile_size;
+        let n_y_tiles: i32 = (sample_extent.y + tile_size - 1) / tile_size;
+        if !scene.lights.is_empty() {
+            // light paths traced per pixel of _pixel_bounds_
+            let light_paths_per_pixel: i64 = self.sampler.get_samples_per_pixel();
+            let num_cores = if num_threads == 0_u8 {
+                num_cpus::get()
+            } else {
+                num_threads as usize
+            };
+            println!("Rendering with {:?} thread(s) ...", num_cores);
+            {
+                // the pixels only serve to distribute the light paths
+                // over the sampler's sample sequences
+                let block_queue = BlockQueue::new(
+                    (
+                        (n_x_tiles * tile_size) as u32,
+                        (n_y_tiles * tile_size) as u32,
+                    ),
+                    (tile_size as u32, tile_size as u32),
+                    (0, 0),
+                );
+                let bq = &block_queue;
+                let integrator = &self;
+                let sampler = &self.get_sampler();
+                let camera = &self.get_camera();
+                let film = &film;
+                // one light path is traced per sample of each pixel in
+                // _pixel_bounds_, but the splats cover all sample bounds
+                let splat_scale: Float =
+                    sample_bounds.area() as Float / integrator.pixel_bounds.area() as Float;
+                // there is no lookup point on the light side, so
+                // always sample lights according to their power
+                let light_distribution: &Option<Arc<LightDistribution>> =
+                    &create_light_sample_distribution(String::from("power"), scene);
+                let shutter_open: Float = camera.get_shutter_open();
+                let shutter_close: Float = camera.get_shutter_close();
+                crossbeam::scope(|scope| {
+                    let (tile_tx, tile_rx) = crossbeam_channel::bounded(num_cores);
+                    // spawn worker threads
+                    for _ in 0..num_cores {
+                        let tile_tx = tile_tx.clone();
+                        scope.spawn(move |_| {
+                            while let Some((x, y)) = bq.next() {
+                                let tile: Point2i = Point2i {
+                                    x: x as i32,
+                                    y: y as i32,
+                                };
+                                let seed: i32 = tile.y * n_x_tiles + tile.x;
+                                let mut tile_sampler: Box<Sampler> =
+                                    sampler.clone_with_seed(seed as u64);
+                                let x0: i32 = sample_bounds.p_min.x + tile.x * tile_size;
+                                let x1: i32 = std::cmp::min(x0 + tile_size, sample_bounds.p_max.x);
+                                let y0: i32 = sample_bounds.p_min.y + tile.y * tile_size;
+                                let y1: i32 = std::cmp::min(y0 + tile_size, sample_bounds.p_max.y);
+                                let tile_bounds: Bounds2i = Bounds2i::new(
+                                    Point2i { x: x0, y: y0 },
+                                    Point2i { x: x1, y: y1 },
+                                );
+                                for p_pixel in &tile_bounds {
+                                    tile_sampler.start_pixel(p_pixel);
+                                    if !pnt2_inside_exclusivei(p_pixel, &integrator.pixel_bounds) {
+                                        continue;
+                                    }
+                                    if let Some(light_distribution) = light_distribution {
+                                        let mut done: bool = false;
+                                        while !done {
+                                            let time: Float = lerp(
+                                                tile_sampler.get_1d(),
+                                                shutter_open,
+                                                shutter_close,
+                                            );
+                                            let light_distr: LocalLightDistribution =
+                                                light_distribution.lookup(&Point3f::default());
+                                            let mut light_vertices: Vec<Vertex> =
+                                                Vec::with_capacity(
+                                                    (integrator.max_depth + 1) as usize,
+                                                );
+                                            let n_light: usize = generate_light_subpath(
+                                                scene,
+                                                &mut tile_sampler,
+                                                integrator.max_depth + 1,
+                                                time,
+                                                light_distr,
+                                                &mut light_vertices,
+                                            );
+                                            // connect each vertex of the light
+                                            // subpath to the camera, this is
+                                            // BDPT's $t=1$ strategy without the
+                                            // MIS weights
+                                            for light_vertex in light_vertices.iter().take(n_light)
+                                            {
+                                                let mut p_raster: Point2f = Point2f::default();
+                                                let (l, _sampled): (Spectrum, Option<Vertex>) =
+                                                    connect_to_camera(
+                                                        scene,
+                                                        light_vertex,
+                                                        camera,
+                                                        &mut tile_sampler,
+                                                        &mut p_raster,
+                                                    );
+                                                if l.is_black() {
+                                                    continue;
+                                                }
+                                                let p_splat: Point2i = Point2i {
+                                                    x: p_raster.x.floor() as i32,
+                                                    y: p_raster.y.floor() as i32,
+                                                };
+                                                if pnt2_inside_exclusivei(
+                                                    p_splat,
+                                                    &integrator.pixel_bounds,
+                                                ) {
+                                                    film.add_splat(p_raster, &(l * splat_scale));
+                                                }
+                                            }
+                                            done = !tile_sampler.start_next_sample();
+                                        }
+                                    }
+                                }
+                                // report the finished tile to the main thread
+                                tile_tx
+                                    .send(tile)
+                                    .unwrap_or_else(|_| panic!("Failed to send tile"));
+                            }
+                        });
+                    }
+                    // spawn thread to report progress
+                    scope.spawn(move |_| {
+                        for _ in pbr::PbIter::new(0..bq.len()) {
+                            tile_rx.recv().unwrap();
+                        }
+                    });
+                })
+                .unwrap();
+            }
+            film.write_image(1.0 as Float / light_paths_per_pixel as Float);
+        }
+    }
+    pub fn get_camera(&self) -> Arc<Camera> {
+        self.camera.clone()
+    }
+    pub fn get_sampler(&self) -> &Sampler {
+        &self.sampler
+    }
+}
@@ -5,8 +5,10 @@ use std::thread;
 // pbrt
 use crate::core::camera::Camera;
 use crate::core::film::Film;
+use crate::core::geometry::pnt2_inside_exclusivei;
 use crate::core::geometry::{Bounds2f, Bounds2i, Point2f, Point2i};
 use crate::core::integrator::compute_light_power_distribution;
+use crate::core::lightdistrib::LocalLightDistribution;
 use crate::core::pbrt::erf_inv;
 use crate::core::pbrt::SQRT_2;
 use crate::core::pbrt::{Float, Spectrum};
@@ -279,6 +281,7 @@ impl MLTSampler {
 /// Metropolis Light Transport
 pub struct MLTIntegrator {
     pub camera: Arc<Camera>,
+    pub pixel_bounds: Bounds2i,
     pub max_depth: u32,
     pub n_bootstrap: u32,
     pub n_chains: u32,
@@ -290,6 +293,7 @@ pub struct MLTIntegrator {
 impl MLTIntegrator {
     pub fn new(
         camera: Arc<Camera>,
+        pixel_bounds: Bounds2i,
         max_depth: u32,
         n_bootstrap: u32,
         n_chains: u32,
@@ -299,6 +303,7 @@ impl MLTIntegrator {
     ) -> Self {
         MLTIntegrator {
             camera,
+            pixel_bounds,
             max_depth,
             n_bootstrap,
             n_chains,
@@ -310,7 +315,7 @@ impl MLTIntegrator {
     pub fn l(
         &self,
         scene: &Scene,
-        light_distr: Arc<Distribution1D>,
+        light_distr: LocalLightDistribution,
         sampler: &mut Sampler,
         depth: u32,
         p_raster: &mut Point2f,
@@ -334,23 +339,23 @@ impl MLTIntegrator {
         }
         // generate a camera subpath with exactly _t_ vertices
         let mut camera_vertices: Vec<Vertex> = Vec::with_capacity(t as usize);
-        let film = self.camera.get_film();
-        let sample_bounds: Bounds2i = film.get_sample_bounds();
-        let sample_bounds_f: Bounds2f = Bounds2f {
+        // only sample paths through the (restricted) pixel bounds
+        let pixel_bounds_f: Bounds2f = Bounds2f {
             p_min: Point2f {
-                x: sample_bounds.p_min.x as Float,
-                y: sample_bounds.p_min.y as Float,
+                x: self.pixel_bounds.p_min.x as Float,
+                y: self.pixel_bounds.p_min.y as Float,
             },
             p_max: Point2f {
-                x: sample_bounds.p_max.x as Float,
-                y: sample_bounds.p_max.y as Float,
+                x: self.pixel_bounds.p_max.x as Float,
+                y: self.pixel_bounds.p_max.y as Float,
             },
         };
-        *p_raster = sample_bounds_f.lerp(sampler.get_2d());
+        *p_raster = pixel_bounds_f.lerp(sampler.get_2d());
         let n_camera;
         let time;
+        let le_medium;
         {
-            let (n_camera_new, _p_new, time_new) = generate_camera_subpath(
+            let (n_camera_new, _p_new, time_new, le_medium_new) = generate_camera_subpath(
                 scene,
                 sampler,
                 t,
@@ -360,6 +365,7 @@ impl MLTIntegrator {
             );
             n_camera = n_camera_new;
             time = time_new;
+            le_medium = le_medium_new;
         }
         if n_camera != t as usize {
             return Spectrum::default();
@@ -390,7 +396,7 @@ impl MLTIntegrator {
             Sampler::MLT(mlt_sampler) => mlt_sampler.start_stream(CONNECTION_STREAM_INDEX as i32),
             _ => panic!("MLTSampler needed."),
         }
-        connect_bdpt(
+        let mut l: Spectrum = connect_bdpt(
             scene,
             &light_vertices,
             &camera_vertices,
@@ -402,7 +408,29 @@ impl MLTIntegrator {
             sampler,
             p_raster,
             None,
-        ) * (n_strategies as Float)
+            0.0 as Float,
+        ) * (n_strategies as Float);
+        if s == 0_u32 {
+            // light emitted by a medium at the last camera vertex (see
+            // generate_camera_subpath())
+            if let Some(le) = le_medium.get(t as usize - 1) {
+                l += *le * (n_strategies as Float);
+            }
+        }
+        if t == 1_u32 {
+            // light tracing may splat outside of _pixel_bounds_, and
+            // camera samples only cover _pixel_bounds_
+            let p_splat: Point2i = Point2i {
+                x: p_raster.x.floor() as i32,
+                y: p_raster.y.floor() as i32,
+            };
+            if !pnt2_inside_exclusivei(p_splat, &self.pixel_bounds) {
+                return Spectrum::default();
+            }
+            let sample_bounds: Bounds2i = self.camera.get_film().get_sample_bounds();
+            return l * (sample_bounds.area() as Float / self.pixel_bounds.area() as Float);
+        }
+        l
     }
     pub fn render(&self, scene: &Scene, num_threads: u8) {
         let mut num_cores: usize;
@@ -412,6 +440,8 @@ impl MLTIntegrator {
             num_threads as usize
         };
         if let Some(light_distr) = compute_light_power_distribution(scene) {
+            let light_distr: LocalLightDistribution =
+                LocalLightDistribution::Tabulated(light_distr);
             println!("Generating bootstrap paths ...");
             // generate bootstrap samples and compute normalization constant $b$
             num_cores = 1; // TMP: disable multi-threading
@@ -478,7 +508,7 @@ impl MLTIntegrator {
             num_cores = num_cores_init; // TMP: re-enable multi-threading
             let film: Arc<Film> = self.get_camera().get_film();
             let n_total_mutations: u64 =
-                self.mutations_per_pixel as u64 * film.get_sample_bounds().area() as u64;
+                self.mutations_per_pixel as u64 * self.pixel_bounds.area() as u64;
             if !scene.lights.is_empty() {
                 // TODO: let progress_frequency = 32768;
                 // TODO: ProgressReporter progress(nTotalMutations / progressFrequency,
@@ -4,9 +4,12 @@
 //! - AOIntegrator
 //! - BDPTIntegrator
 //! - DirectLightingIntegrator
+//! - GPTIntegrator
+//! - LightTracerIntegrator
 //! - MLTIntegrator
 //! - PathIntegrator
 //! - SPPMIntegrator
+//! - VCMIntegrator
 //! - VolPathIntegrator
 //! - WhittedIntegrator
 //!
@@ -39,6 +42,26 @@
 //!
 //! ![Path Tracing](/doc/img/cornell_box_pbrt_rust_path.png)
 //!
+//! With **"integer guidingpasses"** the path tracers first learn the
+//! distribution of indirect light in the scene over a number of
+//! training passes and then sample directions from it as well as from
+//! the BSDFs (see [guiding](../core/guiding/index.html)).
+//!
+//! ## Gradient-Domain Path Tracing (GPT)
+//!
+//! The **GPTIntegrator** traces shifted (offset) paths for the four
+//! neighbors of each pixel alongside the base path, which reuse its
+//! random numbers and reconnect to it at the first vertex pair
+//! without specular components, and estimates the image gradients
+//! from their differences. It takes the parameters of the
+//! **PathIntegrator** (except for guiding). The primal image and the
+//! gradients are written to
+//! `gpt_primal.exr`, `gpt_dx.exr` and `gpt_dy.exr`, the final image
+//! is reconstructed from them by solving a screened Poisson equation.
+//! **"string reconstruction"** selects the `"L1"` (default) or `"L2"`
+//! norm and **"float alpha"** (0.2) weights the primal image against
+//! the gradients.
+//!
 //! ## Bidirectional Path Tracing (BDPT)
 //!
 //! Bidirectional path tracing is a generalization of the standard
@@ -50,6 +73,26 @@
 //! ![Bidirectional Path
 //! Tracing](/doc/img/art_gallery_pbrt_rust_bdpt.png)
 //!
+//! To see which strategies carry the energy, **"bool
+//! visualizestrategies"** writes the unweighted contribution of each
+//! $(s, t)$ strategy to an OpenEXR file named like
+//! `bdpt_d01_s02_t01.exr` (path depth, light and camera vertices) and
+//! **"bool visualizeweights"** writes the MIS weighted contributions,
+//! which sum up to the final image, to files with an additional
+//! `_weighted` suffix (e.g. `bdpt_d01_s02_t01_weighted.exr`). Both
+//! limit **"integer maxdepth"** to 5.
+//!
+//! ## Light Tracing
+//!
+//! The **LightTracerIntegrator** only traces paths starting at the
+//! light sources and splats the connection of every path vertex to
+//! the camera onto the film. These are the $t=1$ strategies of BDPT
+//! without MIS weights, which makes it useful to debug caustics or to
+//! validate BDPT. With **"integer lightpathsperpixel"** the number of
+//! light paths can be chosen independently of the sampler's pixel
+//! samples. Infinite lights and surfaces without diffuse or glossy
+//! components (e.g. glass) can't be seen directly by the camera.
+//!
 //! ## Stochastic Progressive Photon Mapping (SPPM)
 //!
 //! A photon mapping integrator that uses particles to estimate
@@ -57,12 +100,35 @@
 //! particles close to but not quite at the point being shaded.
 //!
 //! ![Stochastic Progressive Photon Mapping](/doc/img/caustic_glass_pbrt_rust_sppm.png)
+//!
+//! ## Vertex Connection and Merging (VCM)
+//!
+//! Each iteration of the **VCMIntegrator** first traces a light
+//! subpath per pixel and stores its vertices as photons. Then the
+//! camera subpaths are connected to another light subpath like in
+//! BDPT and additionally merged with the photons within a radius,
+//! which shrinks from iteration to iteration (**"float radius"** and
+//! **"float radiusalpha"**). Merging finds paths BDPT can't sample,
+//! e.g. light sources behind glass seen via specular surfaces, and
+//! MIS weights all strategies against each other.
+//!
+//! ## Volumetric Path Tracing
+//!
+//! The **VolPathIntegrator** samples real and null collisions in
+//! participating media (delta tracking) and estimates the
+//! transmittance of shadow rays with ratio tracking. Both are MIS
+//! weighted against each other and across the color channels of
+//! chromatic media, like in the null-scattering path integral
+//! formulation of pbrt-v4.
 
 pub mod ao;
 pub mod bdpt;
 pub mod directlighting;
+pub mod gpt;
+pub mod lighttracer;
 pub mod mlt;
 pub mod path;
 pub mod sppm;
+pub mod vcm;
 pub mod volpath;
 pub mod whitted;
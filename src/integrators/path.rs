@@ -5,17 +5,18 @@ use std::sync::Arc;
 // pbrt
 // use crate::core::bssrdf::Bssrdf;
 use crate::core::camera::Camera;
+use crate::core::denoiser::FirstHitFeatures;
 use crate::core::geometry::{vec3_abs_dot_nrmf, vec3_dot_nrmf};
 use crate::core::geometry::{Bounds2i, Point2f, Ray, Vector3f};
+use crate::core::guiding::{add_guide_radiance, GuideVertex, PathGuide};
 use crate::core::integrator::uniform_sample_one_light;
 use crate::core::interaction::{Interaction, SurfaceInteraction};
 use crate::core::lightdistrib::create_light_sample_distribution;
-use crate::core::lightdistrib::LightDistribution;
+use crate::core::lightdistrib::{LightDistribution, LocalLightDistribution};
 use crate::core::material::TransportMode;
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::reflection::BxdfType;
 use crate::core::sampler::Sampler;
-use crate::core::sampling::Distribution1D;
 use crate::core::scene::Scene;
 
 // see path.h
@@ -32,6 +33,8 @@ pub struct PathIntegrator {
     rr_threshold: Float,           // 1.0
     light_sample_strategy: String, // "spatial"
     light_distribution: Option<Arc<LightDistribution>>,
+    guiding_passes: u32, // 0
+    guide: Option<PathGuide>,
 }
 
 impl PathIntegrator {
@@ -42,6 +45,7 @@ impl PathIntegrator {
         pixel_bounds: Bounds2i,
         rr_threshold: Float,
         light_sample_strategy: String,
+        guiding_passes: u32,
     ) -> Self {
         PathIntegrator {
             camera,
@@ -51,11 +55,16 @@ impl PathIntegrator {
             rr_threshold,
             light_sample_strategy,
             light_distribution: None,
+            guiding_passes,
+            guide: None,
         }
     }
     pub fn preprocess(&mut self, scene: &Scene) {
         self.light_distribution =
             create_light_sample_distribution(self.light_sample_strategy.clone(), scene);
+        if self.guiding_passes > 0 {
+            self.guide = Some(PathGuide::new(scene.world_bound(), self.guiding_passes));
+        }
     }
     pub fn li(
         &self,
@@ -63,7 +72,25 @@ impl PathIntegrator {
         scene: &Scene,
         sampler: &mut Sampler,
         // arena: &mut Arena,
+        depth: i32,
+    ) -> Spectrum {
+        let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+        let hit: bool = scene.intersect(r, &mut isect);
+        self.li_with_isect(r, hit, isect, scene, sampler, depth, None)
+    }
+    /// Same as [li()](#method.li), but the closest intersection of
+    /// the camera ray is already known (e.g. from tracing a ray
+    /// packet). If _features_ is given, it receives the albedo and
+    /// shading normal of the first surface hit (for the denoiser).
+    pub fn li_with_isect(
+        &self,
+        r: &Ray,
+        hit: bool,
+        isect: SurfaceInteraction,
+        scene: &Scene,
+        sampler: &mut Sampler,
         _depth: i32,
+        features: Option<&mut FirstHitFeatures>,
     ) -> Spectrum {
         // TODO: ProfilePhase p(Prof::SamplerIntegratorLi);
         let mut l: Spectrum = Spectrum::default();
@@ -87,17 +114,30 @@ impl PathIntegrator {
         // refracted rays that are about to be refracted back out of a
         // medium and thus have their beta value increased.
         let mut eta_scale: Float = 1.0;
+        let mut first_isect: Option<(bool, SurfaceInteraction)> = Some((hit, isect));
+        let mut features: Option<&mut FirstHitFeatures> = features;
+        // vertices whose incident radiance trains the path guide
+        let recording: bool = self.guide.as_ref().is_some_and(|guide| guide.recording);
+        let mut guide_vertices: Vec<GuideVertex> = Vec::new();
         loop {
             // find next path vertex and accumulate contribution
             // println!("Path tracer bounce {:?}, current L = {:?}, beta = {:?}",
             //          bounces, l, beta);
             // intersect _ray_ with scene and store intersection in _isect_
-            let mut isect: SurfaceInteraction = SurfaceInteraction::default();
-            if scene.intersect(&ray, &mut isect) {
+            let (hit, mut isect) = match first_isect.take() {
+                Some(first) => first,
+                None => {
+                    let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+                    (scene.intersect(&ray, &mut isect), isect)
+                }
+            };
+            if hit {
                 // possibly add emitted light at intersection
                 if bounces == 0 || specular_bounce {
                     // add emitted light at path vertex
-                    l += beta * isect.le(&-ray.d);
+                    let le: Spectrum = beta * isect.le(&-ray.d);
+                    l += le;
+                    add_guide_radiance(&mut guide_vertices, &le);
                     // println!("Added Le -> L = {:?}", l);
                 }
                 // terminate path if _maxDepth_ was reached
@@ -115,8 +155,14 @@ impl PathIntegrator {
                     // bounces--;
                     continue;
                 }
+                if bounces == 0 {
+                    if let Some(features) = features.take() {
+                        *features = FirstHitFeatures::new(&isect);
+                    }
+                }
                 if let Some(ref light_distribution) = self.light_distribution {
-                    let distrib: Arc<Distribution1D> = light_distribution.lookup(&isect.common.p);
+                    let distrib: LocalLightDistribution =
+                        light_distribution.lookup(&isect.common.p);
                     // Sample illumination from lights to find path contribution.
                     // (But skip this for perfectly specular BSDFs.)
                     let bsdf_flags: u8 = BxdfType::BsdfAll as u8 & !(BxdfType::BsdfSpecular as u8);
@@ -138,6 +184,7 @@ impl PathIntegrator {
                             // }
                             assert!(ld.y() >= 0.0 as Float, "ld = {:?}", ld);
                             l += ld;
+                            add_guide_radiance(&mut guide_vertices, &ld);
                         }
                         // Sample BSDF to get new path direction
                         let wo: Vector3f = -ray.d;
@@ -145,14 +192,20 @@ impl PathIntegrator {
                         let mut pdf: Float = 0.0 as Float;
                         let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
                         let mut sampled_type: u8 = u8::max_value(); // != 0
-                        let f: Spectrum = bsdf.sample_f(
-                            &wo,
-                            &mut wi,
-                            &sampler.get_2d(),
-                            &mut pdf,
-                            bsdf_flags,
-                            &mut sampled_type,
-                        );
+                        let guide: Option<&PathGuide> =
+                            self.guide.as_ref().filter(|guide| guide.guides(bsdf));
+                        let f: Spectrum = if let Some(guide) = guide {
+                            guide.sample_f(&isect, &mut wi, sampler, &mut pdf, &mut sampled_type)
+                        } else {
+                            bsdf.sample_f(
+                                &wo,
+                                &mut wi,
+                                &sampler.get_2d(),
+                                &mut pdf,
+                                bsdf_flags,
+                                &mut sampled_type,
+                            )
+                        };
 
                         // println!("Sampled BSDF, f = {:?}, pdf = {:?}", f, pdf);
                         if f.is_black() || pdf == 0.0 as Float {
@@ -187,6 +240,16 @@ impl PathIntegrator {
                             }
                         }
                         ray = isect.spawn_ray(&wi);
+                        if let Some(guide) = guide {
+                            if recording && isect.bssrdf.is_none() {
+                                guide_vertices.push(guide.new_vertex(
+                                    &isect.common.p,
+                                    &wi,
+                                    &beta,
+                                    pdf,
+                                ));
+                            }
+                        }
 
                         // account for subsurface scattering, if applicable
                         if let Some(ref bssrdf) = isect.bssrdf {
@@ -212,9 +275,9 @@ impl PathIntegrator {
                                 beta *= s / pdf;
                                 if let Some(pi) = pi_opt {
                                     // account for the direct subsurface scattering component
-                                    let distrib: Arc<Distribution1D> =
+                                    let distrib: LocalLightDistribution =
                                         light_distribution.lookup(&pi.common.p);
-                                    l += beta
+                                    let ld: Spectrum = beta
                                         * uniform_sample_one_light(
                                             &pi,
                                             scene,
@@ -222,6 +285,8 @@ impl PathIntegrator {
                                             false,
                                             Some(&distrib),
                                         );
+                                    l += ld;
+                                    add_guide_radiance(&mut guide_vertices, &ld);
                                     // account for the indirect subsurface scattering component
                                     let mut wi: Vector3f = Vector3f::default();
                                     let mut pdf: Float = 0.0 as Float;
@@ -270,7 +335,9 @@ impl PathIntegrator {
                 if bounces == 0 || specular_bounce {
                     // for (const auto &light : scene.infiniteLights)
                     for light in &scene.infinite_lights {
-                        l += beta * light.le(&ray);
+                        let le: Spectrum = beta * light.le(&ray);
+                        l += le;
+                        add_guide_radiance(&mut guide_vertices, &le);
                     }
                     // println!("Added infinite area lights -> L = {:?}", l);
                 }
@@ -279,6 +346,11 @@ impl PathIntegrator {
             }
             bounces += 1_u32;
         }
+        if let Some(ref guide) = self.guide {
+            if recording {
+                guide.record(&guide_vertices);
+            }
+        }
         l
     }
     pub fn get_camera(&self) -> Arc<Camera> {
@@ -290,4 +362,10 @@ impl PathIntegrator {
     pub fn get_pixel_bounds(&self) -> Bounds2i {
         self.pixel_bounds
     }
+    pub fn get_path_guide(&self) -> Option<&PathGuide> {
+        self.guide.as_ref()
+    }
+    pub fn get_path_guide_mut(&mut self) -> Option<&mut PathGuide> {
+        self.guide.as_mut()
+    }
 }
@@ -11,8 +11,8 @@ use crate::blockqueue::BlockQueue;
 use crate::core::camera::{Camera, CameraSample};
 use crate::core::film::Film;
 use crate::core::geometry::{
-    bnd3_expand, bnd3_union_bnd3f, nrm_abs_dot_vec3f, pnt3_distance_squaredf, vec3_abs_dot_nrmf,
-    vec3_max_componentf,
+    bnd2_intersect_bnd2i, bnd3_expand, bnd3_union_bnd3f, nrm_abs_dot_vec3f, pnt2_inside_exclusivei,
+    pnt3_distance_squaredf, vec3_abs_dot_nrmf, vec3_max_componentf,
 };
 use crate::core::geometry::{
     Bounds2i, Bounds3f, Normal3f, Point2f, Point2i, Point3f, Point3i, Ray, Vector2i, Vector3f,
@@ -33,6 +33,7 @@ use crate::samplers::halton::HaltonSampler;
 /// Stochastic Progressive Photon Mapping
 pub struct SPPMIntegrator {
     pub camera: Arc<Camera>,
+    pub pixel_bounds: Bounds2i,
     pub initial_search_radius: Float,
     pub n_iterations: i32,
     pub max_depth: u32,
@@ -43,6 +44,7 @@ pub struct SPPMIntegrator {
 impl SPPMIntegrator {
     pub fn new(
         camera: Arc<Camera>,
+        pixel_bounds: Bounds2i,
         n_iterations: i32,
         photons_per_iteration: i32,
         max_depth: u32,
@@ -55,8 +57,12 @@ impl SPPMIntegrator {
         } else {
             photons_per_iteration
         };
+        // visible points are only stored for pixels of the image
+        let pixel_bounds: Bounds2i =
+            bnd2_intersect_bnd2i(&pixel_bounds, &camera.get_film().cropped_pixel_bounds);
         SPPMIntegrator {
             camera,
+            pixel_bounds,
             initial_search_radius,
             n_iterations,
             max_depth,
@@ -75,7 +81,7 @@ impl SPPMIntegrator {
 
         // initialize _pixel_bounds_ and _pixels_ array for SPPM
         let film: Arc<Film> = self.get_camera().get_film();
-        let pixel_bounds: Bounds2i = film.cropped_pixel_bounds;
+        let pixel_bounds: Bounds2i = self.pixel_bounds;
         let n_pixels: i32 = pixel_bounds.area();
         let mut pixels: Vec<SPPMPixel> = Vec::with_capacity(n_pixels as usize);
         for _i in 0..n_pixels as usize {
@@ -791,9 +797,26 @@ impl SPPMIntegrator {
                     let x0: i32 = pixel_bounds.p_min.x;
                     let x1: i32 = pixel_bounds.p_max.x;
                     let np: u64 = (iteration + 1) as u64 * self.photons_per_iteration as u64;
-                    let mut image: Vec<Spectrum> = Vec::with_capacity(pixel_bounds.area() as usize);
-                    for y in (pixel_bounds.p_min.y as usize)..(pixel_bounds.p_max.y as usize) {
-                        for x in (x0 as usize)..(x1 as usize) {
+                    // pixels outside of _pixel_bounds_ stay black
+                    let cropped_pixel_bounds: Bounds2i = film.cropped_pixel_bounds;
+                    let mut image: Vec<Spectrum> =
+                        Vec::with_capacity(cropped_pixel_bounds.area() as usize);
+                    for y in (cropped_pixel_bounds.p_min.y as usize)
+                        ..(cropped_pixel_bounds.p_max.y as usize)
+                    {
+                        for x in (cropped_pixel_bounds.p_min.x as usize)
+                            ..(cropped_pixel_bounds.p_max.x as usize)
+                        {
+                            if !pnt2_inside_exclusivei(
+                                Point2i {
+                                    x: x as i32,
+                                    y: y as i32,
+                                },
+                                &pixel_bounds,
+                            ) {
+                                image.push(Spectrum::default());
+                                continue;
+                            }
                             // compute radiance _L_ for SPPM pixel _pixel_
                             let pixel = &pixels[(y - pixel_bounds.p_min.y as usize)
                                 * (x1 as usize - x0 as usize)
@@ -881,7 +904,7 @@ impl<'p> SPPMPixelListNode<'p> {
     }
 }
 
-fn to_grid(p: &Point3f, bounds: &Bounds3f, grid_res: &[i32; 3], pi: &mut Point3i) -> bool {
+pub fn to_grid(p: &Point3f, bounds: &Bounds3f, grid_res: &[i32; 3], pi: &mut Point3i) -> bool {
     let mut in_bounds: bool = true;
     let pg: Vector3f = bounds.offset(p);
     for i in XYZEnum::iter() {
@@ -892,7 +915,7 @@ fn to_grid(p: &Point3f, bounds: &Bounds3f, grid_res: &[i32; 3], pi: &mut Point3i
     in_bounds
 }
 
-fn hash(p: &Point3i, hash_size: i32) -> usize {
+pub fn hash(p: &Point3i, hash_size: i32) -> usize {
     let (x, _overflow) = p.x.overflowing_mul(73_856_093);
     let (y, _overflow) = p.y.overflowing_mul(19_349_663);
     let (z, _overflow) = p.z.overflowing_mul(83_492_791);
@@ -0,0 +1,680 @@
+//! Vertex connection and merging (VCM) combines the connection
+//! strategies of bidirectional path tracing with the merging of
+//! camera subpath vertices with nearby light subpath vertices
+//! (photons), as done by progressive photon mapping, and weights all
+//! of them by multiple importance sampling. Photons are only merged
+//! at surfaces, paths through participating media are sampled by the
+//! connection strategies.
+
+// std
+use std::f32::consts::PI;
+use std::sync::Arc;
+// others
+use strum::IntoEnumIterator;
+// pbrt
+use crate::blockqueue::BlockQueue;
+use crate::core::camera::Camera;
+use crate::core::geometry::{
+    bnd3_union_pnt3f, nrm_abs_dot_vec3f, pnt2_inside_exclusivei, pnt3_distance_squaredf,
+    vec3_max_componentf,
+};
+use crate::core::geometry::{
+    Bounds2i, Bounds3f, Normal3f, Point2f, Point2i, Point3f, Point3i, Vector2i, Vector3f, XYZEnum,
+};
+use crate::core::lightdistrib::create_light_sample_distribution;
+use crate::core::lightdistrib::{LightDistribution, LocalLightDistribution};
+use crate::core::pbrt::lerp;
+use crate::core::pbrt::{Float, Spectrum};
+use crate::core::reflection::BxdfType;
+use crate::core::sampler::Sampler;
+use crate::core::scene::Scene;
+use crate::integrators::bdpt::{
+    connect_bdpt, generate_camera_subpath, generate_light_subpath, Vertex,
+};
+use crate::integrators::sppm::{hash, to_grid};
+
+// see Georgiev et al. 2012, "Light Transport Simulation with Vertex
+// Connection and Merging"
+
+/// A light subpath vertex stored for merging. Vertices can't be
+/// shared between threads, so only the data needed to evaluate a
+/// merge and its MIS weight is kept.
+pub struct VCMPhoton {
+    pub p: Point3f,
+    // direction towards the previous light subpath vertex
+    pub wi: Vector3f,
+    pub beta: Spectrum,
+    // area density of sampling the photon from the light subpath
+    pub pdf_fwd: Float,
+    // number of light subpath vertices before the photon
+    pub n_vertices: usize,
+    // previous light subpath vertex
+    pub prev_p: Point3f,
+    pub prev_ng: Normal3f,
+    pub prev_is_infinite_light: bool,
+    pub prev_is_delta: bool,
+    pub prev_is_mergeable: bool,
+    // MIS ratios of the strategies sampling more of the path from the
+    // camera side, relative to the reverse density at the previous
+    // vertex (connections and merges)
+    pub d_vc: Float,
+    pub d_vm: Float,
+}
+
+impl VCMPhoton {
+    pub fn new(light_vertices: &[Vertex], k: usize) -> Self {
+        let photon: &Vertex = &light_vertices[k];
+        let qs: &Vertex = &light_vertices[k - 1];
+        let remap0 = |f: Float| -> Float {
+            if f != 0.0 as Float {
+                f
+            } else {
+                1.0 as Float
+            }
+        };
+        // walk the light subpath like _mis_weight()_ does for a
+        // connection of _qs_ with a camera vertex at the photon
+        let delta_lightvertex: bool = if k > 1 {
+            light_vertices[k - 2].is_delta()
+        } else {
+            qs.is_delta_light()
+        };
+        let mut d_vc: Float = 0.0 as Float;
+        let mut d_vm: Float = 0.0 as Float;
+        if !qs.is_delta() && !delta_lightvertex {
+            d_vc += 1.0 as Float;
+        }
+        let mut ri: Float = 1.0 as Float;
+        for i in (0..k - 1).rev() {
+            let lv: &Vertex = &light_vertices[i];
+            if i > 0 && lv.is_mergeable() {
+                d_vm += ri * remap0(lv.pdf_rev());
+            }
+            ri *= remap0(lv.pdf_rev()) / remap0(lv.pdf_fwd());
+            let delta_lightvertex: bool = if i > 0 {
+                light_vertices[i - 1].is_delta()
+            } else {
+                lv.is_delta_light()
+            };
+            if !lv.is_delta() && !delta_lightvertex {
+                d_vc += ri;
+            }
+        }
+        let mut wi: Vector3f = Vector3f::default();
+        if let Some(si) = photon.surface_interaction() {
+            wi = si.common.wo;
+        }
+        VCMPhoton {
+            p: photon.p(),
+            wi,
+            beta: photon.beta(),
+            pdf_fwd: photon.pdf_fwd(),
+            n_vertices: k,
+            prev_p: qs.p(),
+            prev_ng: qs.ng(),
+            prev_is_infinite_light: qs.is_infinite_light(),
+            prev_is_delta: qs.is_delta(),
+            prev_is_mergeable: k > 1 && qs.is_mergeable(),
+            d_vc: d_vc / remap0(qs.pdf_fwd()),
+            d_vm: d_vm / remap0(qs.pdf_fwd()),
+        }
+    }
+}
+
+/// Hash grid over the photons of one iteration with cells twice the
+/// merge radius wide, so a lookup touches at most eight cells.
+struct PhotonGrid {
+    bounds: Bounds3f,
+    grid_res: [i32; 3],
+    hash_size: i32,
+    // photon indices sorted by cell and the end of each cell
+    indices: Vec<usize>,
+    cell_ends: Vec<usize>,
+}
+
+impl PhotonGrid {
+    fn new(photons: &[VCMPhoton], radius: Float) -> Self {
+        let mut bounds: Bounds3f = Bounds3f::default();
+        for (i, photon) in photons.iter().enumerate() {
+            if i == 0 {
+                bounds = Bounds3f::new(photon.p, photon.p);
+            } else {
+                bounds = bnd3_union_pnt3f(&bounds, &photon.p);
+            }
+        }
+        let diag: Vector3f = bounds.diagonal();
+        let max_diag: Float = vec3_max_componentf(&diag);
+        let base_grid_res: i32 = ((max_diag / (2.0 as Float * radius)).floor() as i32).max(1);
+        let mut grid_res: [i32; 3] = [1; 3];
+        if max_diag > 0.0 as Float {
+            for i in XYZEnum::iter() {
+                grid_res[i as usize] =
+                    ((base_grid_res as Float * diag[i] / max_diag).floor() as i32).max(1);
+            }
+        }
+        let hash_size: i32 = (photons.len() as i32).max(1);
+        // counting sort of the photons by their hashed grid cells
+        let mut cells: Vec<usize> = Vec::with_capacity(photons.len());
+        let mut cell_ends: Vec<usize> = vec![0_usize; hash_size as usize];
+        for photon in photons {
+            let mut pi: Point3i = Point3i::default();
+            to_grid(&photon.p, &bounds, &grid_res, &mut pi);
+            let h: usize = hash(&pi, hash_size);
+            cells.push(h);
+            cell_ends[h] += 1;
+        }
+        let mut sum: usize = 0;
+        for cell_end in cell_ends.iter_mut() {
+            sum += *cell_end;
+            *cell_end = sum;
+        }
+        let mut cell_starts: Vec<usize> = Vec::with_capacity(hash_size as usize);
+        for h in 0..hash_size as usize {
+            cell_starts.push(if h == 0 { 0 } else { cell_ends[h - 1] });
+        }
+        let mut indices: Vec<usize> = vec![0_usize; photons.len()];
+        for (i, h) in cells.iter().enumerate() {
+            indices[cell_starts[*h]] = i;
+            cell_starts[*h] += 1;
+        }
+        PhotonGrid {
+            bounds,
+            grid_res,
+            hash_size,
+            indices,
+            cell_ends,
+        }
+    }
+    /// Calls _f_ with the index of every photon within _radius_ of
+    /// _p_.
+    fn lookup<F>(&self, photons: &[VCMPhoton], p: &Point3f, radius: Float, mut f: F)
+    where
+        F: FnMut(usize),
+    {
+        if photons.is_empty() {
+            return;
+        }
+        let r: Vector3f = Vector3f {
+            x: radius,
+            y: radius,
+            z: radius,
+        };
+        let mut p_min: Point3i = Point3i::default();
+        let mut p_max: Point3i = Point3i::default();
+        to_grid(&(*p - r), &self.bounds, &self.grid_res, &mut p_min);
+        to_grid(&(*p + r), &self.bounds, &self.grid_res, &mut p_max);
+        let radius2: Float = radius * radius;
+        // different cells may share a hash bucket, visit each once
+        let mut visited: Vec<usize> = Vec::with_capacity(8);
+        for z in p_min.z..=p_max.z {
+            for y in p_min.y..=p_max.y {
+                for x in p_min.x..=p_max.x {
+                    let h: usize = hash(&Point3i { x, y, z }, self.hash_size);
+                    if visited.contains(&h) {
+                        continue;
+                    }
+                    visited.push(h);
+                    let start: usize = if h == 0 { 0 } else { self.cell_ends[h - 1] };
+                    for index in &self.indices[start..self.cell_ends[h]] {
+                        if pnt3_distance_squaredf(&photons[*index].p, p) <= radius2 {
+                            f(*index);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Vertex Connection and Merging
+pub struct VCMIntegrator {
+    pub camera: Arc<Camera>,
+    pub sampler: Box<Sampler>,
+    pub pixel_bounds: Bounds2i,
+    pub max_depth: u32,
+    // merge radius of the first iteration, relative to the scene's
+    // bounding sphere if not positive
+    pub initial_search_radius: Float,
+    // radius reduction, see Knaus and Zwicker 2011
+    pub radius_alpha: Float,
+}
+
+impl VCMIntegrator {
+    pub fn new(
+        camera: Arc<Camera>,
+        sampler: Box<Sampler>,
+        pixel_bounds: Bounds2i,
+        max_depth: u32,
+        initial_search_radius: Float,
+        radius_alpha: Float,
+    ) -> Self {
+        VCMIntegrator {
+            camera,
+            sampler,
+            pixel_bounds,
+            max_depth,
+            initial_search_radius,
+            radius_alpha,
+        }
+    }
+    pub fn render(&self, scene: &Scene, num_threads: u8) {
+        let film = self.get_camera().get_film();
+        let sample_bounds: Bounds2i = film.get_sample_bounds();
+        let sample_extent: Vector2i = sample_bounds.diagonal();
+        let tile_size: i32 = 16;
+        let n_x_tiles: i32 = (sample_extent.x + tile_size - 1) / tile_size;
+        let n_y_tiles: i32 = (sample_extent.y + tile_size - 1) / tile_size;
+        if scene.lights.is_empty() {
+            return;
+        }
+        let num_cores = if num_threads == 0_u8 {
+            num_cpus::get()
+        } else {
+            num_threads as usize
+        };
+        println!("Rendering with {:?} thread(s) ...", num_cores);
+        // each iteration traces one light and one camera subpath per
+        // pixel of _pixel_bounds_
+        let n_iterations: i64 = self.sampler.get_samples_per_pixel();
+        let n_light_paths: Float = self.pixel_bounds.area() as Float;
+        let mut initial_search_radius: Float = self.initial_search_radius;
+        if initial_search_radius <= 0.0 as Float {
+            let mut world_center: Point3f = Point3f::default();
+            let mut world_radius: Float = 0.0;
+            let world_bound: &Bounds3f = scene.world_bound();
+            Bounds3f::bounding_sphere(world_bound, &mut world_center, &mut world_radius);
+            initial_search_radius = 0.003 as Float * world_radius;
+        }
+        let integrator = &self;
+        let sampler = &self.get_sampler();
+        let camera = &self.get_camera();
+        let film = &film;
+        // compensate light tracing splats for the pixels outside of
+        // _pixel_bounds_, which start no light paths
+        let splat_scale: Float =
+            sample_bounds.area() as Float / integrator.pixel_bounds.area() as Float;
+        // there is no lookup point for the photons' light subpaths,
+        // so all light subpaths sample lights according to their power
+        let light_distribution: &Option<Arc<LightDistribution>> =
+            &create_light_sample_distribution(String::from("power"), scene);
+        let shutter_open: Float = camera.get_shutter_open();
+        let shutter_close: Float = camera.get_shutter_close();
+        for iteration in pbr::PbIter::new(0..n_iterations) {
+            // progressively reduce the merge radius
+            let radius: Float = initial_search_radius
+                * ((iteration + 1) as Float).powf((integrator.radius_alpha - 1.0 as Float) * 0.5);
+            let eta_vm: Float = n_light_paths * PI * radius * radius;
+            // trace the light subpaths and store their photons
+            let mut photons: Vec<VCMPhoton> = Vec::new();
+            {
+                let block_queue = BlockQueue::new(
+                    (
+                        (n_x_tiles * tile_size) as u32,
+                        (n_y_tiles * tile_size) as u32,
+                    ),
+                    (tile_size as u32, tile_size as u32),
+                    (0, 0),
+                );
+                let bq = &block_queue;
+                let photons = &mut photons;
+                crossbeam::scope(|scope| {
+                    let (photon_tx, photon_rx) = crossbeam_channel::bounded(num_cores);
+                    // spawn worker threads
+                    for _ in 0..num_cores {
+                        let photon_tx = photon_tx.clone();
+                        scope.spawn(move |_| {
+                            while let Some((x, y)) = bq.next() {
+                                let tile: Point2i = Point2i {
+                                    x: x as i32,
+                                    y: y as i32,
+                                };
+                                // don't reuse the seeds of the camera pass
+                                let seed: i32 = n_x_tiles * n_y_tiles + tile.y * n_x_tiles + tile.x;
+                                let mut tile_sampler: Box<Sampler> =
+                                    sampler.clone_with_seed(seed as u64);
+                                let x0: i32 = sample_bounds.p_min.x + tile.x * tile_size;
+                                let x1: i32 = std::cmp::min(x0 + tile_size, sample_bounds.p_max.x);
+                                let y0: i32 = sample_bounds.p_min.y + tile.y * tile_size;
+                                let y1: i32 = std::cmp::min(y0 + tile_size, sample_bounds.p_max.y);
+                                let tile_bounds: Bounds2i = Bounds2i::new(
+                                    Point2i { x: x0, y: y0 },
+                                    Point2i { x: x1, y: y1 },
+                                );
+                                let mut tile_photons: Vec<VCMPhoton> = Vec::new();
+                                for p_pixel in &tile_bounds {
+                                    tile_sampler.start_pixel(p_pixel);
+                                    if !pnt2_inside_exclusivei(p_pixel, &integrator.pixel_bounds) {
+                                        continue;
+                                    }
+                                    tile_sampler.set_sample_number(iteration);
+                                    if let Some(light_distribution) = light_distribution {
+                                        let time: Float = lerp(
+                                            tile_sampler.get_1d(),
+                                            shutter_open,
+                                            shutter_close,
+                                        );
+                                        let light_distr: LocalLightDistribution =
+                                            light_distribution.lookup(&Point3f::default());
+                                        let mut light_vertices: Vec<Vertex> =
+                                            Vec::with_capacity((integrator.max_depth + 1) as usize);
+                                        let n_light: usize = generate_light_subpath(
+                                            scene,
+                                            &mut tile_sampler,
+                                            integrator.max_depth + 1,
+                                            time,
+                                            light_distr,
+                                            &mut light_vertices,
+                                        );
+                                        // the light source itself is never merged
+                                        for k in 1..n_light {
+                                            if light_vertices[k].is_mergeable()
+                                                && !light_vertices[k].beta().is_black()
+                                            {
+                                                tile_photons
+                                                    .push(VCMPhoton::new(&light_vertices, k));
+                                            }
+                                        }
+                                    }
+                                }
+                                photon_tx
+                                    .send(tile_photons)
+                                    .unwrap_or_else(|_| panic!("Failed to send photons"));
+                            }
+                        });
+                    }
+                    // collect the photons of all tiles
+                    scope.spawn(move |_| {
+                        for _ in 0..bq.len() {
+                            let mut tile_photons: Vec<VCMPhoton> = photon_rx.recv().unwrap();
+                            photons.append(&mut tile_photons);
+                        }
+                    });
+                })
+                .unwrap();
+            }
+            let grid: PhotonGrid = PhotonGrid::new(&photons, radius);
+            // trace the camera subpaths, connect and merge
+            {
+                let block_queue = BlockQueue::new(
+                    (
+                        (n_x_tiles * tile_size) as u32,
+                        (n_y_tiles * tile_size) as u32,
+                    ),
+                    (tile_size as u32, tile_size as u32),
+                    (0, 0),
+                );
+                let bq = &block_queue;
+                let photons = &photons;
+                let grid = &grid;
+                crossbeam::scope(|scope| {
+                    let (pixel_tx, pixel_rx) = crossbeam_channel::bounded(num_cores);
+                    // spawn worker threads
+                    for _ in 0..num_cores {
+                        let pixel_tx = pixel_tx.clone();
+                        scope.spawn(move |_| {
+                            while let Some((x, y)) = bq.next() {
+                                let tile: Point2i = Point2i {
+                                    x: x as i32,
+                                    y: y as i32,
+                                };
+                                let seed: i32 = tile.y * n_x_tiles + tile.x;
+                                let mut tile_sampler: Box<Sampler> =
+                                    sampler.clone_with_seed(seed as u64);
+                                let x0: i32 = sample_bounds.p_min.x + tile.x * tile_size;
+                                let x1: i32 = std::cmp::min(x0 + tile_size, sample_bounds.p_max.x);
+                                let y0: i32 = sample_bounds.p_min.y + tile.y * tile_size;
+                                let y1: i32 = std::cmp::min(y0 + tile_size, sample_bounds.p_max.y);
+                                let tile_bounds: Bounds2i = Bounds2i::new(
+                                    Point2i { x: x0, y: y0 },
+                                    Point2i { x: x1, y: y1 },
+                                );
+                                let mut film_tile = film.get_film_tile(&tile_bounds);
+                                for p_pixel in &tile_bounds {
+                                    tile_sampler.start_pixel(p_pixel);
+                                    if !pnt2_inside_exclusivei(p_pixel, &integrator.pixel_bounds) {
+                                        continue;
+                                    }
+                                    tile_sampler.set_sample_number(iteration);
+                                    if let Some(light_distribution) = light_distribution {
+                                        let p_film: Point2f = Point2f {
+                                            x: p_pixel.x as Float,
+                                            y: p_pixel.y as Float,
+                                        } + tile_sampler.get_2d();
+                                        // trace the camera subpath
+                                        let mut camera_vertices: Vec<Vertex> =
+                                            Vec::with_capacity((integrator.max_depth + 2) as usize);
+                                        let (n_camera, p, time, le_medium) =
+                                            generate_camera_subpath(
+                                                scene,
+                                                &mut tile_sampler,
+                                                integrator.max_depth + 2,
+                                                camera,
+                                                p_film,
+                                                &mut camera_vertices,
+                                            );
+                                        // trace a light subpath for the connections
+                                        let light_distr: LocalLightDistribution =
+                                            light_distribution.lookup(&p);
+                                        let mut light_vertices: Vec<Vertex> =
+                                            Vec::with_capacity((integrator.max_depth + 1) as usize);
+                                        let n_light: usize = generate_light_subpath(
+                                            scene,
+                                            &mut tile_sampler,
+                                            integrator.max_depth + 1,
+                                            time,
+                                            light_distr.clone(),
+                                            &mut light_vertices,
+                                        );
+                                        // execute all connection strategies
+                                        let mut l: Spectrum = le_medium
+                                            .iter()
+                                            .fold(Spectrum::default(), |l, le| l + *le);
+                                        for t in 1..=n_camera {
+                                            for s in 0..=n_light {
+                                                let depth: isize = (t + s) as isize - 2;
+                                                if (s == 1 && t == 1)
+                                                    || depth < 0
+                                                    || depth > integrator.max_depth as isize
+                                                {
+                                                    continue;
+                                                }
+                                                let mut p_film_new: Point2f = p_film;
+                                                let lpath: Spectrum = connect_bdpt(
+                                                    scene,
+                                                    &light_vertices,
+                                                    &camera_vertices,
+                                                    s,
+                                                    t,
+                                                    light_distr.clone(),
+                                                    camera,
+                                                    &mut tile_sampler,
+                                                    &mut p_film_new,
+                                                    None,
+                                                    eta_vm,
+                                                );
+                                                if t != 1 {
+                                                    l += lpath;
+                                                } else if !lpath.is_black() {
+                                                    let p_splat: Point2i = Point2i {
+                                                        x: p_film_new.x.floor() as i32,
+                                                        y: p_film_new.y.floor() as i32,
+                                                    };
+                                                    if pnt2_inside_exclusivei(
+                                                        p_splat,
+                                                        &integrator.pixel_bounds,
+                                                    ) {
+                                                        film.add_splat(
+                                                            p_film_new,
+                                                            &(lpath * splat_scale),
+                                                        );
+                                                    }
+                                                }
+                                            }
+                                        }
+                                        // merge photons at the camera subpath vertices
+                                        for t in 2..=n_camera {
+                                            l += merge_photons(
+                                                &camera_vertices[..t],
+                                                photons,
+                                                grid,
+                                                radius,
+                                                eta_vm,
+                                                integrator.max_depth,
+                                            );
+                                        }
+                                        film_tile.add_sample(p_film, &mut l, 1.0 as Float);
+                                    }
+                                }
+                                // send the tile through the channel to main thread
+                                pixel_tx
+                                    .send(film_tile)
+                                    .unwrap_or_else(|_| panic!("Failed to send tile"));
+                            }
+                        });
+                    }
+                    // spawn thread to collect pixels
+                    scope.spawn(move |_| {
+                        for _ in 0..bq.len() {
+                            let film_tile = pixel_rx.recv().unwrap();
+                            // merge image tile into _Film_
+                            film.merge_film_tile(&film_tile);
+                        }
+                    });
+                })
+                .unwrap();
+            }
+        }
+        film.write_image(1.0 as Float / n_iterations as Float);
+    }
+    pub fn get_camera(&self) -> Arc<Camera> {
+        self.camera.clone()
+    }
+    pub fn get_sampler(&self) -> &Sampler {
+        &self.sampler
+    }
+}
+
+// VCM Utility Functions
+
+/// Merges the photons around the last of the _camera_vertices_ and
+/// returns their MIS weighted contribution.
+fn merge_photons(
+    camera_vertices: &[Vertex],
+    photons: &[VCMPhoton],
+    grid: &PhotonGrid,
+    radius: Float,
+    eta_vm: Float,
+    max_depth: u32,
+) -> Spectrum {
+    let mut l: Spectrum = Spectrum::default();
+    let t: usize = camera_vertices.len();
+    let pt: &Vertex = &camera_vertices[t - 1];
+    let pt_minus: &Vertex = &camera_vertices[t - 2];
+    if !pt.is_mergeable() || pt.beta().is_black() {
+        return l;
+    }
+    if let Some(si) = pt.surface_interaction() {
+        if let Some(ref bsdf) = si.bsdf {
+            let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
+            let wo: Vector3f = si.common.wo;
+            let p: Point3f = pt.p();
+            grid.lookup(photons, &p, radius, |index| {
+                let photon: &VCMPhoton = &photons[index];
+                // the merged path treats the photon as if it was
+                // sampled at _pt_ by its previous vertex
+                if photon.n_vertices + t - 2 > max_depth as usize {
+                    return;
+                }
+                let f: Spectrum = bsdf.f(&wo, &photon.wi, bsdf_flags);
+                if f.is_black() {
+                    return;
+                }
+                let mut w_prev: Vector3f = photon.prev_p - p;
+                if w_prev.length_squared() == 0.0 as Float {
+                    return;
+                }
+                let inv_dist_2: Float = 1.0 as Float / w_prev.length_squared();
+                w_prev = w_prev.normalize();
+                let w_pt_minus: Vector3f = (pt_minus.p() - p).normalize();
+                // reverse density of the previous light vertex
+                let mut pdf_rev_prev: Float = bsdf.pdf(&w_pt_minus, &w_prev, bsdf_flags);
+                if !photon.prev_is_infinite_light {
+                    pdf_rev_prev *= inv_dist_2;
+                    if photon.prev_ng != Normal3f::default() {
+                        pdf_rev_prev *= nrm_abs_dot_vec3f(&photon.prev_ng, &w_prev);
+                    }
+                }
+                // reverse density of _pt_minus_
+                let pdf_rev_pt_minus: Float =
+                    pt.convert_density(bsdf.pdf(&w_prev, &w_pt_minus, bsdf_flags), pt_minus);
+                let weight: Float = merge_weight(
+                    camera_vertices,
+                    photon,
+                    pdf_rev_prev,
+                    pdf_rev_pt_minus,
+                    eta_vm,
+                );
+                l += pt.beta() * f * photon.beta * (weight / eta_vm);
+            });
+        }
+    }
+    l
+}
+
+/// MIS weight of merging _photon_ at the last of the
+/// _camera_vertices_, relative to all connection and merging
+/// strategies sampling the same path.
+fn merge_weight(
+    camera_vertices: &[Vertex],
+    photon: &VCMPhoton,
+    pdf_rev_prev: Float,
+    pdf_rev_pt_minus: Float,
+    eta_vm: Float,
+) -> Float {
+    let remap0 = |f: Float| -> Float {
+        if f != 0.0 as Float {
+            f
+        } else {
+            1.0 as Float
+        }
+    };
+    let t: usize = camera_vertices.len();
+    // strategies along the camera subpath, including this merge
+    let mut sum_ri: Float = 0.0 as Float;
+    let mut ri: Float = 1.0 as Float;
+    let mut i: usize = t - 1;
+    while i > 0 {
+        let cv1: &Vertex = &camera_vertices[i];
+        let cv0: &Vertex = &camera_vertices[i - 1];
+        let pdf_rev: Float = if i == t - 1 {
+            photon.pdf_fwd
+        } else if i == t - 2 {
+            pdf_rev_pt_minus
+        } else {
+            cv1.pdf_rev()
+        };
+        if cv1.is_mergeable() {
+            sum_ri += ri * remap0(pdf_rev) * eta_vm;
+        }
+        ri *= remap0(pdf_rev) / remap0(cv1.pdf_fwd());
+        if !cv1.is_delta() && !cv0.is_delta() {
+            sum_ri += ri;
+        }
+        i -= 1;
+    }
+    // strategies along the light subpath
+    sum_ri += remap0(pdf_rev_prev) * (photon.d_vc + photon.d_vm * eta_vm);
+    if photon.prev_is_mergeable {
+        sum_ri += remap0(pdf_rev_prev) * eta_vm;
+    }
+    // the connection of the photon's previous vertex with _pt_
+    if !photon.prev_is_delta {
+        sum_ri += 1.0 as Float;
+    }
+    let merge_ri: Float = remap0(photon.pdf_fwd) * eta_vm;
+    if sum_ri > 0.0 as Float {
+        merge_ri / sum_ri
+    } else {
+        0.0 as Float
+    }
+}
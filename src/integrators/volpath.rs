@@ -1,21 +1,24 @@
 // std
-use std::borrow::Borrow;
 use std::cell::Cell;
+use std::collections::HashMap;
 use std::sync::Arc;
 // pbrt
 // use crate::core::bssrdf::Bssrdf;
 use crate::core::camera::Camera;
+use crate::core::denoiser::FirstHitFeatures;
 use crate::core::geometry::{vec3_abs_dot_nrmf, vec3_dot_nrmf};
 use crate::core::geometry::{Bounds2i, Point2f, Ray, Vector3f};
-use crate::core::integrator::uniform_sample_one_light;
-use crate::core::interaction::{Interaction, MediumInteraction, SurfaceInteraction};
+use crate::core::guiding::{add_guide_radiance, GuideVertex, PathGuide};
+use crate::core::interaction::{Interaction, InteractionCommon};
+use crate::core::interaction::{MediumInteraction, SurfaceInteraction};
+use crate::core::light::{is_delta_light, Light, VisibilityTester};
 use crate::core::lightdistrib::create_light_sample_distribution;
 use crate::core::lightdistrib::LightDistribution;
 use crate::core::material::TransportMode;
+use crate::core::medium::{sample_channel, HenyeyGreenstein};
 use crate::core::pbrt::{Float, Spectrum};
-use crate::core::reflection::BxdfType;
+use crate::core::reflection::{Bsdf, BxdfType};
 use crate::core::sampler::Sampler;
-use crate::core::sampling::Distribution1D;
 use crate::core::scene::Scene;
 
 // see volpath.h
@@ -33,6 +36,10 @@ pub struct VolPathIntegrator {
     pub rr_threshold: Float,           // 1.0
     pub light_sample_strategy: String, // "spatial"
     pub light_distribution: Option<Arc<LightDistribution>>,
+    /// index in *scene.lights* by address of the light
+    pub light_to_index: HashMap<usize, usize>,
+    pub guiding_passes: u32, // 0
+    pub guide: Option<PathGuide>,
 }
 
 impl VolPathIntegrator {
@@ -43,6 +50,7 @@ impl VolPathIntegrator {
         pixel_bounds: Bounds2i,
         rr_threshold: Float,
         light_sample_strategy: String,
+        guiding_passes: u32,
     ) -> Self {
         VolPathIntegrator {
             camera,
@@ -52,11 +60,23 @@ impl VolPathIntegrator {
             rr_threshold,
             light_sample_strategy,
             light_distribution: None,
+            light_to_index: HashMap::new(),
+            guiding_passes,
+            guide: None,
         }
     }
     pub fn preprocess(&mut self, scene: &Scene) {
         self.light_distribution =
             create_light_sample_distribution(self.light_sample_strategy.clone(), scene);
+        self.light_to_index = scene
+            .lights
+            .iter()
+            .enumerate()
+            .map(|(i, light)| (&**light as *const Light as usize, i))
+            .collect();
+        if self.guiding_passes > 0 {
+            self.guide = Some(PathGuide::new(scene.world_bound(), self.guiding_passes));
+        }
     }
     pub fn li(
         &self,
@@ -64,11 +84,33 @@ impl VolPathIntegrator {
         scene: &Scene,
         sampler: &mut Sampler,
         // arena: &mut Arena,
+        depth: i32,
+    ) -> Spectrum {
+        let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+        let hit: bool = scene.intersect(r, &mut isect);
+        self.li_with_isect(r, hit, isect, scene, sampler, depth, None)
+    }
+    /// Same as [li()](#method.li), but the closest intersection of
+    /// the camera ray is already known (e.g. from tracing a ray
+    /// packet). If _features_ is given, it receives the albedo and
+    /// shading normal of the first surface hit (for the denoiser).
+    pub fn li_with_isect(
+        &self,
+        r: &mut Ray,
+        hit: bool,
+        isect: SurfaceInteraction,
+        scene: &Scene,
+        sampler: &mut Sampler,
         _depth: i32,
+        features: Option<&mut FirstHitFeatures>,
     ) -> Spectrum {
         // TODO: ProfilePhase p(Prof::SamplerIntegratorLi);
         let mut l: Spectrum = Spectrum::default();
         let mut beta: Spectrum = Spectrum::new(1.0 as Float);
+        // rescaled unidirectional and light sampling path densities
+        // (relative to the hero channel's density) for spectral MIS
+        let mut r_u: Spectrum = Spectrum::new(1.0 as Float);
+        let mut r_l: Spectrum = Spectrum::new(1.0 as Float);
         let mut ray: Ray = Ray {
             o: r.o,
             d: r.d,
@@ -79,6 +121,7 @@ impl VolPathIntegrator {
         };
         let mut specular_bounce: bool = false;
         let mut bounces: u32 = 0_u32;
+        let mut features: Option<&mut FirstHitFeatures> = features;
         // Added after book publication: etaScale tracks the
         // accumulated effect of radiance scaling due to rays passing
         // through refractive boundaries (see the derivation on p. 527
@@ -88,93 +131,302 @@ impl VolPathIntegrator {
         // refracted rays that are about to be refracted back out of a
         // medium and thus have their beta value increased.
         let mut eta_scale: Float = 1.0;
+        // the hero channel whose majorants sample distances in media
+        let channel: usize = sample_channel(sampler.get_1d());
+        // the previous scattering vertex (for MIS of emitted light)
+        let mut prev_ctx: MediumInteraction = MediumInteraction::default();
+        let mut first_isect: Option<(bool, SurfaceInteraction)> = Some((hit, isect));
+        // vertices whose incident radiance trains the path guide (only
+        // surface vertices are guided)
+        let recording: bool = self.guide.as_ref().is_some_and(|guide| guide.recording);
+        let mut guide_vertices: Vec<GuideVertex> = Vec::new();
         loop {
-            let mut mi_opt: Option<MediumInteraction> = None;
             // intersect _ray_ with scene and store intersection in _isect_
-            let mut isect: SurfaceInteraction = SurfaceInteraction::default();
-            if scene.intersect(&mut ray, &mut isect) {
-                // sample the participating medium, if present
-                if let Some(ref medium) = ray.medium {
-                    let (spectrum, option) = medium.sample(&ray, sampler);
-                    beta *= spectrum;
-                    if let Some(mi) = option {
-                        mi_opt = Some(mi);
-                    }
+            let (hit, mut isect) = match first_isect.take() {
+                Some(first) => first,
+                None => {
+                    let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+                    (scene.intersect(&mut ray, &mut isect), isect)
                 }
-                if beta.is_black() {
+            };
+            // sample the participating medium, if present
+            if let Some(medium) = ray.medium.clone() {
+                let mut scattered: bool = false;
+                let mut terminated: bool = false;
+                let mut scattered_ray: Option<Ray> = None;
+                let t_max: Float = if hit {
+                    ray.t_max.get()
+                } else {
+                    Float::INFINITY
+                };
+                let t_maj: Spectrum = medium.sample_t_maj(
+                    &ray,
+                    t_max,
+                    channel,
+                    sampler,
+                    |p, mp, sigma_maj, t_maj, sampler| {
+                        // add emission from the medium
+                        if bounces < self.max_depth && !mp.le.is_black() {
+                            let pdf: Float = sigma_maj.c[channel] * t_maj.c[channel];
+                            let beta_p: Spectrum = beta * *t_maj / pdf;
+                            let r_e: Spectrum = r_u * *sigma_maj * *t_maj / pdf;
+                            if !r_e.is_black() {
+                                let le: Spectrum = beta_p * mp.sigma_a * mp.le / r_e.average();
+                                l += le;
+                                add_guide_radiance(&mut guide_vertices, &le);
+                            }
+                        }
+                        // choose absorption, real or null scattering
+                        // with the hero channel's probabilities
+                        let p_absorb: Float = mp.sigma_a.c[channel] / sigma_maj.c[channel];
+                        let p_scatter: Float = mp.sigma_s.c[channel] / sigma_maj.c[channel];
+                        let u: Float = sampler.get_1d();
+                        if u < p_absorb {
+                            terminated = true;
+                            false
+                        } else if u < p_absorb + p_scatter {
+                            // terminate path if _maxDepth_ was reached
+                            if bounces >= self.max_depth {
+                                terminated = true;
+                                return false;
+                            }
+                            bounces += 1_u32;
+                            let pdf: Float = t_maj.c[channel] * mp.sigma_s.c[channel];
+                            beta *= *t_maj * mp.sigma_s / pdf;
+                            r_u *= *t_maj * mp.sigma_s / pdf;
+                            if !beta.is_black() && !r_u.is_black() {
+                                // sample direct lighting at the real
+                                // scattering event
+                                let phase: Arc<HenyeyGreenstein> =
+                                    Arc::new(HenyeyGreenstein { g: mp.g });
+                                let mi: MediumInteraction = MediumInteraction::new(
+                                    p,
+                                    &(-ray.d),
+                                    ray.time,
+                                    Some(medium.clone()),
+                                    Some(phase.clone()),
+                                );
+                                let ld: Spectrum =
+                                    beta * self.sample_ld(&mi, None, scene, sampler, &r_u, channel);
+                                l += ld;
+                                add_guide_radiance(&mut guide_vertices, &ld);
+                                // sample new direction, the phase
+                                // function is sampled perfectly
+                                let mut wi: Vector3f = Vector3f::default();
+                                let pdf: Float =
+                                    phase.sample_p(&(-ray.d), &mut wi, sampler.get_2d());
+                                if pdf == 0.0 as Float {
+                                    terminated = true;
+                                } else {
+                                    r_l = r_u / pdf;
+                                    scattered_ray = Some(mi.spawn_ray(&wi));
+                                    prev_ctx = mi;
+                                    scattered = true;
+                                    specular_bounce = false;
+                                }
+                            }
+                            false
+                        } else {
+                            // null scattering
+                            let sigma_n: Spectrum = (*sigma_maj - mp.sigma_a - mp.sigma_s)
+                                .clamp(0.0 as Float, Float::INFINITY);
+                            let pdf: Float = t_maj.c[channel] * sigma_n.c[channel];
+                            if pdf == 0.0 as Float {
+                                beta = Spectrum::default();
+                            } else {
+                                beta *= *t_maj * sigma_n / pdf;
+                                r_u *= *t_maj * sigma_n / pdf;
+                                r_l *= *t_maj * *sigma_maj / pdf;
+                            }
+                            !beta.is_black() && !r_u.is_black()
+                        }
+                    },
+                );
+                // handle terminated, scattered, and unscattered medium rays
+                if terminated || beta.is_black() || r_u.is_black() {
                     break;
                 }
-                // handle an interaction with a medium or a surface
-                if let Some(mi) = mi_opt {
-                    // terminate path if ray escaped or _maxDepth_ was reached
-                    if bounces >= self.max_depth {
+                if let Some(scattered_ray) = scattered_ray {
+                    ray = scattered_ray;
+                    if !self.survives_russian_roulette(&mut beta, &r_u, eta_scale, bounces, sampler)
+                    {
                         break;
                     }
-                    let mi_p = mi.common.p;
-                    // if mi.is_valid() {...}
-                    if let Some(phase) = mi.clone().phase {
-                        // TODO: ++volumeInteractions;
-                        // handle scattering at point in medium for volumetric path tracer
-                        if let Some(ref light_distribution) = self.light_distribution {
-                            let distrib: Arc<Distribution1D> = light_distribution.lookup(&mi_p);
-                            l += beta
-                                * uniform_sample_one_light(
-                                    &mi as &dyn Interaction,
-                                    scene,
-                                    sampler,
-                                    true,
-                                    Some(&distrib),
-                                );
-                            let mut wi: Vector3f = Vector3f::default();
-                            phase.sample_p(&(-ray.d), &mut wi, sampler.get_2d());
-                            ray = mi.spawn_ray(&wi);
-                            specular_bounce = false;
-                        }
+                    continue;
+                }
+                if scattered || t_maj.c[channel] == 0.0 as Float {
+                    break;
+                }
+                beta *= t_maj / t_maj.c[channel];
+                r_u *= t_maj / t_maj.c[channel];
+                r_l *= t_maj / t_maj.c[channel];
+            }
+            if !hit {
+                // add emitted light from the environment
+                for light in &scene.infinite_lights {
+                    let le: Spectrum = light.le(&ray);
+                    if le.is_black() {
+                        continue;
                     }
+                    let le: Spectrum = if bounces == 0 || specular_bounce {
+                        beta * le / r_u.average()
+                    } else {
+                        let light_pdf: Float = self.light_pdf(&prev_ctx, light, &ray.d.normalize());
+                        beta * le / (r_u + r_l * light_pdf).average()
+                    };
+                    l += le;
+                    add_guide_radiance(&mut guide_vertices, &le);
+                }
+                // terminate path if ray escaped
+                break;
+            }
+            // TODO: ++surfaceInteractions;
+            // add emitted light at the surface
+            let le: Spectrum = isect.le(&-ray.d);
+            if !le.is_black() {
+                let le: Spectrum = if bounces == 0 || specular_bounce {
+                    beta * le / r_u.average()
                 } else {
-                    // TODO: ++surfaceInteractions;
-                    // possibly add emitted light at intersection
-                    if bounces == 0 || specular_bounce {
-                        // add emitted light at path vertex
-                        l += beta * isect.le(&-ray.d);
+                    let mut light_pdf: Float = 0.0 as Float;
+                    if let Some(primitive_raw) = isect.primitive {
+                        let primitive = unsafe { &*primitive_raw };
+                        if let Some(area_light) = primitive.get_area_light() {
+                            light_pdf = self.light_pdf(&prev_ctx, &area_light, &ray.d.normalize());
+                        }
                     }
-                    // terminate path if _maxDepth_ was reached
-                    if bounces >= self.max_depth {
+                    beta * le / (r_u + r_l * light_pdf).average()
+                };
+                l += le;
+                add_guide_radiance(&mut guide_vertices, &le);
+            }
+            // compute scattering functions and skip over medium boundaries
+            let mode: TransportMode = TransportMode::Radiance;
+            isect.compute_scattering_functions(&ray, true, mode);
+            let bsdf: &Bsdf = match isect.bsdf {
+                Some(ref bsdf) => bsdf,
+                None => {
+                    ray = isect.spawn_ray(&ray.d);
+                    continue;
+                }
+            };
+            if bounces == 0 {
+                if let Some(features) = features.take() {
+                    *features = FirstHitFeatures::new(&isect);
+                }
+            }
+            // terminate path if _maxDepth_ was reached
+            if bounces >= self.max_depth {
+                break;
+            }
+            bounces += 1_u32;
+            let guide: Option<&PathGuide> = self.guide.as_ref().filter(|guide| guide.guides(bsdf));
+            // Sample illumination from lights to find attenuated path
+            // contribution.
+            let bsdf_flags: u8 = BxdfType::BsdfAll as u8 & !(BxdfType::BsdfSpecular as u8);
+            if bsdf.num_components(bsdf_flags) > 0 {
+                let ld: Spectrum =
+                    beta * self.sample_ld(&isect, guide, scene, sampler, &r_u, channel);
+                l += ld;
+                add_guide_radiance(&mut guide_vertices, &ld);
+            }
+            prev_ctx = MediumInteraction {
+                common: isect.common.clone(),
+                ..Default::default()
+            };
+            // Sample BSDF to get new path direction
+            let wo: Vector3f = -ray.d;
+            let mut wi: Vector3f = Vector3f::default();
+            let mut pdf: Float = 0.0 as Float;
+            let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
+            let mut sampled_type: u8 = u8::max_value(); // != 0
+            let f: Spectrum = if let Some(guide) = guide {
+                guide.sample_f(&isect, &mut wi, sampler, &mut pdf, &mut sampled_type)
+            } else {
+                bsdf.sample_f(
+                    &wo,
+                    &mut wi,
+                    &sampler.get_2d(),
+                    &mut pdf,
+                    bsdf_flags,
+                    &mut sampled_type,
+                )
+            };
+            if f.is_black() || pdf == 0.0 as Float {
+                break;
+            }
+            beta *= (f * vec3_abs_dot_nrmf(&wi, &isect.shading.n)) / pdf;
+            assert!(
+                !(beta.y().is_infinite()),
+                "[{:#?}, {:?}] = ({:#?} * dot({:#?}, {:#?})) / {:?}",
+                sampler.get_current_pixel(),
+                sampler.get_current_sample_number(),
+                f,
+                wi,
+                isect.shading.n,
+                pdf
+            );
+            r_l = r_u / pdf;
+            specular_bounce = (sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8;
+            if ((sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8)
+                && ((sampled_type & BxdfType::BsdfTransmission as u8) != 0_u8)
+            {
+                let eta: Float = bsdf.eta;
+                // Update the term that tracks radiance scaling for
+                // refraction depending on whether the ray is entering
+                // or leaving the medium.
+                if vec3_dot_nrmf(&wo, &isect.common.n) > 0.0 as Float {
+                    eta_scale *= eta * eta;
+                } else {
+                    eta_scale *= 1.0 as Float / (eta * eta);
+                }
+            }
+            ray = isect.spawn_ray(&wi);
+            if let Some(guide) = guide {
+                if recording && isect.bssrdf.is_none() {
+                    guide_vertices.push(guide.new_vertex(
+                        &isect.common.p,
+                        &wi,
+                        &(beta / r_u.average()),
+                        pdf,
+                    ));
+                }
+            }
+            // account for attenuated subsurface scattering, if applicable
+            if let Some(ref bssrdf) = isect.bssrdf {
+                if (sampled_type & BxdfType::BsdfTransmission as u8) != 0_u8 {
+                    // importance sample the BSSRDF
+                    let s2: Point2f = sampler.get_2d();
+                    let s1: Float = sampler.get_1d();
+                    let (s, pi_opt) = bssrdf.sample_s(
+                        // the next three (extra) parameters are used for SeparableBssrdfAdapter
+                        bssrdf.clone(),
+                        bssrdf.mode,
+                        bssrdf.eta,
+                        // done
+                        scene,
+                        s1,
+                        s2,
+                        &mut pdf,
+                    );
+                    if s.is_black() || pdf == 0.0 as Float {
                         break;
                     }
-                    // compute scattering functions and skip over medium boundaries
-                    let mode: TransportMode = TransportMode::Radiance;
-                    isect.compute_scattering_functions(&ray, true, mode);
-                    if let Some(ref _bsdf) = isect.bsdf {
-                        // we are fine (for below)
-                    } else {
-                        ray = isect.spawn_ray(&ray.d);
-                        // bounces--;
-                        continue;
-                    }
-                    if let Some(ref light_distribution) = self.light_distribution {
-                        let light_distrib: Arc<Distribution1D> =
-                            light_distribution.lookup(&isect.common.p);
-                        // Sample illumination from lights to find
-                        // attenuated path contribution.
-                        let it: &SurfaceInteraction = isect.borrow();
-                        l += beta
-                            * uniform_sample_one_light(
-                                it,
-                                scene,
-                                sampler,
-                                true,
-                                Some(&light_distrib),
-                            );
-                        if let Some(ref bsdf) = isect.bsdf {
-                            // Sample BSDF to get new path direction
-                            let wo: Vector3f = -ray.d;
-                            let mut wi: Vector3f = Vector3f::default();
-                            let mut pdf: Float = 0.0 as Float;
-                            let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
-                            let mut sampled_type: u8 = u8::max_value(); // != 0
+                    assert!(!(beta.y().is_infinite()));
+                    beta *= s / pdf;
+                    if let Some(pi) = pi_opt {
+                        // account for the direct subsurface scattering component
+                        let ld: Spectrum =
+                            beta * self.sample_ld(&pi, None, scene, sampler, &r_u, channel);
+                        l += ld;
+                        add_guide_radiance(&mut guide_vertices, &ld);
+                        // account for the indirect subsurface scattering component
+                        let mut wi: Vector3f = Vector3f::default();
+                        let mut pdf: Float = 0.0 as Float;
+                        let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
+                        let mut sampled_type: u8 = u8::max_value(); // != 0
+                        if let Some(ref bsdf) = pi.bsdf {
                             let f: Spectrum = bsdf.sample_f(
-                                &wo,
+                                &pi.common.wo,
                                 &mut wi,
                                 &sampler.get_2d(),
                                 &mut pdf,
@@ -184,167 +436,209 @@ impl VolPathIntegrator {
                             if f.is_black() || pdf == 0.0 as Float {
                                 break;
                             }
-                            beta *= (f * vec3_abs_dot_nrmf(&wi, &isect.shading.n)) / pdf;
-                            assert!(
-                                !(beta.y().is_infinite()),
-                                "[{:#?}, {:?}] = ({:#?} * dot({:#?}, {:#?})) / {:?}",
-                                sampler.get_current_pixel(),
-                                sampler.get_current_sample_number(),
-                                f,
-                                wi,
-                                isect.shading.n,
-                                pdf
-                            );
+                            beta *= f * vec3_abs_dot_nrmf(&wi, &pi.shading.n) / pdf;
+                            assert!(!(beta.y().is_infinite()));
+                            r_l = r_u / pdf;
                             specular_bounce = (sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8;
-                            if ((sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8)
-                                && ((sampled_type & BxdfType::BsdfTransmission as u8) != 0_u8)
-                            {
-                                let eta: Float = bsdf.eta;
-                                // Update the term that tracks radiance
-                                // scaling for refraction depending on
-                                // whether the ray is entering or leaving
-                                // the medium.
-                                if vec3_dot_nrmf(&wo, &isect.common.n) > 0.0 as Float {
-                                    eta_scale *= eta * eta;
-                                } else {
-                                    eta_scale *= 1.0 as Float / (eta * eta);
-                                }
-                            }
-                            ray = isect.spawn_ray(&wi);
-                            // account for attenuated subsurface scattering, if applicable
-                            if let Some(ref bssrdf) = isect.bssrdf {
-                                if (sampled_type & BxdfType::BsdfTransmission as u8) != 0_u8 {
-                                    // importance sample the BSSRDF
-                                    let s2: Point2f = sampler.get_2d();
-                                    let s1: Float = sampler.get_1d();
-                                    let (s, pi_opt) = bssrdf.sample_s(
-                                        // the next three (extra) parameters are used for SeparableBssrdfAdapter
-                                        bssrdf.clone(),
-                                        bssrdf.mode,
-                                        bssrdf.eta,
-                                        // done
-                                        scene,
-                                        s1,
-                                        s2,
-                                        &mut pdf,
-                                    );
-                                    if s.is_black() || pdf == 0.0 as Float {
-                                        break;
-                                    }
-                                    assert!(!(beta.y().is_infinite()));
-                                    beta *= s / pdf;
-                                    if let Some(pi) = pi_opt {
-                                        // account for the direct subsurface scattering component
-                                        let distrib: Arc<Distribution1D> =
-                                            light_distribution.lookup(&pi.common.p);
-                                        l += beta
-                                            * uniform_sample_one_light(
-                                                &pi,
-                                                scene,
-                                                sampler,
-                                                true,
-                                                Some(&distrib),
-                                            );
-                                        // account for the indirect subsurface scattering component
-                                        let mut wi: Vector3f = Vector3f::default();
-                                        let mut pdf: Float = 0.0 as Float;
-                                        let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
-                                        let mut sampled_type: u8 = u8::max_value(); // != 0
-                                        if let Some(ref bsdf) = pi.bsdf {
-                                            let f: Spectrum = bsdf.sample_f(
-                                                &pi.common.wo,
-                                                &mut wi,
-                                                &sampler.get_2d(),
-                                                &mut pdf,
-                                                bsdf_flags,
-                                                &mut sampled_type,
-                                            );
-                                            if f.is_black() || pdf == 0.0 as Float {
-                                                break;
-                                            }
-                                            beta *= f * vec3_abs_dot_nrmf(&wi, &pi.shading.n) / pdf;
-                                            assert!(!(beta.y().is_infinite()));
-                                            specular_bounce = (sampled_type
-                                                & BxdfType::BsdfSpecular as u8)
-                                                != 0_u8;
-                                            ray = pi.spawn_ray(&wi);
-                                        } else {
-                                            panic!("no pi.bsdf found");
-                                        }
-                                    } else {
-                                        panic!("bssrdf.sample_s() did return (s, None)");
-                                    }
-                                }
-                            }
+                            prev_ctx = MediumInteraction {
+                                common: pi.common.clone(),
+                                ..Default::default()
+                            };
+                            ray = pi.spawn_ray(&wi);
                         } else {
-                            println!("TODO: if let Some(ref bsdf) = isect.bsdf failed");
+                            panic!("no pi.bsdf found");
                         }
+                    } else {
+                        panic!("bssrdf.sample_s() did return (s, None)");
                     }
                 }
-                // Possibly terminate the path with Russian roulette.
-                // Factor out radiance scaling due to refraction in rr_beta.
-                let rr_beta: Spectrum = beta * eta_scale;
-                if rr_beta.max_component_value() < self.rr_threshold && bounces > 3 {
-                    let q: Float =
-                        (0.05 as Float).max(1.0 as Float - rr_beta.max_component_value());
-                    if sampler.get_1d() < q {
-                        break;
-                    }
-                    beta /= 1.0 as Float - q;
-                    assert!(!(beta.y().is_infinite()));
-                }
+            }
+            if !self.survives_russian_roulette(&mut beta, &r_u, eta_scale, bounces, sampler) {
+                break;
+            }
+        }
+        if let Some(ref guide) = self.guide {
+            if recording {
+                guide.record(&guide_vertices);
+            }
+        }
+        l
+    }
+    /// Possibly terminate the path with Russian roulette. Radiance
+    /// scaling due to refraction is factored out.
+    fn survives_russian_roulette(
+        &self,
+        beta: &mut Spectrum,
+        r_u: &Spectrum,
+        eta_scale: Float,
+        bounces: u32,
+        sampler: &mut Sampler,
+    ) -> bool {
+        let rr_beta: Spectrum = *beta * eta_scale / r_u.average();
+        if rr_beta.max_component_value() < self.rr_threshold && bounces > 3 {
+            let q: Float = (0.05 as Float).max(1.0 as Float - rr_beta.max_component_value());
+            if sampler.get_1d() < q {
+                return false;
+            }
+            *beta /= 1.0 as Float - q;
+            assert!(!(beta.y().is_infinite()));
+        }
+        true
+    }
+    /// Samples a light source (according to the light distribution)
+    /// for the scattering vertex *it* and estimates the contribution
+    /// of the light sample. The transmittance of the shadow ray is
+    /// estimated with ratio tracking, which is MIS weighted against
+    /// the unidirectional sampling of the same path (with the BSDF,
+    /// the path guide, or the phase function, and delta tracking).
+    /// *r_p* are the rescaled path densities up to *it*.
+    fn sample_ld(
+        &self,
+        it: &dyn Interaction,
+        guide: Option<&PathGuide>,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        r_p: &Spectrum,
+        channel: usize,
+    ) -> Spectrum {
+        // choose a light source for the direct lighting calculation
+        let light_distribution: &LightDistribution = match self.light_distribution {
+            Some(ref light_distribution) => light_distribution,
+            None => return Spectrum::default(),
+        };
+        let u_light_choice: Float = sampler.get_1d();
+        let u_light: Point2f = sampler.get_2d();
+        let mut light_choice_pdf: Float = 0.0 as Float;
+        let light_num: usize = light_distribution
+            .lookup(&it.get_common().p)
+            .sample_discrete(u_light_choice, Some(&mut light_choice_pdf));
+        if light_choice_pdf == 0.0 as Float {
+            return Spectrum::default();
+        }
+        let light: &Light = &scene.lights[light_num];
+        // sample a point on the light source
+        let mut light_intr: InteractionCommon = InteractionCommon::default();
+        let mut visibility: VisibilityTester = VisibilityTester::default();
+        let mut wi: Vector3f = Vector3f::default();
+        let mut light_pdf: Float = 0.0 as Float;
+        let li: Spectrum = light.sample_li(
+            it.get_common(),
+            &mut light_intr,
+            u_light,
+            &mut wi,
+            &mut light_pdf,
+            &mut visibility,
+        );
+        if light_pdf == 0.0 as Float || li.is_black() {
+            return Spectrum::default();
+        }
+        let p_l: Float = light_choice_pdf * light_pdf;
+        // evaluate BSDF or phase function for light sample direction
+        let wo: &Vector3f = it.get_wo();
+        let (f, scatter_pdf) =
+            if let (Some(bsdf), Some(shading_n)) = (it.get_bsdf(), it.get_shading_n()) {
+                let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
+                let f: Spectrum = bsdf.f(wo, &wi, bsdf_flags) * vec3_abs_dot_nrmf(&wi, shading_n);
+                let pdf: Float = if let Some(guide) = guide {
+                    guide.pdf(bsdf, &it.get_common().p, wo, &wi)
+                } else {
+                    bsdf.pdf(wo, &wi, bsdf_flags)
+                };
+                (f, pdf)
+            } else if let Some(phase) = it.get_phase() {
+                let p: Float = phase.p(wo, &wi);
+                (Spectrum::new(p), p)
             } else {
-                // sample the participating medium, if present
-                if let Some(ref medium) = ray.medium {
-                    let (spectrum, option) = medium.sample(&ray, sampler);
-                    beta *= spectrum;
-                    if let Some(mi) = option {
-                        mi_opt = Some(mi);
+                return Spectrum::default();
+            };
+        if f.is_black() {
+            return Spectrum::default();
+        }
+        // trace ray through media to estimate transmittance
+        let p_light: &InteractionCommon = visibility.p1.unwrap();
+        let mut ray: Ray = it.get_common().spawn_ray_to(p_light);
+        let mut t_ray: Spectrum = Spectrum::new(1.0 as Float);
+        let mut r_l: Spectrum = Spectrum::new(1.0 as Float);
+        let mut r_u: Spectrum = Spectrum::new(1.0 as Float);
+        loop {
+            let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+            let hit: bool = scene.intersect(&ray, &mut isect);
+            // handle opaque surface along ray's path
+            if hit {
+                if let Some(primitive_raw) = isect.primitive {
+                    let primitive = unsafe { &*primitive_raw };
+                    if primitive.get_material().is_some() {
+                        return Spectrum::default();
                     }
                 }
-                if beta.is_black() {
-                    break;
-                }
-                // handle an interaction with a medium
-                if let Some(mi) = mi_opt {
-                    // terminate path if ray escaped or _maxDepth_ was reached
-                    if bounces >= self.max_depth {
-                        break;
-                    }
-                    let mi_p = mi.common.p;
-                    // if mi.is_valid() {...}
-                    if let Some(phase) = mi.clone().phase {
-                        // TODO: ++volumeInteractions;
-                        // handle scattering at point in medium for volumetric path tracer
-                        if let Some(ref light_distribution) = self.light_distribution {
-                            let distrib: Arc<Distribution1D> = light_distribution.lookup(&mi_p);
-                            l += beta
-                                * uniform_sample_one_light(
-                                    &mi as &dyn Interaction,
-                                    scene,
-                                    sampler,
-                                    true,
-                                    Some(&distrib),
-                                );
-                            let mut wi: Vector3f = Vector3f::default();
-                            phase.sample_p(&(-ray.d), &mut wi, sampler.get_2d());
-                            ray = mi.spawn_ray(&wi);
-                            specular_bounce = false;
+            }
+            // update transmittance for current ray segment
+            if let Some(ref medium) = ray.medium {
+                let t_maj: Spectrum = medium.sample_t_maj(
+                    &ray,
+                    ray.t_max.get(),
+                    channel,
+                    sampler,
+                    |_p, mp, sigma_maj, t_maj, sampler| {
+                        // ratio tracking with the null-scattering
+                        // probabilities of the hero channel
+                        let sigma_n: Spectrum = (*sigma_maj - mp.sigma_a - mp.sigma_s)
+                            .clamp(0.0 as Float, Float::INFINITY);
+                        let pdf: Float = t_maj.c[channel] * sigma_maj.c[channel];
+                        t_ray *= *t_maj * sigma_n / pdf;
+                        r_l *= *t_maj * *sigma_maj / pdf;
+                        r_u *= *t_maj * sigma_n / pdf;
+                        // when transmittance gets low, start applying
+                        // Russian roulette to terminate sampling
+                        let tr: Spectrum = t_ray / (r_l + r_u).average();
+                        if tr.max_component_value() < 0.05 as Float {
+                            if sampler.get_1d() < 0.75 as Float {
+                                t_ray = Spectrum::default();
+                            } else {
+                                t_ray /= 0.25 as Float;
+                            }
                         }
-                    }
+                        !t_ray.is_black()
+                    },
+                );
+                if t_maj.c[channel] == 0.0 as Float {
+                    return Spectrum::default();
                 }
-                // add emitted light from the environment
-                if bounces == 0 || specular_bounce {
-                    for light in &scene.infinite_lights {
-                        l += beta * light.le(&mut ray);
-                    }
-                }
-                // terminate path if ray escaped
+                t_ray *= t_maj / t_maj.c[channel];
+                r_l *= t_maj / t_maj.c[channel];
+                r_u *= t_maj / t_maj.c[channel];
+            }
+            // generate next ray segment or return final transmittance
+            if t_ray.is_black() {
+                return Spectrum::default();
+            }
+            if !hit {
                 break;
             }
-            bounces += 1_u32;
+            ray = isect.common.spawn_ray_to(p_light);
+        }
+        // return path contribution function estimate for direct lighting
+        r_l *= *r_p * p_l;
+        r_u *= *r_p * scatter_pdf;
+        if is_delta_light(light.get_flags()) {
+            f * t_ray * li / r_l.average()
+        } else {
+            f * t_ray * li / (r_l + r_u).average()
         }
-        l
+    }
+    /// The density with which [sample_ld()](#method.sample_ld) samples
+    /// the (area or infinite) *light* in direction *wi* from the
+    /// previous path vertex.
+    fn light_pdf(&self, prev_ctx: &MediumInteraction, light: &Light, wi: &Vector3f) -> Float {
+        if let Some(ref light_distribution) = self.light_distribution {
+            if let Some(i) = self.light_to_index.get(&(light as *const Light as usize)) {
+                return light_distribution
+                    .lookup(&prev_ctx.common.p)
+                    .discrete_pdf(*i)
+                    * light.pdf_li(prev_ctx, wi);
+            }
+        }
+        0.0 as Float
     }
     pub fn get_camera(&self) -> Arc<Camera> {
         self.camera.clone()
@@ -355,4 +649,10 @@ impl VolPathIntegrator {
     pub fn get_pixel_bounds(&self) -> Bounds2i {
         self.pixel_bounds
     }
+    pub fn get_path_guide(&self) -> Option<&PathGuide> {
+        self.guide.as_ref()
+    }
+    pub fn get_path_guide_mut(&mut self) -> Option<&mut PathGuide> {
+        self.guide.as_mut()
+    }
 }
@@ -2,6 +2,7 @@
 use std::sync::Arc;
 // pbrt
 use crate::core::camera::Camera;
+use crate::core::denoiser::FirstHitFeatures;
 use crate::core::geometry::{vec3_abs_dot_nrmf, vec3_dot_nrmf};
 use crate::core::geometry::{Bounds2i, Normal3f, Ray, RayDifferential, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
@@ -48,10 +49,27 @@ impl WhittedIntegrator {
         // arena: &mut Arena,
         depth: i32,
     ) -> Spectrum {
-        let mut l: Spectrum = Spectrum::default();
         // find closest ray intersection or return background radiance
         let mut isect: SurfaceInteraction = SurfaceInteraction::default();
-        if scene.intersect(ray, &mut isect) {
+        let hit: bool = scene.intersect(ray, &mut isect);
+        self.li_with_isect(ray, hit, isect, scene, sampler, depth, None)
+    }
+    /// Same as [li()](#method.li), but the closest intersection of
+    /// the camera ray is already known (e.g. from tracing a ray
+    /// packet). If _features_ is given, it receives the albedo and
+    /// shading normal of the first surface hit (for the denoiser).
+    pub fn li_with_isect(
+        &self,
+        ray: &mut Ray,
+        hit: bool,
+        mut isect: SurfaceInteraction,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        depth: i32,
+        features: Option<&mut FirstHitFeatures>,
+    ) -> Spectrum {
+        let mut l: Spectrum = Spectrum::default();
+        if hit {
             // compute emitted and reflected light at ray intersection point
 
             // initialize common variables for Whitted integrator
@@ -64,7 +82,13 @@ impl WhittedIntegrator {
             // if (!isect.bsdf)
             if let Some(ref _bsdf) = isect.bsdf {
             } else {
-                return self.li(&mut isect.spawn_ray(&ray.d), scene, sampler, depth);
+                let mut ray: Ray = isect.spawn_ray(&ray.d);
+                let mut next: SurfaceInteraction = SurfaceInteraction::default();
+                let hit: bool = scene.intersect(&ray, &mut next);
+                return self.li_with_isect(&mut ray, hit, next, scene, sampler, depth, features);
+            }
+            if let Some(features) = features {
+                *features = FirstHitFeatures::new(&isect);
             }
             // compute emitted light if ray hit an area light source
             l += isect.le(&wo);
@@ -5,7 +5,7 @@ use std::sync::Arc;
 use crate::core::geometry::{nrm_abs_dot_vec3f, nrm_dot_vec3f, vec3_coordinate_system};
 use crate::core::geometry::{Normal3f, Point2f, Ray, Vector3f, XYEnum};
 use crate::core::interaction::{Interaction, InteractionCommon};
-use crate::core::light::{LightFlags, VisibilityTester};
+use crate::core::light::{LightBounds, LightFlags, VisibilityTester};
 use crate::core::medium::{Medium, MediumInterface};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::rng::FLOAT_ONE_MINUS_EPSILON;
@@ -91,6 +91,42 @@ impl DiffuseAreaLight {
         };
         self.l_emit * factor * self.area * PI
     }
+    pub fn bounds(&self) -> LightBounds {
+        let phi: Float = self.power().y();
+        // planar shapes emit around their (sampled) surface normal
+        let planar: bool = match &*self.shape {
+            Shape::Dsk(_) => true,
+            Shape::Trngl(triangle) => !triangle.has_vertex_motion(),
+            _ => false,
+        };
+        if !planar {
+            return LightBounds::new_omnidirectional(self.shape.world_bound(), phi);
+        }
+        let mut pdf: Float = 0.0 as Float;
+        let n: Normal3f = self
+            .shape
+            .sample(
+                Point2f {
+                    x: 0.25 as Float,
+                    y: 0.25 as Float,
+                },
+                &mut pdf,
+            )
+            .n;
+        LightBounds {
+            bounds: self.shape.world_bound(),
+            phi,
+            w: Vector3f {
+                x: n.x,
+                y: n.y,
+                z: n.z,
+            }
+            .normalize(),
+            cos_theta_o: 1.0 as Float,
+            cos_theta_e: (PI / 2.0 as Float).cos(),
+            two_sided: self.two_sided,
+        }
+    }
     pub fn preprocess(&self, _scene: &Scene) {
         // TODO?
     }
@@ -147,7 +183,13 @@ impl DiffuseAreaLight {
         self.l(&ic, &w)
     }
     pub fn pdf_le(&self, ray: &Ray, n: &Normal3f, pdf_pos: &mut Float, pdf_dir: &mut Float) {
-        *pdf_pos = self.shape.pdf(&InteractionCommon::default());
+        let it: InteractionCommon = InteractionCommon {
+            p: ray.o,
+            time: ray.time,
+            n: *n,
+            ..Default::default()
+        };
+        *pdf_pos = self.shape.pdf(&it);
         if self.two_sided {
             *pdf_dir = 0.5 as Float * cosine_hemisphere_pdf(nrm_abs_dot_vec3f(&n, &ray.d));
         } else {
@@ -4,9 +4,9 @@ use std::f32::consts::PI;
 use std::sync::Arc;
 // pbrt
 use crate::core::geometry::pnt3_distance_squaredf;
-use crate::core::geometry::{Normal3f, Point2f, Point3f, Ray, Vector3f};
+use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon};
-use crate::core::light::{LightFlags, VisibilityTester};
+use crate::core::light::{LightBounds, LightFlags, VisibilityTester};
 use crate::core::medium::{Medium, MediumInterface};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::sampling::{uniform_sample_sphere, uniform_sphere_pdf};
@@ -70,6 +70,15 @@ impl PointLight {
     pub fn power(&self) -> Spectrum {
         self.i * (4.0 as Float * PI)
     }
+    pub fn bounds(&self) -> LightBounds {
+        LightBounds::new_omnidirectional(
+            Bounds3f {
+                p_min: self.p_light,
+                p_max: self.p_light,
+            },
+            self.power().y(),
+        )
+    }
     pub fn preprocess(&self, _scene: &Scene) {}
     /// Default implementation returns no emitted radiance for a ray
     /// that escapes the scene bounds.
@@ -4,9 +4,9 @@ use std::f32::consts::PI;
 use std::sync::Arc;
 // pbrt
 use crate::core::geometry::pnt3_distance_squaredf;
-use crate::core::geometry::{Normal3f, Point2f, Point3f, Ray, Vector3f};
+use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon};
-use crate::core::light::{LightFlags, VisibilityTester};
+use crate::core::light::{LightBounds, LightFlags, VisibilityTester};
 use crate::core::medium::{Medium, MediumInterface};
 use crate::core::pbrt::radians;
 use crate::core::pbrt::{Float, Spectrum};
@@ -110,6 +110,30 @@ impl SpotLight {
             * PI
             * (1.0 as Float - 0.5 as Float * (self.cos_falloff_start + self.cos_total_width))
     }
+    pub fn bounds(&self) -> LightBounds {
+        let w: Vector3f = self
+            .light_to_world
+            .transform_vector(&Vector3f {
+                x: 0.0 as Float,
+                y: 0.0 as Float,
+                z: 1.0 as Float,
+            })
+            .normalize();
+        // full intensity up to the falloff start, then up to the
+        // total width with decreasing intensity
+        let theta_e: Float = self.cos_total_width.acos() - self.cos_falloff_start.acos();
+        LightBounds {
+            bounds: Bounds3f {
+                p_min: self.p_light,
+                p_max: self.p_light,
+            },
+            phi: self.power().y(),
+            w,
+            cos_theta_o: self.cos_falloff_start,
+            cos_theta_e: theta_e.max(0.0 as Float).cos(),
+            two_sided: false,
+        }
+    }
     pub fn preprocess(&self, _scene: &Scene) {}
     /// Default implementation returns no emitted radiance for a ray
     /// that escapes the scene bounds.
@@ -36,6 +36,7 @@ pub struct DisneyMaterial {
     flatness: Arc<dyn Texture<Float> + Send + Sync>,
     diff_trans: Arc<dyn Texture<Float> + Send + Sync>,
     bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>>,
     thin: bool,
 }
 
@@ -57,6 +58,7 @@ impl DisneyMaterial {
         let flatness = mp.get_float_texture("flatness", 0.0);
         let diff_trans = mp.get_float_texture("difftrans", 1.0);
         let bump_map = mp.get_float_texture_or_null("bumpmap");
+        let normal_map = mp.get_spectrum_texture_or_null("normalmap");
 
         Arc::new(Material::Disney(Box::new(DisneyMaterial {
             color,
@@ -74,6 +76,7 @@ impl DisneyMaterial {
             flatness,
             diff_trans,
             bump_map,
+            normal_map,
             thin,
         })))
     }
@@ -95,6 +98,9 @@ impl DisneyMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         // diffuse
         let c = self.color.evaluate(si).clamp(0.0, f32::INFINITY);
         let metallic_weight = self.metallic.evaluate(si);
@@ -14,27 +14,33 @@ use crate::core::texture::Texture;
 pub struct FourierMaterial {
     pub bsdf_table: Arc<FourierBSDFTable>,
     pub bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+    pub normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
 }
 
 impl FourierMaterial {
     pub fn new(
         bsdf_table: Arc<FourierBSDFTable>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+        normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
     ) -> Self {
         FourierMaterial {
             bump_map,
+            normal_map,
             bsdf_table,
         }
     }
     pub fn create(mp: &mut TextureParams, bsdf_state: &mut BsdfState) -> Arc<Material> {
         let bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>> =
             mp.get_float_texture_or_null("bumpmap");
+        let normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>> =
+            mp.get_spectrum_texture_or_null("normalmap");
         let bsdffile: String = mp.find_filename("bsdffile", String::new());
         if let Some(bsdf_table) = bsdf_state.loaded_bsdfs.get(&bsdffile) {
             // use the BSDF table found
             Arc::new(Material::Fourier(Box::new(FourierMaterial::new(
                 bsdf_table.clone(),
                 bump_map,
+                normal_map,
             ))))
         } else {
             // read BSDF table from file
@@ -49,6 +55,7 @@ impl FourierMaterial {
             Arc::new(Material::Fourier(Box::new(FourierMaterial::new(
                 bsdf_table_arc,
                 bump_map,
+                normal_map,
             ))))
         }
     }
@@ -71,6 +78,9 @@ impl FourierMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         si.bsdf = Some(Bsdf::new(si, 1.0));
         if let Some(bsdf) = &mut si.bsdf {
             if use_scale {
@@ -23,6 +23,7 @@ pub struct GlassMaterial {
     pub v_roughness: Arc<dyn Texture<Float> + Sync + Send>, // default: 0.0
     pub index: Arc<dyn Texture<Float> + Sync + Send>,
     pub bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    pub normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>>,
     pub remap_roughness: bool,
 }
 
@@ -34,6 +35,7 @@ impl GlassMaterial {
         v_roughness: Arc<dyn Texture<Float> + Sync + Send>,
         index: Arc<dyn Texture<Float> + Send + Sync>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+        normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
         remap_roughness: bool,
     ) -> Self {
         GlassMaterial {
@@ -43,6 +45,7 @@ impl GlassMaterial {
             v_roughness,
             index,
             bump_map,
+            normal_map,
             remap_roughness,
         }
     }
@@ -52,6 +55,7 @@ impl GlassMaterial {
         let roughu = mp.get_float_texture("uroughness", 0.0 as Float);
         let roughv = mp.get_float_texture("vroughness", 0.0 as Float);
         let bump_map = mp.get_float_texture_or_null("bumpmap");
+        let normal_map = mp.get_spectrum_texture_or_null("normalmap");
         let remap_roughness: bool = mp.find_bool("remaproughness", true);
         let eta_option: Option<Arc<dyn Texture<Float> + Send + Sync>> =
             mp.get_float_texture_or_null("eta");
@@ -63,6 +67,7 @@ impl GlassMaterial {
                 roughv,
                 eta.clone(),
                 bump_map,
+                normal_map,
                 remap_roughness,
             ))))
         } else {
@@ -75,6 +80,7 @@ impl GlassMaterial {
                 roughv,
                 eta,
                 bump_map,
+                normal_map,
                 remap_roughness,
             ))))
         }
@@ -98,6 +104,9 @@ impl GlassMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         let mut urough: Float = self.u_roughness.evaluate(si);
         let mut vrough: Float = self.v_roughness.evaluate(si);
         let r: Spectrum = self
@@ -16,6 +16,7 @@ pub struct MatteMaterial {
     pub kd: Arc<dyn Texture<Spectrum> + Sync + Send>, // default: 0.5
     pub sigma: Arc<dyn Texture<Float> + Sync + Send>, // default: 0.0
     pub bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    pub normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>>,
 }
 
 impl MatteMaterial {
@@ -23,11 +24,13 @@ impl MatteMaterial {
         kd: Arc<dyn Texture<Spectrum> + Send + Sync>,
         sigma: Arc<dyn Texture<Float> + Sync + Send>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+        normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
     ) -> Self {
         MatteMaterial {
             kd,
             sigma,
             bump_map,
+            normal_map,
         }
     }
     pub fn create(mp: &mut TextureParams) -> Arc<Material> {
@@ -35,8 +38,9 @@ impl MatteMaterial {
             mp.get_spectrum_texture("Kd", Spectrum::new(0.5));
         let sigma: Arc<dyn Texture<Float> + Sync + Send> = mp.get_float_texture("sigma", 0.0);
         let bump_map = mp.get_float_texture_or_null("bumpmap");
+        let normal_map = mp.get_spectrum_texture_or_null("normalmap");
         Arc::new(Material::Matte(Box::new(MatteMaterial::new(
-            kd, sigma, bump_map,
+            kd, sigma, bump_map, normal_map,
         ))))
     }
     // Material
@@ -58,6 +62,9 @@ impl MatteMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         let r: Spectrum = self
             .kd
             .evaluate(si)
@@ -1,6 +1,7 @@
 //std
 use std::sync::Arc;
 // pbrt
+use crate::core::colorspace::RGBColorSpace;
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::material::{Material, TransportMode};
 use crate::core::microfacet::{MicrofacetDistribution, TrowbridgeReitzDistribution};
@@ -91,6 +92,7 @@ pub struct MetalMaterial {
     pub u_roughness: Option<Arc<dyn Texture<Float> + Sync + Send>>,
     pub v_roughness: Option<Arc<dyn Texture<Float> + Sync + Send>>,
     pub bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    pub normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>>,
     pub remap_roughness: bool,
 }
 
@@ -102,6 +104,7 @@ impl MetalMaterial {
         u_roughness: Option<Arc<dyn Texture<Float> + Sync + Send>>,
         v_roughness: Option<Arc<dyn Texture<Float> + Sync + Send>>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+        normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
         remap_roughness: bool,
     ) -> Self {
         MetalMaterial {
@@ -111,16 +114,17 @@ impl MetalMaterial {
             u_roughness,
             v_roughness,
             bump_map,
+            normal_map,
             remap_roughness,
         }
     }
-    pub fn create(mp: &mut TextureParams) -> Arc<Material> {
+    pub fn create(mp: &mut TextureParams, color_space: RGBColorSpace) -> Arc<Material> {
         let copper_n: Spectrum =
-            Spectrum::from_sampled(&COPPER_WAVELENGTHS, &COPPER_N, COPPER_SAMPLES as i32);
+            color_space.from_sampled(&COPPER_WAVELENGTHS, &COPPER_N, COPPER_SAMPLES as i32);
         let eta: Arc<dyn Texture<Spectrum> + Send + Sync> =
             mp.get_spectrum_texture("eta", copper_n);
         let copper_k: Spectrum =
-            Spectrum::from_sampled(&COPPER_WAVELENGTHS, &COPPER_K, COPPER_SAMPLES as i32);
+            color_space.from_sampled(&COPPER_WAVELENGTHS, &COPPER_K, COPPER_SAMPLES as i32);
         let k: Arc<dyn Texture<Spectrum> + Send + Sync> = mp.get_spectrum_texture("k", copper_k);
         let roughness: Arc<dyn Texture<Float> + Send + Sync> =
             mp.get_float_texture("roughness", 0.01 as Float);
@@ -129,6 +133,7 @@ impl MetalMaterial {
         let v_roughness: Option<Arc<dyn Texture<Float> + Send + Sync>> =
             mp.get_float_texture_or_null("vroughness");
         let bump_map = mp.get_float_texture_or_null("bumpmap");
+        let normal_map = mp.get_spectrum_texture_or_null("normalmap");
         let remap_roughness: bool = mp.find_bool("remaproughness", true);
         Arc::new(Material::Metal(Box::new(MetalMaterial::new(
             eta,
@@ -137,6 +142,7 @@ impl MetalMaterial {
             u_roughness,
             v_roughness,
             bump_map,
+            normal_map,
             remap_roughness,
         ))))
     }
@@ -159,6 +165,9 @@ impl MetalMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         let mut u_rough: Float;
         if let Some(ref u_roughness) = self.u_roughness {
             u_rough = u_roughness.evaluate(si);
@@ -14,20 +14,27 @@ use crate::core::texture::Texture;
 pub struct MirrorMaterial {
     pub kr: Arc<dyn Texture<Spectrum> + Sync + Send>, // default: 0.9
     pub bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    pub normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>>,
 }
 
 impl MirrorMaterial {
     pub fn new(
         kr: Arc<dyn Texture<Spectrum> + Send + Sync>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+        normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
     ) -> Self {
-        MirrorMaterial { kr, bump_map }
+        MirrorMaterial {
+            kr,
+            bump_map,
+            normal_map,
+        }
     }
     pub fn create(mp: &mut TextureParams) -> Arc<Material> {
         let kr = mp.get_spectrum_texture("Kr", Spectrum::new(0.9 as Float));
         let bump_map = mp.get_float_texture_or_null("bumpmap");
+        let normal_map = mp.get_spectrum_texture_or_null("normalmap");
         Arc::new(Material::Mirror(Box::new(MirrorMaterial::new(
-            kr, bump_map,
+            kr, bump_map, normal_map,
         ))))
     }
     // Material
@@ -49,6 +56,9 @@ impl MirrorMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         let r: Spectrum = self
             .kr
             .evaluate(si)
@@ -20,6 +20,7 @@ pub struct PlasticMaterial {
     pub ks: Arc<dyn Texture<Spectrum> + Sync + Send>, // default: 0.25
     pub roughness: Arc<dyn Texture<Float> + Sync + Send>, // default: 0.1
     pub bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    pub normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>>,
     pub remap_roughness: bool,
 }
 
@@ -29,6 +30,7 @@ impl PlasticMaterial {
         ks: Arc<dyn Texture<Spectrum> + Send + Sync>,
         roughness: Arc<dyn Texture<Float> + Sync + Send>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+        normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
         remap_roughness: bool,
     ) -> Self {
         PlasticMaterial {
@@ -36,6 +38,7 @@ impl PlasticMaterial {
             ks,
             roughness,
             bump_map,
+            normal_map,
             remap_roughness,
         }
     }
@@ -44,12 +47,14 @@ impl PlasticMaterial {
         let ks = mp.get_spectrum_texture("Ks", Spectrum::new(0.25 as Float));
         let roughness = mp.get_float_texture("roughness", 0.1 as Float);
         let bump_map = mp.get_float_texture_or_null("bumpmap");
+        let normal_map = mp.get_spectrum_texture_or_null("normalmap");
         let remap_roughness: bool = mp.find_bool("remaproughness", true);
         Arc::new(Material::Plastic(Box::new(PlasticMaterial::new(
             kd,
             ks,
             roughness,
             bump_map,
+            normal_map,
             remap_roughness,
         ))))
     }
@@ -72,6 +77,9 @@ impl PlasticMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         let kd: Spectrum = self
             .kd
             .evaluate(si)
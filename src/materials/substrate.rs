@@ -17,6 +17,7 @@ pub struct SubstrateMaterial {
     pub nu: Arc<dyn Texture<Float> + Sync + Send>,    // default: 0.1
     pub nv: Arc<dyn Texture<Float> + Sync + Send>,    // default: 0.1
     pub bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    pub normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>>,
     pub remap_roughness: bool,
 }
 
@@ -27,6 +28,7 @@ impl SubstrateMaterial {
         nu: Arc<dyn Texture<Float> + Sync + Send>,
         nv: Arc<dyn Texture<Float> + Sync + Send>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+        normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
         remap_roughness: bool,
     ) -> Self {
         SubstrateMaterial {
@@ -35,6 +37,7 @@ impl SubstrateMaterial {
             nu,
             nv,
             bump_map,
+            normal_map,
             remap_roughness,
         }
     }
@@ -48,6 +51,7 @@ impl SubstrateMaterial {
         let vroughness: Arc<dyn Texture<Float> + Sync + Send> =
             mp.get_float_texture("vroughness", 0.1);
         let bump_map = mp.get_float_texture_or_null("bumpmap");
+        let normal_map = mp.get_spectrum_texture_or_null("normalmap");
         let remap_roughness: bool = mp.find_bool("remaproughness", true);
         Arc::new(Material::Substrate(Box::new(SubstrateMaterial::new(
             kd,
@@ -55,6 +59,7 @@ impl SubstrateMaterial {
             uroughness,
             vroughness,
             bump_map,
+            normal_map,
             remap_roughness,
         ))))
     }
@@ -77,6 +82,9 @@ impl SubstrateMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         let d: Spectrum = self
             .kd
             .evaluate(si)
@@ -29,6 +29,7 @@ pub struct SubsurfaceMaterial {
     pub u_roughness: Arc<dyn Texture<Float> + Sync + Send>, // default: 0.0
     pub v_roughness: Arc<dyn Texture<Float> + Sync + Send>, // default: 0.0
     pub bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    pub normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>>,
     pub eta: Float,            // default: 1.33
     pub remap_roughness: bool, // default: true
     pub table: Arc<BssrdfTable>,
@@ -46,6 +47,7 @@ impl SubsurfaceMaterial {
         u_roughness: Arc<dyn Texture<Float> + Sync + Send>,
         v_roughness: Arc<dyn Texture<Float> + Sync + Send>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+        normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
         remap_roughness: bool,
     ) -> Self {
         let mut table: BssrdfTable = BssrdfTable::new(100, 64);
@@ -59,6 +61,7 @@ impl SubsurfaceMaterial {
             u_roughness,
             v_roughness,
             bump_map,
+            normal_map,
             eta,
             remap_roughness,
             table: Arc::new(table),
@@ -99,6 +102,7 @@ impl SubsurfaceMaterial {
         let roughv: Arc<dyn Texture<Float> + Sync + Send> =
             mp.get_float_texture("vroughness", 0.0 as Float);
         let bump_map = mp.get_float_texture_or_null("bumpmap");
+        let normal_map = mp.get_spectrum_texture_or_null("normalmap");
         let remap_roughness: bool = mp.find_bool("remaproughness", true);
         // let start = PreciseTime::now();
         //let tmp =
@@ -113,6 +117,7 @@ impl SubsurfaceMaterial {
             roughu,
             roughv,
             bump_map,
+            normal_map,
             remap_roughness,
         ))))
         //;
@@ -142,6 +147,9 @@ impl SubsurfaceMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         // initialize BSDF for _SubsurfaceMaterial_
         let r: Spectrum = self
             .kr
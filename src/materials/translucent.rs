@@ -19,6 +19,7 @@ pub struct TranslucentMaterial {
     pub reflect: Arc<dyn Texture<Spectrum> + Sync + Send>, // default: 0.5
     pub transmit: Arc<dyn Texture<Spectrum> + Sync + Send>, // default: 0.5
     pub bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    pub normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>>,
     pub remap_roughness: bool, // default: true
 }
 
@@ -30,6 +31,7 @@ impl TranslucentMaterial {
         reflect: Arc<dyn Texture<Spectrum> + Send + Sync>,
         transmit: Arc<dyn Texture<Spectrum> + Send + Sync>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+        normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
         remap_roughness: bool,
     ) -> Self {
         TranslucentMaterial {
@@ -39,6 +41,7 @@ impl TranslucentMaterial {
             reflect,
             transmit,
             bump_map,
+            normal_map,
             remap_roughness,
         }
     }
@@ -49,6 +52,7 @@ impl TranslucentMaterial {
         let transmit = mp.get_spectrum_texture("transmit", Spectrum::new(0.5 as Float));
         let roughness = mp.get_float_texture("roughness", 0.1 as Float);
         let bump_map = mp.get_float_texture_or_null("bumpmap");
+        let normal_map = mp.get_spectrum_texture_or_null("normalmap");
         let remap_roughness: bool = mp.find_bool("remaproughness", true);
         Arc::new(Material::Translucent(Box::new(TranslucentMaterial::new(
             kd,
@@ -57,6 +61,7 @@ impl TranslucentMaterial {
             reflect,
             transmit,
             bump_map,
+            normal_map,
             remap_roughness,
         ))))
     }
@@ -79,6 +84,9 @@ impl TranslucentMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         let eta: Float = 1.5;
         let r: Spectrum = self
             .reflect
@@ -25,6 +25,7 @@ pub struct UberMaterial {
     pub v_roughness: Option<Arc<dyn Texture<Float> + Sync + Send>>,
     pub eta: Arc<dyn Texture<Float> + Sync + Send>, // default: 1.5
     pub bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+    pub normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
     pub remap_roughness: bool,
 }
 
@@ -40,6 +41,7 @@ impl UberMaterial {
         opacity: Arc<dyn Texture<Spectrum> + Sync + Send>,
         eta: Arc<dyn Texture<Float> + Send + Sync>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
+        normal_map: Option<Arc<dyn Texture<Spectrum> + Sync + Send>>,
         remap_roughness: bool,
     ) -> Self {
         UberMaterial {
@@ -53,6 +55,7 @@ impl UberMaterial {
             v_roughness,
             eta,
             bump_map,
+            normal_map,
             remap_roughness,
         }
     }
@@ -75,6 +78,8 @@ impl UberMaterial {
             mp.get_spectrum_texture("opacity", Spectrum::new(1.0));
         let bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>> =
             mp.get_float_texture_or_null("bumpmap");
+        let normal_map: Option<Arc<dyn Texture<Spectrum> + Send + Sync>> =
+            mp.get_spectrum_texture_or_null("normalmap");
         let remap_roughness: bool = mp.find_bool("remaproughness", true);
         let eta_option: Option<Arc<dyn Texture<Float> + Send + Sync>> =
             mp.get_float_texture_or_null("eta");
@@ -90,6 +95,7 @@ impl UberMaterial {
                 opacity,
                 eta.clone(),
                 bump_map,
+                normal_map,
                 remap_roughness,
             ))))
         } else {
@@ -106,6 +112,7 @@ impl UberMaterial {
                 opacity,
                 eta,
                 bump_map,
+                normal_map,
                 remap_roughness,
             ))))
         }
@@ -129,6 +136,9 @@ impl UberMaterial {
         if let Some(ref bump) = self.bump_map {
             Material::bump(bump, si);
         }
+        if let Some(ref normal_map) = self.normal_map {
+            Material::normal_map(normal_map, si);
+        }
         let e: Float = self.eta.evaluate(si);
         let op: Spectrum = self
             .opacity
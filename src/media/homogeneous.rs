@@ -6,7 +6,9 @@ use strum::IntoEnumIterator;
 // pbrt
 use crate::core::geometry::Ray;
 use crate::core::interaction::MediumInteraction;
-use crate::core::medium::{HenyeyGreenstein, Medium};
+use crate::core::medium::{
+    sample_channel, HenyeyGreenstein, Medium, MediumEmission, MediumProperties, RayMajorantSegment,
+};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::sampler::Sampler;
 use crate::core::spectrum::RGBEnum;
@@ -18,15 +20,42 @@ pub struct HomogeneousMedium {
     pub sigma_s: Spectrum,
     pub sigma_t: Spectrum,
     pub g: Float,
+    pub emission: MediumEmission,
 }
 
 impl HomogeneousMedium {
-    pub fn new(sigma_a: &Spectrum, sigma_s: &Spectrum, g: Float) -> Self {
+    pub fn new(sigma_a: &Spectrum, sigma_s: &Spectrum, g: Float, emission: MediumEmission) -> Self {
         HomogeneousMedium {
             sigma_a: *sigma_a,
             sigma_s: *sigma_s,
             sigma_t: *sigma_s + *sigma_a,
             g,
+            emission,
+        }
+    }
+    pub fn is_emissive(&self) -> bool {
+        self.emission.is_emissive(false)
+    }
+    pub fn le(&self) -> Spectrum {
+        self.emission.le()
+    }
+    pub fn traverse_majorants<F>(&self, ray: &Ray, mut segment: F)
+    where
+        F: FnMut(RayMajorantSegment) -> bool,
+    {
+        // a single segment bounded by the constant extinction
+        segment(RayMajorantSegment {
+            t_min: 0.0 as Float,
+            t_max: ray.t_max.get(),
+            sigma_maj: self.sigma_t,
+        });
+    }
+    pub fn sample_point(&self) -> MediumProperties {
+        MediumProperties {
+            sigma_a: self.sigma_a,
+            sigma_s: self.sigma_s,
+            le: self.le(),
+            g: self.g,
         }
     }
     // Medium
@@ -41,7 +70,7 @@ impl HomogeneousMedium {
     ) -> (Spectrum, Option<MediumInteraction>) {
         // TODO: ProfilePhase _(Prof::MediumSample);
         // sample a channel and distance along the ray
-        let channel: usize = ((sampler.get_1d() * 3.0 as Float) as usize).min(2_usize);
+        let channel: usize = sample_channel(sampler.get_1d());
         let channel_rgb: RGBEnum = match channel {
             0 => RGBEnum::Red,
             1 => RGBEnum::Green,
@@ -50,7 +79,7 @@ impl HomogeneousMedium {
         let dist: Float = -((1.0 as Float - sampler.get_1d()).ln()) / self.sigma_t[channel_rgb];
         let t: Float = (dist / ray.d.length()).min(ray.t_max.get());
         let sampled_medium: bool = t < ray.t_max.get();
-        let mi_opt = if sampled_medium {
+        let mut mi_opt = if sampled_medium {
             let mi: MediumInteraction = MediumInteraction::new(
                 &ray.position(t),
                 &(-ray.d),
@@ -59,6 +88,7 @@ impl HomogeneousMedium {
                     &self.sigma_a,
                     &self.sigma_s,
                     self.g,
+                    self.emission.clone(),
                 )))),
                 Some(Arc::new(HenyeyGreenstein { g: self.g })),
             );
@@ -84,6 +114,12 @@ impl HomogeneousMedium {
             pdf = 1.0 as Float;
         }
         if sampled_medium {
+            if let Some(ref mut mi) = mi_opt {
+                if self.is_emissive() {
+                    // absorbed light is re-emitted as _Le_
+                    mi.le = tr * self.sigma_a * self.le() / pdf;
+                }
+            }
             (tr * self.sigma_s / pdf, mi_opt)
         } else {
             (tr / pdf, mi_opt)
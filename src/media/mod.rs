@@ -4,6 +4,13 @@
 //!
 //! - GridDensityMedium
 //! - HomogeneousMedium
+//! - SparseGridMedium
+//!
+//! Scattering and absorption coefficients may vary per color
+//! channel. Distances along a ray are sampled with the majorant of a
+//! randomly chosen hero channel and the other channels are weighted
+//! with spectral MIS (see
+//! [Medium::sample_t_maj()](../core/medium/enum.Medium.html#method.sample_t_maj)).
 //!
 //! ## Grid Density Medium
 //!
@@ -12,6 +19,13 @@
 //! ## Homogeneous Medium
 //!
 //! ![A Volumetric Caustic](/doc/img/volume_caustic_pbrt_rust_mlt.png)
+//!
+//! ## Sparse Grid Medium
+//!
+//! Density (and temperature) grids are read from a brick file on
+//! disk, where only non-empty bricks of voxels are stored. A coarse
+//! majorant grid bounds the density for delta and ratio tracking.
 
 pub mod grid;
 pub mod homogeneous;
+pub mod sparse;
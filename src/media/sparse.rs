@@ -0,0 +1,740 @@
+//! A heterogeneous medium which reads its density (and optionally a
+//! temperature) grid from disk. Only the non-empty parts of the grids
+//! are stored, split into cubic bricks of voxels, and a coarse grid
+//! of per-cell density maxima (the majorant grid) lets delta tracking
+//! take large steps through thin or empty regions.
+//!
+//! The brick file format (all numbers little endian):
+//!
+//! ```text
+//! magic     8 bytes  "PBRTBRKS"
+//! nx ny nz  3 x i32  resolution of the grid in voxels
+//! brick     i32      edge length of a brick in voxels
+//! channels  i32      number of channels, followed by one
+//!                    (i32 length, UTF-8 name) pair per channel
+//! bricks    i32      number of non-empty bricks, each followed by
+//!                    its brick coordinates (3 x i32) and
+//!                    channels x brick^3 f32 values
+//! ```
+//!
+//! Within a brick the voxels are stored with x varying fastest, then
+//! y, then z. Voxels of bricks which aren't stored are zero.
+//!
+//! OpenVDB (.vdb) and NanoVDB (.nvdb) files can't be read directly
+//! (their trees are compressed and use half floats); their leaf nodes
+//! (8^3 voxels) map one to one to bricks of size 8, so the grids
+//! have to be converted to the brick format first. Dense grids can be
+//! converted with [BrickGrid::from_dense()] and
+//! [write_brick_grids()].
+
+// std
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+// others
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+// pbrt
+use crate::core::geometry::{Bounds3f, Point3f, Point3i, Ray};
+use crate::core::medium::{MediumEmission, MediumProperties, RayMajorantSegment};
+use crate::core::pbrt::{clamp_t, Float, Spectrum};
+use crate::core::transform::Transform;
+
+/// Brick offset of bricks which aren't stored.
+pub const EMPTY_BRICK: usize = usize::MAX;
+
+/// One channel of a sparse volume, stored as bricks of voxels.
+pub struct BrickGrid {
+    pub nx: i32,
+    pub ny: i32,
+    pub nz: i32,
+    pub brick_size: i32,
+    /// number of bricks along each axis
+    pub n_bricks: Point3i,
+    /// offset of each brick into *values* (EMPTY_BRICK for empty
+    /// bricks)
+    pub brick_offsets: Vec<usize>,
+    pub values: Vec<Float>,
+}
+
+impl BrickGrid {
+    pub fn new(nx: i32, ny: i32, nz: i32, brick_size: i32) -> Self {
+        let n_bricks: Point3i = Point3i {
+            x: (nx + brick_size - 1) / brick_size,
+            y: (ny + brick_size - 1) / brick_size,
+            z: (nz + brick_size - 1) / brick_size,
+        };
+        BrickGrid {
+            nx,
+            ny,
+            nz,
+            brick_size,
+            n_bricks,
+            brick_offsets: vec![
+                EMPTY_BRICK;
+                n_bricks.x as usize * n_bricks.y as usize * n_bricks.z as usize
+            ],
+            values: Vec::new(),
+        }
+    }
+    /// Split a dense grid (x varying fastest, then y, then z) into
+    /// bricks, leaving out the bricks whose voxels are all zero.
+    pub fn from_dense(nx: i32, ny: i32, nz: i32, brick_size: i32, values: &[Float]) -> Self {
+        assert_eq!(values.len(), nx as usize * ny as usize * nz as usize);
+        let mut grid: BrickGrid = BrickGrid::new(nx, ny, nz, brick_size);
+        let bs: i32 = brick_size;
+        let mut brick: Vec<Float> = vec![0.0 as Float; grid.brick_voxels()];
+        for bz in 0..grid.n_bricks.z {
+            for by in 0..grid.n_bricks.y {
+                for bx in 0..grid.n_bricks.x {
+                    let mut empty: bool = true;
+                    for z in 0..bs {
+                        for y in 0..bs {
+                            for x in 0..bs {
+                                let (gx, gy, gz) = (bx * bs + x, by * bs + y, bz * bs + z);
+                                let v: Float = if gx < nx && gy < ny && gz < nz {
+                                    values[(gz as usize * ny as usize + gy as usize) * nx as usize
+                                        + gx as usize]
+                                } else {
+                                    0.0 as Float
+                                };
+                                empty &= v == 0.0 as Float;
+                                brick[((z * bs + y) * bs + x) as usize] = v;
+                            }
+                        }
+                    }
+                    if !empty {
+                        grid.add_brick(bx, by, bz, &brick);
+                    }
+                }
+            }
+        }
+        grid
+    }
+    /// Number of voxels per brick.
+    pub fn brick_voxels(&self) -> usize {
+        let bs: usize = self.brick_size as usize;
+        bs * bs * bs
+    }
+    fn brick_index(&self, bx: i32, by: i32, bz: i32) -> usize {
+        (bz as usize * self.n_bricks.y as usize + by as usize) * self.n_bricks.x as usize
+            + bx as usize
+    }
+    /// Store the values of one brick (replacing an earlier one with
+    /// the same brick coordinates).
+    pub fn add_brick(&mut self, bx: i32, by: i32, bz: i32, values: &[Float]) {
+        let index: usize = self.brick_index(bx, by, bz);
+        if self.brick_offsets[index] != EMPTY_BRICK {
+            let offset: usize = self.brick_offsets[index];
+            self.values[offset..offset + values.len()].copy_from_slice(values);
+        } else {
+            self.brick_offsets[index] = self.values.len();
+            self.values.extend_from_slice(values);
+        }
+    }
+    /// Value of a single voxel (zero outside the grid and for voxels
+    /// of empty bricks).
+    pub fn d(&self, p: &Point3i) -> Float {
+        if p.x < 0 || p.y < 0 || p.z < 0 || p.x >= self.nx || p.y >= self.ny || p.z >= self.nz {
+            return 0.0 as Float;
+        }
+        let bs: i32 = self.brick_size;
+        let offset: usize = self.brick_offsets[self.brick_index(p.x / bs, p.y / bs, p.z / bs)];
+        if offset == EMPTY_BRICK {
+            0.0 as Float
+        } else {
+            let (x, y, z) = (p.x % bs, p.y % bs, p.z % bs);
+            self.values[offset + ((z * bs + y) * bs + x) as usize]
+        }
+    }
+    /// Trilinearly interpolated value at *p* in [0,1]^3.
+    pub fn value(&self, p: &Point3f) -> Float {
+        // compute voxel coordinates and offsets for _p_
+        let x: Float = p.x * self.nx as Float - 0.5 as Float;
+        let y: Float = p.y * self.ny as Float - 0.5 as Float;
+        let z: Float = p.z * self.nz as Float - 0.5 as Float;
+        let pi: Point3i = Point3i {
+            x: x.floor() as i32,
+            y: y.floor() as i32,
+            z: z.floor() as i32,
+        };
+        let dx: Float = x - pi.x as Float;
+        let dy: Float = y - pi.y as Float;
+        let dz: Float = z - pi.z as Float;
+        // trilinearly interpolate the eight surrounding voxels
+        let mut value: Float = 0.0 as Float;
+        for corner in 0..8 {
+            let (ox, oy, oz) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let w: Float = (if ox == 1 { dx } else { 1.0 as Float - dx })
+                * (if oy == 1 { dy } else { 1.0 as Float - dy })
+                * (if oz == 1 { dz } else { 1.0 as Float - dz });
+            if w > 0.0 as Float {
+                value += w * self.d(&Point3i {
+                    x: pi.x + ox,
+                    y: pi.y + oy,
+                    z: pi.z + oz,
+                });
+            }
+        }
+        value
+    }
+    /// Conservative bounds (in [0,1]^3) of the region influenced by a
+    /// brick, including the half voxel reached by interpolation.
+    fn brick_bounds(&self, bx: i32, by: i32, bz: i32) -> Bounds3f {
+        let bs: Float = self.brick_size as Float;
+        Bounds3f {
+            p_min: Point3f {
+                x: (bx as Float * bs - 0.5 as Float) / self.nx as Float,
+                y: (by as Float * bs - 0.5 as Float) / self.ny as Float,
+                z: (bz as Float * bs - 0.5 as Float) / self.nz as Float,
+            },
+            p_max: Point3f {
+                x: ((bx + 1) as Float * bs + 0.5 as Float) / self.nx as Float,
+                y: ((by + 1) as Float * bs + 0.5 as Float) / self.ny as Float,
+                z: ((bz + 1) as Float * bs + 0.5 as Float) / self.nz as Float,
+            },
+        }
+    }
+}
+
+/// Read all channels of a brick file, indexed by channel name.
+pub fn read_brick_grids(filename: &str) -> Option<HashMap<String, BrickGrid>> {
+    let extension: String = Path::new(filename)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if extension == "vdb" || extension == "nvdb" {
+        println!(
+            "ERROR: {:?}: VDB files are not supported, convert them to a brick volume file",
+            filename
+        );
+        return None;
+    }
+    let file = match File::open(filename) {
+        Ok(f) => f,
+        Err(_) => {
+            println!("ERROR: Unable to open file {:?}", filename);
+            return None;
+        }
+    };
+    let mut reader = BufReader::new(file);
+    match read_brick_grids_from(&mut reader) {
+        Ok(Some(grids)) => Some(grids),
+        Ok(None) => {
+            println!("ERROR: {:?} is not a brick volume file", filename);
+            None
+        }
+        Err(err) => {
+            println!("ERROR: Reading brick volume {:?} failed: {}", filename, err);
+            None
+        }
+    }
+}
+
+/// Resolution, brick size and channel names of a brick file.
+#[derive(Debug, PartialEq)]
+struct BrickHeader {
+    nx: i32,
+    ny: i32,
+    nz: i32,
+    brick_size: i32,
+    /// number of voxels per brick (and channel)
+    brick_voxels: usize,
+    names: Vec<String>,
+}
+
+/// Reads the header of a brick file up to the number of bricks;
+/// returns None if the magic number is wrong or the header describes
+/// a grid which can't be allocated.
+fn read_brick_header<R: Read>(reader: &mut R) -> std::io::Result<Option<BrickHeader>> {
+    let mut magic: [u8; 8] = [0_u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != b"PBRTBRKS" {
+        return Ok(None);
+    }
+    let nx: i32 = reader.read_i32::<LittleEndian>()?;
+    let ny: i32 = reader.read_i32::<LittleEndian>()?;
+    let nz: i32 = reader.read_i32::<LittleEndian>()?;
+    let brick_size: i32 = reader.read_i32::<LittleEndian>()?;
+    let n_channels: i32 = reader.read_i32::<LittleEndian>()?;
+    if nx <= 0 || ny <= 0 || nz <= 0 || brick_size <= 0 || n_channels <= 0 {
+        return Ok(None);
+    }
+    // the brick index and the voxel offsets within a brick are
+    // computed in i32 and usize, neither may overflow
+    let bs: usize = brick_size as usize;
+    let brick_voxels: usize = match bs.checked_mul(bs).and_then(|v| v.checked_mul(bs)) {
+        Some(brick_voxels) if brick_voxels <= i32::MAX as usize => brick_voxels,
+        _ => return Ok(None),
+    };
+    let n_bricks = |n: i32| (n as usize).div_ceil(bs);
+    if [nx, ny, nz]
+        .iter()
+        .any(|n| *n as usize + bs - 1 > i32::MAX as usize)
+        || n_bricks(nx)
+            .checked_mul(n_bricks(ny))
+            .and_then(|v| v.checked_mul(n_bricks(nz)))
+            .is_none()
+    {
+        return Ok(None);
+    }
+    let mut names: Vec<String> = Vec::with_capacity(n_channels as usize);
+    for _c in 0..n_channels {
+        let len: i32 = reader.read_i32::<LittleEndian>()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let mut name: Vec<u8> = Vec::new();
+        reader.take(len as u64).read_to_end(&mut name)?;
+        if name.len() != len as usize {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        names.push(String::from_utf8_lossy(&name).into_owned());
+    }
+    Ok(Some(BrickHeader {
+        nx,
+        ny,
+        nz,
+        brick_size,
+        brick_voxels,
+        names,
+    }))
+}
+
+fn read_brick_grids_from<R: Read>(
+    reader: &mut R,
+) -> std::io::Result<Option<HashMap<String, BrickGrid>>> {
+    let header: BrickHeader = match read_brick_header(reader)? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let mut grids: Vec<BrickGrid> = header
+        .names
+        .iter()
+        .map(|_name| BrickGrid::new(header.nx, header.ny, header.nz, header.brick_size))
+        .collect();
+    let n_bricks: i32 = reader.read_i32::<LittleEndian>()?;
+    let mut values: Vec<f32> = vec![0.0_f32; header.brick_voxels];
+    for _b in 0..n_bricks {
+        let bx: i32 = reader.read_i32::<LittleEndian>()?;
+        let by: i32 = reader.read_i32::<LittleEndian>()?;
+        let bz: i32 = reader.read_i32::<LittleEndian>()?;
+        let inside: bool = bx >= 0
+            && by >= 0
+            && bz >= 0
+            && bx < grids[0].n_bricks.x
+            && by < grids[0].n_bricks.y
+            && bz < grids[0].n_bricks.z;
+        for grid in grids.iter_mut() {
+            reader.read_f32_into::<LittleEndian>(&mut values)?;
+            if inside {
+                let brick: Vec<Float> = values.iter().map(|v| *v as Float).collect();
+                grid.add_brick(bx, by, bz, &brick);
+            }
+        }
+        if !inside {
+            println!(
+                "WARNING: Ignoring brick ({}, {}, {}) outside of the grid",
+                bx, by, bz
+            );
+        }
+    }
+    Ok(Some(header.names.into_iter().zip(grids).collect()))
+}
+
+/// Write named channels, which must share resolution and brick size,
+/// to a brick file. A brick is stored if it is non-empty in any
+/// channel.
+pub fn write_brick_grids(filename: &str, channels: &[(&str, &BrickGrid)]) -> std::io::Result<()> {
+    let file = File::create(filename)?;
+    let mut writer = BufWriter::new(file);
+    write_brick_grids_to(&mut writer, channels)?;
+    writer.flush()
+}
+
+fn write_brick_grids_to<W: Write>(
+    writer: &mut W,
+    channels: &[(&str, &BrickGrid)],
+) -> std::io::Result<()> {
+    assert!(!channels.is_empty(), "No channels to write");
+    let first: &BrickGrid = channels[0].1;
+    for (name, grid) in channels.iter() {
+        assert!(
+            grid.nx == first.nx
+                && grid.ny == first.ny
+                && grid.nz == first.nz
+                && grid.brick_size == first.brick_size,
+            "Channel \"{}\" doesn't match the resolution of the first channel",
+            name
+        );
+    }
+    writer.write_all(b"PBRTBRKS")?;
+    writer.write_i32::<LittleEndian>(first.nx)?;
+    writer.write_i32::<LittleEndian>(first.ny)?;
+    writer.write_i32::<LittleEndian>(first.nz)?;
+    writer.write_i32::<LittleEndian>(first.brick_size)?;
+    writer.write_i32::<LittleEndian>(channels.len() as i32)?;
+    for (name, _grid) in channels.iter() {
+        writer.write_i32::<LittleEndian>(name.len() as i32)?;
+        writer.write_all(name.as_bytes())?;
+    }
+    let brick_voxels: usize = first.brick_voxels();
+    let mut bricks: Vec<(i32, i32, i32)> = Vec::new();
+    for bz in 0..first.n_bricks.z {
+        for by in 0..first.n_bricks.y {
+            for bx in 0..first.n_bricks.x {
+                let index: usize = first.brick_index(bx, by, bz);
+                if channels
+                    .iter()
+                    .any(|(_name, grid)| grid.brick_offsets[index] != EMPTY_BRICK)
+                {
+                    bricks.push((bx, by, bz));
+                }
+            }
+        }
+    }
+    writer.write_i32::<LittleEndian>(bricks.len() as i32)?;
+    for (bx, by, bz) in bricks {
+        writer.write_i32::<LittleEndian>(bx)?;
+        writer.write_i32::<LittleEndian>(by)?;
+        writer.write_i32::<LittleEndian>(bz)?;
+        for (_name, grid) in channels.iter() {
+            let offset: usize = grid.brick_offsets[grid.brick_index(bx, by, bz)];
+            for i in 0..brick_voxels {
+                let v: Float = if offset == EMPTY_BRICK {
+                    0.0 as Float
+                } else {
+                    grid.values[offset + i]
+                };
+                writer.write_f32::<LittleEndian>(v as f32)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Coarse grid over [0,1]^3 storing the maximum density of each cell.
+pub struct MajorantGrid {
+    pub res: Point3i,
+    pub voxels: Vec<Float>,
+}
+
+impl MajorantGrid {
+    pub fn new(grid: &BrickGrid, res: i32) -> Self {
+        let res: Point3i = Point3i {
+            x: res.max(1),
+            y: res.max(1),
+            z: res.max(1),
+        };
+        let mut voxels: Vec<Float> = vec![0.0 as Float; (res.x * res.y * res.z) as usize];
+        let brick_voxels: usize = grid.brick_voxels();
+        for bz in 0..grid.n_bricks.z {
+            for by in 0..grid.n_bricks.y {
+                for bx in 0..grid.n_bricks.x {
+                    let offset: usize = grid.brick_offsets[grid.brick_index(bx, by, bz)];
+                    if offset == EMPTY_BRICK {
+                        continue;
+                    }
+                    let max_value: Float = grid.values[offset..offset + brick_voxels]
+                        .iter()
+                        .fold(0.0 as Float, |a, b| a.max(*b));
+                    if max_value <= 0.0 as Float {
+                        continue;
+                    }
+                    // update all majorant cells overlapped by the brick
+                    let bounds: Bounds3f = grid.brick_bounds(bx, by, bz);
+                    let x0: i32 = clamp_t((bounds.p_min.x * res.x as Float) as i32, 0, res.x - 1);
+                    let y0: i32 = clamp_t((bounds.p_min.y * res.y as Float) as i32, 0, res.y - 1);
+                    let z0: i32 = clamp_t((bounds.p_min.z * res.z as Float) as i32, 0, res.z - 1);
+                    let x1: i32 = clamp_t((bounds.p_max.x * res.x as Float) as i32, 0, res.x - 1);
+                    let y1: i32 = clamp_t((bounds.p_max.y * res.y as Float) as i32, 0, res.y - 1);
+                    let z1: i32 = clamp_t((bounds.p_max.z * res.z as Float) as i32, 0, res.z - 1);
+                    for z in z0..=z1 {
+                        for y in y0..=y1 {
+                            for x in x0..=x1 {
+                                let index: usize = ((z * res.y + y) * res.x + x) as usize;
+                                voxels[index] = voxels[index].max(max_value);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        MajorantGrid { res, voxels }
+    }
+    pub fn lookup(&self, x: i32, y: i32, z: i32) -> Float {
+        self.voxels[((z * self.res.y + y) * self.res.x + x) as usize]
+    }
+    /// Walk the cells pierced by *ray* (in medium space) between
+    /// *t_min* and *t_max* with a 3D DDA and call *segment* with the
+    /// parametric range and majorant of each cell, until it returns
+    /// false.
+    pub fn traverse<F>(&self, ray: &Ray, t_min: Float, t_max: Float, mut segment: F)
+    where
+        F: FnMut(Float, Float, Float) -> bool,
+    {
+        let o: [Float; 3] = [ray.o.x, ray.o.y, ray.o.z];
+        let d: [Float; 3] = [ray.d.x, ray.d.y, ray.d.z];
+        let res: [i32; 3] = [self.res.x, self.res.y, self.res.z];
+        let mut voxel: [i32; 3] = [0_i32; 3];
+        let mut step: [i32; 3] = [0_i32; 3];
+        let mut voxel_limit: [i32; 3] = [0_i32; 3];
+        let mut next_crossing_t: [Float; 3] = [0.0 as Float; 3];
+        let mut delta_t: [Float; 3] = [0.0 as Float; 3];
+        // set up 3D DDA for ray through the majorant grid
+        for axis in 0..3 {
+            let p: Float = o[axis] + d[axis] * t_min;
+            voxel[axis] = clamp_t((p * res[axis] as Float) as i32, 0, res[axis] - 1);
+            if d[axis] == 0.0 as Float {
+                next_crossing_t[axis] = Float::INFINITY;
+                delta_t[axis] = Float::INFINITY;
+                voxel_limit[axis] = -1;
+            } else if d[axis] > 0.0 as Float {
+                let next_p: Float = (voxel[axis] + 1) as Float / res[axis] as Float;
+                next_crossing_t[axis] = t_min + (next_p - p) / d[axis];
+                delta_t[axis] = 1.0 as Float / (d[axis] * res[axis] as Float);
+                step[axis] = 1;
+                voxel_limit[axis] = res[axis];
+            } else {
+                let next_p: Float = voxel[axis] as Float / res[axis] as Float;
+                next_crossing_t[axis] = t_min + (next_p - p) / d[axis];
+                delta_t[axis] = -1.0 as Float / (d[axis] * res[axis] as Float);
+                step[axis] = -1;
+                voxel_limit[axis] = -1;
+            }
+        }
+        let mut t0: Float = t_min;
+        loop {
+            // find _step_axis_ for stepping to next voxel
+            let mut step_axis: usize = 0;
+            if next_crossing_t[1] < next_crossing_t[step_axis] {
+                step_axis = 1;
+            }
+            if next_crossing_t[2] < next_crossing_t[step_axis] {
+                step_axis = 2;
+            }
+            let t1: Float = t_max.min(next_crossing_t[step_axis]);
+            let majorant: Float = self.lookup(voxel[0], voxel[1], voxel[2]);
+            if t1 > t0 && !segment(t0, t1, majorant) {
+                return;
+            }
+            if next_crossing_t[step_axis] >= t_max {
+                return;
+            }
+            t0 = t1;
+            voxel[step_axis] += step[step_axis];
+            if voxel[step_axis] == voxel_limit[step_axis] {
+                return;
+            }
+            next_crossing_t[step_axis] += delta_t[step_axis];
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SparseGridMedium {
+    pub sigma_a: Spectrum,
+    pub sigma_s: Spectrum,
+    pub g: Float,
+    pub world_to_medium: Transform,
+    pub density: Arc<BrickGrid>,
+    pub temperature: Option<Arc<BrickGrid>>,
+    pub majorant_grid: Arc<MajorantGrid>,
+    pub sigma_t: Spectrum,
+    pub emission: MediumEmission,
+}
+
+impl SparseGridMedium {
+    pub fn new(
+        sigma_a: &Spectrum,
+        sigma_s: &Spectrum,
+        g: Float,
+        medium_to_world: &Transform,
+        density: Arc<BrickGrid>,
+        temperature: Option<Arc<BrickGrid>>,
+        majorant_res: i32,
+        emission: MediumEmission,
+    ) -> Self {
+        let majorant_grid: Arc<MajorantGrid> = Arc::new(MajorantGrid::new(&density, majorant_res));
+        SparseGridMedium {
+            sigma_a: *sigma_a,
+            sigma_s: *sigma_s,
+            g,
+            world_to_medium: Transform::inverse(medium_to_world),
+            density,
+            temperature,
+            majorant_grid,
+            sigma_t: *sigma_s + *sigma_a,
+            emission,
+        }
+    }
+    pub fn density(&self, p: &Point3f) -> Float {
+        self.density.value(p)
+    }
+    /// Interpolated temperature (zero without a temperature channel).
+    pub fn temperature(&self, p: &Point3f) -> Float {
+        if let Some(ref temperature) = self.temperature {
+            temperature.value(p)
+        } else {
+            0.0 as Float
+        }
+    }
+    pub fn is_emissive(&self) -> bool {
+        self.emission.is_emissive(self.temperature.is_some())
+    }
+    /// Emitted radiance at *p* (in medium space).
+    pub fn le(&self, p: &Point3f) -> Spectrum {
+        if self.temperature.is_some() {
+            self.emission.le_temperature(self.temperature(p))
+        } else {
+            self.emission.le()
+        }
+    }
+    /// Transform the ray into medium space and clip it against the
+    /// unit cube covered by the grids.
+    fn medium_ray(&self, r_world: &Ray) -> Option<(Ray, Float, Float)> {
+        let in_ray: Ray = Ray {
+            o: r_world.o,
+            d: r_world.d.normalize(),
+            t_max: Cell::new(r_world.t_max.get() * r_world.d.length()),
+            ..Default::default()
+        };
+        let ray: Ray = self.world_to_medium.transform_ray(&in_ray);
+        // compute $[\tmin, \tmax]$ interval of _ray_'s overlap with medium bounds
+        let b: Bounds3f = Bounds3f::new(
+            Point3f {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Point3f {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+        );
+        let mut t_min: Float = 0.0;
+        let mut t_max: Float = 0.0;
+        if !b.intersect_b(&ray, &mut t_min, &mut t_max) {
+            return None;
+        }
+        Some((ray, t_min, t_max))
+    }
+    // Medium
+    pub fn traverse_majorants<F>(&self, r_world: &Ray, mut segment: F)
+    where
+        F: FnMut(RayMajorantSegment) -> bool,
+    {
+        if let Some((ray, t_min, t_max)) = self.medium_ray(r_world) {
+            // one segment per majorant grid cell
+            self.majorant_grid.traverse(
+                &ray,
+                t_min,
+                t_max,
+                |t0: Float, t1: Float, majorant: Float| {
+                    segment(RayMajorantSegment {
+                        t_min: t0,
+                        t_max: t1,
+                        sigma_maj: self.sigma_t * majorant,
+                    })
+                },
+            );
+        }
+    }
+    /// Scattering properties at *p* (in medium space).
+    pub fn sample_point(&self, p: &Point3f) -> MediumProperties {
+        let density: Float = self.density(p);
+        let le: Spectrum = if self.is_emissive() {
+            self.le(p)
+        } else {
+            Spectrum::default()
+        };
+        MediumProperties {
+            sigma_a: self.sigma_a * density,
+            sigma_s: self.sigma_s * density,
+            le,
+            g: self.g,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(nx: i32, ny: i32, nz: i32, brick_size: i32, names: &[&str]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = b"PBRTBRKS".to_vec();
+        for v in [nx, ny, nz, brick_size, names.len() as i32].iter() {
+            bytes.write_i32::<LittleEndian>(*v).unwrap();
+        }
+        for name in names.iter() {
+            bytes.write_i32::<LittleEndian>(name.len() as i32).unwrap();
+            bytes.extend_from_slice(name.as_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn parse_header() {
+        let bytes: Vec<u8> = header(10, 20, 30, 8, &["density", "temperature"]);
+        let header: BrickHeader = read_brick_header(&mut &bytes[..]).unwrap().unwrap();
+        assert_eq!(
+            header,
+            BrickHeader {
+                nx: 10,
+                ny: 20,
+                nz: 30,
+                brick_size: 8,
+                brick_voxels: 512,
+                names: vec![String::from("density"), String::from("temperature")],
+            }
+        );
+    }
+
+    #[test]
+    fn reject_bad_headers() {
+        let mut bytes: Vec<u8> = header(8, 8, 8, 8, &["density"]);
+        bytes[0] = b'X';
+        assert_eq!(read_brick_header(&mut &bytes[..]).unwrap(), None);
+        // brick_size^3 overflows
+        let bytes: Vec<u8> = header(8, 8, 8, 1 << 11, &["density"]);
+        assert_eq!(read_brick_header(&mut &bytes[..]).unwrap(), None);
+        let bytes: Vec<u8> = header(i32::MAX, 8, 8, 8, &["density"]);
+        assert_eq!(read_brick_header(&mut &bytes[..]).unwrap(), None);
+        let bytes: Vec<u8> = header(8, 0, 8, 8, &["density"]);
+        assert_eq!(read_brick_header(&mut &bytes[..]).unwrap(), None);
+        // negative length of a channel name
+        let mut bytes: Vec<u8> = header(8, 8, 8, 8, &["density"]);
+        bytes[28..32].copy_from_slice(&(-4_i32).to_le_bytes());
+        assert_eq!(read_brick_header(&mut &bytes[..]).unwrap(), None);
+        // truncated channel name
+        let bytes: Vec<u8> = header(8, 8, 8, 8, &["density"]);
+        assert!(read_brick_header(&mut &bytes[..bytes.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn write_and_read_bricks() {
+        let (nx, ny, nz): (i32, i32, i32) = (5, 3, 6);
+        let mut dense: Vec<Float> = vec![0.0 as Float; (nx * ny * nz) as usize];
+        dense[0] = 1.0;
+        dense[((5 * ny + 2) * nx + 4) as usize] = 2.5;
+        let grid: BrickGrid = BrickGrid::from_dense(nx, ny, nz, 4, &dense);
+        // only two of the 2 x 1 x 2 bricks are stored
+        assert_eq!(grid.values.len(), 2 * 64);
+        let mut bytes: Vec<u8> = Vec::new();
+        write_brick_grids_to(&mut bytes, &[("density", &grid)]).unwrap();
+        let grids: HashMap<String, BrickGrid> =
+            read_brick_grids_from(&mut &bytes[..]).unwrap().unwrap();
+        let read: &BrickGrid = &grids["density"];
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let p: Point3i = Point3i { x, y, z };
+                    assert_eq!(read.d(&p), dense[((z * ny + y) * nx + x) as usize]);
+                }
+            }
+        }
+    }
+}
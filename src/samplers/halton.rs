@@ -76,6 +76,37 @@ pub struct HaltonSampler {
     array_2d_offset: usize,
 }
 
+impl Clone for HaltonSampler {
+    fn clone(&self) -> Self {
+        let pixel_for_offset_x: i32 = self.pixel_for_offset_x.load(Ordering::Relaxed);
+        let pixel_for_offset_y: i32 = self.pixel_for_offset_y.load(Ordering::Relaxed);
+        let offset_for_current_pixel: u64 = self.offset_for_current_pixel.load(Ordering::Relaxed);
+        HaltonSampler {
+            samples_per_pixel: self.samples_per_pixel,
+            base_scales: self.base_scales,
+            base_exponents: self.base_exponents,
+            sample_stride: self.sample_stride,
+            mult_inverse: self.mult_inverse,
+            pixel_for_offset_x: AtomicI32::new(pixel_for_offset_x),
+            pixel_for_offset_y: AtomicI32::new(pixel_for_offset_y),
+            offset_for_current_pixel: AtomicU64::new(offset_for_current_pixel),
+            sample_at_pixel_center: self.sample_at_pixel_center,
+            dimension: self.dimension,
+            interval_sample_index: self.interval_sample_index,
+            array_start_dim: self.array_start_dim,
+            array_end_dim: self.array_end_dim,
+            current_pixel: self.current_pixel,
+            current_pixel_sample_index: self.current_pixel_sample_index,
+            samples_1d_array_sizes: self.samples_1d_array_sizes.to_vec(),
+            samples_2d_array_sizes: self.samples_2d_array_sizes.to_vec(),
+            sample_array_1d: self.sample_array_1d.to_vec(),
+            sample_array_2d: self.sample_array_2d.to_vec(),
+            array_1d_offset: self.array_1d_offset,
+            array_2d_offset: self.array_2d_offset,
+        }
+    }
+}
+
 impl HaltonSampler {
     pub fn new(
         samples_per_pixel: i64,
@@ -131,34 +162,7 @@ impl HaltonSampler {
         }
     }
     pub fn clone_with_seed(&self, _seed: u64) -> Box<Sampler> {
-        let pixel_for_offset_x: i32 = self.pixel_for_offset_x.load(Ordering::Relaxed);
-        let pixel_for_offset_y: i32 = self.pixel_for_offset_y.load(Ordering::Relaxed);
-        let offset_for_current_pixel: u64 = self.offset_for_current_pixel.load(Ordering::Relaxed);
-        let halton_sampler = HaltonSampler {
-            samples_per_pixel: self.samples_per_pixel,
-            base_scales: self.base_scales,
-            base_exponents: self.base_exponents,
-            sample_stride: self.sample_stride,
-            mult_inverse: self.mult_inverse,
-            pixel_for_offset_x: AtomicI32::new(pixel_for_offset_x),
-            pixel_for_offset_y: AtomicI32::new(pixel_for_offset_y),
-            offset_for_current_pixel: AtomicU64::new(offset_for_current_pixel),
-            sample_at_pixel_center: self.sample_at_pixel_center,
-            dimension: self.dimension,
-            interval_sample_index: self.interval_sample_index,
-            array_start_dim: self.array_start_dim,
-            array_end_dim: self.array_end_dim,
-            current_pixel: self.current_pixel,
-            current_pixel_sample_index: self.current_pixel_sample_index,
-            samples_1d_array_sizes: self.samples_1d_array_sizes.to_vec(),
-            samples_2d_array_sizes: self.samples_2d_array_sizes.to_vec(),
-            sample_array_1d: self.sample_array_1d.to_vec(),
-            sample_array_2d: self.sample_array_2d.to_vec(),
-            array_1d_offset: self.array_1d_offset,
-            array_2d_offset: self.array_2d_offset,
-        };
-        let sampler = Sampler::Halton(halton_sampler);
-        Box::new(sampler)
+        Box::new(Sampler::Halton(self.clone()))
     }
     pub fn create(params: &ParamSet, sample_bounds: &Bounds2i) -> Box<Sampler> {
         let nsamp: i32 = params.find_one_int("pixelsamples", 16);
@@ -9,6 +9,7 @@ use crate::core::rng::Rng;
 use crate::core::sampler::Sampler;
 use crate::core::sampling::shuffle;
 
+#[derive(Clone)]
 pub struct MaxMinDistSampler {
     pub samples_per_pixel: i64,
     c_pixel: [u32; 32],
@@ -230,6 +231,17 @@ impl MaxMinDistSampler {
         self.current_pixel_sample_index += 1_i64;
         self.current_pixel_sample_index < self.samples_per_pixel
     }
+    pub fn set_sample_number(&mut self, sample_num: i64) -> bool {
+        // PixelSampler::SetSampleNumber(...)
+        self.current_1d_dimension = 0_i32;
+        self.current_2d_dimension = 0_i32;
+        // Sampler::SetSampleNumber(...)
+        // reset array offsets for next pixel sample
+        self.array_1d_offset = 0_usize;
+        self.array_2d_offset = 0_usize;
+        self.current_pixel_sample_index = sample_num;
+        self.current_pixel_sample_index < self.samples_per_pixel
+    }
     pub fn reseed(&mut self, seed: u64) {
         self.rng.set_sequence(seed);
     }
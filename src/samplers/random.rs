@@ -7,6 +7,7 @@ use crate::core::sampler::Sampler;
 
 // see random.h
 
+#[derive(Clone)]
 pub struct RandomSampler {
     pub samples_per_pixel: i64,
     rng: Rng,
@@ -136,6 +137,14 @@ impl RandomSampler {
         self.current_pixel_sample_index += 1_i64;
         self.current_pixel_sample_index < self.samples_per_pixel
     }
+    pub fn set_sample_number(&mut self, sample_num: i64) -> bool {
+        // Sampler::SetSampleNumber(...)
+        // reset array offsets for next pixel sample
+        self.array_1d_offset = 0_usize;
+        self.array_2d_offset = 0_usize;
+        self.current_pixel_sample_index = sample_num;
+        self.current_pixel_sample_index < self.samples_per_pixel
+    }
     pub fn get_current_pixel(&self) -> Point2i {
         self.current_pixel
     }
@@ -6,6 +6,7 @@ use crate::core::rng::Rng;
 use crate::core::sampler::Sampler;
 use crate::core::sampling::{latin_hypercube, shuffle, stratified_sample_1d, stratified_sample_2d};
 
+#[derive(Clone)]
 pub struct StratifiedSampler {
     pub samples_per_pixel: i64,
     x_pixel_samples: i32,
@@ -232,6 +233,17 @@ impl StratifiedSampler {
         self.current_pixel_sample_index += 1_i64;
         self.current_pixel_sample_index < self.samples_per_pixel
     }
+    pub fn set_sample_number(&mut self, sample_num: i64) -> bool {
+        // PixelSampler::SetSampleNumber(...)
+        self.current_1d_dimension = 0_i32;
+        self.current_2d_dimension = 0_i32;
+        // Sampler::SetSampleNumber(...)
+        // reset array offsets for next pixel sample
+        self.array_1d_offset = 0_usize;
+        self.array_2d_offset = 0_usize;
+        self.current_pixel_sample_index = sample_num;
+        self.current_pixel_sample_index < self.samples_per_pixel
+    }
     pub fn reseed(&mut self, seed: u64) {
         self.rng.set_sequence(seed);
     }
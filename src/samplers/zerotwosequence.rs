@@ -9,6 +9,7 @@ use crate::core::sampler::Sampler;
 
 // see zerotwosequence.h
 
+#[derive(Clone)]
 pub struct ZeroTwoSequenceSampler {
     pub samples_per_pixel: i64,
     pub n_sampled_dimensions: i64,
@@ -226,6 +227,17 @@ impl ZeroTwoSequenceSampler {
         self.current_pixel_sample_index += 1_i64;
         self.current_pixel_sample_index < self.samples_per_pixel
     }
+    pub fn set_sample_number(&mut self, sample_num: i64) -> bool {
+        // PixelSampler::SetSampleNumber(...)
+        self.current_1d_dimension = 0_i32;
+        self.current_2d_dimension = 0_i32;
+        // Sampler::SetSampleNumber(...)
+        // reset array offsets for next pixel sample
+        self.array_1d_offset = 0_usize;
+        self.array_2d_offset = 0_usize;
+        self.current_pixel_sample_index = sample_num;
+        self.current_pixel_sample_index < self.samples_per_pixel
+    }
     pub fn reseed(&mut self, seed: u64) {
         self.rng.set_sequence(seed);
     }
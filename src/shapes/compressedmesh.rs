@@ -0,0 +1,464 @@
+//! Compact storage for (very) large triangle meshes. The triangles
+//! are sorted along a Morton curve and split into (spatially compact)
+//! clusters, which index their vertices with 16-bit indices relative
+//! to the first vertex of the cluster. Vertex positions get quantized
+//! to 16 bits per component relative to the bounds of their cluster,
+//! normals and tangents are stored as octahedral encoded unit vectors
+//! (two 16-bit components).
+//!
+//! The quantized positions of all clusters lie on one fine grid (with
+//! 2^24 positions per axis over the bounds of the mesh): a cluster
+//! stores its origin on that grid and (per axis) a power of two
+//! spacing, which makes 16 bits cover its extent. Vertices shared by
+//! several clusters are duplicated, but snapped to the coarsest
+//! spacing of these clusters, so that all of them decode to the same
+//! position and the mesh stays watertight.
+//!
+//! A **CompressedTriangleMesh** is rendered through a
+//! **MeshPrimitive**, which has its own BVH referencing the triangles
+//! by index.
+
+// std
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Arc;
+// pbrt
+use crate::accelerators::bvh::encode_morton_3;
+use crate::core::geometry::bnd3_union_pnt3f;
+use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector3f};
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::pbrt::Float;
+use crate::core::texture::Texture;
+use crate::core::transform::Transform;
+use crate::shapes::triangle::{
+    intersect_triangle, triangle_alpha_test_p, triangle_interaction, TriangleMesh,
+};
+
+/// Number of triangles per cluster. The (at most three times as many)
+/// vertices of a cluster can always be addressed with 16-bit indices.
+pub const CLUSTER_TRIANGLES: usize = 1024;
+
+/// Largest coordinate of the fine grid all positions are snapped to.
+const GRID_MAX: u32 = (1 << 24) - 1;
+
+/// Rounds the grid coordinate *g* to a multiple of 2^*shift*.
+fn snap_to_grid(g: u32, shift: u8) -> u32 {
+    if shift == 0 {
+        g
+    } else {
+        ((g + (1 << (shift - 1))) >> shift) << shift
+    }
+}
+
+/// Encoding of the zero vector (both components are -32768, which is
+/// never used for unit vectors).
+const OCTAHEDRAL_ZERO: u32 = 0x8000_8000;
+
+fn sign_not_zero(v: Float) -> Float {
+    if v < 0.0 as Float {
+        -1.0 as Float
+    } else {
+        1.0 as Float
+    }
+}
+
+fn encode_snorm16(v: Float) -> u32 {
+    let clamped: Float = v.max(-1.0 as Float).min(1.0 as Float);
+    ((clamped * 32767.0 as Float).round() as i16) as u16 as u32
+}
+
+fn decode_snorm16(v: u32) -> Float {
+    ((v as u16 as i16) as Float / 32767.0 as Float)
+        .max(-1.0 as Float)
+        .min(1.0 as Float)
+}
+
+/// Maps a unit vector onto the faces of an octahedron, which gets
+/// unfolded into a square, and stores both coordinates as 16-bit
+/// signed normalized values. Zero vectors (e.g. missing normals) are
+/// preserved.
+pub fn encode_octahedral(v: &Vector3f) -> u32 {
+    let l1: Float = v.x.abs() + v.y.abs() + v.z.abs();
+    if l1 == 0.0 as Float {
+        return OCTAHEDRAL_ZERO;
+    }
+    let mut x: Float = v.x / l1;
+    let mut y: Float = v.y / l1;
+    if v.z < 0.0 as Float {
+        // fold the lower hemisphere over the diagonals
+        let xp: Float = (1.0 as Float - y.abs()) * sign_not_zero(x);
+        let yp: Float = (1.0 as Float - x.abs()) * sign_not_zero(y);
+        x = xp;
+        y = yp;
+    }
+    encode_snorm16(x) | (encode_snorm16(y) << 16)
+}
+
+/// Inverse of [encode_octahedral()](fn.encode_octahedral.html),
+/// returns a unit vector (or the zero vector).
+pub fn decode_octahedral(e: u32) -> Vector3f {
+    if e == OCTAHEDRAL_ZERO {
+        return Vector3f::default();
+    }
+    let mut v: Vector3f = Vector3f {
+        x: decode_snorm16(e & 0xffff),
+        y: decode_snorm16(e >> 16),
+        z: 0.0 as Float,
+    };
+    v.z = 1.0 as Float - v.x.abs() - v.y.abs();
+    if v.z < 0.0 as Float {
+        let xp: Float = (1.0 as Float - v.y.abs()) * sign_not_zero(v.x);
+        let yp: Float = (1.0 as Float - v.x.abs()) * sign_not_zero(v.y);
+        v.x = xp;
+        v.y = yp;
+    }
+    v.normalize()
+}
+
+pub struct CompressedTriangleMesh {
+    /// the total number of triangles in the mesh
+    pub n_triangles: u32,
+    /// (world space) bounds of all vertex positions
+    pub bounds: Bounds3f,
+    /// distance between two positions of the fine grid (per axis)
+    pub grid_step: Vector3f,
+    /// for each cluster the index of its first vertex
+    pub cluster_offsets: Vec<u32>,
+    /// for each cluster the fine grid coordinates of its origin
+    pub cluster_origins: Vec<[u32; 3]>,
+    /// for each cluster the spacing (per axis, as a power of two of
+    /// the fine grid's spacing) of its quantized positions
+    pub cluster_shifts: Vec<[u8; 3]>,
+    /// three vertex indices per triangle (relative to the cluster)
+    pub vertex_indices: Vec<u16>,
+    /// quantized vertex positions
+    pub p: Vec<[u16; 3]>,
+    /// octahedral encoded normal vectors (can be empty)
+    pub n: Vec<u32>,
+    /// octahedral encoded tangent vectors (can be empty)
+    pub s: Vec<u32>,
+    /// texture coordinates (can be empty)
+    pub uv: Vec<Point2f>,
+    pub alpha_mask: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    pub shadow_alpha_mask: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    pub object_to_world: Transform,
+    pub world_to_object: Transform,
+    pub reverse_orientation: bool,
+    pub transform_swaps_handedness: bool,
+}
+
+impl CompressedTriangleMesh {
+    pub fn new(mesh: &TriangleMesh) -> Self {
+        // fine grid over the bounds of the mesh
+        let mut bounds: Bounds3f = Bounds3f::default();
+        for p in mesh.p.iter() {
+            bounds = bnd3_union_pnt3f(&bounds, p);
+        }
+        let extent: Vector3f = if mesh.p.is_empty() {
+            Vector3f::default()
+        } else {
+            bounds.diagonal()
+        };
+        let grid_max: Float = GRID_MAX as Float;
+        let to_grid = |v: Float, min: Float, extent: Float| -> u32 {
+            if extent > 0.0 as Float {
+                ((v - min) / extent * grid_max)
+                    .round()
+                    .max(0.0 as Float)
+                    .min(grid_max) as u32
+            } else {
+                0_u32
+            }
+        };
+        let grid: Vec<[u32; 3]> = mesh
+            .p
+            .iter()
+            .map(|p| {
+                [
+                    to_grid(p.x, bounds.p_min.x, extent.x),
+                    to_grid(p.y, bounds.p_min.y, extent.y),
+                    to_grid(p.z, bounds.p_min.z, extent.z),
+                ]
+            })
+            .collect();
+        // sort the triangles along a Morton curve (of their centroids),
+        // so that consecutive triangles form compact clusters
+        let mut order: Vec<(u32, u32)> = (0..mesh.n_triangles)
+            .map(|t| {
+                let mut centroid: Vector3f = Vector3f::default();
+                for vi in &mesh.vertex_indices[3 * t as usize..3 * t as usize + 3] {
+                    let g: &[u32; 3] = &grid[*vi as usize];
+                    centroid += Vector3f {
+                        x: g[0] as Float,
+                        y: g[1] as Float,
+                        z: g[2] as Float,
+                    };
+                }
+                // 10 bits per axis
+                (
+                    encode_morton_3(&(centroid * (1024.0 as Float / (3.0 * grid_max)))),
+                    t,
+                )
+            })
+            .collect();
+        order.sort_unstable();
+        // the (distinct) vertices of each cluster
+        let clusters: Vec<Vec<u32>> = order
+            .chunks(CLUSTER_TRIANGLES)
+            .map(|triangles| {
+                let mut vertices: Vec<u32> = Vec::new();
+                let mut seen: HashMap<u32, ()> = HashMap::new();
+                for (_code, t) in triangles {
+                    for vi in &mesh.vertex_indices[3 * *t as usize..3 * *t as usize + 3] {
+                        if seen.insert(*vi, ()).is_none() {
+                            vertices.push(*vi);
+                        }
+                    }
+                }
+                vertices
+            })
+            .collect();
+        // find the finest spacing for each cluster, which lets all its
+        // (snapped) vertices be stored with 16 bits; snapping a shared
+        // vertex to a coarser spacing can grow a cluster, so repeat
+        // until nothing changes
+        let mut shifts: Vec<[u8; 3]> = vec![[0_u8; 3]; clusters.len()];
+        let (snapped, origins) = loop {
+            let mut vertex_shifts: Vec<[u8; 3]> = vec![[0_u8; 3]; grid.len()];
+            for (vertices, shift) in clusters.iter().zip(shifts.iter()) {
+                for v in vertices {
+                    for axis in 0..3 {
+                        let vs: &mut u8 = &mut vertex_shifts[*v as usize][axis];
+                        *vs = (*vs).max(shift[axis]);
+                    }
+                }
+            }
+            let snapped: Vec<[u32; 3]> = grid
+                .iter()
+                .zip(vertex_shifts.iter())
+                .map(|(g, vs)| {
+                    [
+                        snap_to_grid(g[0], vs[0]),
+                        snap_to_grid(g[1], vs[1]),
+                        snap_to_grid(g[2], vs[2]),
+                    ]
+                })
+                .collect();
+            let mut changed: bool = false;
+            let mut origins: Vec<[u32; 3]> = Vec::with_capacity(clusters.len());
+            for (vertices, shift) in clusters.iter().zip(shifts.iter_mut()) {
+                let mut origin: [u32; 3] = [0_u32; 3];
+                for axis in 0..3 {
+                    let min: u32 = vertices
+                        .iter()
+                        .map(|v| snapped[*v as usize][axis])
+                        .min()
+                        .unwrap_or(0);
+                    let max: u32 = vertices
+                        .iter()
+                        .map(|v| snapped[*v as usize][axis])
+                        .max()
+                        .unwrap_or(0);
+                    origin[axis] = (min >> shift[axis]) << shift[axis];
+                    while (max - origin[axis]) >> shift[axis] > u16::MAX as u32 {
+                        shift[axis] += 1;
+                        origin[axis] = (min >> shift[axis]) << shift[axis];
+                        changed = true;
+                    }
+                }
+                origins.push(origin);
+            }
+            if !changed {
+                break (snapped, origins);
+            }
+        };
+        let mut compressed: CompressedTriangleMesh = CompressedTriangleMesh {
+            n_triangles: mesh.n_triangles,
+            bounds,
+            grid_step: extent / grid_max,
+            cluster_offsets: Vec::with_capacity(clusters.len()),
+            cluster_origins: origins,
+            cluster_shifts: shifts,
+            vertex_indices: Vec::with_capacity(3 * mesh.n_triangles as usize),
+            p: Vec::with_capacity(mesh.p.len()),
+            n: Vec::new(),
+            s: Vec::new(),
+            uv: Vec::new(),
+            alpha_mask: mesh.alpha_mask.clone(),
+            shadow_alpha_mask: mesh.shadow_alpha_mask.clone(),
+            object_to_world: mesh.object_to_world,
+            world_to_object: mesh.world_to_object,
+            reverse_orientation: mesh.reverse_orientation,
+            transform_swaps_handedness: mesh.transform_swaps_handedness,
+        };
+        for (c, (triangles, vertices)) in order
+            .chunks(CLUSTER_TRIANGLES)
+            .zip(clusters.iter())
+            .enumerate()
+        {
+            compressed.cluster_offsets.push(compressed.p.len() as u32);
+            let origin: [u32; 3] = compressed.cluster_origins[c];
+            let shift: [u8; 3] = compressed.cluster_shifts[c];
+            let mut local_indices: HashMap<u32, u16> = HashMap::with_capacity(vertices.len());
+            for (local, vi) in vertices.iter().enumerate() {
+                local_indices.insert(*vi, local as u16);
+                let v: usize = *vi as usize;
+                let g: &[u32; 3] = &snapped[v];
+                compressed.p.push([
+                    ((g[0] - origin[0]) >> shift[0]) as u16,
+                    ((g[1] - origin[1]) >> shift[1]) as u16,
+                    ((g[2] - origin[2]) >> shift[2]) as u16,
+                ]);
+                if !mesh.n.is_empty() {
+                    compressed
+                        .n
+                        .push(encode_octahedral(&Vector3f::from(mesh.n[v])));
+                }
+                if !mesh.s.is_empty() {
+                    compressed.s.push(encode_octahedral(&mesh.s[v]));
+                }
+                if !mesh.uv.is_empty() {
+                    compressed.uv.push(mesh.uv[v]);
+                }
+            }
+            for (_code, t) in triangles {
+                for vi in &mesh.vertex_indices[3 * *t as usize..3 * *t as usize + 3] {
+                    compressed.vertex_indices.push(local_indices[vi]);
+                }
+            }
+        }
+        compressed
+    }
+    /// Number of bytes used for the vertex and index data.
+    pub fn memory_usage(&self) -> usize {
+        self.cluster_offsets.len() * mem::size_of::<u32>()
+            + self.cluster_origins.len() * mem::size_of::<[u32; 3]>()
+            + self.cluster_shifts.len() * mem::size_of::<[u8; 3]>()
+            + self.vertex_indices.len() * mem::size_of::<u16>()
+            + self.p.len() * mem::size_of::<[u16; 3]>()
+            + self.n.len() * mem::size_of::<u32>()
+            + self.s.len() * mem::size_of::<u32>()
+            + self.uv.len() * mem::size_of::<Point2f>()
+    }
+    /// Number of bytes the vertex and index data of *mesh* uses.
+    pub fn uncompressed_memory_usage(mesh: &TriangleMesh) -> usize {
+        mesh.vertex_indices.len() * mem::size_of::<u32>()
+            + mesh.p.len() * mem::size_of::<Point3f>()
+            + mesh.n.len() * mem::size_of::<Normal3f>()
+            + mesh.s.len() * mem::size_of::<Vector3f>()
+            + mesh.uv.len() * mem::size_of::<Point2f>()
+    }
+    fn vertices(&self, triangle: u32) -> [usize; 3] {
+        let offset: usize = self.cluster_offsets[triangle as usize / CLUSTER_TRIANGLES] as usize;
+        let idx: usize = 3 * triangle as usize;
+        [
+            offset + self.vertex_indices[idx] as usize,
+            offset + self.vertex_indices[idx + 1] as usize,
+            offset + self.vertex_indices[idx + 2] as usize,
+        ]
+    }
+    fn position(&self, cluster: usize, v: usize) -> Point3f {
+        let q: &[u16; 3] = &self.p[v];
+        let origin: &[u32; 3] = &self.cluster_origins[cluster];
+        let shift: &[u8; 3] = &self.cluster_shifts[cluster];
+        let g =
+            |axis: usize| -> Float { (origin[axis] + ((q[axis] as u32) << shift[axis])) as Float };
+        Point3f {
+            x: self.bounds.p_min.x + g(0) * self.grid_step.x,
+            y: self.bounds.p_min.y + g(1) * self.grid_step.y,
+            z: self.bounds.p_min.z + g(2) * self.grid_step.z,
+        }
+    }
+    pub fn get_positions(&self, triangle: u32) -> [Point3f; 3] {
+        let cluster: usize = triangle as usize / CLUSTER_TRIANGLES;
+        let v: [usize; 3] = self.vertices(triangle);
+        [
+            self.position(cluster, v[0]),
+            self.position(cluster, v[1]),
+            self.position(cluster, v[2]),
+        ]
+    }
+    pub fn get_normals(&self, triangle: u32) -> Option<[Normal3f; 3]> {
+        if self.n.is_empty() {
+            None
+        } else {
+            let v: [usize; 3] = self.vertices(triangle);
+            Some([
+                Normal3f::from(decode_octahedral(self.n[v[0]])),
+                Normal3f::from(decode_octahedral(self.n[v[1]])),
+                Normal3f::from(decode_octahedral(self.n[v[2]])),
+            ])
+        }
+    }
+    pub fn get_tangents(&self, triangle: u32) -> Option<[Vector3f; 3]> {
+        if self.s.is_empty() {
+            None
+        } else {
+            let v: [usize; 3] = self.vertices(triangle);
+            Some([
+                decode_octahedral(self.s[v[0]]),
+                decode_octahedral(self.s[v[1]]),
+                decode_octahedral(self.s[v[2]]),
+            ])
+        }
+    }
+    pub fn get_uvs(&self, triangle: u32) -> [Point2f; 3] {
+        if self.uv.is_empty() {
+            [
+                Point2f { x: 0.0, y: 0.0 },
+                Point2f { x: 1.0, y: 0.0 },
+                Point2f { x: 1.0, y: 1.0 },
+            ]
+        } else {
+            let v: [usize; 3] = self.vertices(triangle);
+            [self.uv[v[0]], self.uv[v[1]], self.uv[v[2]]]
+        }
+    }
+    pub fn world_bound(&self, triangle: u32) -> Bounds3f {
+        let p: [Point3f; 3] = self.get_positions(triangle);
+        bnd3_union_pnt3f(&Bounds3f::new(p[0], p[1]), &p[2])
+    }
+    pub fn intersect(
+        &self,
+        triangle: u32,
+        ray: &Ray,
+        t_hit: &mut Float,
+        isect: &mut SurfaceInteraction,
+    ) -> bool {
+        let p: [Point3f; 3] = self.get_positions(triangle);
+        if let Some((t, b)) = intersect_triangle(ray, &p) {
+            let n: Option<[Normal3f; 3]> = self.get_normals(triangle);
+            let s: Option<[Vector3f; 3]> = self.get_tangents(triangle);
+            if triangle_interaction(
+                ray,
+                &p,
+                &self.get_uvs(triangle),
+                n.as_ref(),
+                s.as_ref(),
+                b,
+                self.reverse_orientation ^ self.transform_swaps_handedness,
+                &self.alpha_mask,
+                isect,
+            ) {
+                *t_hit = t;
+                return true;
+            }
+        }
+        false
+    }
+    pub fn intersect_p(&self, triangle: u32, ray: &Ray) -> bool {
+        let p: [Point3f; 3] = self.get_positions(triangle);
+        if let Some((_t, b)) = intersect_triangle(ray, &p) {
+            triangle_alpha_test_p(
+                ray,
+                &p,
+                &self.get_uvs(triangle),
+                b,
+                &self.alpha_mask,
+                &self.shadow_alpha_mask,
+            )
+        } else {
+            false
+        }
+    }
+}
@@ -1,22 +1,29 @@
 // std
+use std::f32::consts::PI;
 use std::sync::Arc;
 // pbrt
 use crate::core::geometry::{
-    bnd3_expand, bnd3_union_bnd3f, nrm_abs_dot_vec3f, nrm_cross_vec3, nrm_dot_nrmf,
-    pnt3_distance_squaredf, pnt3_distancef, pnt3_lerp, vec2_dotf, vec3_coordinate_system,
-    vec3_cross_vec3,
+    bnd3_expand, bnd3_union_bnd3f, nrm_abs_dot_vec3f, nrm_cross_vec3, nrm_dot_nrmf, nrm_dot_vec3f,
+    pnt3_distance_squaredf, pnt3_lerp, vec2_dotf, vec3_coordinate_system, vec3_cross_vec3,
+};
+use crate::core::geometry::{
+    Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector2f, Vector3f, XYEnum,
 };
-use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector2f, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
 use crate::core::material::Material;
 use crate::core::paramset::ParamSet;
 use crate::core::pbrt::Float;
 use crate::core::pbrt::{clamp_t, float_to_bits, lerp};
+use crate::core::sampling::Distribution1D;
 use crate::core::shape::Shape;
 use crate::core::transform::Transform;
 
 // see curve.h
 
+/// Number of intervals of the piecewise constant distribution used to
+/// sample points on a curve segment.
+const CURVE_SAMPLE_INTERVALS: usize = 16;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum CurveType {
     Flat,
@@ -74,6 +81,8 @@ pub struct Curve {
     pub common: Arc<CurveCommon>,
     pub u_min: Float,
     pub u_max: Float,
+    /// distribution of the points sampled along the segment
+    area_distribution: Distribution1D,
     // inherited from class Shape (see shape.h)
     pub object_to_world: Transform,
     pub world_to_object: Transform,
@@ -91,18 +100,21 @@ impl Curve {
         u_min: Float,
         u_max: Float,
     ) -> Self {
-        Curve {
+        let mut curve: Curve = Curve {
             // Curve
             common,
             u_min,
             u_max,
+            area_distribution: Distribution1D::default(),
             // Shape
             object_to_world,
             world_to_object,
             reverse_orientation,
             transform_swaps_handedness: object_to_world.swaps_handedness(),
             material: None,
-        }
+        };
+        curve.area_distribution = curve.compute_area_distribution();
+        curve
     }
     pub fn create(
         o2w: Transform,
@@ -152,6 +164,7 @@ impl Curve {
 
         if depth > 0_i32 {
             // split curve segment into sub-segments and test for intersection
+            let mut hit: bool = false;
             let mut cp_split: [Point3f; 7] = [Point3f::default(); 7];
             subdivide_bezier(cp, &mut cp_split);
 
@@ -204,14 +217,10 @@ impl Curve {
                     t_hit,
                     isect,
                 ) {
-                    // If we found an intersection and this is a shadow ray,
-                    // we can exit out immediately.
-                    if *t_hit == 0.0 as Float {
-                        true;
-                    }
+                    hit = true;
                 }
             }
-            false
+            hit
         } else {
             // intersect ray with curve segment
 
@@ -255,11 +264,7 @@ impl Curve {
             let mut n_hit: Normal3f = Normal3f::default();
             if self.common.curve_type == CurveType::Ribbon {
                 // scale _hitWidth_ based on ribbon orientation
-                let sin0: Float = ((1.0 as Float - u) * self.common.normal_angle).sin()
-                    * self.common.inv_sin_normal_angle;
-                let sin1: Float =
-                    (u * self.common.normal_angle).sin() * self.common.inv_sin_normal_angle;
-                n_hit = self.common.n[0] * sin0 + self.common.n[1] * sin1;
+                n_hit = self.ribbon_normal(u);
                 hit_width *= nrm_abs_dot_vec3f(&n_hit, &ray.d) / ray_length;
             }
 
@@ -333,11 +338,17 @@ impl Curve {
                 ray.time,
                 None,
             );
+            if self.common.curve_type != CurveType::Ribbon
+                && nrm_dot_vec3f(&si.common.n, &ray.d) > 0.0 as Float
+            {
+                // flat and cylinder curves always face the viewer
+                si.common.n = -si.common.n;
+                si.shading.n = -si.shading.n;
+            }
             self.object_to_world.transform_surface_interaction(&mut si);
-            // if let Some(ref shape) = si.shape {
-            //     isect.shape = Some(shape.clone());
-            // }
-            // }
+            *isect = si;
+            // only accept closer hits from the remaining sub-segments
+            ray.t_max.set(*t_hit);
             // TODO: ++n_hits;
             true
         }
@@ -490,24 +501,34 @@ impl Curve {
         self.object_to_world
     }
     pub fn area(&self) -> Float {
-        // compute object-space control points for curve segment, _cp_obj_
-        let mut cp_obj: [Point3f; 4] = [Point3f::default(); 4];
-        cp_obj[0] = blossom_bezier(&self.common.cp_obj, self.u_min, self.u_min, self.u_min);
-        cp_obj[1] = blossom_bezier(&self.common.cp_obj, self.u_min, self.u_min, self.u_max);
-        cp_obj[2] = blossom_bezier(&self.common.cp_obj, self.u_min, self.u_max, self.u_max);
-        cp_obj[3] = blossom_bezier(&self.common.cp_obj, self.u_max, self.u_max, self.u_max);
-        let width0: Float = lerp(self.u_min, self.common.width[0], self.common.width[1]);
-        let width1: Float = lerp(self.u_max, self.common.width[0], self.common.width[1]);
-        let avg_width: Float = (width0 + width1) * 0.5 as Float;
-        let mut approx_length: Float = 0.0 as Float;
-        for i in 0..3 {
-            approx_length += pnt3_distancef(&cp_obj[i], &cp_obj[i + 1]);
+        // integrate the (world space) area element with the midpoint rule
+        let du: Float = (self.u_max - self.u_min) / CURVE_SAMPLE_INTERVALS as Float;
+        let mut area: Float = 0.0 as Float;
+        for i in 0..CURVE_SAMPLE_INTERVALS {
+            let u: Float = self.u_min + (i as Float + 0.5 as Float) * du;
+            let (_pc, dpdu, dpdv) = self.surface_frame(u, None);
+            area += self.world_area_element(&dpdu, &dpdv) * du;
         }
-        approx_length * avg_width
+        area
+    }
+    pub fn sample(&self, u: Point2f, pdf: &mut Float) -> InteractionCommon {
+        self.sample_surface(u, None, pdf)
     }
-    pub fn sample(&self, _u: Point2f, _pdf: &mut Float) -> InteractionCommon {
-        println!("FATAL: Curve::sample not implemented.");
-        InteractionCommon::default()
+    /// Area density of [sample()](#method.sample) at the point *it*
+    /// on the curve. Flat and cylinder curves get sampled as
+    /// cylinders of the curve's width.
+    pub fn pdf(&self, it: &InteractionCommon) -> Float {
+        let u: Float = self.closest_u(&self.world_to_object.transform_point(&it.p));
+        let (_pc, dpdu, dpdv) = self.surface_frame(u, None);
+        let mut area_element: Float = self.world_area_element(&dpdu, &dpdv);
+        if self.common.curve_type != CurveType::Ribbon {
+            area_element *= PI;
+        }
+        if area_element > 0.0 as Float {
+            self.pdf_u(u) / area_element
+        } else {
+            0.0 as Float
+        }
     }
     pub fn sample_with_ref_point(
         &self,
@@ -515,7 +536,7 @@ impl Curve {
         u: Point2f,
         pdf: &mut Float,
     ) -> InteractionCommon {
-        let intr: InteractionCommon = self.sample(u, pdf);
+        let intr: InteractionCommon = self.sample_surface(u, Some(&iref.p), pdf);
         let mut wi: Vector3f = intr.p - iref.p;
         if wi.length_squared() == 0.0 as Float {
             *pdf = 0.0 as Float;
@@ -539,10 +560,22 @@ impl Curve {
         let mut t_hit: Float = 0.0;
         let mut isect_light: SurfaceInteraction = SurfaceInteraction::default();
         if self.intersect(&ray, &mut t_hit, &mut isect_light) {
+            // evaluate the area density of _sample_surface()_ for the
+            // surface the reference point sees
+            let u: Float = isect_light.uv.x;
+            let viewer: Point3f = self.world_to_object.transform_point(iref.get_p());
+            let (_pc, dpdu, dpdv) = self.surface_frame(u, Some(&viewer));
+            let n: Normal3f = Normal3f::from(vec3_cross_vec3(
+                &self.object_to_world.transform_vector(&dpdu),
+                &self.object_to_world.transform_vector(&dpdv),
+            ))
+            .normalize();
+            let pdf_area: Float = self.pdf_u(u) / self.world_area_element(&dpdu, &dpdv);
             // convert light sample weight to solid angle measure
             let mut pdf: Float = pnt3_distance_squaredf(&iref.get_p(), &isect_light.common.p)
-                / (nrm_abs_dot_vec3f(&isect_light.common.n, &-(*wi)) * self.area());
-            if pdf.is_infinite() {
+                * pdf_area
+                / nrm_abs_dot_vec3f(&n, &-(*wi));
+            if pdf.is_infinite() || pdf.is_nan() {
                 pdf = 0.0 as Float;
             }
             pdf
@@ -550,6 +583,180 @@ impl Curve {
             0.0 as Float
         }
     }
+
+    fn width_at(&self, u: Float) -> Float {
+        lerp(u, self.common.width[0], self.common.width[1])
+    }
+    /// Spherically interpolated normal of a ribbon curve.
+    fn ribbon_normal(&self, u: Float) -> Normal3f {
+        if self.common.normal_angle == 0.0 as Float {
+            return self.common.n[0];
+        }
+        let sin0: Float = ((1.0 as Float - u) * self.common.normal_angle).sin()
+            * self.common.inv_sin_normal_angle;
+        let sin1: Float = (u * self.common.normal_angle).sin() * self.common.inv_sin_normal_angle;
+        self.common.n[0] * sin0 + self.common.n[1] * sin1
+    }
+    /// Returns the (object space) point on the curve at *u* and the
+    /// partial derivatives of the surface which gets intersected
+    /// there. Flat and cylinder curves are ribbons facing the
+    /// *viewer*; without one an arbitrary orientation is used.
+    fn surface_frame(&self, u: Float, viewer: Option<&Point3f>) -> (Point3f, Vector3f, Vector3f) {
+        let mut dpdu: Vector3f = Vector3f::default();
+        let pc: Point3f = eval_bezier(&self.common.cp_obj, u, Some(&mut dpdu));
+        let mut dir: Vector3f = if self.common.curve_type == CurveType::Ribbon {
+            nrm_cross_vec3(&self.ribbon_normal(u), &dpdu)
+        } else if let Some(viewer) = viewer {
+            vec3_cross_vec3(&(pc - *viewer), &dpdu)
+        } else {
+            Vector3f::default()
+        };
+        if dir.length_squared() == 0.0 as Float {
+            let mut dummy: Vector3f = Vector3f::default();
+            vec3_coordinate_system(&dpdu.normalize(), &mut dir, &mut dummy);
+        }
+        let dpdv: Vector3f = dir.normalize() * self.width_at(u);
+        (pc, dpdu, dpdv)
+    }
+    fn world_area_element(&self, dpdu: &Vector3f, dpdv: &Vector3f) -> Float {
+        vec3_cross_vec3(
+            &self.object_to_world.transform_vector(dpdu),
+            &self.object_to_world.transform_vector(dpdv),
+        )
+        .length()
+    }
+    /// Piecewise constant distribution over the segment's parametric
+    /// range, proportional to the (object space) area per unit *u*.
+    fn compute_area_distribution(&self) -> Distribution1D {
+        let mut func: Vec<Float> = Vec::with_capacity(CURVE_SAMPLE_INTERVALS);
+        for i in 0..CURVE_SAMPLE_INTERVALS {
+            let u: Float = lerp(
+                (i as Float + 0.5 as Float) / CURVE_SAMPLE_INTERVALS as Float,
+                self.u_min,
+                self.u_max,
+            );
+            let mut dpdu: Vector3f = Vector3f::default();
+            eval_bezier(&self.common.cp_obj, u, Some(&mut dpdu));
+            func.push(dpdu.length() * self.width_at(u));
+        }
+        Distribution1D::new(func)
+    }
+    /// Density of the parametric coordinate *u* chosen by
+    /// _sample_surface()_.
+    fn pdf_u(&self, u: Float) -> Float {
+        let distrib: &Distribution1D = &self.area_distribution;
+        if distrib.func_int == 0.0 as Float {
+            return 0.0 as Float;
+        }
+        let x: Float = (u - self.u_min) / (self.u_max - self.u_min);
+        let index: usize = clamp_t(
+            (x * CURVE_SAMPLE_INTERVALS as Float) as isize,
+            0_isize,
+            CURVE_SAMPLE_INTERVALS as isize - 1_isize,
+        ) as usize;
+        distrib.func[index] / (distrib.func_int * (self.u_max - self.u_min))
+    }
+    /// Parametric coordinate of the point on the segment's center line
+    /// closest to the (object space) point *p*.
+    fn closest_u(&self, p: &Point3f) -> Float {
+        let dist2 = |u: Float| -> Float {
+            pnt3_distance_squaredf(&eval_bezier(&self.common.cp_obj, u, None), p)
+        };
+        // find the closest of some points along the segment ...
+        let du: Float = (self.u_max - self.u_min) / CURVE_SAMPLE_INTERVALS as Float;
+        let mut u_closest: Float = self.u_min;
+        let mut d_closest: Float = Float::INFINITY;
+        for i in 0..=CURVE_SAMPLE_INTERVALS {
+            let u: Float = self.u_min + i as Float * du;
+            let d: Float = dist2(u);
+            if d < d_closest {
+                u_closest = u;
+                d_closest = d;
+            }
+        }
+        // ... and refine it with a ternary search around it
+        let mut lo: Float = (u_closest - du).max(self.u_min);
+        let mut hi: Float = (u_closest + du).min(self.u_max);
+        for _ in 0..20 {
+            let m1: Float = lo + (hi - lo) / 3.0 as Float;
+            let m2: Float = hi - (hi - lo) / 3.0 as Float;
+            if dist2(m1) < dist2(m2) {
+                hi = m2;
+            } else {
+                lo = m1;
+            }
+        }
+        0.5 as Float * (lo + hi)
+    }
+    /// Samples a point (roughly) uniformly on the curve's surface and
+    /// returns its area density in *pdf*.
+    fn sample_surface(
+        &self,
+        u: Point2f,
+        viewer: Option<&Point3f>,
+        pdf: &mut Float,
+    ) -> InteractionCommon {
+        let distrib: &Distribution1D = &self.area_distribution;
+        let mut pdf_x: Float = 0.0 as Float;
+        let x: Float = distrib.sample_continuous(u[XYEnum::X], Some(&mut pdf_x), None);
+        let cu: Float = lerp(x, self.u_min, self.u_max);
+        let viewer_obj: Option<Point3f> = viewer.map(|p| self.world_to_object.transform_point(p));
+        let (pc, dpdu, dpdv) = self.surface_frame(cu, viewer_obj.as_ref());
+        let width: Float = self.width_at(cu);
+        // flat and cylinder curves always face the viewer, so they
+        // radiate like a cylinder of the curve's width; without a
+        // viewer, sample a point on that cylinder
+        let cylinder: bool = viewer.is_none() && self.common.curve_type != CurveType::Ribbon;
+        let p_obj: Point3f;
+        let n_obj: Normal3f;
+        if cylinder {
+            let mut a: Vector3f = Vector3f::default();
+            let mut b: Vector3f = Vector3f::default();
+            vec3_coordinate_system(&dpdu.normalize(), &mut a, &mut b);
+            let phi: Float = 2.0 as Float * PI * u[XYEnum::Y];
+            let dir: Vector3f = a * phi.cos() + b * phi.sin();
+            p_obj = pc + dir * (0.5 as Float * width);
+            n_obj = Normal3f::from(dir);
+        } else {
+            p_obj = pc + dpdv * (u[XYEnum::Y] - 0.5 as Float);
+            n_obj = Normal3f::from(vec3_cross_vec3(&dpdu, &dpdv));
+        }
+        let p_obj_error: Vector3f = Vector3f {
+            x: 2.0 as Float * width,
+            y: 2.0 as Float * width,
+            z: 2.0 as Float * width,
+        };
+        let mut p_error: Vector3f = Vector3f::default();
+        let p: Point3f =
+            self.object_to_world
+                .transform_point_with_abs_error(&p_obj, &p_obj_error, &mut p_error);
+        let mut n: Normal3f = self.object_to_world.transform_normal(&n_obj).normalize();
+        if let Some(viewer) = viewer {
+            if self.common.curve_type != CurveType::Ribbon
+                && nrm_dot_vec3f(&n, &(*viewer - p)) < 0.0 as Float
+            {
+                n = -n;
+            }
+        }
+        // the cylinder's circumference is PI times its width
+        let mut area_element: Float = self.world_area_element(&dpdu, &dpdv);
+        if cylinder {
+            area_element *= PI;
+        }
+        *pdf = if area_element > 0.0 as Float {
+            pdf_x / ((self.u_max - self.u_min) * area_element)
+        } else {
+            0.0 as Float
+        };
+        InteractionCommon {
+            p,
+            time: 0.0 as Float,
+            p_error,
+            wo: Vector3f::default(),
+            n,
+            medium_interface: None,
+        }
+    }
 }
 
 pub fn create_curve_shape(
@@ -0,0 +1,181 @@
+//! True displacement of triangle meshes. The mesh gets uniformly
+//! tessellated (each triangle is split into four by inserting the
+//! edge midpoints) until all edges are shorter than a given length,
+//! then every vertex is moved along its normal by the value of a
+//! float texture.
+
+// std
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::hash::BuildHasher;
+use std::sync::Arc;
+// pbrt
+use crate::core::geometry::nrm_dot_nrmf;
+use crate::core::geometry::{Normal3f, Point2f, Point3f, Vector3f};
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::paramset::ParamSet;
+use crate::core::pbrt::Float;
+use crate::core::texture::Texture;
+use crate::shapes::triangle::{compute_vertex_normals, TriangleMesh};
+
+/// Never split a mesh more often than this (each level multiplies the
+/// number of triangles by four).
+const MAX_DISPLACEMENT_LEVELS: i32 = 6;
+
+/// Tessellate the (world space) triangle mesh until no edge is longer
+/// than *edge_length* and displace the resulting vertices along their
+/// normals by *scale* times the value of the *displacement* texture.
+/// Vertex normals are recomputed from the displaced geometry, tangent
+/// vectors are dropped. Deforming meshes move their end positions
+/// along with the tessellation and by the same displacement offsets.
+pub fn displace_triangle_mesh(
+    mesh: &TriangleMesh,
+    displacement: &Arc<dyn Texture<Float> + Send + Sync>,
+    scale: Float,
+    edge_length: Float,
+) -> Arc<TriangleMesh> {
+    let mut vertex_indices: Vec<u32> = mesh.vertex_indices.clone();
+    let mut p: Vec<Point3f> = mesh.p.clone();
+    let mut p_end: Vec<Point3f> = mesh.p_end.clone();
+    let mut uv: Vec<Point2f> = mesh.uv.clone();
+    // without shading normals displace along the smoothed geometric normals
+    let mut n: Vec<Normal3f> = if mesh.n.is_empty() {
+        compute_vertex_normals(&vertex_indices, &p)
+    } else {
+        mesh.n.clone()
+    };
+    // find out how often the triangles have to be split
+    let mut n_levels: i32 = 0;
+    if edge_length > 0.0 as Float {
+        let mut max_edge: Float = 0.0 as Float;
+        for tri in vertex_indices.chunks(3) {
+            for e in 0..3 {
+                let v0: usize = tri[e] as usize;
+                let v1: usize = tri[(e + 1) % 3] as usize;
+                max_edge = max_edge.max((p[v1] - p[v0]).length());
+            }
+        }
+        while n_levels < MAX_DISPLACEMENT_LEVELS
+            && max_edge / (1 << n_levels) as Float > edge_length
+        {
+            n_levels += 1;
+        }
+    }
+    for _level in 0..n_levels {
+        // split each triangle into four, share midpoints of common edges
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut new_indices: Vec<u32> = Vec::with_capacity(vertex_indices.len() * 4);
+        for tri in vertex_indices.chunks(3) {
+            let mut mid: [u32; 3] = [0_u32; 3];
+            for e in 0..3 {
+                let v0: u32 = tri[e];
+                let v1: u32 = tri[(e + 1) % 3];
+                let key: (u32, u32) = if v0 < v1 { (v0, v1) } else { (v1, v0) };
+                mid[e] = *midpoints.entry(key).or_insert_with(|| {
+                    let (a, b) = (v0 as usize, v1 as usize);
+                    p.push(Point3f {
+                        x: 0.5 as Float * (p[a].x + p[b].x),
+                        y: 0.5 as Float * (p[a].y + p[b].y),
+                        z: 0.5 as Float * (p[a].z + p[b].z),
+                    });
+                    if !p_end.is_empty() {
+                        p_end.push(Point3f {
+                            x: 0.5 as Float * (p_end[a].x + p_end[b].x),
+                            y: 0.5 as Float * (p_end[a].y + p_end[b].y),
+                            z: 0.5 as Float * (p_end[a].z + p_end[b].z),
+                        });
+                    }
+                    if !uv.is_empty() {
+                        uv.push(Point2f {
+                            x: 0.5 as Float * (uv[a].x + uv[b].x),
+                            y: 0.5 as Float * (uv[a].y + uv[b].y),
+                        });
+                    }
+                    let nm: Normal3f = n[a] + n[b];
+                    if nm.length_squared() > 0.0 as Float {
+                        n.push(nm.normalize());
+                    } else {
+                        n.push(n[a]);
+                    }
+                    (p.len() - 1) as u32
+                });
+            }
+            new_indices.extend_from_slice(&[tri[0], mid[0], mid[2]]);
+            new_indices.extend_from_slice(&[mid[0], tri[1], mid[1]]);
+            new_indices.extend_from_slice(&[mid[2], mid[1], tri[2]]);
+            new_indices.extend_from_slice(&[mid[0], mid[1], mid[2]]);
+        }
+        vertex_indices = new_indices;
+    }
+    // displace vertices along their normals
+    for i in 0..p.len() {
+        let mut si: SurfaceInteraction = SurfaceInteraction::default();
+        si.common.p = p[i];
+        si.common.n = n[i];
+        si.shading.n = n[i];
+        if !uv.is_empty() {
+            si.uv = uv[i];
+        }
+        let d: Float = displacement.evaluate(&si) * scale;
+        p[i] += Vector3f::from(n[i]) * d;
+        if !p_end.is_empty() {
+            p_end[i] += Vector3f::from(n[i]) * d;
+        }
+    }
+    // recompute normals, but keep them on the side of the original ones
+    let mut displaced_n: Vec<Normal3f> = compute_vertex_normals(&vertex_indices, &p);
+    for (dn, on) in displaced_n.iter_mut().zip(n.iter()) {
+        if nrm_dot_nrmf(dn, on) < 0.0 as Float {
+            *dn = -*dn;
+        }
+    }
+    let n_triangles: usize = vertex_indices.len() / 3;
+    let n_vertices: usize = p.len();
+    let mut displaced: TriangleMesh = TriangleMesh::new(
+        mesh.object_to_world,
+        mesh.world_to_object,
+        mesh.reverse_orientation,
+        n_triangles.try_into().unwrap(),
+        vertex_indices,
+        n_vertices.try_into().unwrap(),
+        p,          // in world space
+        Vec::new(), // in world space
+        displaced_n,
+        uv,
+        mesh.alpha_mask.clone(),
+        mesh.shadow_alpha_mask.clone(),
+    );
+    if !p_end.is_empty() {
+        displaced.set_vertex_motion(p_end, mesh.time_start, mesh.time_end);
+    }
+    Arc::new(displaced)
+}
+
+/// Tessellate and displace a triangle mesh if the shape parameters
+/// name a **"displacement"** float texture (scaled by
+/// **"displacementscale"**, edges are split down to
+/// **"displacementedgelength"**).
+pub fn displace_mesh_from_params<S: BuildHasher>(
+    mesh: Arc<TriangleMesh>,
+    params: &ParamSet,
+    float_textures: &HashMap<String, Arc<dyn Texture<Float> + Send + Sync>, S>,
+) -> Arc<TriangleMesh> {
+    let displacement_tex_name: String = params.find_texture("displacement");
+    if displacement_tex_name.is_empty() {
+        return mesh;
+    }
+    match float_textures.get(displacement_tex_name.as_str()) {
+        Some(float_texture) => {
+            let scale: Float = params.find_one_float("displacementscale", 1.0 as Float);
+            let edge_length: Float = params.find_one_float("displacementedgelength", 1.0 as Float);
+            displace_triangle_mesh(&mesh, float_texture, scale, edge_length)
+        }
+        None => {
+            println!(
+                "Couldn't find float texture {:?} for \"displacement\" parameter",
+                displacement_tex_name.as_str()
+            );
+            mesh
+        }
+    }
+}
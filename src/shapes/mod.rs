@@ -39,6 +39,10 @@
 //! positions where each individual triangle just stores three offsets
 //! into this array for its three vertices.
 //!
+//! For huge meshes a **CompressedTriangleMesh** stores quantized
+//! positions, octahedral encoded normals and 16-bit vertex indices
+//! instead (see the **compressedmesh** module).
+//!
 //! ## Disks
 //!
 //! The disk is an interesting quadric since it has a particularly
@@ -59,9 +63,11 @@
 //! TODO
 //!
 
+pub mod compressedmesh;
 pub mod curve;
 pub mod cylinder;
 pub mod disk;
+pub mod displacement;
 pub mod loopsubdiv;
 pub mod nurbs;
 pub mod plymesh;
@@ -158,3 +158,84 @@ pub fn nurbs_evaluate_surface(
         z: p.z / p.w,
     }
 }
+
+/// Computes parameter values along one direction of a NURBS surface
+/// for a curvature-driven tessellation. Starting with the knot spans
+/// in `[t0, t1]`, the span with the largest distance between the
+/// surface and the chords between neighbouring parameter values (for
+/// all `cross` parameters of the other direction) gets halved, until
+/// that distance stays below `max_error` everywhere or the result
+/// would exceed `max_segments` segments. `eval` maps `(t, cross)` to
+/// a (world space) surface point.
+pub fn nurbs_adaptive_dicing<F>(
+    knot: &[Float],
+    t0: Float,
+    t1: Float,
+    cross: &[Float],
+    max_error: Float,
+    max_segments: usize,
+    eval: F,
+) -> Vec<Float>
+where
+    F: Fn(Float, Float) -> Point3f,
+{
+    struct Span {
+        a: Float,
+        b: Float,
+        n: usize,
+        error: Float,
+    }
+    let span_error = |a: Float, b: Float, n: usize| -> Float {
+        let mut error: Float = 0.0 as Float;
+        for s in cross.iter() {
+            for i in 0..n {
+                let ta: Float = a + (b - a) * i as Float / n as Float;
+                let tb: Float = a + (b - a) * (i + 1) as Float / n as Float;
+                let pa: Point3f = eval(ta, *s);
+                let pb: Point3f = eval(tb, *s);
+                let pm: Point3f = eval(0.5 as Float * (ta + tb), *s);
+                let chord: Point3f = (pa + pb) * 0.5 as Float;
+                error = error.max((pm - chord).length());
+            }
+        }
+        error
+    };
+    // the knot spans form the initial breakpoints
+    let mut breaks: Vec<Float> = vec![t0];
+    for k in knot.iter() {
+        if *k > t0 && *k < t1 && *k > breaks[breaks.len() - 1] {
+            breaks.push(*k);
+        }
+    }
+    breaks.push(t1);
+    let mut spans: Vec<Span> = breaks
+        .windows(2)
+        .map(|w| Span {
+            a: w[0],
+            b: w[1],
+            n: 1,
+            error: span_error(w[0], w[1], 1),
+        })
+        .collect();
+    let mut n_segments: usize = spans.len();
+    while let Some(worst) = spans.iter_mut().max_by(|x, y| {
+        x.error
+            .partial_cmp(&y.error)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    }) {
+        if worst.error <= max_error || n_segments + worst.n > max_segments {
+            break;
+        }
+        n_segments += worst.n;
+        worst.n *= 2;
+        worst.error = span_error(worst.a, worst.b, worst.n);
+    }
+    let mut ts: Vec<Float> = Vec::with_capacity(n_segments + 1);
+    for span in spans.iter() {
+        for i in 0..span.n {
+            ts.push(span.a + (span.b - span.a) * i as Float / span.n as Float);
+        }
+    }
+    ts.push(t1);
+    ts
+}
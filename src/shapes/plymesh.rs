@@ -18,6 +18,7 @@ use crate::core::pbrt::Float;
 use crate::core::shape::Shape;
 use crate::core::texture::Texture;
 use crate::core::transform::Transform;
+use crate::shapes::displacement::displace_mesh_from_params;
 use crate::shapes::triangle::{Triangle, TriangleMesh};
 use crate::textures::constant::ConstantTexture;
 
@@ -28,6 +29,7 @@ pub fn create_ply_mesh<S: BuildHasher>(
     params: &ParamSet,
     float_textures: Arc<HashMap<String, Arc<dyn Texture<Float> + Send + Sync>, S>>,
     search_directory: Option<&PathBuf>,
+    shutter_times: Option<(Float, Float)>,
 ) -> Vec<Arc<Shape>> {
     let mut filename: String = params.find_one_string("filename", String::new());
     if let Some(ref search_directory) = search_directory {
@@ -60,8 +62,10 @@ pub fn create_ply_mesh<S: BuildHasher>(
     let mut p: Vec<Point3f> = Vec::new();
     let mut n: Vec<Normal3f> = Vec::new();
     let mut uvs: Vec<Point2f> = Vec::new();
+    let mut velocities: Vec<Vector3f> = Vec::new();
     let mut has_normals: bool = false;
     let mut has_uvs: bool = false;
+    let mut has_velocities: bool = false;
     let mut tm_vertex_indices: Vec<u32> = Vec::new();
     for (name, list) in payload.into_iter() {
         match name.as_ref() {
@@ -70,6 +74,7 @@ pub fn create_ply_mesh<S: BuildHasher>(
                     let mut pnt: Point3f = Point3f::default();
                     let mut nrm: Normal3f = Normal3f::default();
                     let mut pt2: Point2f = Point2f::default();
+                    let mut vel: Vector3f = Vector3f::default();
                     for (name2, list2) in elem.into_iter() {
                         match name2.as_ref() {
                             "x" => {
@@ -117,6 +122,24 @@ pub fn create_ply_mesh<S: BuildHasher>(
                                     pt2.y = y;
                                 }
                             }
+                            "vx" => {
+                                has_velocities = true;
+                                if let ply::Property::Float(x) = list2 {
+                                    vel.x = x;
+                                }
+                            }
+                            "vy" => {
+                                has_velocities = true;
+                                if let ply::Property::Float(y) = list2 {
+                                    vel.y = y;
+                                }
+                            }
+                            "vz" => {
+                                has_velocities = true;
+                                if let ply::Property::Float(z) = list2 {
+                                    vel.z = z;
+                                }
+                            }
                             _ => {
                                 println!("name2 = {:?}", name2);
                                 unreachable!();
@@ -124,6 +147,9 @@ pub fn create_ply_mesh<S: BuildHasher>(
                         }
                     }
                     p.push(pnt);
+                    if has_velocities {
+                        velocities.push(vel);
+                    }
                     if has_normals {
                         n.push(nrm);
                     }
@@ -267,7 +293,7 @@ pub fn create_ply_mesh<S: BuildHasher>(
     } else if params.find_one_float("shadowalpha", 1.0 as Float) == 0.0 as Float {
         shadow_alpha_tex = Some(Arc::new(ConstantTexture::new(0.0 as Float)));
     }
-    let mesh = Arc::new(TriangleMesh::new(
+    let mut mesh = TriangleMesh::new(
         *o2w,
         *w2o,
         reverse_orientation,
@@ -280,7 +306,24 @@ pub fn create_ply_mesh<S: BuildHasher>(
         uvs,
         alpha_tex,
         shadow_alpha_tex,
-    ));
+    );
+    if !velocities.is_empty() {
+        assert!(velocities.len() == p.len());
+        if let Some((time_start, time_end)) = shutter_times {
+            // move the vertices along their (object space) velocities
+            // until the shutter closes
+            let mut p_end_ws: Vec<Point3f> = Vec::with_capacity(n_vertices);
+            for (item, vel) in p.iter().zip(velocities.iter()) {
+                p_end_ws.push(o2w.transform_point(&(*item + *vel * (time_end - time_start))));
+            }
+            mesh.set_vertex_motion(p_end_ws, time_start, time_end);
+        } else {
+            println!(
+                "WARNING: vertex motion is not supported for area lights; ignoring velocities"
+            );
+        }
+    }
+    let mesh = displace_mesh_from_params(Arc::new(mesh), params, &*float_textures);
     let mut shapes: Vec<Arc<Shape>> = Vec::new();
     for id in 0..mesh.n_triangles {
         let triangle = Arc::new(Shape::Trngl(Triangle::new(
@@ -5,15 +5,15 @@ use std::sync::Arc;
 // pbrt
 use crate::core::geometry::{
     bnd3_union_pnt3f, nrm_abs_dot_vec3f, nrm_faceforward_nrm, pnt3_abs, pnt3_distance_squaredf,
-    pnt3_permutef, vec3_coordinate_system, vec3_cross_nrm, vec3_cross_vec3, vec3_max_componentf,
-    vec3_max_dimensionf, vec3_permutef,
+    pnt3_lerp, pnt3_permutef, vec3_coordinate_system, vec3_cross_nrm, vec3_cross_vec3,
+    vec3_dot_vec3f, vec3_max_componentf, vec3_max_dimensionf, vec3_permutef,
 };
 use crate::core::geometry::{
     Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector2f, Vector3f, XYEnum,
 };
 use crate::core::interaction::{Interaction, InteractionCommon, Shading, SurfaceInteraction};
-use crate::core::pbrt::gamma;
 use crate::core::pbrt::Float;
+use crate::core::pbrt::{clamp_t, gamma};
 // use crate::core::sampling::uniform_sample_triangle;
 use crate::core::texture::Texture;
 use crate::core::transform::Transform;
@@ -36,6 +36,15 @@ pub struct TriangleMesh {
     pub s: Vec<Vector3f>,
     /// an optional vector of paramtric (u, v) values (texture coordinates)
     pub uv: Vec<Point2f>,
+    /// an optional vector of vertex positions at *time_end* (can be empty)
+    pub p_end: Vec<Point3f>,
+    /// normal and tangent vectors at *time_end* (empty without
+    /// vertex motion or without *n* and *s*)
+    pub n_end: Vec<Normal3f>,
+    pub s_end: Vec<Vector3f>,
+    /// the times at which the vertices are located at *p* and *p_end*
+    pub time_start: Float,
+    pub time_end: Float,
     pub alpha_mask: Option<Arc<dyn Texture<Float> + Send + Sync>>,
     pub shadow_alpha_mask: Option<Arc<dyn Texture<Float> + Send + Sync>>,
     // inherited from class Shape (see shape.h)
@@ -74,10 +83,52 @@ impl TriangleMesh {
             n,
             s,
             uv,
+            p_end: Vec::new(),
+            n_end: Vec::new(),
+            s_end: Vec::new(),
+            time_start: 0.0 as Float,
+            time_end: 1.0 as Float,
             alpha_mask,
             shadow_alpha_mask,
         }
     }
+    /// Makes the mesh deform over time. The vertices are located at
+    /// *p* at *time_start* and move linearly to *p_end* at
+    /// *time_end*. Normal and tangent vectors follow the rotation of
+    /// the (smoothed) surface around each vertex.
+    pub fn set_vertex_motion(&mut self, p_end: Vec<Point3f>, time_start: Float, time_end: Float) {
+        assert_eq!(p_end.len(), self.p.len());
+        if !self.n.is_empty() || !self.s.is_empty() {
+            let g_start: Vec<Normal3f> = compute_vertex_normals(&self.vertex_indices, &self.p);
+            let g_end: Vec<Normal3f> = compute_vertex_normals(&self.vertex_indices, &p_end);
+            self.n_end = self
+                .n
+                .iter()
+                .enumerate()
+                .map(|(i, n)| {
+                    Normal3f::from(rotate_between(
+                        &Vector3f::from(g_start[i]),
+                        &Vector3f::from(g_end[i]),
+                        &Vector3f::from(*n),
+                    ))
+                })
+                .collect();
+            self.s_end = self
+                .s
+                .iter()
+                .enumerate()
+                .map(|(i, s)| {
+                    rotate_between(&Vector3f::from(g_start[i]), &Vector3f::from(g_end[i]), s)
+                })
+                .collect();
+        }
+        self.p_end = p_end;
+        self.time_start = time_start;
+        self.time_end = time_end;
+    }
+    pub fn has_vertex_motion(&self) -> bool {
+        !self.p_end.is_empty()
+    }
 }
 
 #[derive(Clone)]
@@ -108,6 +159,111 @@ impl Triangle {
             ]
         }
     }
+    pub fn get_mesh(&self) -> Arc<TriangleMesh> {
+        self.mesh.clone()
+    }
+    fn vertex_index(&self, i: usize) -> usize {
+        self.mesh.vertex_indices[(self.id * 3) as usize + i] as usize
+    }
+    pub fn get_positions(&self) -> [Point3f; 3] {
+        [
+            self.mesh.p[self.vertex_index(0)],
+            self.mesh.p[self.vertex_index(1)],
+            self.mesh.p[self.vertex_index(2)],
+        ]
+    }
+    pub fn has_vertex_motion(&self) -> bool {
+        self.mesh.has_vertex_motion()
+    }
+    /// Returns how far (between 0 and 1) a deforming mesh has moved
+    /// at the given *time*, or *None* for a static mesh.
+    fn motion_fraction(&self, time: Float) -> Option<Float> {
+        if !self.mesh.has_vertex_motion() {
+            return None;
+        }
+        let dt: Float = self.mesh.time_end - self.mesh.time_start;
+        if dt > 0.0 as Float {
+            Some(clamp_t(
+                (time - self.mesh.time_start) / dt,
+                0.0 as Float,
+                1.0 as Float,
+            ))
+        } else {
+            Some(0.0 as Float)
+        }
+    }
+    /// Returns the vertex positions at the given *time*, clamped to
+    /// the shutter interval of a deforming mesh.
+    pub fn get_positions_at(&self, time: Float) -> [Point3f; 3] {
+        let p: [Point3f; 3] = self.get_positions();
+        let t: Float = match self.motion_fraction(time) {
+            Some(t) => t,
+            None => return p,
+        };
+        let p_end: [Point3f; 3] = self.get_end_positions();
+        [
+            pnt3_lerp(t, &p[0], &p_end[0]),
+            pnt3_lerp(t, &p[1], &p_end[1]),
+            pnt3_lerp(t, &p[2], &p_end[2]),
+        ]
+    }
+    fn get_end_positions(&self) -> [Point3f; 3] {
+        [
+            self.mesh.p_end[self.vertex_index(0)],
+            self.mesh.p_end[self.vertex_index(1)],
+            self.mesh.p_end[self.vertex_index(2)],
+        ]
+    }
+    pub fn get_normals(&self) -> Option<[Normal3f; 3]> {
+        if self.mesh.n.is_empty() {
+            None
+        } else {
+            Some([
+                self.mesh.n[self.vertex_index(0)],
+                self.mesh.n[self.vertex_index(1)],
+                self.mesh.n[self.vertex_index(2)],
+            ])
+        }
+    }
+    pub fn get_tangents(&self) -> Option<[Vector3f; 3]> {
+        if self.mesh.s.is_empty() {
+            None
+        } else {
+            Some([
+                self.mesh.s[self.vertex_index(0)],
+                self.mesh.s[self.vertex_index(1)],
+                self.mesh.s[self.vertex_index(2)],
+            ])
+        }
+    }
+    /// Returns the normals at the given *time* (see
+    /// [get_positions_at()](struct.Triangle.html#method.get_positions_at)).
+    pub fn get_normals_at(&self, time: Float) -> Option<[Normal3f; 3]> {
+        let n: [Normal3f; 3] = self.get_normals()?;
+        match self.motion_fraction(time) {
+            Some(t) if !self.mesh.n_end.is_empty() => {
+                let lerp = |i: usize| -> Normal3f {
+                    n[i] * (1.0 as Float - t) + self.mesh.n_end[self.vertex_index(i)] * t
+                };
+                Some([lerp(0), lerp(1), lerp(2)])
+            }
+            _ => Some(n),
+        }
+    }
+    /// Returns the tangents at the given *time* (see
+    /// [get_positions_at()](struct.Triangle.html#method.get_positions_at)).
+    pub fn get_tangents_at(&self, time: Float) -> Option<[Vector3f; 3]> {
+        let s: [Vector3f; 3] = self.get_tangents()?;
+        match self.motion_fraction(time) {
+            Some(t) if !self.mesh.s_end.is_empty() => {
+                let lerp = |i: usize| -> Vector3f {
+                    s[i] * (1.0 as Float - t) + self.mesh.s_end[self.vertex_index(i)] * t
+                };
+                Some([lerp(0), lerp(1), lerp(2)])
+            }
+            _ => Some(s),
+        }
+    }
     // Shape
     pub fn object_bound(&self) -> Bounds3f {
         let idx1: usize = (self.id * 3) as usize;
@@ -115,13 +271,20 @@ impl Triangle {
         let p0: &Point3f = &self.mesh.p[idx[0] as usize];
         let p1: &Point3f = &self.mesh.p[idx[1] as usize];
         let p2: &Point3f = &self.mesh.p[idx[2] as usize];
-        bnd3_union_pnt3f(
+        let mut bounds: Bounds3f = bnd3_union_pnt3f(
             &Bounds3f::new(
                 self.mesh.world_to_object.transform_point(p0),
                 self.mesh.world_to_object.transform_point(p1),
             ),
             &self.mesh.world_to_object.transform_point(p2),
-        )
+        );
+        if self.mesh.has_vertex_motion() {
+            // enclose the triangle at both ends of the shutter interval
+            for p in self.get_end_positions().iter() {
+                bounds = bnd3_union_pnt3f(&bounds, &self.mesh.world_to_object.transform_point(p));
+            }
+        }
+        bounds
     }
     pub fn world_bound(&self) -> Bounds3f {
         let idx1: usize = (self.id * 3) as usize;
@@ -129,531 +292,57 @@ impl Triangle {
         let p0: &Point3f = &self.mesh.p[idx[0] as usize];
         let p1: &Point3f = &self.mesh.p[idx[1] as usize];
         let p2: &Point3f = &self.mesh.p[idx[2] as usize];
-        bnd3_union_pnt3f(&Bounds3f::new(*p0, *p1), p2)
+        let mut bounds: Bounds3f = bnd3_union_pnt3f(&Bounds3f::new(*p0, *p1), p2);
+        if self.mesh.has_vertex_motion() {
+            // enclose the triangle at both ends of the shutter interval
+            for p in self.get_end_positions().iter() {
+                bounds = bnd3_union_pnt3f(&bounds, p);
+            }
+        }
+        bounds
     }
     pub fn intersect(&self, ray: &Ray, t_hit: &mut Float, isect: &mut SurfaceInteraction) -> bool {
         // get triangle vertices in _p0_, _p1_, and _p2_
-        let idx1: usize = (self.id * 3) as usize;
-        let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
-        let p0: &Point3f = &self.mesh.p[idx[0] as usize];
-        let p1: &Point3f = &self.mesh.p[idx[1] as usize];
-        let p2: &Point3f = &self.mesh.p[idx[2] as usize];
-        // translate vertices based on ray origin
-        let mut p0t: Point3f = *p0
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        let mut p1t: Point3f = *p1
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        let mut p2t: Point3f = *p2
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        // permute components of triangle vertices and ray direction
-        let kz: usize = vec3_max_dimensionf(&ray.d.abs());
-        let mut kx: usize = kz + 1;
-        if kx == 3 {
-            kx = 0;
-        }
-        let mut ky: usize = kx + 1;
-        if ky == 3 {
-            ky = 0;
-        }
-        let d: Vector3f = vec3_permutef(&ray.d, kx, ky, kz);
-        p0t = pnt3_permutef(&p0t, kx, ky, kz);
-        p1t = pnt3_permutef(&p1t, kx, ky, kz);
-        p2t = pnt3_permutef(&p2t, kx, ky, kz);
-        // apply shear transformation to translated vertex positions
-        let sx: Float = -d.x / d.z;
-        let sy: Float = -d.y / d.z;
-        let sz: Float = 1.0 / d.z;
-        p0t.x += sx * p0t.z;
-        p0t.y += sy * p0t.z;
-        p1t.x += sx * p1t.z;
-        p1t.y += sy * p1t.z;
-        p2t.x += sx * p2t.z;
-        p2t.y += sy * p2t.z;
-        // compute edge function coefficients _e0_, _e1_, and _e2_
-        let mut e0: Float = p1t.x * p2t.y - p1t.y * p2t.x;
-        let mut e1: Float = p2t.x * p0t.y - p2t.y * p0t.x;
-        let mut e2: Float = p0t.x * p1t.y - p0t.y * p1t.x;
-        // fall back to double precision test at triangle edges
-        if mem::size_of::<Float>() == mem::size_of::<f32>() && (e0 == 0.0 || e1 == 0.0 || e2 == 0.0)
-        {
-            let p2txp1ty: f64 = p2t.x as f64 * p1t.y as f64;
-            let p2typ1tx: f64 = p2t.y as f64 * p1t.x as f64;
-            e0 = (p2typ1tx - p2txp1ty) as Float;
-            let p0txp2ty = p0t.x as f64 * p2t.y as f64;
-            let p0typ2tx = p0t.y as f64 * p2t.x as f64;
-            e1 = (p0typ2tx - p0txp2ty) as Float;
-            let p1txp0ty = p1t.x as f64 * p0t.y as f64;
-            let p1typ0tx = p1t.y as f64 * p0t.x as f64;
-            e2 = (p1typ0tx - p1txp0ty) as Float;
-        }
-        // perform triangle edge and determinant tests
-        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
-            return false;
-        }
-        let det: Float = e0 + e1 + e2;
-        if det == 0.0 {
-            return false;
-        }
-        // compute scaled hit distance to triangle and test against ray $t$ range
-        p0t.z *= sz;
-        p1t.z *= sz;
-        p2t.z *= sz;
-        let t_scaled: Float = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
-        if det < 0.0 && (t_scaled >= 0.0 || t_scaled < ray.t_max.get() * det) {
-            return false;
-        } else if det > 0.0 && (t_scaled <= 0.0 || t_scaled > ray.t_max.get() * det) {
-            return false;
-        }
-        // compute barycentric coordinates and $t$ value for triangle intersection
-        let inv_det: Float = 1.0 / det;
-        let b0: Float = e0 * inv_det;
-        let b1: Float = e1 * inv_det;
-        let b2: Float = e2 * inv_det;
-        let t: Float = t_scaled * inv_det;
-
-        // ensure that computed triangle $t$ is conservatively greater than zero
-
-        // compute $\delta_z$ term for triangle $t$ error bounds
-        let max_zt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.z,
-                y: p1t.z,
-                z: p2t.z,
-            }
-            .abs(),
-        );
-        let delta_z: Float = gamma(3_i32) * max_zt;
-        // compute $\delta_x$ and $\delta_y$ terms for triangle $t$ error bounds
-        let max_xt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.x,
-                y: p1t.x,
-                z: p2t.x,
-            }
-            .abs(),
-        );
-        let max_yt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.y,
-                y: p1t.y,
-                z: p2t.y,
-            }
-            .abs(),
-        );
-        let delta_x: Float = gamma(5) * (max_xt + max_zt);
-        let delta_y: Float = gamma(5) * (max_yt + max_zt);
-        // compute $\delta_e$ term for triangle $t$ error bounds
-        let delta_e: Float =
-            2.0 * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
-        // compute $\delta_t$ term for triangle $t$ error bounds and check _t_
-        let max_e: Float = vec3_max_componentf(
-            &Vector3f {
-                x: e0,
-                y: e1,
-                z: e2,
-            }
-            .abs(),
-        );
-        let delta_t: Float =
-            3.0 * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * inv_det.abs();
-        if t <= delta_t {
-            return false;
-        }
-        // compute triangle partial derivatives
-        let uv: [Point2f; 3] = self.get_uvs();
-        // compute deltas for triangle partial derivatives
-        let duv02: Vector2f = uv[0] - uv[2];
-        let duv12: Vector2f = uv[1] - uv[2];
-        let dp02: Vector3f = *p0 - *p2;
-        let dp12: Vector3f = *p1 - *p2;
-        let determinant: Float = duv02.x * duv12.y - duv02.y * duv12.x;
-        let degenerate_uv: bool = determinant.abs() < 1e-8 as Float;
-        let mut dpdu: Vector3f = Vector3f::default();
-        let mut dpdv: Vector3f = if !degenerate_uv {
-            let invdet: Float = 1.0 / determinant;
-            dpdu = (dp02 * duv12.y - dp12 * duv02.y) * invdet;
-            (dp02 * -duv12.x + dp12 * duv02.x) * invdet
-        } else {
-            Vector3f::default()
-        };
-        if degenerate_uv || vec3_cross_vec3(&dpdu, &dpdv).length_squared() == 0.0 {
-            // handle zero determinant for triangle partial derivative matrix
-            vec3_coordinate_system(
-                &vec3_cross_vec3(&(*p2 - *p0), &(*p1 - *p0)).normalize(),
-                &mut dpdu,
-                &mut dpdv,
-            );
-        }
-        // compute error bounds for triangle intersection
-        let x_abs_sum: Float = (b0 * p0.x).abs() + (b1 * p1.x).abs() + (b2 * p2.x).abs();
-        let y_abs_sum: Float = (b0 * p0.y).abs() + (b1 * p1.y).abs() + (b2 * p2.y).abs();
-        let z_abs_sum: Float = (b0 * p0.z).abs() + (b1 * p1.z).abs() + (b2 * p2.z).abs();
-        let p_error: Vector3f = Vector3f {
-            x: x_abs_sum,
-            y: y_abs_sum,
-            z: z_abs_sum,
-        } * gamma(7);
-        // interpolate $(u,v)$ parametric coordinates and hit point
-        let p_hit: Point3f = *p0 * b0 + *p1 * b1 + *p2 * b2;
-        let uv_hit: Point2f = uv[0] * b0 + uv[1] * b1 + uv[2] * b2;
-        // test intersection against alpha texture, if present
-        // TODO: testAlphaTexture
-        if let Some(alpha_mask) = &self.mesh.alpha_mask {
-            let wo: Vector3f = -ray.d;
-            let isect_local: SurfaceInteraction = SurfaceInteraction::new(
-                &p_hit,
-                &Vector3f::default(),
-                uv_hit,
-                &wo,
-                &dpdu,
-                &dpdv,
-                &Normal3f::default(),
-                &Normal3f::default(),
-                ray.time,
-                None,
-            );
-            if alpha_mask.evaluate(&isect_local) == 0.0 as Float {
-                return false;
+        let p: [Point3f; 3] = self.get_positions_at(ray.time);
+        if let Some((t, b)) = intersect_triangle(ray, &p) {
+            let uv: [Point2f; 3] = self.get_uvs();
+            let n: Option<[Normal3f; 3]> = self.get_normals_at(ray.time);
+            let s: Option<[Vector3f; 3]> = self.get_tangents_at(ray.time);
+            if triangle_interaction(
+                ray,
+                &p,
+                &uv,
+                n.as_ref(),
+                s.as_ref(),
+                b,
+                self.mesh.reverse_orientation ^ self.mesh.transform_swaps_handedness,
+                &self.mesh.alpha_mask,
+                isect,
+            ) {
+                *t_hit = t;
+                return true;
             }
         }
-        // fill in _SurfaceInteraction_ from triangle hit
-        let dndu: Normal3f = Normal3f::default();
-        let dndv: Normal3f = Normal3f::default();
-        let wo: Vector3f = -ray.d;
-        // override surface normal in _isect_ for triangle
-        let mut surface_normal: Normal3f =
-            Normal3f::from(vec3_cross_vec3(&dp02, &dp12).normalize());
-        if self.mesh.reverse_orientation ^ self.mesh.transform_swaps_handedness {
-            surface_normal = -surface_normal;
-        }
-        let mut shading: Shading = Shading {
-            n: surface_normal,
-            dpdu,
-            dpdv,
-            dndu,
-            dndv,
-        };
-        if !self.mesh.n.is_empty() || !self.mesh.s.is_empty() {
-            // initialize _Triangle_ shading geometry
-
-            // compute shading normal _ns_ for triangle
-            let mut ns: Normal3f;
-            if !self.mesh.n.is_empty() {
-                let n0 = self.mesh.n[self.mesh.vertex_indices[(self.id * 3) as usize] as usize];
-                let n1 = self.mesh.n[self.mesh.vertex_indices[(self.id * 3) as usize + 1] as usize];
-                let n2 = self.mesh.n[self.mesh.vertex_indices[(self.id * 3) as usize + 2] as usize];
-                ns = n0 * b0 + n1 * b1 + n2 * b2;
-                if ns.length_squared() > 0.0 {
-                    ns = ns.normalize();
-                } else {
-                    ns = surface_normal;
-                }
-            } else {
-                ns = surface_normal;
-            }
-            // compute shading tangent _ss_ for triangle
-            let mut ss: Vector3f;
-            if !self.mesh.s.is_empty() {
-                let s0 = self.mesh.s[self.mesh.vertex_indices[(self.id * 3) as usize] as usize];
-                let s1 = self.mesh.s[self.mesh.vertex_indices[(self.id * 3) as usize + 1] as usize];
-                let s2 = self.mesh.s[self.mesh.vertex_indices[(self.id * 3) as usize + 2] as usize];
-                ss = s0 * b0 + s1 * b1 + s2 * b2;
-                if ss.length_squared() > 0.0 {
-                    ss = ss.normalize();
-                } else {
-                    ss = dpdu.normalize();
-                }
-            } else {
-                ss = dpdu.normalize();
-            }
-            // compute shading bitangent _ts_ for triangle and adjust _ss_
-            let mut ts: Vector3f = vec3_cross_nrm(&ss, &ns);
-            if ts.length_squared() > 0.0 {
-                ts = ts.normalize();
-                ss = vec3_cross_nrm(&ts, &ns);
-            } else {
-                vec3_coordinate_system(&Vector3f::from(ns), &mut ss, &mut ts);
-            }
-            // compute $\dndu$ and $\dndv$ for triangle shading geometry
-            let dndu: Normal3f;
-            let dndv: Normal3f;
-            if !self.mesh.n.is_empty() {
-                // compute deltas for triangle partial derivatives of normal
-                let duv02: Vector2f = uv[0] - uv[2];
-                let duv12: Vector2f = uv[1] - uv[2];
-                let dn1: Normal3f = self.mesh.n
-                    [self.mesh.vertex_indices[(self.id * 3) as usize] as usize]
-                    - self.mesh.n[self.mesh.vertex_indices[(self.id * 3) as usize + 2] as usize];
-                let dn2: Normal3f = self.mesh.n
-                    [self.mesh.vertex_indices[(self.id * 3) as usize + 1] as usize]
-                    - self.mesh.n[self.mesh.vertex_indices[(self.id * 3) as usize + 2] as usize];
-                let determinant: Float = duv02.x * duv12.y - duv02.y * duv12.x;
-                let degenerate_uv: bool = determinant.abs() < 1e-8;
-                if degenerate_uv {
-                    dndu = Normal3f::default();
-                    dndv = Normal3f::default();
-                } else {
-                    let inv_det: Float = 1.0 / determinant;
-                    dndu = (dn1 * duv12.y - dn2 * duv02.y) * inv_det;
-                    dndv = (dn1 * -duv12.x + dn2 * duv02.x) * inv_det;
-                }
-            } else {
-                dndu = Normal3f::default();
-                dndv = Normal3f::default();
-            }
-            shading.n = Normal3f::from(vec3_cross_vec3(&ss, &ts)).normalize();
-            surface_normal = nrm_faceforward_nrm(&surface_normal, &shading.n);
-            shading.dpdu = ss;
-            shading.dpdv = ts;
-            shading.dndu = dndu;
-            shading.dndv = dndv;
-        }
-        {
-            isect.common.p = p_hit;
-            isect.common.time = ray.time;
-            isect.common.p_error = p_error;
-            isect.common.wo = wo;
-            isect.common.n = surface_normal;
-            isect.common.medium_interface = None;
-        }
-        isect.uv = uv_hit;
-        isect.dpdu = dpdu;
-        isect.dpdv = dpdv;
-        isect.dndu = dndu;
-        isect.dndv = dndv;
-        isect.dpdx = Cell::new(Vector3f::default());
-        isect.dpdy = Cell::new(Vector3f::default());
-        isect.dudx = Cell::new(0.0 as Float);
-        isect.dvdx = Cell::new(0.0 as Float);
-        isect.dudy = Cell::new(0.0 as Float);
-        isect.dvdy = Cell::new(0.0 as Float);
-        isect.primitive = None;
-        isect.shading = shading;
-        isect.bsdf = None;
-        // isect.bssrdf = None;
-        isect.shape = None;
-        *t_hit = t;
-        true
+        false
     }
     pub fn intersect_p(&self, ray: &Ray) -> bool {
         // TODO: ProfilePhase p(Prof::TriIntersectP);
         // TODO: ++nTests;
         // get triangle vertices in _p0_, _p1_, and _p2_
-        let idx1: usize = (self.id * 3) as usize;
-        let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
-        let p0: &Point3f = &self.mesh.p[idx[0] as usize];
-        let p1: &Point3f = &self.mesh.p[idx[1] as usize];
-        let p2: &Point3f = &self.mesh.p[idx[2] as usize];
-        // translate vertices based on ray origin
-        let mut p0t: Point3f = *p0
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        let mut p1t: Point3f = *p1
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        let mut p2t: Point3f = *p2
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        // permute components of triangle vertices and ray direction
-        let kz: usize = vec3_max_dimensionf(&ray.d.abs());
-        let mut kx: usize = kz + 1;
-        if kx == 3 {
-            kx = 0;
-        }
-        let mut ky: usize = kx + 1;
-        if ky == 3 {
-            ky = 0;
-        }
-        let d: Vector3f = vec3_permutef(&ray.d, kx, ky, kz);
-        p0t = pnt3_permutef(&p0t, kx, ky, kz);
-        p1t = pnt3_permutef(&p1t, kx, ky, kz);
-        p2t = pnt3_permutef(&p2t, kx, ky, kz);
-        // apply shear transformation to translated vertex positions
-        let sx: Float = -d.x / d.z;
-        let sy: Float = -d.y / d.z;
-        let sz: Float = 1.0 / d.z;
-        p0t.x += sx * p0t.z;
-        p0t.y += sy * p0t.z;
-        p1t.x += sx * p1t.z;
-        p1t.y += sy * p1t.z;
-        p2t.x += sx * p2t.z;
-        p2t.y += sy * p2t.z;
-        // compute edge function coefficients _e0_, _e1_, and _e2_
-        let mut e0: Float = p1t.x * p2t.y - p1t.y * p2t.x;
-        let mut e1: Float = p2t.x * p0t.y - p2t.y * p0t.x;
-        let mut e2: Float = p0t.x * p1t.y - p0t.y * p1t.x;
-        // fall back to double precision test at triangle edges
-        if mem::size_of::<Float>() == mem::size_of::<f32>() && (e0 == 0.0 || e1 == 0.0 || e2 == 0.0)
-        {
-            let p2txp1ty: f64 = p2t.x as f64 * p1t.y as f64;
-            let p2typ1tx: f64 = p2t.y as f64 * p1t.x as f64;
-            e0 = (p2typ1tx - p2txp1ty) as Float;
-            let p0txp2ty = p0t.x as f64 * p2t.y as f64;
-            let p0typ2tx = p0t.y as f64 * p2t.x as f64;
-            e1 = (p0typ2tx - p0txp2ty) as Float;
-            let p1txp0ty = p1t.x as f64 * p0t.y as f64;
-            let p1typ0tx = p1t.y as f64 * p0t.x as f64;
-            e2 = (p1typ0tx - p1txp0ty) as Float;
-        }
-        // perform triangle edge and determinant tests
-        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
-            return false;
-        }
-        let det: Float = e0 + e1 + e2;
-        if det == 0.0 {
-            return false;
-        }
-        // compute scaled hit distance to triangle and test against ray $t$ range
-        p0t.z *= sz;
-        p1t.z *= sz;
-        p2t.z *= sz;
-        let t_scaled: Float = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
-        if det < 0.0 && (t_scaled >= 0.0 || t_scaled < ray.t_max.get() * det) {
-            return false;
-        } else if det > 0.0 && (t_scaled <= 0.0 || t_scaled > ray.t_max.get() * det) {
-            return false;
-        }
-        // compute barycentric coordinates and $t$ value for triangle intersection
-        let inv_det: Float = 1.0 / det;
-        let b0: Float = e0 * inv_det;
-        let b1: Float = e1 * inv_det;
-        let b2: Float = e2 * inv_det;
-        let t: Float = t_scaled * inv_det;
-
-        // ensure that computed triangle $t$ is conservatively greater than zero
-
-        // compute $\delta_z$ term for triangle $t$ error bounds
-        let max_zt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.z,
-                y: p1t.z,
-                z: p2t.z,
-            }
-            .abs(),
-        );
-        let delta_z: Float = gamma(3_i32) * max_zt;
-        // compute $\delta_x$ and $\delta_y$ terms for triangle $t$ error bounds
-        let max_xt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.x,
-                y: p1t.x,
-                z: p2t.x,
-            }
-            .abs(),
-        );
-        let max_yt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.y,
-                y: p1t.y,
-                z: p2t.y,
-            }
-            .abs(),
-        );
-        let delta_x: Float = gamma(5) * (max_xt + max_zt);
-        let delta_y: Float = gamma(5) * (max_yt + max_zt);
-        // compute $\delta_e$ term for triangle $t$ error bounds
-        let delta_e: Float =
-            2.0 * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
-        // compute $\delta_t$ term for triangle $t$ error bounds and check _t_
-        let max_e: Float = vec3_max_componentf(
-            &Vector3f {
-                x: e0,
-                y: e1,
-                z: e2,
-            }
-            .abs(),
-        );
-        let delta_t: Float =
-            3.0 * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * inv_det.abs();
-        if t <= delta_t {
-            return false;
-        }
-        // TODO: if (testAlphaTexture && (mesh->alphaMask || mesh->shadowAlphaMask)) { ... }
-        if self.mesh.alpha_mask.is_some() || self.mesh.shadow_alpha_mask.is_some() {
-            // compute triangle partial derivatives
-            let mut dpdu: Vector3f = Vector3f::default();
-            let mut dpdv: Vector3f = Vector3f::default();
-            let uv: [Point2f; 3] = self.get_uvs();
-            // compute deltas for triangle partial derivatives
-            let duv02: Vector2f = uv[0] - uv[2];
-            let duv12: Vector2f = uv[1] - uv[2];
-            let dp02: Vector3f = *p0 - *p2;
-            let dp12: Vector3f = *p1 - *p2;
-            let duv02x = duv02[XYEnum::X];
-            let duv02y = duv02[XYEnum::Y];
-            let duv12x = duv12[XYEnum::X];
-            let duv12y = duv12[XYEnum::Y];
-            let determinant: Float = duv02x * duv12y - duv02y * duv12x;
-            let degenerate_uv: bool = determinant.abs() < 1e-8 as Float;
-            if !degenerate_uv {
-                let invdet: Float = 1.0 as Float / determinant;
-                dpdu = (dp02 * duv12y - dp12 * duv02y) * invdet;
-                dpdv = (dp02 * -duv12x + dp12 * duv02x) * invdet;
-            }
-            if degenerate_uv || vec3_cross_vec3(&dpdu, &dpdv).length_squared() == 0.0 {
-                // handle zero determinant for triangle partial derivative matrix
-                let ng = vec3_cross_vec3(&(*p2 - *p0), &(*p1 - *p0));
-                if ng.length_squared() == 0.0 as Float {
-                    // the triangle is actually degenerate; the
-                    // intersection is bogus
-                    return false;
-                }
-                vec3_coordinate_system(
-                    &vec3_cross_vec3(&(*p2 - *p0), &(*p1 - *p0)).normalize(),
-                    &mut dpdu,
-                    &mut dpdv,
-                );
-            }
-            // interpolate $(u,v)$ parametric coordinates and hit point
-            let p_hit: Point3f = *p0 * b0 + *p1 * b1 + *p2 * b2;
-            let uv_hit: Point2f = uv[0] * b0 + uv[1] * b1 + uv[2] * b2;
-            let wo: Vector3f = -ray.d;
-            let isect_local: SurfaceInteraction = SurfaceInteraction::new(
-                &p_hit,
-                &Vector3f::default(),
-                uv_hit,
-                &wo,
-                &dpdu,
-                &dpdv,
-                &Normal3f::default(),
-                &Normal3f::default(),
-                ray.time,
-                None,
-            );
-            if let Some(alpha_mask) = &self.mesh.alpha_mask {
-                if alpha_mask.evaluate(&isect_local) == 0.0 as Float {
-                    return false;
-                }
-            }
-            if let Some(shadow_alpha_mask) = &self.mesh.shadow_alpha_mask {
-                if shadow_alpha_mask.evaluate(&isect_local) == 0.0 as Float {
-                    return false;
-                }
-            }
+        let p: [Point3f; 3] = self.get_positions_at(ray.time);
+        if let Some((_t, b)) = intersect_triangle(ray, &p) {
+            // TODO: ++nHits;
+            triangle_alpha_test_p(
+                ray,
+                &p,
+                &self.get_uvs(),
+                b,
+                &self.mesh.alpha_mask,
+                &self.mesh.shadow_alpha_mask,
+            )
+        } else {
+            false
         }
-        // TODO: ++nHits;
-        true
     }
     pub fn get_reverse_orientation(&self) -> bool {
         self.mesh.reverse_orientation
@@ -664,6 +353,9 @@ impl Triangle {
     pub fn get_object_to_world(&self) -> Transform {
         self.mesh.object_to_world
     }
+    // area() and sample() use the vertex positions at *time_start*,
+    // area lights don't support vertex motion (see
+    // get_shapes_and_materials())
     pub fn area(&self) -> Float {
         let idx1: usize = (self.id * 3) as usize;
         let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
@@ -763,3 +455,446 @@ impl Triangle {
         }
     }
 }
+
+/// Area weighted average of the face normals around each vertex.
+pub fn compute_vertex_normals(vertex_indices: &[u32], p: &[Point3f]) -> Vec<Normal3f> {
+    let mut n: Vec<Normal3f> = vec![Normal3f::default(); p.len()];
+    for tri in vertex_indices.chunks(3) {
+        let (v0, v1, v2) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+        // the length of the cross product is twice the triangle area
+        let face_n: Normal3f = Normal3f::from(vec3_cross_vec3(&(p[v1] - p[v0]), &(p[v2] - p[v0])));
+        n[v0] = n[v0] + face_n;
+        n[v1] = n[v1] + face_n;
+        n[v2] = n[v2] + face_n;
+    }
+    for item in n.iter_mut() {
+        if item.length_squared() > 0.0 as Float {
+            *item = item.normalize();
+        }
+    }
+    n
+}
+
+/// Rotates *v* by the smallest rotation, which turns the unit vector
+/// *from* into the unit vector *to* (keeps *v* if there is no unique
+/// one).
+fn rotate_between(from: &Vector3f, to: &Vector3f, v: &Vector3f) -> Vector3f {
+    let c: Float = vec3_dot_vec3f(from, to);
+    if c <= -1.0 as Float + 1e-4 as Float || from.length_squared() == 0.0 as Float {
+        return *v;
+    }
+    // Rodrigues' formula with k = from x to (|k| = sin, c = cos)
+    let k: Vector3f = vec3_cross_vec3(from, to);
+    let kxv: Vector3f = vec3_cross_vec3(&k, v);
+    *v + kxv + vec3_cross_vec3(&k, &kxv) / (1.0 as Float + c)
+}
+
+/// Watertight ray-triangle intersection test (see
+/// [intersect()](struct.Triangle.html#method.intersect)). Returns
+/// the parametric distance along the ray and the barycentric
+/// coordinates of the hit point.
+pub fn intersect_triangle(ray: &Ray, p: &[Point3f; 3]) -> Option<(Float, [Float; 3])> {
+    let p0: &Point3f = &p[0];
+    let p1: &Point3f = &p[1];
+    let p2: &Point3f = &p[2];
+    // translate vertices based on ray origin
+    let mut p0t: Point3f = *p0
+        - Vector3f {
+            x: ray.o.x,
+            y: ray.o.y,
+            z: ray.o.z,
+        };
+    let mut p1t: Point3f = *p1
+        - Vector3f {
+            x: ray.o.x,
+            y: ray.o.y,
+            z: ray.o.z,
+        };
+    let mut p2t: Point3f = *p2
+        - Vector3f {
+            x: ray.o.x,
+            y: ray.o.y,
+            z: ray.o.z,
+        };
+    // permute components of triangle vertices and ray direction
+    let kz: usize = vec3_max_dimensionf(&ray.d.abs());
+    let mut kx: usize = kz + 1;
+    if kx == 3 {
+        kx = 0;
+    }
+    let mut ky: usize = kx + 1;
+    if ky == 3 {
+        ky = 0;
+    }
+    let d: Vector3f = vec3_permutef(&ray.d, kx, ky, kz);
+    p0t = pnt3_permutef(&p0t, kx, ky, kz);
+    p1t = pnt3_permutef(&p1t, kx, ky, kz);
+    p2t = pnt3_permutef(&p2t, kx, ky, kz);
+    // apply shear transformation to translated vertex positions
+    let sx: Float = -d.x / d.z;
+    let sy: Float = -d.y / d.z;
+    let sz: Float = 1.0 / d.z;
+    p0t.x += sx * p0t.z;
+    p0t.y += sy * p0t.z;
+    p1t.x += sx * p1t.z;
+    p1t.y += sy * p1t.z;
+    p2t.x += sx * p2t.z;
+    p2t.y += sy * p2t.z;
+    // compute edge function coefficients _e0_, _e1_, and _e2_
+    let mut e0: Float = p1t.x * p2t.y - p1t.y * p2t.x;
+    let mut e1: Float = p2t.x * p0t.y - p2t.y * p0t.x;
+    let mut e2: Float = p0t.x * p1t.y - p0t.y * p1t.x;
+    // fall back to double precision test at triangle edges
+    if mem::size_of::<Float>() == mem::size_of::<f32>() && (e0 == 0.0 || e1 == 0.0 || e2 == 0.0) {
+        let p2txp1ty: f64 = p2t.x as f64 * p1t.y as f64;
+        let p2typ1tx: f64 = p2t.y as f64 * p1t.x as f64;
+        e0 = (p2typ1tx - p2txp1ty) as Float;
+        let p0txp2ty = p0t.x as f64 * p2t.y as f64;
+        let p0typ2tx = p0t.y as f64 * p2t.x as f64;
+        e1 = (p0typ2tx - p0txp2ty) as Float;
+        let p1txp0ty = p1t.x as f64 * p0t.y as f64;
+        let p1typ0tx = p1t.y as f64 * p0t.x as f64;
+        e2 = (p1typ0tx - p1txp0ty) as Float;
+    }
+    // perform triangle edge and determinant tests
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+    let det: Float = e0 + e1 + e2;
+    if det == 0.0 {
+        return None;
+    }
+    // compute scaled hit distance to triangle and test against ray $t$ range
+    p0t.z *= sz;
+    p1t.z *= sz;
+    p2t.z *= sz;
+    let t_scaled: Float = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
+    if det < 0.0 && (t_scaled >= 0.0 || t_scaled < ray.t_max.get() * det) {
+        return None;
+    } else if det > 0.0 && (t_scaled <= 0.0 || t_scaled > ray.t_max.get() * det) {
+        return None;
+    }
+    // compute barycentric coordinates and $t$ value for triangle intersection
+    let inv_det: Float = 1.0 / det;
+    let b0: Float = e0 * inv_det;
+    let b1: Float = e1 * inv_det;
+    let b2: Float = e2 * inv_det;
+    let t: Float = t_scaled * inv_det;
+
+    // ensure that computed triangle $t$ is conservatively greater than zero
+
+    // compute $\delta_z$ term for triangle $t$ error bounds
+    let max_zt: Float = vec3_max_componentf(
+        &Vector3f {
+            x: p0t.z,
+            y: p1t.z,
+            z: p2t.z,
+        }
+        .abs(),
+    );
+    let delta_z: Float = gamma(3_i32) * max_zt;
+    // compute $\delta_x$ and $\delta_y$ terms for triangle $t$ error bounds
+    let max_xt: Float = vec3_max_componentf(
+        &Vector3f {
+            x: p0t.x,
+            y: p1t.x,
+            z: p2t.x,
+        }
+        .abs(),
+    );
+    let max_yt: Float = vec3_max_componentf(
+        &Vector3f {
+            x: p0t.y,
+            y: p1t.y,
+            z: p2t.y,
+        }
+        .abs(),
+    );
+    let delta_x: Float = gamma(5) * (max_xt + max_zt);
+    let delta_y: Float = gamma(5) * (max_yt + max_zt);
+    // compute $\delta_e$ term for triangle $t$ error bounds
+    let delta_e: Float = 2.0 * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
+    // compute $\delta_t$ term for triangle $t$ error bounds and check _t_
+    let max_e: Float = vec3_max_componentf(
+        &Vector3f {
+            x: e0,
+            y: e1,
+            z: e2,
+        }
+        .abs(),
+    );
+    let delta_t: Float =
+        3.0 * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * inv_det.abs();
+    if t <= delta_t {
+        return None;
+    }
+    Some((t, [b0, b1, b2]))
+}
+
+/// Fills in *isect* for a ray hitting a triangle at barycentric
+/// coordinates *b* (as returned by
+/// [intersect_triangle()](fn.intersect_triangle.html)). Returns
+/// *false* if the hit gets rejected by the alpha mask.
+pub fn triangle_interaction(
+    ray: &Ray,
+    p: &[Point3f; 3],
+    uv: &[Point2f; 3],
+    n: Option<&[Normal3f; 3]>,
+    s: Option<&[Vector3f; 3]>,
+    b: [Float; 3],
+    flip_normal: bool,
+    alpha_mask: &Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    isect: &mut SurfaceInteraction,
+) -> bool {
+    let p0: &Point3f = &p[0];
+    let p1: &Point3f = &p[1];
+    let p2: &Point3f = &p[2];
+    let b0: Float = b[0];
+    let b1: Float = b[1];
+    let b2: Float = b[2];
+    // compute triangle partial derivatives
+    // compute deltas for triangle partial derivatives
+    let duv02: Vector2f = uv[0] - uv[2];
+    let duv12: Vector2f = uv[1] - uv[2];
+    let dp02: Vector3f = *p0 - *p2;
+    let dp12: Vector3f = *p1 - *p2;
+    let determinant: Float = duv02.x * duv12.y - duv02.y * duv12.x;
+    let degenerate_uv: bool = determinant.abs() < 1e-8 as Float;
+    let mut dpdu: Vector3f = Vector3f::default();
+    let mut dpdv: Vector3f = if !degenerate_uv {
+        let invdet: Float = 1.0 / determinant;
+        dpdu = (dp02 * duv12.y - dp12 * duv02.y) * invdet;
+        (dp02 * -duv12.x + dp12 * duv02.x) * invdet
+    } else {
+        Vector3f::default()
+    };
+    if degenerate_uv || vec3_cross_vec3(&dpdu, &dpdv).length_squared() == 0.0 {
+        // handle zero determinant for triangle partial derivative matrix
+        vec3_coordinate_system(
+            &vec3_cross_vec3(&(*p2 - *p0), &(*p1 - *p0)).normalize(),
+            &mut dpdu,
+            &mut dpdv,
+        );
+    }
+    // compute error bounds for triangle intersection
+    let x_abs_sum: Float = (b0 * p0.x).abs() + (b1 * p1.x).abs() + (b2 * p2.x).abs();
+    let y_abs_sum: Float = (b0 * p0.y).abs() + (b1 * p1.y).abs() + (b2 * p2.y).abs();
+    let z_abs_sum: Float = (b0 * p0.z).abs() + (b1 * p1.z).abs() + (b2 * p2.z).abs();
+    let p_error: Vector3f = Vector3f {
+        x: x_abs_sum,
+        y: y_abs_sum,
+        z: z_abs_sum,
+    } * gamma(7);
+    // interpolate $(u,v)$ parametric coordinates and hit point
+    let p_hit: Point3f = *p0 * b0 + *p1 * b1 + *p2 * b2;
+    let uv_hit: Point2f = uv[0] * b0 + uv[1] * b1 + uv[2] * b2;
+    // test intersection against alpha texture, if present
+    // TODO: testAlphaTexture
+    if let Some(alpha_mask) = alpha_mask {
+        let wo: Vector3f = -ray.d;
+        let isect_local: SurfaceInteraction = SurfaceInteraction::new(
+            &p_hit,
+            &Vector3f::default(),
+            uv_hit,
+            &wo,
+            &dpdu,
+            &dpdv,
+            &Normal3f::default(),
+            &Normal3f::default(),
+            ray.time,
+            None,
+        );
+        if alpha_mask.evaluate(&isect_local) == 0.0 as Float {
+            return false;
+        }
+    }
+    // fill in _SurfaceInteraction_ from triangle hit
+    let dndu: Normal3f = Normal3f::default();
+    let dndv: Normal3f = Normal3f::default();
+    let wo: Vector3f = -ray.d;
+    // override surface normal in _isect_ for triangle
+    let mut surface_normal: Normal3f = Normal3f::from(vec3_cross_vec3(&dp02, &dp12).normalize());
+    if flip_normal {
+        surface_normal = -surface_normal;
+    }
+    let mut shading: Shading = Shading {
+        n: surface_normal,
+        dpdu,
+        dpdv,
+        dndu,
+        dndv,
+    };
+    if n.is_some() || s.is_some() {
+        // initialize _Triangle_ shading geometry
+
+        // compute shading normal _ns_ for triangle
+        let mut ns: Normal3f;
+        if let Some(n) = n {
+            ns = n[0] * b0 + n[1] * b1 + n[2] * b2;
+            if ns.length_squared() > 0.0 {
+                ns = ns.normalize();
+            } else {
+                ns = surface_normal;
+            }
+        } else {
+            ns = surface_normal;
+        }
+        // compute shading tangent _ss_ for triangle
+        let mut ss: Vector3f;
+        if let Some(s) = s {
+            ss = s[0] * b0 + s[1] * b1 + s[2] * b2;
+            if ss.length_squared() > 0.0 {
+                ss = ss.normalize();
+            } else {
+                ss = dpdu.normalize();
+            }
+        } else {
+            ss = dpdu.normalize();
+        }
+        // compute shading bitangent _ts_ for triangle and adjust _ss_
+        let mut ts: Vector3f = vec3_cross_nrm(&ss, &ns);
+        if ts.length_squared() > 0.0 {
+            ts = ts.normalize();
+            ss = vec3_cross_nrm(&ts, &ns);
+        } else {
+            vec3_coordinate_system(&Vector3f::from(ns), &mut ss, &mut ts);
+        }
+        // compute $\dndu$ and $\dndv$ for triangle shading geometry
+        let dndu: Normal3f;
+        let dndv: Normal3f;
+        if let Some(n) = n {
+            // compute deltas for triangle partial derivatives of normal
+            let duv02: Vector2f = uv[0] - uv[2];
+            let duv12: Vector2f = uv[1] - uv[2];
+            let dn1: Normal3f = n[0] - n[2];
+            let dn2: Normal3f = n[1] - n[2];
+            let determinant: Float = duv02.x * duv12.y - duv02.y * duv12.x;
+            let degenerate_uv: bool = determinant.abs() < 1e-8;
+            if degenerate_uv {
+                dndu = Normal3f::default();
+                dndv = Normal3f::default();
+            } else {
+                let inv_det: Float = 1.0 / determinant;
+                dndu = (dn1 * duv12.y - dn2 * duv02.y) * inv_det;
+                dndv = (dn1 * -duv12.x + dn2 * duv02.x) * inv_det;
+            }
+        } else {
+            dndu = Normal3f::default();
+            dndv = Normal3f::default();
+        }
+        shading.n = Normal3f::from(vec3_cross_vec3(&ss, &ts)).normalize();
+        surface_normal = nrm_faceforward_nrm(&surface_normal, &shading.n);
+        shading.dpdu = ss;
+        shading.dpdv = ts;
+        shading.dndu = dndu;
+        shading.dndv = dndv;
+    }
+    {
+        isect.common.p = p_hit;
+        isect.common.time = ray.time;
+        isect.common.p_error = p_error;
+        isect.common.wo = wo;
+        isect.common.n = surface_normal;
+        isect.common.medium_interface = None;
+    }
+    isect.uv = uv_hit;
+    isect.dpdu = dpdu;
+    isect.dpdv = dpdv;
+    isect.dndu = dndu;
+    isect.dndv = dndv;
+    isect.dpdx = Cell::new(Vector3f::default());
+    isect.dpdy = Cell::new(Vector3f::default());
+    isect.dudx = Cell::new(0.0 as Float);
+    isect.dvdx = Cell::new(0.0 as Float);
+    isect.dudy = Cell::new(0.0 as Float);
+    isect.dvdy = Cell::new(0.0 as Float);
+    isect.primitive = None;
+    isect.shading = shading;
+    isect.bsdf = None;
+    // isect.bssrdf = None;
+    isect.shape = None;
+    true
+}
+
+/// Tests a shadow ray hit (see
+/// [intersect_triangle()](fn.intersect_triangle.html)) against the
+/// alpha masks of a triangle. Returns *false* if the hit gets
+/// rejected.
+pub fn triangle_alpha_test_p(
+    ray: &Ray,
+    p: &[Point3f; 3],
+    uv: &[Point2f; 3],
+    b: [Float; 3],
+    alpha_mask: &Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    shadow_alpha_mask: &Option<Arc<dyn Texture<Float> + Send + Sync>>,
+) -> bool {
+    let p0: &Point3f = &p[0];
+    let p1: &Point3f = &p[1];
+    let p2: &Point3f = &p[2];
+    let b0: Float = b[0];
+    let b1: Float = b[1];
+    let b2: Float = b[2];
+    // TODO: if (testAlphaTexture && (mesh->alphaMask || mesh->shadowAlphaMask)) { ... }
+    if alpha_mask.is_some() || shadow_alpha_mask.is_some() {
+        // compute triangle partial derivatives
+        let mut dpdu: Vector3f = Vector3f::default();
+        let mut dpdv: Vector3f = Vector3f::default();
+        // compute deltas for triangle partial derivatives
+        let duv02: Vector2f = uv[0] - uv[2];
+        let duv12: Vector2f = uv[1] - uv[2];
+        let dp02: Vector3f = *p0 - *p2;
+        let dp12: Vector3f = *p1 - *p2;
+        let duv02x = duv02[XYEnum::X];
+        let duv02y = duv02[XYEnum::Y];
+        let duv12x = duv12[XYEnum::X];
+        let duv12y = duv12[XYEnum::Y];
+        let determinant: Float = duv02x * duv12y - duv02y * duv12x;
+        let degenerate_uv: bool = determinant.abs() < 1e-8 as Float;
+        if !degenerate_uv {
+            let invdet: Float = 1.0 as Float / determinant;
+            dpdu = (dp02 * duv12y - dp12 * duv02y) * invdet;
+            dpdv = (dp02 * -duv12x + dp12 * duv02x) * invdet;
+        }
+        if degenerate_uv || vec3_cross_vec3(&dpdu, &dpdv).length_squared() == 0.0 {
+            // handle zero determinant for triangle partial derivative matrix
+            let ng = vec3_cross_vec3(&(*p2 - *p0), &(*p1 - *p0));
+            if ng.length_squared() == 0.0 as Float {
+                // the triangle is actually degenerate; the
+                // intersection is bogus
+                return false;
+            }
+            vec3_coordinate_system(
+                &vec3_cross_vec3(&(*p2 - *p0), &(*p1 - *p0)).normalize(),
+                &mut dpdu,
+                &mut dpdv,
+            );
+        }
+        // interpolate $(u,v)$ parametric coordinates and hit point
+        let p_hit: Point3f = *p0 * b0 + *p1 * b1 + *p2 * b2;
+        let uv_hit: Point2f = uv[0] * b0 + uv[1] * b1 + uv[2] * b2;
+        let wo: Vector3f = -ray.d;
+        let isect_local: SurfaceInteraction = SurfaceInteraction::new(
+            &p_hit,
+            &Vector3f::default(),
+            uv_hit,
+            &wo,
+            &dpdu,
+            &dpdv,
+            &Normal3f::default(),
+            &Normal3f::default(),
+            ray.time,
+            None,
+        );
+        if let Some(alpha_mask) = alpha_mask {
+            if alpha_mask.evaluate(&isect_local) == 0.0 as Float {
+                return false;
+            }
+        }
+        if let Some(shadow_alpha_mask) = shadow_alpha_mask {
+            if shadow_alpha_mask.evaluate(&isect_local) == 0.0 as Float {
+                return false;
+            }
+        }
+    }
+    true
+}
@@ -1,11 +1,10 @@
 // std
 use std::ops::{Add, AddAssign, Div, Mul};
-use std::path::Path;
-use std::sync::Arc;
-// others
-use image::{DynamicImage, ImageResult};
+use std::sync::{Arc, OnceLock};
 // pbrt
+use crate::core::colorspace::{RGBColorSpace, TextureColorSpace};
 use crate::core::geometry::{Point2f, Point2i, Vector2f};
+use crate::core::imageio::read_image;
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::mipmap::{Clampable, ImageWrap, MipMap};
 use crate::core::pbrt::{Float, Spectrum};
@@ -38,49 +37,41 @@ where
         max_aniso: Float,
         wrap_mode: ImageWrap,
         scale: Float,
-        gamma: bool,
+        colorspace: TextureColorSpace,
+        working_color_space: RGBColorSpace,
+        convert: F,
+    ) -> ImageTexture<T> {
+        let (texels, res) = read_texels(&filename);
+        ImageTexture::from_texels(
+            mapping,
+            &texels,
+            res,
+            do_trilinear,
+            max_aniso,
+            wrap_mode,
+            scale,
+            colorspace,
+            working_color_space,
+            convert,
+        )
+    }
+    /// Creates the texture from texels returned by [read_texels()].
+    pub fn from_texels<F: Fn(&Spectrum) -> T>(
+        mapping: Box<TextureMapping2D>,
+        texels: &[Spectrum],
+        res: Point2i,
+        do_trilinear: bool,
+        max_aniso: Float,
+        wrap_mode: ImageWrap,
+        scale: Float,
+        colorspace: TextureColorSpace,
+        working_color_space: RGBColorSpace,
         convert: F,
     ) -> ImageTexture<T> {
-        let path = Path::new(&filename);
-        let img_result: ImageResult<DynamicImage> = image::open(path);
-        if img_result.is_err() {
-            panic!("Error reading \"{}\"", filename);
-        }
-        let buf = img_result.unwrap();
-        let rgb = buf.to_rgb8();
-        let res = Point2i {
-            x: rgb.width() as i32,
-            y: rgb.height() as i32,
-        };
-        let mut texels: Vec<Spectrum> = rgb
-            .pixels()
-            .map(|p| {
-                let r = Float::from(p[0]) / 255.0;
-                let g = Float::from(p[1]) / 255.0;
-                let b = Float::from(p[2]) / 255.0;
-                Spectrum::rgb(r, g, b)
-            })
-            .collect();
-        // flip image in y; texture coordinate space has (0,0) at the
-        // lower left corner.
-        for y in 0..res.y / 2 {
-            for x in 0..res.x {
-                let o1 = (y * res.x + x) as usize;
-                let o2 = ((res.y - 1 - y) * res.x + x) as usize;
-                texels.swap(o1, o2);
-            }
-        }
         // instead of convertIn(texels[i], &convertedTexels[i], scale, gamma);
         let converted_texels: Vec<T> = texels
             .iter()
-            .map(|p| {
-                let s = if gamma {
-                    p.inverse_gamma_correct() * scale
-                } else {
-                    *p * scale
-                };
-                convert(&s)
-            })
+            .map(|p| convert(&(colorspace.to_working(p, working_color_space) * scale)))
             .collect();
         // create _MipMap_ from converted texels (see above)
         let mipmap = Arc::new(MipMap::new(
@@ -94,6 +85,80 @@ where
     }
 }
 
+/// Reads the texels of an image texture as stored in the file,
+/// flipped in y, together with the resolution of the image.
+pub fn read_texels(filename: &str) -> (Vec<Spectrum>, Point2i) {
+    let mut res: Point2i = Point2i::default();
+    let mut texels: Vec<Spectrum> = match read_image(filename, &mut res) {
+        Some(texels) => texels,
+        None => panic!("Error reading \"{}\"", filename),
+    };
+    // flip image in y; texture coordinate space has (0,0) at the
+    // lower left corner.
+    for y in 0..res.y / 2 {
+        for x in 0..res.x {
+            let o1 = (y * res.x + x) as usize;
+            let o2 = ((res.y - 1 - y) * res.x + x) as usize;
+            texels.swap(o1, o2);
+        }
+    }
+    (texels, res)
+}
+
+/// The texels and lookup parameters of a spectrum image texture, so
+/// that it can be used again as data: the texels of a normal map
+/// are vectors, which must not be color converted.
+pub struct ImageTextureSource {
+    mapping: TextureMapping2D,
+    texels: Vec<Spectrum>,
+    res: Point2i,
+    do_trilinear: bool,
+    max_aniso: Float,
+    wrap_mode: ImageWrap,
+    raw: OnceLock<Arc<dyn Texture<Spectrum> + Send + Sync>>,
+}
+
+impl ImageTextureSource {
+    pub fn new(
+        mapping: &TextureMapping2D,
+        texels: Vec<Spectrum>,
+        res: Point2i,
+        do_trilinear: bool,
+        max_aniso: Float,
+        wrap_mode: ImageWrap,
+    ) -> Self {
+        ImageTextureSource {
+            mapping: mapping.clone(),
+            texels,
+            res,
+            do_trilinear,
+            max_aniso,
+            wrap_mode,
+            raw: OnceLock::new(),
+        }
+    }
+    /// Returns the texture with its texels as stored in the file (it
+    /// is created on first use and shared afterwards).
+    pub fn raw_texture(&self) -> Arc<dyn Texture<Spectrum> + Send + Sync> {
+        self.raw
+            .get_or_init(|| {
+                Arc::new(ImageTexture::from_texels(
+                    Box::new(self.mapping.clone()),
+                    &self.texels,
+                    self.res,
+                    self.do_trilinear,
+                    self.max_aniso,
+                    self.wrap_mode.clone(),
+                    1.0 as Float,
+                    TextureColorSpace::Raw,
+                    RGBColorSpace::SRGB,
+                    convert_to_spectrum,
+                ))
+            })
+            .clone()
+    }
+}
+
 pub trait ImageTextureConvert<T> {
     fn convert_out(from: &T, to: &mut T);
 }